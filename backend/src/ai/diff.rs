@@ -0,0 +1,293 @@
+use serde::{Deserialize, Serialize};
+use similar::{ChangeTag, TextDiff};
+
+use crate::ai::provider::ChatMessage;
+use crate::ai::review::{line_count, ReviewResponse, SYSTEM_PROMPT};
+
+/// A review of only what changed since `base`, or of the lines `diff` touches.
+/// For a stored file, `base_revision` names the base instead and the file
+/// supplies `language` and `code`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DiffReviewRequest {
+    #[serde(default)]
+    pub language: String,
+    /// The file as it is now; suggestions point at its lines.
+    #[serde(default)]
+    pub code: String,
+    /// Saved revision `code` was read from, if it is one.
+    #[serde(default)]
+    pub revision: Option<u64>,
+    /// The content of the base revision, e.g. from `GET .../revisions/{n}`.
+    pub base: Option<String>,
+    /// A unified diff from the base to `code`, instead of `base`.
+    pub diff: Option<String>,
+    /// Saved revision of the file to review the changes since.
+    #[serde(default)]
+    pub base_revision: Option<u64>,
+    /// Unchanged lines shown around each change.
+    #[serde(default = "default_context")]
+    pub context: usize,
+}
+
+fn default_context() -> usize {
+    3
+}
+
+impl DiffReviewRequest {
+    pub fn excerpt(&self) -> Result<Excerpt, String> {
+        match (&self.base, &self.diff) {
+            (Some(base), None) => Ok(Excerpt::between(base, &self.code, self.context)),
+            (None, Some(diff)) => Excerpt::from_unified(diff, &self.code),
+            _ => Err("Send either `base` or `diff`".to_string()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Context,
+    Added,
+    Removed,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct ExcerptLine {
+    kind: Kind,
+    text: String,
+    /// The line of the current file this stands for. A removed line stands
+    /// for the line that now sits where it was.
+    current: usize,
+}
+
+/// The changed hunks of a file with their context, numbered from 1 for the
+/// model and mapped back onto the current file.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Excerpt {
+    hunks: Vec<Vec<ExcerptLine>>,
+}
+
+impl Excerpt {
+    /// The changes from `base` to `current`.
+    pub fn between(base: &str, current: &str, context: usize) -> Self {
+        let lines = line_count(current);
+        let diff = TextDiff::from_lines(base, current);
+        let hunks = diff
+            .grouped_ops(context)
+            .iter()
+            .map(|group| {
+                group
+                    .iter()
+                    .flat_map(|op| {
+                        let at = (op.new_range().start + 1).min(lines);
+                        diff.iter_changes(op).map(move |change| ExcerptLine {
+                            kind: match change.tag() {
+                                ChangeTag::Equal => Kind::Context,
+                                ChangeTag::Insert => Kind::Added,
+                                ChangeTag::Delete => Kind::Removed,
+                            },
+                            text: change.value().trim_end_matches(['\n', '\r']).to_string(),
+                            current: change.new_index().map_or(at, |index| index + 1),
+                        })
+                    })
+                    .collect()
+            })
+            .collect();
+        Self { hunks }
+    }
+
+    /// The hunks of a unified diff, checked against `current`.
+    pub fn from_unified(diff: &str, current: &str) -> Result<Self, String> {
+        let code: Vec<&str> = current.lines().collect();
+        let lines = line_count(current);
+        let mut hunks: Vec<Vec<ExcerptLine>> = Vec::new();
+        // Lines still to come in the open hunk, old and new side.
+        let (mut old, mut new) = (0usize, 0usize);
+        let mut cursor = 1;
+        for line in diff.lines() {
+            if old == 0 && new == 0 {
+                if let Some(header) = line.strip_prefix("@@ ") {
+                    let (old_len, new_start, new_len) = hunk_header(header).ok_or_else(|| format!("bad hunk header: {}", line))?;
+                    (old, new) = (old_len, new_len);
+                    cursor = if new_len == 0 { new_start + 1 } else { new_start };
+                    hunks.push(Vec::new());
+                }
+                continue;
+            }
+            let (kind, text) = match line.split_at_checked(1) {
+                Some(("\\", _)) => continue,
+                Some((" ", text)) => (Kind::Context, text),
+                Some(("+", text)) => (Kind::Added, text),
+                Some(("-", text)) => (Kind::Removed, text),
+                // Some tools drop the space of empty context lines.
+                None => (Kind::Context, ""),
+                Some(_) => return Err(format!("unexpected line in hunk: {}", line)),
+            };
+            let hunk = hunks.last_mut().expect("a hunk is open");
+            if kind == Kind::Removed {
+                old = old.checked_sub(1).ok_or("a hunk is longer than its header says")?;
+                hunk.push(ExcerptLine { kind, text: text.to_string(), current: cursor.min(lines) });
+                continue;
+            }
+            if code.get(cursor - 1).map(|l| l.trim_end_matches('\r')) != Some(text.trim_end_matches('\r')) {
+                return Err(format!("the diff does not match line {} of the current code", cursor));
+            }
+            if kind == Kind::Context {
+                old = old.checked_sub(1).ok_or("a hunk is longer than its header says")?;
+            }
+            new = new.checked_sub(1).ok_or("a hunk is longer than its header says")?;
+            hunk.push(ExcerptLine { kind, text: text.to_string(), current: cursor });
+            cursor += 1;
+        }
+        if old != 0 || new != 0 {
+            return Err("the diff ends in the middle of a hunk".to_string());
+        }
+        hunks.retain(|hunk| !hunk.is_empty());
+        Ok(Self { hunks })
+    }
+
+    /// True when nothing changed.
+    pub fn is_empty(&self) -> bool {
+        self.hunks.iter().flatten().all(|line| line.kind == Kind::Context)
+    }
+
+    /// Numbered lines in the excerpt.
+    pub fn len(&self) -> usize {
+        self.hunks.iter().map(Vec::len).sum()
+    }
+
+    fn line(&self, number: usize) -> Option<&ExcerptLine> {
+        self.hunks.iter().flatten().nth(number.checked_sub(1)?)
+    }
+
+    /// The conversation that asks a model to review the changes.
+    pub fn messages(&self, language: &str) -> Vec<ChatMessage> {
+        let mut number = 0;
+        let hunks: Vec<String> = self
+            .hunks
+            .iter()
+            .map(|hunk| {
+                hunk.iter()
+                    .map(|line| {
+                        number += 1;
+                        let marker = match line.kind {
+                            Kind::Context => ' ',
+                            Kind::Added => '+',
+                            Kind::Removed => '-',
+                        };
+                        format!("{:>4} |{} {}\n", number, marker, line.text)
+                    })
+                    .collect()
+            })
+            .collect();
+        let prompt = format!("{}\n\nChanges:\n---\n{}---", INSTRUCTIONS.replace("{language}", language), hunks.join("     ...\n"));
+        vec![ChatMessage::system(SYSTEM_PROMPT), ChatMessage::user(&prompt)]
+    }
+
+    /// Moves the suggestions of a review of [`Excerpt::messages`] onto the
+    /// lines of the current file. Fixes for removed lines are dropped, as
+    /// those lines are gone.
+    pub fn map(&self, mut review: ReviewResponse) -> ReviewResponse {
+        review.suggestions.retain_mut(|suggestion| match self.line(suggestion.line) {
+            Some(line) => {
+                suggestion.line = line.current;
+                if line.kind == Kind::Removed {
+                    suggestion.fix = None;
+                }
+                true
+            }
+            None => false,
+        });
+        review
+    }
+
+    /// Keeps the suggestions of a whole-file review that fall within a hunk.
+    pub fn keep_changed(&self, mut review: ReviewResponse) -> ReviewResponse {
+        let spans: Vec<(usize, usize)> = self
+            .hunks
+            .iter()
+            .filter_map(|hunk| Some((hunk.first()?.current, hunk.last()?.current)))
+            .collect();
+        review.suggestions.retain(|suggestion| spans.iter().any(|(first, last)| (*first..=*last).contains(&suggestion.line)));
+        review
+    }
+}
+
+/// `-a,b +c,d @@` as `(b, c, d)`; a missing length is 1. Only an empty
+/// new side may start at line 0.
+fn hunk_header(header: &str) -> Option<(usize, usize, usize)> {
+    let mut ranges = header.split_whitespace();
+    let range = |range: Option<&str>, sign: char| -> Option<(usize, usize)> {
+        let range = range?.strip_prefix(sign)?;
+        match range.split_once(',') {
+            Some((start, len)) => Some((start.parse().ok()?, len.parse().ok()?)),
+            None => Some((range.parse().ok()?, 1)),
+        }
+    };
+    let (_, old_len) = range(ranges.next(), '-')?;
+    let (new_start, new_len) = range(ranges.next(), '+')?;
+    if new_start == 0 && new_len > 0 {
+        return None;
+    }
+    Some((old_len, new_start, new_len))
+}
+
+const INSTRUCTIONS: &str = r#"Review the changes to the {language} code below. Each line starts with a number and a "|", then "+" for an added line, "-" for a removed line or a space for unchanged context; none of these are part of the code. "..." separates changes in different parts of the file.
+
+Only comment on what the changes add or remove; the context is there to help you understand them.
+
+Answer with a JSON object of this shape and nothing else:
+{"suggestions": [{"line": 2, "message": "Use a list comprehension instead of the loop", "severity": "info", "fix": "result = [x * 2 for x in arr]"}]}
+
+- line: the number of the line the suggestion is about, as shown
+- message: a short description of the issue or improvement
+- severity: "info", "warning" or "error"
+- fix: the corrected code for that line, or null unless the fix is obvious
+
+Answer {"suggestions": []} if there is nothing worth changing."#;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ai::review::Suggestion;
+
+    const BASE: &str = "a = 1\nb = 2\nc = 3\nd = 4\ne = 5\nf = 6\ng = 7\nh = 8\ni = 9\nj = 10\n";
+    const CURRENT: &str = "a = 1\nb = 2\nc = 3\nd = 4\ne = 5\nf = 6\ng = 7\nh = 8\nj = 10\nk = 11\n";
+
+    fn suggestion(line: usize) -> Suggestion {
+        Suggestion { line, message: "Look here".into(), severity: None, fix: Some("x".into()), edit: None }
+    }
+
+    #[test]
+    fn changes_are_excerpted_and_mapped_back() {
+        let excerpt = Excerpt::between("x = 1\nold = 2\ny = 3\n", "x = 1\nnew = 2\ny = 3\n", 1);
+        let prompt = &excerpt.messages("Python")[1].content;
+        assert!(prompt.contains("   1 |  x = 1\n   2 |- old = 2\n   3 |+ new = 2\n   4 |  y = 3\n"), "{}", prompt);
+
+        let mapped = excerpt.map(ReviewResponse { suggestions: vec![suggestion(3), suggestion(2), suggestion(9)], problems: vec![] });
+        assert_eq!(mapped.suggestions.iter().map(|s| (s.line, s.fix.is_some())).collect::<Vec<_>>(), [(2, true), (2, false)]);
+        assert!(Excerpt::between(BASE, BASE, 3).is_empty());
+    }
+
+    #[test]
+    fn unified_diffs_give_the_same_excerpt() {
+        let diff = TextDiff::from_lines(BASE, CURRENT).unified_diff().context_radius(1).header("a", "b").to_string();
+        let from_diff = Excerpt::from_unified(&diff, CURRENT).unwrap();
+        assert_eq!(from_diff, Excerpt::between(BASE, CURRENT, 1));
+        assert_eq!(from_diff.len(), 4);
+        // `k = 11` is excerpt line 4 and line 10 of the current file.
+        assert_eq!(from_diff.map(ReviewResponse { suggestions: vec![suggestion(4)], problems: vec![] }).suggestions[0].line, 10);
+
+        let whole = ReviewResponse { suggestions: vec![suggestion(2), suggestion(9)], problems: vec![] };
+        assert_eq!(from_diff.keep_changed(whole).suggestions, [suggestion(9)]);
+
+        let stale = diff.replace(" h = 8", " h = 80");
+        assert_eq!(Excerpt::from_unified(&stale, CURRENT).unwrap_err(), "the diff does not match line 8 of the current code");
+    }
+
+    #[test]
+    fn hunks_cannot_start_before_the_first_line() {
+        assert_eq!(Excerpt::from_unified("@@ -1 +0,1 @@\n+a = 1\n", "a = 1\n").unwrap_err(), "bad hunk header: @@ -1 +0,1 @@");
+        let emptied = Excerpt::from_unified("@@ -1 +0,0 @@\n-a = 1\n", "").unwrap();
+        assert!(!emptied.is_empty());
+    }
+}
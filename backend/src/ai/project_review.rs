@@ -0,0 +1,279 @@
+use futures_util::stream::{self, StreamExt};
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::ops::RangeInclusive;
+use std::sync::{Arc, Mutex};
+use tokio::sync::Semaphore;
+use uuid::Uuid;
+
+use crate::ai::provider::{AiProvider, AiResult};
+use crate::ai::review::{ReviewRequest, ReviewResponse, Suggestion};
+use crate::model::revision::now_millis;
+
+/// Finished jobs kept for fetching; older ones are forgotten first.
+const KEPT_JOBS: usize = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JobStatus {
+    Running,
+    Done,
+}
+
+/// A file handed to a project review.
+#[derive(Debug, Clone)]
+pub struct SourceFile {
+    pub id: Uuid,
+    pub path: String,
+    pub language: String,
+    pub content: String,
+    /// Saved revision `content` is, or `None` when it has unsaved changes.
+    pub revision: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FileReview {
+    pub file_id: Uuid,
+    pub path: String,
+    pub language: String,
+    /// Pieces the file was split into to fit the model's context.
+    pub chunks: usize,
+    pub chunks_done: usize,
+    /// Ordered by line, which counts from the start of the file.
+    pub suggestions: Vec<Suggestion>,
+    /// Parts of the model's replies that were left out, and why.
+    pub problems: Vec<String>,
+    /// Chunks that could not be reviewed, and why.
+    pub errors: Vec<String>,
+}
+
+/// A review of every file in a project, filled in as chunks come back.
+#[derive(Debug, Clone, Serialize)]
+pub struct ReviewJob {
+    pub id: Uuid,
+    pub project_id: Uuid,
+    pub started_by: Uuid,
+    /// Milliseconds since the Unix epoch.
+    pub started_at: u64,
+    pub finished_at: Option<u64>,
+    pub status: JobStatus,
+    pub chunks: usize,
+    pub chunks_done: usize,
+    pub files: Vec<FileReview>,
+}
+
+/// A run of whole lines of a file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Chunk {
+    /// 1-based line of the file the chunk starts on.
+    pub first_line: usize,
+    pub code: String,
+}
+
+/// Splits `content` into runs of whole lines of at most `limit` characters.
+/// A line longer than that is a chunk of its own.
+pub fn chunks(content: &str, limit: usize) -> Vec<Chunk> {
+    let mut chunks: Vec<Chunk> = Vec::new();
+    for (index, line) in content.split_inclusive('\n').enumerate() {
+        match chunks.last_mut() {
+            Some(chunk) if chunk.code.chars().count() + line.chars().count() <= limit => chunk.code.push_str(line),
+            _ => chunks.push(Chunk { first_line: index + 1, code: line.to_string() }),
+        }
+    }
+    chunks
+}
+
+/// Runs project reviews in the background, sharing one pool of workers
+/// between all jobs, and keeps their reports.
+pub struct ReviewJobs {
+    ai: Arc<dyn AiProvider>,
+    /// Chunks reviewed at once across all jobs.
+    permits: Semaphore,
+    workers: usize,
+    /// Characters of code sent to the model at once.
+    context_chars: usize,
+    jobs: Mutex<HashMap<Uuid, ReviewJob>>,
+    finished: Mutex<VecDeque<Uuid>>,
+}
+
+impl ReviewJobs {
+    pub fn new(ai: Arc<dyn AiProvider>, workers: usize, context_chars: usize) -> Self {
+        let workers = workers.max(1);
+        Self {
+            ai,
+            permits: Semaphore::new(workers),
+            workers,
+            context_chars: context_chars.max(1),
+            jobs: Mutex::new(HashMap::new()),
+            finished: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// Reads `AI_REVIEW_WORKERS` (default 4) and `AI_CONTEXT_CHARS` (default 12000).
+    pub fn from_env(ai: Arc<dyn AiProvider>) -> AiResult<Self> {
+        fn var(name: &str, default: usize) -> AiResult<usize> {
+            match std::env::var(name) {
+                Ok(value) => value.parse().map_err(|_| format!("{} must be a number, got '{}'", name, value).into()),
+                Err(_) => Ok(default),
+            }
+        }
+        Ok(Self::new(ai, var("AI_REVIEW_WORKERS", 4)?, var("AI_CONTEXT_CHARS", 12_000)?))
+    }
+
+    /// Starts reviewing `files` and returns the job as it begins. Empty
+    /// files are left out. A project has one review running at a time; while
+    /// it does, this returns the id of that job instead.
+    pub fn start(self: &Arc<Self>, project_id: Uuid, started_by: Uuid, mut files: Vec<SourceFile>) -> Result<ReviewJob, Uuid> {
+        files.retain(|file| !file.content.trim().is_empty());
+        files.sort_by(|a, b| a.path.cmp(&b.path));
+        let work: Vec<(usize, Chunk)> = files
+            .iter()
+            .enumerate()
+            .flat_map(|(index, file)| chunks(&file.content, self.context_chars).into_iter().map(move |chunk| (index, chunk)))
+            .collect();
+
+        let reviews = files
+            .iter()
+            .enumerate()
+            .map(|(index, file)| FileReview {
+                file_id: file.id,
+                path: file.path.clone(),
+                language: file.language.clone(),
+                chunks: work.iter().filter(|(of, _)| *of == index).count(),
+                chunks_done: 0,
+                suggestions: Vec::new(),
+                problems: Vec::new(),
+                errors: Vec::new(),
+            })
+            .collect();
+        let job = ReviewJob {
+            id: Uuid::new_v4(),
+            project_id,
+            started_by,
+            started_at: now_millis(),
+            finished_at: None,
+            status: JobStatus::Running,
+            chunks: work.len(),
+            chunks_done: 0,
+            files: reviews,
+        };
+        let mut jobs = self.jobs.lock().unwrap();
+        if let Some(running) = jobs.values().find(|job| job.project_id == project_id && job.status == JobStatus::Running) {
+            return Err(running.id);
+        }
+        jobs.insert(job.id, job.clone());
+        drop(jobs);
+        println!("🔍 Project review {} started: {} files in {} chunks", job.id, files.len(), work.len());
+
+        tokio::spawn(Arc::clone(self).run(job.id, files, work));
+        Ok(job)
+    }
+
+    /// The job as it stands, while it runs or once it is done.
+    pub fn get(&self, job_id: Uuid) -> Option<ReviewJob> {
+        self.jobs.lock().unwrap().get(&job_id).cloned()
+    }
+
+    async fn run(self: Arc<Self>, job_id: Uuid, files: Vec<SourceFile>, work: Vec<(usize, Chunk)>) {
+        let reviewed = stream::iter(work).map(|(index, chunk)| {
+            let lines = chunk.first_line..=chunk.first_line + chunk.code.lines().count().max(1) - 1;
+            let request = ReviewRequest { language: files[index].language.clone(), code: chunk.code, revision: None };
+            let this = &self;
+            async move {
+                let _permit = this.permits.acquire().await.expect("the review pool is never closed");
+                (index, lines, this.ai.review(&request).await)
+            }
+        });
+        reviewed
+            .buffer_unordered(self.workers)
+            .for_each(|(index, lines, result)| {
+                self.record(job_id, &files[index], index, lines, result);
+                async {}
+            })
+            .await;
+
+        if let Some(job) = self.jobs.lock().unwrap().get_mut(&job_id) {
+            job.status = JobStatus::Done;
+            job.finished_at = Some(now_millis());
+            println!("✅ Project review {} finished", job_id);
+        }
+        let mut finished = self.finished.lock().unwrap();
+        finished.push_back(job_id);
+        while finished.len() > KEPT_JOBS {
+            if let Some(oldest) = finished.pop_front() {
+                self.jobs.lock().unwrap().remove(&oldest);
+            }
+        }
+    }
+
+    /// Adds the review of the chunk holding `lines` of `file` to its report,
+    /// with suggestions moved onto the lines of the whole file.
+    fn record(&self, job_id: Uuid, file: &SourceFile, index: usize, lines: RangeInclusive<usize>, result: AiResult<ReviewResponse>) {
+        let mut jobs = self.jobs.lock().unwrap();
+        let Some(job) = jobs.get_mut(&job_id) else { return };
+        job.chunks_done += 1;
+        let report = &mut job.files[index];
+        report.chunks_done += 1;
+        let first_line = *lines.start();
+        let whole_file = report.chunks == 1;
+        let label = |message: &str| if whole_file { message.to_string() } else { format!("lines {}-{}: {}", lines.start(), lines.end(), message) };
+        match result {
+            Ok(review) => {
+                let problems: Vec<String> = review.problems.iter().map(|problem| label(problem)).collect();
+                report.problems.extend(problems);
+                report.suggestions.extend(review.suggestions.into_iter().map(|mut suggestion| {
+                    suggestion.line += first_line - 1;
+                    suggestion.with_edit(&file.content, file.revision)
+                }));
+                report.suggestions.sort_by_key(|suggestion| suggestion.line);
+            }
+            Err(e) => {
+                eprintln!("❌ Review of {} failed: {}", file.path, e);
+                let error = label(&e.to_string());
+                report.errors.push(error);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_trait::async_trait;
+
+    use crate::ai::provider::ChatMessage;
+
+    /// Never answers, so its reviews keep running.
+    struct Stalled;
+
+    #[async_trait]
+    impl AiProvider for Stalled {
+        fn name(&self) -> &'static str {
+            "stalled"
+        }
+
+        async fn chat(&self, _: &[ChatMessage]) -> AiResult<String> {
+            std::future::pending().await
+        }
+    }
+
+    #[test]
+    fn files_are_chunked_on_line_boundaries() {
+        let content = "aaaa\nbbbb\ncccccccccccc\ndd\n";
+        let chunks = chunks(content, 10);
+        let starts: Vec<(usize, &str)> = chunks.iter().map(|chunk| (chunk.first_line, chunk.code.as_str())).collect();
+        assert_eq!(starts, [(1, "aaaa\nbbbb\n"), (3, "cccccccccccc\n"), (4, "dd\n")]);
+        assert_eq!(chunks.iter().map(|chunk| chunk.code.as_str()).collect::<String>(), content);
+    }
+
+    #[tokio::test]
+    async fn one_review_runs_per_project() {
+        let reviews = Arc::new(ReviewJobs::new(Arc::new(Stalled), 1, 100));
+        let file = SourceFile { id: Uuid::new_v4(), path: "a.py".into(), language: "Python".into(), content: "x = 1\n".into(), revision: Some(1) };
+        let (project, other) = (Uuid::new_v4(), Uuid::new_v4());
+
+        let job = reviews.start(project, Uuid::new_v4(), vec![file.clone()]).unwrap();
+        assert_eq!(reviews.start(project, Uuid::new_v4(), vec![file.clone()]).unwrap_err(), job.id);
+        assert!(reviews.start(other, Uuid::new_v4(), vec![file]).is_ok());
+    }
+}
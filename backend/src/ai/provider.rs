@@ -0,0 +1,508 @@
+use async_trait::async_trait;
+use reqwest::Client;
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::error::Error;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc::UnboundedSender;
+
+use crate::ai::diff::Excerpt;
+use crate::ai::review::{
+    correction, line_count, parse_review, review_messages, suggestion, ReviewEvent, ReviewProgress, ReviewRequest, ReviewResponse,
+    SuggestionStream,
+};
+
+pub type AiResult<T> = Result<T, Box<dyn Error + Send + Sync>>;
+
+/// One turn of a chat with a model.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ChatMessage {
+    /// `system`, `user` or `assistant`.
+    pub role: &'static str,
+    pub content: String,
+}
+
+impl ChatMessage {
+    pub fn system(content: &str) -> Self {
+        Self { role: "system", content: content.to_string() }
+    }
+
+    pub fn user(content: &str) -> Self {
+        Self { role: "user", content: content.to_string() }
+    }
+
+    pub fn assistant(content: &str) -> Self {
+        Self { role: "assistant", content: content.to_string() }
+    }
+}
+
+/// A model that reviews code.
+#[async_trait]
+pub trait AiProvider: Send + Sync {
+    /// Name for logs and error messages.
+    fn name(&self) -> &'static str;
+
+    /// Sends the conversation and returns the model's reply.
+    async fn chat(&self, messages: &[ChatMessage]) -> AiResult<String>;
+
+    /// Like `chat`, but hands each piece of the reply to `chunks` as the model
+    /// writes it. Stops once nobody is listening. Without streaming support
+    /// the whole reply is one piece.
+    async fn chat_stream(&self, messages: &[ChatMessage], chunks: &UnboundedSender<String>) -> AiResult<String> {
+        let reply = self.chat(messages).await?;
+        let _ = chunks.send(reply.clone());
+        Ok(reply)
+    }
+
+    /// Reviews one file. Backends that build their own prompt override this.
+    async fn review(&self, request: &ReviewRequest) -> AiResult<ReviewResponse> {
+        let messages = review_messages(request);
+        let reply = self.chat(&messages).await?;
+        settle(self, messages, &reply, line_count(&request.code)).await
+    }
+
+    /// Reviews only the changes in `excerpt` of the file `code`, with the
+    /// suggestions moved onto the lines of `code`.
+    async fn review_diff(&self, language: &str, _code: &str, excerpt: &Excerpt) -> AiResult<ReviewResponse> {
+        let messages = excerpt.messages(language);
+        let reply = self.chat(&messages).await?;
+        Ok(excerpt.map(settle(self, messages, &reply, excerpt.len()).await?))
+    }
+
+    /// Reviews one file, sending `events` each suggestion as soon as the
+    /// model has written it, with progress in between. The caller reports
+    /// the outcome; if the reply needed a correction, the returned review
+    /// replaces what was streamed.
+    async fn review_stream(&self, request: &ReviewRequest, events: &UnboundedSender<ReviewEvent>) -> AiResult<ReviewResponse> {
+        let messages = review_messages(request);
+        let lines = line_count(&request.code);
+        let (chunks, mut received) = tokio::sync::mpsc::unbounded_channel();
+        let chat = async {
+            let reply = self.chat_stream(&messages, &chunks).await;
+            drop(chunks);
+            reply
+        };
+        let relay = async {
+            let mut stream = SuggestionStream::default();
+            let (mut found, mut reported) = (0, Instant::now());
+            while let Some(chunk) = received.recv().await {
+                let suggestions: Vec<_> = stream.push(&chunk).iter().filter_map(|item| suggestion(item, lines).ok()).collect();
+                found += suggestions.len();
+                let mut sent = suggestions.into_iter().all(|suggestion| events.send(ReviewEvent::Suggestion(suggestion)).is_ok());
+                if reported.elapsed() >= PROGRESS_INTERVAL {
+                    reported = Instant::now();
+                    let progress = ReviewProgress { characters: stream.reply().chars().count(), suggestions: found };
+                    sent &= events.send(ReviewEvent::Progress(progress)).is_ok();
+                }
+                if !sent {
+                    // The client left; dropping `received` stops the model.
+                    break;
+                }
+            }
+        };
+        let (reply, ()) = tokio::join!(chat, relay);
+        settle(self, messages, &reply?, lines).await
+    }
+}
+
+/// Reads the model's `reply` to `messages`. When it could not be used in
+/// full, the model is told what was wrong and gets one more try; the second
+/// answer wins unless it has fewer usable suggestions than the first.
+async fn settle<P: AiProvider + ?Sized>(provider: &P, mut messages: Vec<ChatMessage>, reply: &str, lines: usize) -> AiResult<ReviewResponse> {
+    let first = parse_review(reply, lines);
+    let problems = match &first {
+        Ok(review) if review.problems.is_empty() => return Ok(first?),
+        Ok(review) => review.problems.clone(),
+        Err(problem) => vec![problem.clone()],
+    };
+    eprintln!("⚠️ {} review needs a correction: {}", provider.name(), problems.join("; "));
+    messages.push(ChatMessage::assistant(reply));
+    messages.push(correction(&problems, lines));
+    let second = parse_review(&provider.chat(&messages).await?, lines);
+    match (first, second) {
+        (Ok(first), Ok(second)) if first.suggestions.len() > second.suggestions.len() => Ok(first),
+        (_, Ok(review)) | (Ok(review), Err(_)) => Ok(review),
+        (Err(_), Err(problem)) => Err(format!("{} did not answer with a usable review: {}", provider.name(), problem).into()),
+    }
+}
+
+/// How often a streaming review reports progress.
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Reads a streamed response body a line at a time.
+struct Lines {
+    response: reqwest::Response,
+    pending: Vec<u8>,
+}
+
+impl Lines {
+    fn new(response: reqwest::Response) -> Self {
+        Self { response, pending: Vec::new() }
+    }
+
+    async fn next(&mut self) -> AiResult<Option<String>> {
+        loop {
+            if let Some(end) = self.pending.iter().position(|b| *b == b'\n') {
+                let line: Vec<u8> = self.pending.drain(..=end).collect();
+                return Ok(Some(String::from_utf8_lossy(&line).trim_end().to_string()));
+            }
+            match self.response.chunk().await? {
+                Some(chunk) => self.pending.extend_from_slice(&chunk),
+                None if self.pending.is_empty() => return Ok(None),
+                None => return Ok(Some(String::from_utf8_lossy(&std::mem::take(&mut self.pending)).trim_end().to_string())),
+            }
+        }
+    }
+}
+
+/// Passes a piece of a streamed reply on, failing once the listener is gone.
+fn forward(chunks: &UnboundedSender<String>, reply: &mut String, piece: &str) -> AiResult<()> {
+    if piece.is_empty() {
+        return Ok(());
+    }
+    reply.push_str(piece);
+    chunks.send(piece.to_string()).map_err(|_| "The review was cancelled".into())
+}
+
+/// The response, or an error naming the provider when the status is not a success.
+async fn checked(provider: &str, response: reqwest::Response) -> AiResult<reqwest::Response> {
+    let status = response.status();
+    if !status.is_success() {
+        let body = response.text().await.unwrap_or_default();
+        return Err(format!("{} returned {}: {}", provider, status, body.trim()).into());
+    }
+    Ok(response)
+}
+
+/// Picks the provider from `AI_PROVIDER`: `ollama` (the default), `openai` for
+/// any OpenAI-compatible chat-completions API, or `python` for the FastAPI
+/// review service. `AI_BASE_URL` and `AI_MODEL` override each one's defaults,
+/// `AI_API_KEY` is sent as a bearer token to OpenAI-compatible APIs and
+/// `AI_TIMEOUT_SECONDS` (120) bounds each request.
+pub fn from_env() -> AiResult<Arc<dyn AiProvider>> {
+    let timeout = match std::env::var("AI_TIMEOUT_SECONDS") {
+        Ok(value) => value.parse().map_err(|_| format!("AI_TIMEOUT_SECONDS must be a number, got '{}'", value))?,
+        Err(_) => 120,
+    };
+    let client = Client::builder().timeout(Duration::from_secs(timeout)).build()?;
+    let base_url = |default: &str| std::env::var("AI_BASE_URL").unwrap_or_else(|_| default.to_string());
+    let model = std::env::var("AI_MODEL").ok();
+
+    let provider: Arc<dyn AiProvider> = match std::env::var("AI_PROVIDER").as_deref() {
+        Ok("ollama") | Err(_) => Arc::new(Ollama::new(client, &base_url("http://localhost:11434"), model.as_deref().unwrap_or("llama3"))),
+        Ok("openai") => {
+            let model = model.ok_or("AI_MODEL must name the model when AI_PROVIDER=openai")?;
+            let api_key = std::env::var("AI_API_KEY").ok();
+            Arc::new(OpenAiCompatible::new(client, &base_url("https://api.openai.com/v1"), &model, api_key))
+        }
+        Ok("python") => Arc::new(PythonService::new(client, &base_url("http://localhost:8001"))),
+        Ok(other) => return Err(format!("AI_PROVIDER must be 'ollama', 'openai' or 'python', got '{}'", other).into()),
+    };
+    println!("✅ AI reviews by {}", provider.name());
+    Ok(provider)
+}
+
+async fn checked_json(provider: &str, response: reqwest::Response) -> AiResult<Value> {
+    Ok(checked(provider, response).await?.json().await?)
+}
+
+/// Talks to Ollama's `/api/chat` directly.
+pub struct Ollama {
+    client: Client,
+    url: String,
+    model: String,
+}
+
+impl Ollama {
+    pub fn new(client: Client, base_url: &str, model: &str) -> Self {
+        Self { client, url: format!("{}/api/chat", base_url.trim_end_matches('/')), model: model.to_string() }
+    }
+
+    fn body(&self, messages: &[ChatMessage], stream: bool) -> Value {
+        json!({
+            "model": self.model,
+            "messages": messages,
+            "stream": stream,
+            "options": { "temperature": 0.2 }
+        })
+    }
+}
+
+#[async_trait]
+impl AiProvider for Ollama {
+    fn name(&self) -> &'static str {
+        "Ollama"
+    }
+
+    async fn chat(&self, messages: &[ChatMessage]) -> AiResult<String> {
+        let response = self.client.post(&self.url).json(&self.body(messages, false)).send().await?;
+        let reply = checked_json(self.name(), response).await?;
+        reply["message"]["content"]
+            .as_str()
+            .map(str::to_string)
+            .ok_or_else(|| "Ollama sent a reply without a message".into())
+    }
+
+    /// Ollama streams one JSON object per line.
+    async fn chat_stream(&self, messages: &[ChatMessage], chunks: &UnboundedSender<String>) -> AiResult<String> {
+        let response = self.client.post(&self.url).json(&self.body(messages, true)).send().await?;
+        let mut lines = Lines::new(checked(self.name(), response).await?);
+        let mut reply = String::new();
+        while let Some(line) = lines.next().await? {
+            if line.is_empty() {
+                continue;
+            }
+            let part: Value = serde_json::from_str(&line)?;
+            if let Some(error) = part["error"].as_str() {
+                return Err(format!("Ollama: {}", error).into());
+            }
+            forward(chunks, &mut reply, part["message"]["content"].as_str().unwrap_or(""))?;
+            if part["done"].as_bool() == Some(true) {
+                break;
+            }
+        }
+        Ok(reply)
+    }
+}
+
+/// Any API that speaks OpenAI's `/chat/completions`.
+pub struct OpenAiCompatible {
+    client: Client,
+    url: String,
+    model: String,
+    api_key: Option<String>,
+}
+
+impl OpenAiCompatible {
+    pub fn new(client: Client, base_url: &str, model: &str, api_key: Option<String>) -> Self {
+        Self {
+            client,
+            url: format!("{}/chat/completions", base_url.trim_end_matches('/')),
+            model: model.to_string(),
+            api_key,
+        }
+    }
+
+    fn request(&self, messages: &[ChatMessage], stream: bool) -> reqwest::RequestBuilder {
+        let body = json!({
+            "model": self.model,
+            "messages": messages,
+            "temperature": 0.2,
+            "stream": stream
+        });
+        let request = self.client.post(&self.url).json(&body);
+        match &self.api_key {
+            Some(key) => request.bearer_auth(key),
+            None => request,
+        }
+    }
+}
+
+#[async_trait]
+impl AiProvider for OpenAiCompatible {
+    fn name(&self) -> &'static str {
+        "OpenAI-compatible API"
+    }
+
+    async fn chat(&self, messages: &[ChatMessage]) -> AiResult<String> {
+        let reply = checked_json(self.name(), self.request(messages, false).send().await?).await?;
+        reply["choices"][0]["message"]["content"]
+            .as_str()
+            .map(str::to_string)
+            .ok_or_else(|| "The chat API sent a reply without a message".into())
+    }
+
+    /// Streamed completions arrive as server-sent events ending with `[DONE]`.
+    async fn chat_stream(&self, messages: &[ChatMessage], chunks: &UnboundedSender<String>) -> AiResult<String> {
+        let response = self.request(messages, true).send().await?;
+        let mut lines = Lines::new(checked(self.name(), response).await?);
+        let mut reply = String::new();
+        while let Some(line) = lines.next().await? {
+            let Some(data) = line.strip_prefix("data:").map(str::trim) else { continue };
+            if data == "[DONE]" {
+                break;
+            }
+            let part: Value = serde_json::from_str(data)?;
+            forward(chunks, &mut reply, part["choices"][0]["delta"]["content"].as_str().unwrap_or(""))?;
+        }
+        Ok(reply)
+    }
+}
+
+/// The FastAPI service in `ai_review_service.py`, which builds its own prompt.
+pub struct PythonService {
+    client: Client,
+    url: String,
+}
+
+impl PythonService {
+    pub fn new(client: Client, base_url: &str) -> Self {
+        Self { client, url: format!("{}/review", base_url.trim_end_matches('/')) }
+    }
+}
+
+#[async_trait]
+impl AiProvider for PythonService {
+    fn name(&self) -> &'static str {
+        "Python review service"
+    }
+
+    async fn chat(&self, _messages: &[ChatMessage]) -> AiResult<String> {
+        Err("The Python review service only reviews whole files; use AI_PROVIDER=ollama or openai".into())
+    }
+
+    async fn review(&self, request: &ReviewRequest) -> AiResult<ReviewResponse> {
+        let response = self.client.post(&self.url).json(request).send().await?;
+        Ok(serde_json::from_value(checked_json(self.name(), response).await?)?)
+    }
+
+    /// The service sees the whole file; what it says outside the changes is dropped.
+    async fn review_diff(&self, language: &str, code: &str, excerpt: &Excerpt) -> AiResult<ReviewResponse> {
+        let review = self.review(&ReviewRequest { language: language.to_string(), code: code.to_string(), revision: None }).await?;
+        Ok(excerpt.keep_changed(review))
+    }
+
+    /// The service answers all at once, so suggestions arrive together at the end.
+    async fn review_stream(&self, request: &ReviewRequest, events: &UnboundedSender<ReviewEvent>) -> AiResult<ReviewResponse> {
+        let review = self.review(request).await?;
+        for suggestion in &review.suggestions {
+            let _ = events.send(ReviewEvent::Suggestion(suggestion.clone()));
+        }
+        Ok(review)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{http::HeaderMap, response::IntoResponse, routing::post, Json, Router};
+    use std::sync::Mutex;
+
+    /// Requests a fake backend got: the `Authorization` header and the body.
+    type Seen = Arc<Mutex<Vec<(Option<String>, Value)>>>;
+
+    const SUGGESTIONS: &str = r#"{"suggestions": [{"line": 1, "message": "Name the constant", "severity": "info", "fix": null}]}"#;
+
+    /// Serves canned replies in each backend's format, streamed when asked,
+    /// and keeps the requests it got.
+    async fn fake_backends() -> (String, Seen) {
+        let seen = Seen::default();
+        let record = |seen: Seen, reply: Value, streamed: String| {
+            move |headers: HeaderMap, Json(body): Json<Value>| async move {
+                let auth = headers.get("authorization").map(|v| v.to_str().unwrap().to_string());
+                let stream = body["stream"] == json!(true);
+                seen.lock().unwrap().push((auth, body));
+                if stream { streamed.into_response() } else { Json(reply).into_response() }
+            }
+        };
+        // The reply in small pieces, each written out by `piece`.
+        let pieces = |piece: fn(&str) -> String| -> String {
+            let chars: Vec<char> = SUGGESTIONS.chars().collect();
+            chars.chunks(7).map(|chunk| piece(&chunk.iter().collect::<String>())).collect()
+        };
+        let ndjson = pieces(|text| format!("{}\n", json!({ "message": { "role": "assistant", "content": text }, "done": false })))
+            + "{\"done\": true}\n";
+        let sse = pieces(|text| format!("data: {}\n\n", json!({ "choices": [{ "delta": { "content": text } }] }))) + "data: [DONE]\n\n";
+        let app = Router::new()
+            .route("/api/chat", post(record(Arc::clone(&seen), json!({ "message": { "role": "assistant", "content": SUGGESTIONS } }), ndjson)))
+            .route(
+                "/v1/chat/completions",
+                post(record(Arc::clone(&seen), json!({ "choices": [{ "message": { "role": "assistant", "content": SUGGESTIONS } }] }), sse)),
+            )
+            .route("/review", post(record(Arc::clone(&seen), serde_json::from_str(SUGGESTIONS).unwrap(), String::new())));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        (url, seen)
+    }
+
+    #[tokio::test]
+    async fn every_backend_returns_parsed_suggestions() {
+        let (url, seen) = fake_backends().await;
+        let request = ReviewRequest { language: "Python".into(), code: "x = 42".into(), revision: None };
+        let providers: Vec<Box<dyn AiProvider>> = vec![
+            Box::new(Ollama::new(Client::new(), &url, "llama3")),
+            Box::new(OpenAiCompatible::new(Client::new(), &format!("{}/v1/", url), "gpt-test", Some("secret".into()))),
+            Box::new(PythonService::new(Client::new(), &url)),
+        ];
+        for provider in &providers {
+            let review = provider.review(&request).await.unwrap();
+            assert_eq!(review.suggestions[0].message, "Name the constant", "{}", provider.name());
+        }
+
+        let seen = seen.lock().unwrap().clone();
+        assert_eq!(seen[0].1["model"], "llama3");
+        assert_eq!(seen[0].1["messages"][0]["role"], "system");
+        assert!(seen[0].1["messages"][1]["content"].as_str().unwrap().contains("1 | x = 42"));
+        assert_eq!((seen[1].0.as_deref(), &seen[1].1["model"]), (Some("Bearer secret"), &json!("gpt-test")));
+        assert_eq!(seen[2].1, json!({ "language": "Python", "code": "x = 42" }));
+
+        let unreachable = Ollama::new(Client::new(), "http://127.0.0.1:9", "llama3");
+        assert!(unreachable.review(&request).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn streaming_backends_send_suggestions_as_they_are_written() {
+        let (url, seen) = fake_backends().await;
+        let request = ReviewRequest { language: "Python".into(), code: "x = 42".into(), revision: None };
+        let providers: Vec<Box<dyn AiProvider>> = vec![
+            Box::new(Ollama::new(Client::new(), &url, "llama3")),
+            Box::new(OpenAiCompatible::new(Client::new(), &format!("{}/v1", url), "gpt-test", None)),
+            Box::new(PythonService::new(Client::new(), &url)),
+        ];
+        for provider in &providers {
+            let (events, mut received) = tokio::sync::mpsc::unbounded_channel();
+            let review = provider.review_stream(&request, &events).await.unwrap();
+            drop(events);
+            let mut streamed = Vec::new();
+            while let Some(event) = received.recv().await {
+                if let ReviewEvent::Suggestion(suggestion) = event {
+                    streamed.push(suggestion);
+                }
+            }
+            assert_eq!(streamed, review.suggestions, "{}", provider.name());
+            assert_eq!(review.suggestions[0].message, "Name the constant", "{}", provider.name());
+        }
+        let seen = seen.lock().unwrap().clone();
+        assert_eq!((&seen[0].1["stream"], &seen[1].1["stream"]), (&json!(true), &json!(true)));
+    }
+
+    /// Answers with canned replies in turn and keeps the conversations it got.
+    struct Scripted(Mutex<Vec<&'static str>>, Mutex<Vec<Vec<ChatMessage>>>);
+
+    #[async_trait]
+    impl AiProvider for Scripted {
+        fn name(&self) -> &'static str {
+            "scripted"
+        }
+
+        async fn chat(&self, messages: &[ChatMessage]) -> AiResult<String> {
+            self.1.lock().unwrap().push(messages.to_vec());
+            Ok(self.0.lock().unwrap().remove(0).to_string())
+        }
+    }
+
+    #[tokio::test]
+    async fn unusable_replies_are_retried_once_with_a_correction() {
+        let request = ReviewRequest { language: "Python".into(), code: "x = 42\nprint(x)".into(), revision: None };
+        let scripted = Scripted(Mutex::new(vec!["Looks good to me!", SUGGESTIONS]), Mutex::default());
+        let review = scripted.review(&request).await.unwrap();
+        assert_eq!(review.suggestions[0].message, "Name the constant");
+        let conversations = scripted.1.lock().unwrap().clone();
+        let retry = &conversations[1];
+        assert_eq!((retry.len(), retry[2].role, retry[2].content.as_str()), (4, "assistant", "Looks good to me!"));
+        assert!(retry[3].content.contains("the reply holds no JSON review"));
+
+        // A second bad answer is reported rather than passed off as a clean review.
+        let stubborn = Scripted(Mutex::new(vec!["No.", "Still no."]), Mutex::default());
+        let error = stubborn.review(&request).await.unwrap_err();
+        assert_eq!(error.to_string(), "scripted did not answer with a usable review: the reply holds no JSON review");
+
+        // When the retry is no better, what was usable the first time is kept.
+        let out_of_range = r#"[{"line": 1, "message": "Fine"}, {"line": 7, "message": "Nowhere"}]"#;
+        let partial = Scripted(Mutex::new(vec![out_of_range, "[]"]), Mutex::default());
+        let review = partial.review(&request).await.unwrap();
+        assert_eq!((review.suggestions.len(), review.problems.as_slice()), (1, ["suggestion 2: line 7 is outside the code (1-2)".to_string()].as_slice()));
+    }
+}
@@ -0,0 +1,539 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::ai::provider::ChatMessage;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReviewRequest {
+    pub language: String,
+    pub code: String,
+    /// Saved revision of the file `code` was read from, if it is one; fixes
+    /// from the review are only applied to that revision.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub revision: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Info,
+    Warning,
+    Error,
+}
+
+impl Severity {
+    /// Reads the names models use for severities, in any case.
+    fn parse(name: &str) -> Option<Self> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "info" | "information" | "note" | "hint" | "suggestion" => Some(Self::Info),
+            "warning" | "warn" => Some(Self::Warning),
+            "error" | "critical" => Some(Self::Error),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Suggestion {
+    /// 1-based line in the reviewed code.
+    pub line: usize,
+    pub message: String,
+    pub severity: Option<Severity>,
+    /// Replacement text for the line, when the fix is obvious.
+    pub fix: Option<String>,
+    /// The fix as an edit of the reviewed code; see [`Suggestion::with_edit`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub edit: Option<Edit>,
+}
+
+/// Replaces whole lines of a file, remembering what they said and which
+/// revision they were read from so that a fix is not applied over code that
+/// has changed since.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Edit {
+    /// First and last replaced line, 1-based and inclusive.
+    pub start_line: usize,
+    pub end_line: usize,
+    /// The lines as they were reviewed, without the final line break.
+    pub original: String,
+    pub replacement: String,
+    /// Saved revision of the reviewed file; see `File::revision`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub revision: Option<u64>,
+}
+
+impl Edit {
+    /// Checks that the edit makes sense for `content` at all: it starts on a
+    /// line of the file and `end_line` agrees with the lines in `original`.
+    /// Edits come from clients, so this is a bad request rather than a conflict.
+    pub fn check(&self, content: &str) -> Result<(), String> {
+        let lines = content.split_inclusive('\n').count();
+        if self.start_line == 0 {
+            return Err("Edits start at line 1".to_string());
+        }
+        if self.start_line > lines {
+            return Err(format!("Line {} is past the end of the file, which has {} lines", self.start_line, lines));
+        }
+        let original = self.original.split('\n').count();
+        match self.start_line.checked_add(original - 1) {
+            Some(end) if end == self.end_line => Ok(()),
+            Some(end) => Err(format!("The edit replaces {} lines, so it should end on line {}, not {}", original, end, self.end_line)),
+            None => Err("The edit is too long".to_string()),
+        }
+    }
+
+    /// Applies the edit to `content`, which is at saved revision `revision`
+    /// or has unsaved changes on top of it when `None`. Content that is not
+    /// the reviewed revision is only edited if the replaced lines still read
+    /// the same at the same place; anything else conflicts.
+    pub fn apply(&self, content: &str, revision: Option<u64>) -> Result<String, String> {
+        let lines: Vec<&str> = content.split_inclusive('\n').collect();
+        let original: Vec<&str> = self.original.split('\n').collect();
+        let start = self.start_line.saturating_sub(1);
+        let in_place = |end: usize| {
+            end <= lines.len() && lines[start..end].iter().zip(&original).all(|(line, want)| line.trim_end_matches(['\n', '\r']) == *want)
+        };
+        let Some(end) = start.checked_add(original.len()).filter(|end| in_place(*end)) else {
+            return Err(match (self.revision, revision) {
+                (Some(reviewed), Some(current)) if reviewed != current => {
+                    format!("The file changed since the review (revision {}, now {}) and line {} no longer matches", reviewed, current, self.start_line)
+                }
+                _ => format!("Line {} changed since the review", self.start_line),
+            });
+        };
+        let line_break = lines[end - 1].strip_prefix(lines[end - 1].trim_end_matches(['\n', '\r'])).unwrap_or("");
+        Ok([&lines[..start].concat(), self.replacement.as_str(), line_break, &lines[end..].concat()].concat())
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ReviewResponse {
+    pub suggestions: Vec<Suggestion>,
+    /// Parts of the model's reply that were left out, and why.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub problems: Vec<String>,
+}
+
+/// What a streaming review reports while the model is writing.
+#[derive(Debug, Clone)]
+pub enum ReviewEvent {
+    Progress(ReviewProgress),
+    /// A suggestion complete enough to show.
+    Suggestion(Suggestion),
+    /// The whole review, parsed from the full reply.
+    Done(ReviewResponse),
+    Error(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct ReviewProgress {
+    /// Characters of the reply received so far.
+    pub characters: usize,
+    pub suggestions: usize,
+}
+
+pub const SYSTEM_PROMPT: &str = "\
+You are an expert code reviewer. You point out bugs, risky constructs and clear improvements, \
+and you answer with JSON only.";
+
+const INSTRUCTIONS: &str = r#"Review the {language} code below. Each line starts with its 1-based number and a "|", which are not part of the code.
+
+Answer with a JSON object of this shape and nothing else:
+{"suggestions": [{"line": 2, "message": "Use a list comprehension instead of the loop", "severity": "info", "fix": "result = [x * 2 for x in arr]"}]}
+
+- line: the line the suggestion is about
+- message: a short description of the issue or improvement
+- severity: "info", "warning" or "error"
+- fix: the corrected code for that line, or null unless the fix is obvious
+
+Answer {"suggestions": []} if there is nothing worth changing."#;
+
+/// The conversation that asks a model to review `request.code`.
+pub fn review_messages(request: &ReviewRequest) -> Vec<ChatMessage> {
+    let numbered: String = request
+        .code
+        .lines()
+        .enumerate()
+        .map(|(index, line)| format!("{:>4} | {}\n", index + 1, line))
+        .collect();
+    let prompt = format!("{}\n\nCode:\n---\n{}---", INSTRUCTIONS.replace("{language}", &request.language), numbered);
+    vec![ChatMessage::system(SYSTEM_PROMPT), ChatMessage::user(&prompt)]
+}
+
+const CORRECTION: &str = r#"Your answer could not be used as it was: {problems}
+
+Answer again with only the JSON object, {"suggestions": [...]}, where every suggestion has a "line" between 1 and {lines}, a "message", a "severity" of "info", "warning" or "error", and a "fix" or null."#;
+
+/// Asks the model to answer again after `problems` with its last reply.
+pub fn correction(problems: &[String], lines: usize) -> ChatMessage {
+    let prompt = CORRECTION.replace("{problems}", &problems.join("; ")).replace("{lines}", &lines.to_string());
+    ChatMessage::user(&prompt)
+}
+
+impl Suggestion {
+    /// Turns the suggested fix into an [`Edit`] of `code`, the code that was
+    /// reviewed, read from saved `revision` if it was. Models tend to drop
+    /// indentation, so an unindented fix takes the indentation of the line it
+    /// replaces.
+    pub fn with_edit(mut self, code: &str, revision: Option<u64>) -> Self {
+        let original = code.lines().nth(self.line.saturating_sub(1)).map(|line| line.trim_end_matches('\r'));
+        self.edit = match (&self.fix, original) {
+            (Some(fix), Some(original)) if fix != original => {
+                let indent = &original[..original.len() - original.trim_start().len()];
+                let replacement = if fix.starts_with(char::is_whitespace) || indent.is_empty() {
+                    fix.clone()
+                } else {
+                    fix.lines().map(|line| if line.is_empty() { String::new() } else { format!("{}{}", indent, line) }).collect::<Vec<_>>().join("\n")
+                };
+                Some(Edit { start_line: self.line, end_line: self.line, original: original.to_string(), replacement, revision })
+            }
+            _ => None,
+        };
+        self
+    }
+}
+
+impl ReviewResponse {
+    /// Adds edits to every suggestion with a fix; see [`Suggestion::with_edit`].
+    pub fn with_edits(mut self, code: &str, revision: Option<u64>) -> Self {
+        self.suggestions = self.suggestions.into_iter().map(|suggestion| suggestion.with_edit(code, revision)).collect();
+        self
+    }
+}
+
+/// How many lines a suggestion on `code` may point at.
+pub fn line_count(code: &str) -> usize {
+    code.lines().count().max(1)
+}
+
+/// Reads the suggestions out of a model's reply about a file of `lines`
+/// lines: a `{"suggestions": [...]}` object or a bare array, possibly fenced
+/// or surrounded by prose, and repaired if it is not quite JSON. Suggestions
+/// that do not check out are left out and listed in `problems`; a reply with
+/// nothing that looks like a review is an error.
+pub fn parse_review(reply: &str, lines: usize) -> Result<ReviewResponse, String> {
+    let (items, mut problems) = match find_review(reply) {
+        Some(items) => (items, Vec::new()),
+        None => {
+            // A reply cut short still holds the suggestions it finished.
+            let mut stream = SuggestionStream::default();
+            let items = stream.push(&repair_quotes(reply));
+            if items.is_empty() {
+                return Err("the reply holds no JSON review".to_string());
+            }
+            (items, vec!["the JSON in the reply is incomplete".to_string()])
+        }
+    };
+    let mut suggestions = Vec::new();
+    for (index, item) in items.iter().enumerate() {
+        match suggestion(item, lines) {
+            Ok(found) => suggestions.push(found),
+            Err(problem) => problems.push(format!("suggestion {}: {}", index + 1, problem)),
+        }
+    }
+    Ok(ReviewResponse { suggestions, problems })
+}
+
+/// Checks one entry of a reply and turns it into a suggestion.
+pub fn suggestion(item: &Value, lines: usize) -> Result<Suggestion, String> {
+    let Value::Object(fields) = item else { return Err("not an object".to_string()) };
+    let line = match &fields.get("line") {
+        Some(Value::Number(n)) => n.as_u64(),
+        Some(Value::String(s)) => s.trim().parse().ok(),
+        _ => None,
+    };
+    let line = line.ok_or("no line number")? as usize;
+    if line == 0 || line > lines {
+        return Err(format!("line {} is outside the code (1-{})", line, lines));
+    }
+    let message = fields.get("message").and_then(Value::as_str).map(str::trim).unwrap_or_default();
+    if message.is_empty() {
+        return Err("no message".to_string());
+    }
+    let severity = match fields.get("severity") {
+        None | Some(Value::Null) => None,
+        Some(Value::String(name)) => Some(Severity::parse(name).ok_or_else(|| format!("unknown severity \"{}\"", name))?),
+        Some(other) => return Err(format!("unknown severity {}", other)),
+    };
+    let fix = match fields.get("fix") {
+        None | Some(Value::Null) => None,
+        Some(Value::String(fix)) => Some(fix.clone()),
+        Some(_) => return Err("the fix is not text".to_string()),
+    };
+    Ok(Suggestion { line, message: message.to_string(), severity, fix, edit: None })
+}
+
+/// The entries of the first review in `reply`, looking inside code fences
+/// first so that stray brackets in prose are not taken for the answer.
+fn find_review(reply: &str) -> Option<Vec<Value>> {
+    let fenced = reply.split("```").skip(1).step_by(2).map(|block| block.trim_start_matches(|c: char| c.is_alphanumeric()));
+    fenced.chain(std::iter::once(reply)).find_map(|text| {
+        text.char_indices().filter(|(_, c)| *c == '{' || *c == '[').find_map(|(start, _)| {
+            let value = first_value(&text[start..]).or_else(|| first_value(&repair(&text[start..])))?;
+            review_items(value)
+        })
+    })
+}
+
+fn first_value(text: &str) -> Option<Value> {
+    serde_json::Deserializer::from_str(text).into_iter::<Value>().next()?.ok()
+}
+
+/// The suggestions of a value shaped like a review.
+fn review_items(value: Value) -> Option<Vec<Value>> {
+    match value {
+        Value::Object(mut object) => match object.remove("suggestions")? {
+            Value::Array(items) => Some(items),
+            _ => None,
+        },
+        Value::Array(items) if items.iter().all(Value::is_object) => Some(items),
+        _ => None,
+    }
+}
+
+/// Typographic quotes become plain ones.
+fn repair_quotes(text: &str) -> String {
+    text.replace(['\u{201c}', '\u{201d}'], "\"")
+}
+
+/// Fixes the mistakes models make most when writing JSON by hand, up to the
+/// end of the first value in `text`: single quotes, trailing commas,
+/// comments, unquoted keys, Python's `None`/`True`/`False` and raw newlines
+/// in strings.
+fn repair(text: &str) -> String {
+    let chars: Vec<char> = repair_quotes(text).chars().collect();
+    let mut out = String::with_capacity(text.len());
+    let mut depth = 0usize;
+    let mut quote = None;
+    let mut escaped = false;
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        i += 1;
+        if let Some(q) = quote {
+            match c {
+                _ if escaped => {
+                    escaped = false;
+                    if c == '\'' {
+                        // `\'` is not a JSON escape.
+                        out.pop();
+                    }
+                    out.push(c);
+                }
+                '\\' => {
+                    escaped = true;
+                    out.push(c);
+                }
+                _ if c == q => {
+                    quote = None;
+                    out.push('"');
+                }
+                '"' => out.push_str("\\\""),
+                '\n' => out.push_str("\\n"),
+                _ => out.push(c),
+            }
+            continue;
+        }
+        match c {
+            '"' | '\'' => {
+                quote = Some(c);
+                out.push('"');
+            }
+            '{' | '[' => {
+                depth += 1;
+                out.push(c);
+            }
+            '}' | ']' => {
+                let kept = out.trim_end().len();
+                out.truncate(kept);
+                if out.ends_with(',') {
+                    out.pop();
+                }
+                out.push(c);
+                depth = depth.saturating_sub(1);
+                if depth == 0 {
+                    break;
+                }
+            }
+            '/' if chars.get(i) == Some(&'/') => {
+                while i < chars.len() && chars[i] != '\n' {
+                    i += 1;
+                }
+            }
+            c if c.is_alphabetic() || c == '_' => {
+                let mut word = c.to_string();
+                while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                    word.push(chars[i]);
+                    i += 1;
+                }
+                let is_key = chars[i..].iter().find(|c| !c.is_whitespace()) == Some(&':');
+                match word.as_str() {
+                    _ if is_key => out.push_str(&format!("\"{}\"", word)),
+                    "None" | "null" => out.push_str("null"),
+                    "True" | "true" => out.push_str("true"),
+                    "False" | "false" => out.push_str("false"),
+                    _ => out.push_str(&word),
+                }
+            }
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+/// Picks suggestions out of a reply as it arrives: every object that sits
+/// directly in a JSON array is emitted once its closing brace shows up, to
+/// be checked with [`suggestion`].
+#[derive(Debug, Default)]
+pub struct SuggestionStream {
+    reply: String,
+    scanned: usize,
+    /// Open brackets and where they start, once the JSON has begun.
+    open: Vec<(char, usize)>,
+    in_json: bool,
+    in_string: bool,
+    escaped: bool,
+}
+
+impl SuggestionStream {
+    /// Adds the next piece of the reply and returns the objects it completed.
+    pub fn push(&mut self, chunk: &str) -> Vec<Value> {
+        self.reply.push_str(chunk);
+        let mut found = Vec::new();
+        for (index, c) in self.reply[self.scanned..].char_indices().map(|(i, c)| (i + self.scanned, c)) {
+            if !self.in_json {
+                // Prose around the JSON may hold stray quotes; skip it.
+                if c != '{' && c != '[' {
+                    continue;
+                }
+                self.in_json = true;
+            }
+            if self.in_string {
+                match c {
+                    _ if self.escaped => self.escaped = false,
+                    '\\' => self.escaped = true,
+                    '"' => self.in_string = false,
+                    _ => {}
+                }
+                continue;
+            }
+            match c {
+                '"' => self.in_string = true,
+                '{' | '[' => self.open.push((c, index)),
+                '}' | ']' => {
+                    let Some((opener, start)) = self.open.pop() else { continue };
+                    let in_array = self.open.last().is_some_and(|(parent, _)| *parent == '[');
+                    if opener == '{' && c == '}' && in_array {
+                        found.extend(serde_json::from_str::<Value>(&self.reply[start..=index]).ok());
+                    }
+                    if self.open.is_empty() {
+                        self.in_json = false;
+                    }
+                }
+                _ => {}
+            }
+        }
+        self.scanned = self.reply.len();
+        found
+    }
+
+    /// Everything received so far.
+    pub fn reply(&self) -> &str {
+        &self.reply
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prompts_number_lines_and_replies_are_parsed() {
+        let request = ReviewRequest { language: "Python".into(), code: "x = 1\nprint(x)".into(), revision: None };
+        let messages = review_messages(&request);
+        assert!(messages[1].content.contains("Review the Python code"));
+        assert!(messages[1].content.contains("   1 | x = 1\n   2 | print(x)\n"));
+
+        let reply = "Sure! Here is my review:\n```json\n{\"suggestions\": [{\"line\": 2, \"message\": \"Use f-strings\", \"severity\": \"info\", \"fix\": null}, {\"note\": \"not a suggestion\"}]}\n```";
+        let review = parse_review(reply, 2).unwrap();
+        assert_eq!(
+            review.suggestions,
+            vec![Suggestion { line: 2, message: "Use f-strings".into(), severity: Some(Severity::Info), fix: None, edit: None }]
+        );
+        assert_eq!(review.problems, ["suggestion 2: no line number"]);
+
+        let bare = parse_review("[{\"line\": 1, \"message\": \"Unused variable\"}]", 2).unwrap();
+        assert_eq!(bare.suggestions[0].severity, None);
+        assert!(parse_review("No issues found.", 2).is_err());
+    }
+
+    #[test]
+    fn sloppy_json_is_repaired_and_suggestions_are_checked() {
+        let reply = "I found [2] issues.\n```json\n{\n  // the review\n  suggestions: [\n    {'line': '3', 'message': 'Don\\'t shadow \"list\"', severity: 'Warning', fix: None},\n    {\"line\": 9, \"message\": \"Past the end\", \"severity\": \"info\"},\n    {\"line\": 1, \"message\": \"Odd\", \"severity\": \"cosmetic\"},\n  ],\n}\n```";
+        let review = parse_review(reply, 4).unwrap();
+        assert_eq!(
+            review.suggestions,
+            vec![Suggestion { line: 3, message: "Don't shadow \"list\"".into(), severity: Some(Severity::Warning), fix: None, edit: None }]
+        );
+        assert_eq!(
+            review.problems,
+            ["suggestion 2: line 9 is outside the code (1-4)", "suggestion 3: unknown severity \"cosmetic\""]
+        );
+
+        // A reply that was cut off keeps the suggestions it finished.
+        let cut = parse_review("{\"suggestions\": [{\"line\": 1, \"message\": \"Fine\"}, {\"line\": 2, \"mess", 4).unwrap();
+        assert_eq!((cut.suggestions.len(), cut.problems.as_slice()), (1, ["the JSON in the reply is incomplete".to_string()].as_slice()));
+
+        let retry = correction(&review.problems, 4);
+        assert!(retry.content.contains("line 9 is outside the code") && retry.content.contains("between 1 and 4"));
+    }
+
+    #[test]
+    fn suggestions_stream_out_as_their_objects_close() {
+        let reply = r#"Here's the "review": {"suggestions": [{"line": 1, "message": "Say \"hi\" {politely}", "severity": "info", "fix": "print('}')"}, {"line": 3, "message": "Unused import", "severity": "warning", "fix": null}]}"#;
+        let mut stream = SuggestionStream::default();
+        let mut seen = Vec::new();
+        // Feed it a few characters at a time, splitting strings and escapes.
+        let chars: Vec<char> = reply.chars().collect();
+        for piece in chars.chunks(3) {
+            let found = stream.push(&piece.iter().collect::<String>());
+            seen.extend(found.iter().map(|item| (suggestion(item, 3).unwrap().line, stream.reply().len())));
+        }
+        assert_eq!(seen.iter().map(|(line, _)| *line).collect::<Vec<_>>(), vec![1, 3]);
+        assert!(seen[0].1 < reply.find("Unused").unwrap(), "the first suggestion came out before the second began");
+        assert_eq!(parse_review(stream.reply(), 3).unwrap().suggestions.len(), 2);
+    }
+
+    #[test]
+    fn fixes_become_edits_that_only_apply_where_the_reviewed_lines_still_are() {
+        let code = "def total(items):\n    s = 0\n    for i in items: s += i\n    return s\n";
+        let suggestion = Suggestion { line: 2, message: "Use sum".into(), severity: None, fix: Some("return sum(items)".into()), edit: None };
+        let edit = suggestion.with_edit(code, Some(3)).edit.unwrap();
+        assert_eq!((edit.original.as_str(), edit.replacement.as_str(), edit.revision), ("    s = 0", "    return sum(items)", Some(3)));
+        let fixed = "def total(items):\n    return sum(items)\n    for i in items: s += i\n    return s\n";
+        assert_eq!(edit.apply(code, Some(3)).unwrap(), fixed);
+
+        // A later revision that left the lines alone still takes the fix.
+        let appended = format!("{}print(total([1]))\n", code);
+        assert_eq!(edit.apply(&appended, Some(4)).unwrap(), format!("{}print(total([1]))\n", fixed));
+
+        // Lines that moved are not chased, even when their old text is still unique.
+        let moved = format!("# totals\n{}", code);
+        assert_eq!(
+            edit.apply(&moved, Some(4)).unwrap_err(),
+            "The file changed since the review (revision 3, now 4) and line 2 no longer matches"
+        );
+        let changed = code.replace("s = 0", "s = 1");
+        assert_eq!(edit.apply(&changed, None).unwrap_err(), "Line 2 changed since the review");
+
+        // Edits come from clients and are checked before anything is compared.
+        assert_eq!(edit.check(code), Ok(()));
+        let at = |start_line, end_line| Edit { start_line, end_line, ..edit.clone() };
+        assert_eq!(at(0, 0).check(code).unwrap_err(), "Edits start at line 1");
+        assert!(at(5, 5).check(code).unwrap_err().contains("past the end of the file"));
+        assert!(at(2, 3).check(code).unwrap_err().contains("should end on line 2"));
+        let huge = Edit { start_line: 1, end_line: 1, original: "\n".repeat(4), ..edit.clone() };
+        assert!(huge.check(code).unwrap_err().contains("should end on line 5"));
+        assert!(at(usize::MAX, usize::MAX).apply(code, None).is_err());
+    }
+}
@@ -0,0 +1,682 @@
+//! `codecollab-sandbox`, the privileged half of the code execution sandbox.
+//! The server runs unprivileged and leaves everything that needs root to this
+//! helper, installed setuid root and executable only by the server's group:
+//!
+//! ```text
+//! codecollab-sandbox prepare <workspace> <uid>
+//! codecollab-sandbox run <workspace> --uid <uid> --isolation <namespaces|none>
+//!     --cpu-seconds <n> --memory-bytes <n> --file-bytes <n> --processes <n>
+//!     [--env NAME=VALUE]... -- <program> [argument]...
+//! codecollab-sandbox clean <workspace>
+//! ```
+//!
+//! A workspace is a directory the caller owns; programs run in its `work`
+//! folder. `prepare` hands what the caller wrote there to a sandbox user,
+//! `run` runs a program as that user and `clean` empties the workspace so the
+//! caller can remove it. The helper only ever touches files through the
+//! workspace it opened, and gives up root before entering any namespace.
+
+mod seccomp;
+
+use std::ffi::{CStr, CString, OsStr, OsString};
+use std::fs::Permissions;
+use std::io;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+/// The users sandboxed programs run as; the server leases them out one per
+/// workspace (see `executor/sandbox.rs`).
+const FIRST_SANDBOX_UID: u32 = 1_000_000;
+const SANDBOX_UIDS: u32 = 65_536;
+
+/// Exit status when the helper itself fails, as `env` and `chroot` use. The
+/// reason goes to stderr after `ERROR_PREFIX`.
+const HELPER_FAILED: i32 = 125;
+const ERROR_PREFIX: &str = "codecollab-sandbox: ";
+
+/// Host paths bind-mounted read-only into a namespaced sandbox, when they
+/// exist, next to the `PATH` entries and toolchain homes. Toolchains read
+/// their settings from `/etc` (the dynamic linker's cache, the JDK's
+/// configuration reached through `/usr/lib/jvm`).
+const SYSTEM_PATHS: &[&str] = &[
+    "/usr", "/bin", "/sbin", "/lib", "/lib32", "/lib64", "/libx32", "/etc",
+    "/dev/null", "/dev/zero", "/dev/random", "/dev/urandom",
+];
+
+/// Besides the sandboxed program, a namespaced run has a parent waiting on
+/// its behalf and an init process for its PID namespace, both running as the
+/// sandbox user and counted against its process limit.
+const HELPER_PROCESSES: u64 = 2;
+
+/// Signals the helper takes as "stop the program": the server's kill, and
+/// the server going away.
+const STOP_SIGNALS: [libc::c_int; 3] = [libc::SIGTERM, libc::SIGINT, libc::SIGHUP];
+
+fn main() {
+    let args: Vec<OsString> = std::env::args_os().skip(1).collect();
+    let result = match args.split_first() {
+        Some((command, rest)) if command == "prepare" => prepare(rest),
+        Some((command, rest)) if command == "run" => Run::parse(rest).and_then(|run| run.start()),
+        Some((command, rest)) if command == "clean" => clean(rest),
+        _ => Err(invalid("usage: codecollab-sandbox prepare|run|clean <workspace> ...")),
+    };
+    if let Err(error) = result {
+        fail(&error);
+    }
+}
+
+fn fail(error: &io::Error) -> ! {
+    eprintln!("{}{}", ERROR_PREFIX, error);
+    std::process::exit(HELPER_FAILED)
+}
+
+fn invalid(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message.into())
+}
+
+fn check(result: libc::c_int) -> io::Result<()> {
+    if result < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+/// The user who ran the helper, as opposed to the root it runs as.
+fn caller() -> libc::uid_t {
+    // SAFETY: getuid has no preconditions and cannot fail.
+    unsafe { libc::getuid() }
+}
+
+fn sandbox_uid(value: &OsStr) -> io::Result<u32> {
+    let uid: u32 = value.to_str().and_then(|value| value.parse().ok()).ok_or_else(|| invalid("The uid must be a number"))?;
+    if !(FIRST_SANDBOX_UID..FIRST_SANDBOX_UID + SANDBOX_UIDS).contains(&uid) {
+        return Err(invalid(format!("{} is not a sandbox user", uid)));
+    }
+    Ok(uid)
+}
+
+fn stat_of(fd: &OwnedFd) -> io::Result<libc::stat> {
+    // SAFETY: fstat only writes into the zeroed struct.
+    unsafe {
+        let mut stat: libc::stat = std::mem::zeroed();
+        check(libc::fstat(fd.as_raw_fd(), &mut stat))?;
+        Ok(stat)
+    }
+}
+
+fn is_dir(stat: &libc::stat) -> bool {
+    stat.st_mode & libc::S_IFMT == libc::S_IFDIR
+}
+
+/// Opens the directory `name` under `dir` (or the path `name` when `dir` is
+/// `None`), refusing symlinks.
+fn open_dir(dir: Option<&OwnedFd>, name: &CStr) -> io::Result<OwnedFd> {
+    let flags = libc::O_RDONLY | libc::O_DIRECTORY | libc::O_NOFOLLOW | libc::O_CLOEXEC;
+    let at = dir.map_or(libc::AT_FDCWD, |dir| dir.as_raw_fd());
+    // SAFETY: `name` is a valid C string; the descriptor is owned from here on.
+    unsafe {
+        let fd = libc::openat(at, name.as_ptr(), flags);
+        check(fd)?;
+        Ok(OwnedFd::from_raw_fd(fd))
+    }
+}
+
+/// Opens the caller's workspace and checks that it really is theirs, so the
+/// helper cannot be pointed at anyone else's files.
+fn open_workspace(path: &OsStr) -> io::Result<OwnedFd> {
+    let workspace = open_dir(None, &CString::new(path.as_bytes())?)?;
+    if stat_of(&workspace)?.st_uid != caller() {
+        return Err(io::Error::new(io::ErrorKind::PermissionDenied, "The workspace does not belong to the caller"));
+    }
+    Ok(workspace)
+}
+
+/// The entries of `dir` with their own (not their targets') metadata.
+fn entries(dir: &OwnedFd) -> io::Result<Vec<(CString, libc::stat)>> {
+    let mut entries = Vec::new();
+    // SAFETY: the stream reads a duplicate of `dir` and is closed before
+    // returning; fstatat only writes into the zeroed struct.
+    unsafe {
+        let copy = libc::fcntl(dir.as_raw_fd(), libc::F_DUPFD_CLOEXEC, 0);
+        check(copy)?;
+        let stream = libc::fdopendir(copy);
+        if stream.is_null() {
+            libc::close(copy);
+            return Err(io::Error::last_os_error());
+        }
+        loop {
+            let entry = libc::readdir(stream);
+            if entry.is_null() {
+                break;
+            }
+            let name = CStr::from_ptr((*entry).d_name.as_ptr());
+            if name == c"." || name == c".." {
+                continue;
+            }
+            let mut stat: libc::stat = std::mem::zeroed();
+            if libc::fstatat(dir.as_raw_fd(), name.as_ptr(), &mut stat, libc::AT_SYMLINK_NOFOLLOW) == 0 {
+                entries.push((name.to_owned(), stat));
+            }
+        }
+        libc::closedir(stream);
+    }
+    Ok(entries)
+}
+
+/// `prepare <workspace> <uid>`: gives everything the caller wrote into the
+/// workspace's `work` folder to the sandbox user, so builds can write next to
+/// their sources. Files anyone else owns are left alone.
+fn prepare(args: &[OsString]) -> io::Result<()> {
+    let [workspace, uid] = args else { return Err(invalid("usage: codecollab-sandbox prepare <workspace> <uid>")) };
+    let uid = sandbox_uid(uid)?;
+    let work = open_dir(Some(&open_workspace(workspace)?), c"work")?;
+    if stat_of(&work)?.st_uid == caller() {
+        // SAFETY: plain system call on an open descriptor.
+        check(unsafe { libc::fchown(work.as_raw_fd(), uid, uid) })?;
+    }
+    hand_over(&work, uid)
+}
+
+fn hand_over(dir: &OwnedFd, uid: u32) -> io::Result<()> {
+    for (name, stat) in entries(dir)? {
+        if stat.st_uid != caller() {
+            continue;
+        }
+        if is_dir(&stat) {
+            hand_over(&open_dir(Some(dir), &name)?, uid)?;
+        }
+        // SAFETY: plain system call on an open descriptor and a valid name.
+        check(unsafe { libc::fchownat(dir.as_raw_fd(), name.as_ptr(), uid, uid, libc::AT_SYMLINK_NOFOLLOW) })?;
+    }
+    Ok(())
+}
+
+/// `clean <workspace>`: removes everything in the workspace, whoever wrote it,
+/// leaving the empty directory for the caller to remove.
+fn clean(args: &[OsString]) -> io::Result<()> {
+    let [workspace] = args else { return Err(invalid("usage: codecollab-sandbox clean <workspace>")) };
+    empty(&open_workspace(workspace)?)
+}
+
+fn empty(dir: &OwnedFd) -> io::Result<()> {
+    for (name, stat) in entries(dir)? {
+        let flags = if is_dir(&stat) {
+            empty(&open_dir(Some(dir), &name)?)?;
+            libc::AT_REMOVEDIR
+        } else {
+            0
+        };
+        // SAFETY: plain system call on an open descriptor and a valid name.
+        check(unsafe { libc::unlinkat(dir.as_raw_fd(), name.as_ptr(), flags) })?;
+    }
+    Ok(())
+}
+
+/// Resource limits for the sandboxed program and everything it starts.
+struct Limits {
+    cpu_seconds: u64,
+    memory_bytes: u64,
+    file_bytes: u64,
+    processes: u64,
+}
+
+/// A parsed `run` command.
+struct Run {
+    workspace: PathBuf,
+    uid: u32,
+    namespaces: bool,
+    limits: Limits,
+    /// The program's whole environment, as `NAME=VALUE`.
+    env: Vec<CString>,
+    argv: Vec<CString>,
+}
+
+impl Run {
+    fn parse(args: &[OsString]) -> io::Result<Self> {
+        let (workspace, mut rest) = args.split_first().ok_or_else(|| invalid("run needs a workspace"))?;
+        let (mut uid, mut isolation) = (None, None);
+        let mut limits = Limits { cpu_seconds: 0, memory_bytes: 0, file_bytes: 0, processes: 0 };
+        let mut env = Vec::new();
+
+        loop {
+            let Some((flag, after)) = rest.split_first() else { return Err(invalid("run needs `--` and a program")) };
+            if flag == "--" {
+                rest = after;
+                break;
+            }
+            let (value, after) = after.split_first().ok_or_else(|| invalid(format!("{} needs a value", flag.to_string_lossy())))?;
+            let number = || value.to_str().and_then(|value| value.parse::<u64>().ok()).ok_or_else(|| invalid(format!("{} must be a number", flag.to_string_lossy())));
+            match flag.to_str() {
+                Some("--uid") => uid = Some(sandbox_uid(value)?),
+                Some("--isolation") => isolation = Some(value.clone()),
+                Some("--cpu-seconds") => limits.cpu_seconds = number()?,
+                Some("--memory-bytes") => limits.memory_bytes = number()?,
+                Some("--file-bytes") => limits.file_bytes = number()?,
+                Some("--processes") => limits.processes = number()?,
+                Some("--env") if value.as_bytes().contains(&b'=') => env.push(CString::new(value.as_bytes())?),
+                _ => return Err(invalid(format!("Unexpected argument {}", flag.to_string_lossy()))),
+            }
+            rest = after;
+        }
+
+        let namespaces = match isolation.as_ref().and_then(|isolation| isolation.to_str()) {
+            Some("namespaces") => true,
+            Some("none") => false,
+            _ => return Err(invalid("--isolation must be 'namespaces' or 'none'")),
+        };
+        if rest.is_empty() {
+            return Err(invalid("run needs a program"));
+        }
+        Ok(Run {
+            workspace: PathBuf::from(workspace),
+            uid: uid.ok_or_else(|| invalid("run needs --uid"))?,
+            namespaces,
+            limits,
+            env,
+            argv: rest.iter().map(|arg| CString::new(arg.as_bytes())).collect::<Result<_, _>>()?,
+        })
+    }
+
+    /// The value of `name` in the program's environment.
+    fn var(&self, name: &str) -> Option<&OsStr> {
+        self.env.iter().find_map(|entry| {
+            let value = entry.as_bytes().strip_prefix(name.as_bytes())?.strip_prefix(b"=")?;
+            Some(OsStr::from_bytes(value))
+        })
+    }
+
+    fn workdir(&self) -> PathBuf {
+        self.workspace.join("work")
+    }
+
+    /// Starts the program as the sandbox user and stays behind, still root,
+    /// to stop it: on `SIGTERM` (the server's kill, which cannot reach the
+    /// sandbox user's processes itself), when the server goes away and once
+    /// the program exits, every process the sandbox user still runs is
+    /// killed. Exits the way the program did.
+    fn start(self) -> io::Result<()> {
+        let workspace = open_workspace(self.workspace.as_os_str())?;
+        let root = if self.namespaces { Some(self.make_root(&workspace)?) } else { None };
+
+        // SAFETY: plain system calls on zeroed signal sets; the helper is
+        // single-threaded, so the child may carry on with ordinary Rust.
+        unsafe {
+            let server = libc::getppid();
+            let mut signals: libc::sigset_t = std::mem::zeroed();
+            let mut previous: libc::sigset_t = std::mem::zeroed();
+            libc::sigemptyset(&mut signals);
+            for signal in STOP_SIGNALS.into_iter().chain([libc::SIGCHLD]) {
+                libc::sigaddset(&mut signals, signal);
+            }
+            check(libc::sigprocmask(libc::SIG_BLOCK, &signals, &mut previous))?;
+            check(libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGTERM))?;
+            if libc::getppid() != server {
+                return Err(io::Error::other("The server went away"));
+            }
+
+            let child = libc::fork();
+            if child < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            if child == 0 {
+                libc::sigprocmask(libc::SIG_SETMASK, &previous, std::ptr::null_mut());
+                let error = self.confine(root.as_deref()).unwrap_err();
+                fail(&error);
+            }
+
+            // Only the program may hold its pipes open.
+            let null = libc::open(c"/dev/null".as_ptr(), libc::O_RDWR);
+            for fd in 0..3 {
+                libc::dup2(null, fd);
+            }
+            loop {
+                let signal = libc::sigwaitinfo(&signals, std::ptr::null_mut());
+                if signal != libc::SIGCHLD {
+                    // The child may not have become the sandbox user yet.
+                    libc::kill(child, libc::SIGKILL);
+                    kill_all(self.uid);
+                    continue;
+                }
+                let mut status = 0;
+                if libc::waitpid(child, &mut status, libc::WNOHANG) == child {
+                    kill_all(self.uid);
+                    exit_like(status);
+                }
+            }
+        }
+    }
+
+    /// Makes an empty directory in the workspace for the namespaced root and
+    /// gives it to the sandbox user, who lays out the mount points in it.
+    fn make_root(&self, workspace: &OwnedFd) -> io::Result<PathBuf> {
+        // SAFETY: getpid cannot fail.
+        let name = format!("root-{}", unsafe { libc::getpid() });
+        let c_name = CString::new(name.as_str())?;
+        // SAFETY: plain system calls on an open descriptor and a valid name.
+        unsafe {
+            check(libc::mkdirat(workspace.as_raw_fd(), c_name.as_ptr(), 0o755))?;
+            check(libc::fchownat(workspace.as_raw_fd(), c_name.as_ptr(), self.uid, self.uid, libc::AT_SYMLINK_NOFOLLOW))?;
+        }
+        Ok(self.workspace.join(name))
+    }
+
+    /// Runs in the helper's child. It becomes the sandbox user for good, and
+    /// with `root` enters fresh namespaces and forks twice: the first child
+    /// becomes the PID namespace's init, which sets up the root and waits for
+    /// the program it forks in turn, so the program never runs as PID 1. The
+    /// resource limits and the system call filter apply to the program alone.
+    /// Returns only on failure.
+    fn confine(&self, root: Option<&Path>) -> io::Result<()> {
+        let uid = self.uid;
+        let mut helpers = 0;
+        // SAFETY: plain system calls on valid pointers.
+        unsafe {
+            check(libc::setrlimit(libc::RLIMIT_CORE, &libc::rlimit { rlim_cur: 0, rlim_max: 0 }))?;
+            check(libc::setgroups(0, std::ptr::null()))?;
+            check(libc::setresgid(uid, uid, uid))?;
+            check(libc::setresuid(uid, uid, uid))?;
+            // Changing user cleared the parent-death signal.
+            check(libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGKILL))?;
+
+            match root {
+                Some(root) => {
+                    let mounts = prepare_root(root, &self.workdir(), self)?;
+                    check(libc::unshare(
+                        libc::CLONE_NEWUSER
+                            | libc::CLONE_NEWNS
+                            | libc::CLONE_NEWPID
+                            | libc::CLONE_NEWNET
+                            | libc::CLONE_NEWIPC
+                            | libc::CLONE_NEWUTS,
+                    ))?;
+                    // The init process reports the program's status here, since
+                    // the parent only sees how init itself ended.
+                    let mut report = [0; 2];
+                    check(libc::pipe2(report.as_mut_ptr(), libc::O_CLOEXEC))?;
+                    fork_behind(report[0])?;
+
+                    libc::close(report[0]);
+                    check(libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGKILL))?;
+                    enter_root(&mounts)?;
+
+                    let program = libc::fork();
+                    if program < 0 {
+                        return Err(io::Error::last_os_error());
+                    }
+                    if program > 0 {
+                        close_all_but(report[1]);
+                        let status = wait_for(program);
+                        libc::write(report[1], &status as *const libc::c_int as *const libc::c_void, std::mem::size_of::<libc::c_int>());
+                        // Exiting as init takes down anything the program left running.
+                        libc::_exit(0);
+                    }
+                    libc::close(report[1]);
+                    helpers = HELPER_PROCESSES;
+                }
+                None => check(libc::chdir(c_path(&self.workdir())?.as_ptr()))?,
+            }
+
+            let limits = &self.limits;
+            let rlimits = [
+                (libc::RLIMIT_CPU, limits.cpu_seconds, limits.cpu_seconds + 1),
+                (libc::RLIMIT_AS, limits.memory_bytes, limits.memory_bytes),
+                (libc::RLIMIT_FSIZE, limits.file_bytes, limits.file_bytes),
+                (libc::RLIMIT_NPROC, limits.processes + helpers, limits.processes + helpers),
+            ];
+            for (resource, soft, hard) in rlimits {
+                check(libc::setrlimit(resource, &libc::rlimit { rlim_cur: soft, rlim_max: hard }))?;
+            }
+
+            let program = self.resolve()?;
+            let mut argv: Vec<*const libc::c_char> = self.argv.iter().map(|arg| arg.as_ptr()).collect();
+            argv.push(std::ptr::null());
+            let mut envp: Vec<*const libc::c_char> = self.env.iter().map(|entry| entry.as_ptr()).collect();
+            envp.push(std::ptr::null());
+            seccomp::install(&seccomp::denylist())?;
+            libc::execve(program.as_ptr(), argv.as_ptr(), envp.as_ptr());
+            Err(io::Error::last_os_error())
+        }
+    }
+
+    /// Looks the program up in its own `PATH`, as seen from inside the sandbox.
+    fn resolve(&self) -> io::Result<CString> {
+        let program = &self.argv[0];
+        if program.as_bytes().contains(&b'/') {
+            return Ok(program.clone());
+        }
+        let search_path = self.var("PATH").unwrap_or_default();
+        std::env::split_paths(search_path)
+            .map(|dir| dir.join(OsStr::from_bytes(program.as_bytes())))
+            .find(|candidate| {
+                std::fs::metadata(candidate).is_ok_and(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+            })
+            .map(|candidate| c_path(&candidate))
+            .unwrap_or_else(|| Err(io::Error::from_raw_os_error(libc::ENOENT)))
+    }
+}
+
+/// Kills every process of the sandbox user, from a child that takes on that
+/// user: `kill(-1)` then reaches exactly the processes it owns.
+fn kill_all(uid: u32) {
+    // SAFETY: the child only makes system calls and exits.
+    unsafe {
+        let killer = libc::fork();
+        if killer == 0 {
+            if libc::setgroups(0, std::ptr::null()) == 0 && libc::setresgid(uid, uid, uid) == 0 && libc::setresuid(uid, uid, uid) == 0 {
+                libc::kill(-1, libc::SIGKILL);
+            }
+            libc::_exit(0);
+        }
+        if killer > 0 {
+            let mut status = 0;
+            while libc::waitpid(killer, &mut status, 0) < 0 && io::Error::last_os_error().kind() == io::ErrorKind::Interrupted {}
+        }
+    }
+}
+
+/// Flips the read-only flag of the mount at `path` (and below it, with `AT_RECURSIVE`).
+unsafe fn set_read_only(path: &CStr, read_only: bool, flags: libc::c_int) -> io::Result<()> {
+    let attr = libc::mount_attr {
+        attr_set: if read_only { libc::MOUNT_ATTR_RDONLY } else { 0 },
+        attr_clr: if read_only { 0 } else { libc::MOUNT_ATTR_RDONLY },
+        propagation: 0,
+        userns_fd: 0,
+    };
+    check(libc::syscall(
+        libc::SYS_mount_setattr,
+        libc::AT_FDCWD,
+        path.as_ptr(),
+        flags,
+        &attr as *const libc::mount_attr,
+        std::mem::size_of::<libc::mount_attr>(),
+    ) as libc::c_int)
+}
+
+/// Host paths to bind into a namespaced sandbox: `SYSTEM_PATHS`, the
+/// `PATH` entries programs are looked up in and the toolchain homes, leaving
+/// out any that another one already covers.
+fn toolchain_paths(run: &Run) -> Vec<PathBuf> {
+    let search_path = run.var("PATH").unwrap_or_default();
+    let homes = ["RUSTUP_HOME", "CARGO_HOME", "JAVA_HOME"].into_iter().filter_map(|name| run.var(name));
+    let candidates = SYSTEM_PATHS
+        .iter()
+        .map(PathBuf::from)
+        .chain(std::env::split_paths(search_path))
+        .chain(homes.map(PathBuf::from));
+
+    let mut paths: Vec<PathBuf> = Vec::new();
+    for path in candidates {
+        if path.is_absolute() && !paths.iter().any(|covered| path.starts_with(covered)) {
+            paths.push(path);
+        }
+    }
+    paths
+}
+
+/// The mounts that turn an empty directory into a sandbox's root.
+struct Mounts {
+    root: CString,
+    /// Host path and the mount point under `root` it is bound onto.
+    binds: Vec<(CString, CString)>,
+    workdir: CString,
+    workdir_target: CString,
+    proc_target: CString,
+}
+
+fn c_path(path: &Path) -> io::Result<CString> {
+    Ok(CString::new(path.as_os_str().as_bytes())?)
+}
+
+/// Creates `root` + `host_path`'s parents, each as permissive as it is on the
+/// host, so the sandbox user reaches nothing it could not reach outside.
+/// Returns where `host_path` itself goes.
+fn mount_point(root: &Path, host_path: &Path) -> io::Result<PathBuf> {
+    let relative = host_path.strip_prefix("/").map_err(io::Error::other)?;
+    let mut host = PathBuf::from("/");
+    let mut inside = root.to_path_buf();
+    if let Some(parent) = relative.parent() {
+        for component in parent.components() {
+            host.push(component);
+            inside.push(component);
+            if !inside.exists() {
+                std::fs::create_dir(&inside)?;
+                let mode = std::fs::metadata(&host).map(|m| m.permissions().mode() & 0o7777).unwrap_or(0o755);
+                std::fs::set_permissions(&inside, Permissions::from_mode(mode))?;
+            }
+        }
+    }
+    Ok(root.join(relative))
+}
+
+/// Lays out the empty `root` for one namespaced run: a mount point for every
+/// toolchain path, the working directory and `/proc`. The mounts themselves
+/// are made by the init process, inside its own mount namespace.
+fn prepare_root(root: &Path, workdir: &Path, run: &Run) -> io::Result<Mounts> {
+    let mut binds = Vec::new();
+    for source in toolchain_paths(run) {
+        let Ok(metadata) = std::fs::symlink_metadata(&source) else { continue };
+        let target = mount_point(root, &source)?;
+        if target.exists() {
+            continue;
+        }
+        if metadata.file_type().is_symlink() {
+            // Merged-usr links such as `/bin -> usr/bin` resolve inside the new root.
+            std::os::unix::fs::symlink(std::fs::read_link(&source)?, &target)?;
+            continue;
+        }
+        if metadata.is_dir() {
+            std::fs::create_dir(&target)?;
+        } else {
+            std::fs::File::create(&target)?;
+        }
+        binds.push((c_path(&source)?, c_path(&target)?));
+    }
+
+    let dev = root.join("dev");
+    std::fs::create_dir_all(&dev)?;
+    for (name, target) in [("fd", "/proc/self/fd"), ("stdin", "/proc/self/fd/0"), ("stdout", "/proc/self/fd/1"), ("stderr", "/proc/self/fd/2")] {
+        std::os::unix::fs::symlink(target, dev.join(name))?;
+    }
+
+    let workdir_target = mount_point(root, workdir)?;
+    std::fs::create_dir(&workdir_target)?;
+    let proc_target = root.join("proc");
+    std::fs::create_dir(&proc_target)?;
+
+    Ok(Mounts {
+        root: c_path(root)?,
+        binds,
+        workdir: c_path(workdir)?,
+        workdir_target: c_path(&workdir_target)?,
+        proc_target: c_path(&proc_target)?,
+    })
+}
+
+/// Closes every descriptor but `keep`, so a waiting process holds none of
+/// the sandboxed program's pipes open.
+unsafe fn close_all_but(keep: libc::c_int) {
+    libc::syscall(libc::SYS_close_range, 0, keep - 1, 0);
+    libc::syscall(libc::SYS_close_range, keep + 1, libc::c_uint::MAX, 0);
+}
+
+/// Waits for `pid` to exit, reaping whatever else exits first (an init
+/// process inherits every orphan in its namespace), and returns its status.
+unsafe fn wait_for(pid: libc::pid_t) -> libc::c_int {
+    let mut status = 0;
+    loop {
+        let exited = libc::waitpid(-1, &mut status, 0);
+        if exited == pid || (exited < 0 && io::Error::last_os_error().kind() != io::ErrorKind::Interrupted) {
+            return status;
+        }
+    }
+}
+
+/// Ends the calling process the way `status` says another one ended.
+unsafe fn exit_like(status: libc::c_int) -> ! {
+    if libc::WIFSIGNALED(status) {
+        let signal = libc::WTERMSIG(status);
+        let mut unblock: libc::sigset_t = std::mem::zeroed();
+        libc::sigemptyset(&mut unblock);
+        libc::sigaddset(&mut unblock, signal);
+        libc::sigprocmask(libc::SIG_UNBLOCK, &unblock, std::ptr::null_mut());
+        libc::signal(signal, libc::SIG_DFL);
+        libc::kill(libc::getpid(), signal);
+        libc::_exit(128 + signal);
+    }
+    libc::_exit(libc::WEXITSTATUS(status))
+}
+
+/// Forks; the parent only waits for the child and then exits the way the
+/// child did (or the way `report` says, when a status arrives on it), so
+/// whoever waits on the parent sees the sandboxed program's status. Returns in
+/// the child.
+unsafe fn fork_behind(report: libc::c_int) -> io::Result<()> {
+    let child = libc::fork();
+    if child < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    if child > 0 {
+        close_all_but(report);
+        let status = wait_for(child);
+        let mut reported: libc::c_int = 0;
+        let size = std::mem::size_of::<libc::c_int>();
+        let read = libc::read(report, &mut reported as *mut libc::c_int as *mut libc::c_void, size);
+        exit_like(if read == size as isize { reported } else { status });
+    }
+    Ok(())
+}
+
+/// Builds the sandbox's root from `mounts` and moves into it: the toolchains
+/// read-only, the working directory writable and a `/proc` showing only the
+/// sandbox's own processes.
+unsafe fn enter_root(mounts: &Mounts) -> io::Result<()> {
+    let none = std::ptr::null();
+    // Nothing mounted from here on may propagate back to the host.
+    check(libc::mount(none, c"/".as_ptr(), none, libc::MS_REC | libc::MS_PRIVATE, std::ptr::null()))?;
+    // `pivot_root` needs the new root to be a mount point of its own.
+    check(libc::mount(mounts.root.as_ptr(), mounts.root.as_ptr(), none, libc::MS_BIND, std::ptr::null()))?;
+    for (source, target) in &mounts.binds {
+        // Paths the sandbox user cannot reach on the host stay empty here.
+        libc::mount(source.as_ptr(), target.as_ptr(), none, libc::MS_BIND | libc::MS_REC, std::ptr::null());
+    }
+    check(libc::mount(mounts.workdir.as_ptr(), mounts.workdir_target.as_ptr(), none, libc::MS_BIND, std::ptr::null()))?;
+    check(libc::mount(
+        c"proc".as_ptr(),
+        mounts.proc_target.as_ptr(),
+        c"proc".as_ptr(),
+        libc::MS_NOSUID | libc::MS_NODEV | libc::MS_NOEXEC,
+        std::ptr::null(),
+    ))?;
+
+    // Stack the old root on top of the new one, then detach it.
+    check(libc::chdir(mounts.root.as_ptr()))?;
+    check(libc::syscall(libc::SYS_pivot_root, c".".as_ptr(), c".".as_ptr()) as libc::c_int)?;
+    check(libc::umount2(c".".as_ptr(), libc::MNT_DETACH))?;
+
+    set_read_only(c"/", true, libc::AT_RECURSIVE)?;
+    set_read_only(&mounts.workdir, false, 0)?;
+    check(libc::chdir(mounts.workdir.as_ptr()))
+}
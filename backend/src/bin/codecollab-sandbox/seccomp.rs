@@ -0,0 +1,139 @@
+//! A seccomp denylist for sandboxed programs. It refuses the system calls that
+//! reach past the namespaces (mounting, entering or creating namespaces,
+//! tracing other processes, loading kernel code) while leaving ordinary
+//! programs and toolchains alone.
+
+use std::io;
+
+#[cfg(target_arch = "x86_64")]
+const AUDIT_ARCH: u32 = 0xC000_003E;
+#[cfg(target_arch = "aarch64")]
+const AUDIT_ARCH: u32 = 0xC000_00B7;
+
+/// x32 system calls on x86_64 carry this bit; none are allowed.
+const X32_SYSCALL_BIT: u32 = 0x4000_0000;
+
+/// Offsets into `struct seccomp_data`.
+const NR_OFFSET: u32 = 0;
+const ARCH_OFFSET: u32 = 4;
+/// Low half of the first argument (both supported architectures are little-endian).
+const ARG0_OFFSET: u32 = 16;
+
+/// Refused with `EPERM`.
+const DENIED: &[libc::c_long] = &[
+    libc::SYS_mount,
+    libc::SYS_umount2,
+    libc::SYS_pivot_root,
+    libc::SYS_chroot,
+    libc::SYS_fsopen,
+    libc::SYS_fsconfig,
+    libc::SYS_fsmount,
+    libc::SYS_fspick,
+    libc::SYS_move_mount,
+    libc::SYS_open_tree,
+    libc::SYS_mount_setattr,
+    libc::SYS_unshare,
+    libc::SYS_setns,
+    libc::SYS_ptrace,
+    libc::SYS_process_vm_readv,
+    libc::SYS_process_vm_writev,
+    libc::SYS_pidfd_getfd,
+    libc::SYS_open_by_handle_at,
+    libc::SYS_name_to_handle_at,
+    libc::SYS_init_module,
+    libc::SYS_finit_module,
+    libc::SYS_delete_module,
+    libc::SYS_kexec_load,
+    libc::SYS_kexec_file_load,
+    libc::SYS_bpf,
+    libc::SYS_perf_event_open,
+    libc::SYS_userfaultfd,
+    libc::SYS_keyctl,
+    libc::SYS_add_key,
+    libc::SYS_request_key,
+    libc::SYS_reboot,
+    libc::SYS_swapon,
+    libc::SYS_swapoff,
+    libc::SYS_syslog,
+    libc::SYS_acct,
+    libc::SYS_quotactl,
+    libc::SYS_settimeofday,
+    libc::SYS_clock_settime,
+    libc::SYS_adjtimex,
+    libc::SYS_vhangup,
+];
+
+/// Refused with `ENOSYS`, which runtimes take as "not supported here" and
+/// fall back from: `clone3` to `clone` (whose flags can be checked, unlike
+/// `clone3`'s, which sit behind a pointer) and io_uring to plain reads and writes.
+const UNSUPPORTED: &[libc::c_long] = &[
+    libc::SYS_clone3,
+    libc::SYS_io_uring_setup,
+    libc::SYS_io_uring_enter,
+    libc::SYS_io_uring_register,
+];
+
+/// `clone` flags that would create namespaces.
+const NAMESPACE_FLAGS: u32 = (libc::CLONE_NEWUSER
+    | libc::CLONE_NEWNS
+    | libc::CLONE_NEWPID
+    | libc::CLONE_NEWNET
+    | libc::CLONE_NEWIPC
+    | libc::CLONE_NEWUTS
+    | libc::CLONE_NEWCGROUP) as u32;
+
+fn load(offset: u32) -> libc::sock_filter {
+    libc::sock_filter { code: (libc::BPF_LD | libc::BPF_W | libc::BPF_ABS) as u16, jt: 0, jf: 0, k: offset }
+}
+
+fn jump(condition: u32, k: u32, jt: u8, jf: u8) -> libc::sock_filter {
+    libc::sock_filter { code: (libc::BPF_JMP | condition | libc::BPF_K) as u16, jt, jf, k }
+}
+
+fn ret(action: u32) -> libc::sock_filter {
+    libc::sock_filter { code: (libc::BPF_RET | libc::BPF_K) as u16, jt: 0, jf: 0, k: action }
+}
+
+/// Builds the filter program.
+pub fn denylist() -> Vec<libc::sock_filter> {
+    let eperm = libc::SECCOMP_RET_ERRNO | libc::EPERM as u32;
+    let enosys = libc::SECCOMP_RET_ERRNO | libc::ENOSYS as u32;
+
+    let mut filter = vec![
+        load(ARCH_OFFSET),
+        jump(libc::BPF_JEQ, AUDIT_ARCH, 1, 0),
+        ret(libc::SECCOMP_RET_KILL_PROCESS),
+        load(NR_OFFSET),
+        jump(libc::BPF_JGE, X32_SYSCALL_BIT, 0, 1),
+        ret(eperm),
+    ];
+    for (calls, action) in [(DENIED, eperm), (UNSUPPORTED, enosys)] {
+        for &call in calls {
+            filter.push(jump(libc::BPF_JEQ, call as u32, 0, 1));
+            filter.push(ret(action));
+        }
+    }
+    filter.extend([
+        jump(libc::BPF_JEQ, libc::SYS_clone as u32, 0, 3),
+        load(ARG0_OFFSET),
+        jump(libc::BPF_JSET, NAMESPACE_FLAGS, 0, 1),
+        ret(eperm),
+        ret(libc::SECCOMP_RET_ALLOW),
+    ]);
+    filter
+}
+
+/// Applies `filter` to the calling thread and everything it later starts.
+///
+/// # Safety
+/// Only makes system calls on `filter`.
+pub unsafe fn install(filter: &[libc::sock_filter]) -> io::Result<()> {
+    let program = libc::sock_fprog { len: filter.len() as u16, filter: filter.as_ptr() as *mut libc::sock_filter };
+    if libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    if libc::syscall(libc::SYS_seccomp, libc::SECCOMP_SET_MODE_FILTER, 0, &program as *const libc::sock_fprog) < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
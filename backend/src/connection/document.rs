@@ -0,0 +1,793 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::error::Error;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{Mutex, Notify};
+use uuid::Uuid;
+
+use crate::connection::real_time::CollabHub;
+use crate::connection::storage::Storage;
+
+// Operations older than this many revisions can no longer be rebased; clients
+// that far behind are sent a fresh snapshot instead.
+const MAX_HISTORY: usize = 1000;
+
+/// One component of a text operation. Lengths count Unicode scalar values.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Op {
+    Retain(usize),
+    Insert(String),
+    Delete(usize),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OtError {
+    /// The operation does not span the whole document it was applied to.
+    LengthMismatch { expected: usize, actual: usize },
+    /// The client is ahead of the server.
+    RevisionInFuture { revision: u64, current: u64 },
+    /// The client's base revision has already been dropped from history.
+    RevisionTooOld { revision: u64, oldest: u64 },
+    /// The lengths of the components add up to more than `usize` can hold.
+    TooLong,
+}
+
+impl fmt::Display for OtError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OtError::LengthMismatch { expected, actual } => {
+                write!(f, "operation spans {} characters but the document has {}", expected, actual)
+            }
+            OtError::RevisionInFuture { revision, current } => {
+                write!(f, "revision {} is ahead of the server revision {}", revision, current)
+            }
+            OtError::RevisionTooOld { revision, oldest } => {
+                write!(f, "revision {} is older than the oldest kept revision {}", revision, oldest)
+            }
+            OtError::TooLong => write!(f, "operation is too long"),
+        }
+    }
+}
+
+impl Error for OtError {}
+
+/// A sequence of retain/insert/delete components that walks the whole document,
+/// in the style of ot.js.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "Vec<Op>", into = "Vec<Op>")]
+pub struct TextOperation {
+    ops: Vec<Op>,
+    base_len: usize,
+    target_len: usize,
+}
+
+// Client-supplied lengths are checked here, so the builders below can add them up freely.
+impl TryFrom<Vec<Op>> for TextOperation {
+    type Error = OtError;
+
+    fn try_from(ops: Vec<Op>) -> Result<Self, OtError> {
+        let (mut base_len, mut target_len) = (0usize, 0usize);
+        for op in &ops {
+            let (base, target) = match op {
+                Op::Retain(n) => (*n, *n),
+                Op::Insert(s) => (0, s.chars().count()),
+                Op::Delete(n) => (*n, 0),
+            };
+            base_len = base_len.checked_add(base).ok_or(OtError::TooLong)?;
+            target_len = target_len.checked_add(target).ok_or(OtError::TooLong)?;
+        }
+
+        let mut operation = TextOperation::new();
+        for op in ops {
+            match op {
+                Op::Retain(n) => operation.retain(n),
+                Op::Insert(s) => operation.insert(&s),
+                Op::Delete(n) => operation.delete(n),
+            };
+        }
+        Ok(operation)
+    }
+}
+
+impl From<TextOperation> for Vec<Op> {
+    fn from(operation: TextOperation) -> Self {
+        operation.ops
+    }
+}
+
+impl TextOperation {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn retain(&mut self, n: usize) -> &mut Self {
+        if n == 0 {
+            return self;
+        }
+        self.base_len += n;
+        self.target_len += n;
+        if let Some(Op::Retain(last)) = self.ops.last_mut() {
+            *last += n;
+        } else {
+            self.ops.push(Op::Retain(n));
+        }
+        self
+    }
+
+    pub fn insert(&mut self, s: &str) -> &mut Self {
+        if s.is_empty() {
+            return self;
+        }
+        self.target_len += s.chars().count();
+        match self.ops.as_mut_slice() {
+            [.., Op::Insert(last)] => last.push_str(s),
+            // Keep inserts ahead of deletes so equivalent operations compare equal.
+            [.., Op::Insert(prev), Op::Delete(_)] => prev.push_str(s),
+            [.., Op::Delete(_)] => {
+                let delete = self.ops.pop().expect("slice matched a last element");
+                self.ops.push(Op::Insert(s.to_string()));
+                self.ops.push(delete);
+            }
+            _ => self.ops.push(Op::Insert(s.to_string())),
+        }
+        self
+    }
+
+    pub fn delete(&mut self, n: usize) -> &mut Self {
+        if n == 0 {
+            return self;
+        }
+        self.base_len += n;
+        if let Some(Op::Delete(last)) = self.ops.last_mut() {
+            *last += n;
+        } else {
+            self.ops.push(Op::Delete(n));
+        }
+        self
+    }
+
+    /// Builds the operation that turns `old` into `new` by trimming their common prefix and suffix.
+    pub fn diff(old: &str, new: &str) -> Self {
+        let old_chars: Vec<char> = old.chars().collect();
+        let new_chars: Vec<char> = new.chars().collect();
+
+        let prefix = old_chars
+            .iter()
+            .zip(&new_chars)
+            .take_while(|(a, b)| a == b)
+            .count();
+        let suffix = old_chars[prefix..]
+            .iter()
+            .rev()
+            .zip(new_chars[prefix..].iter().rev())
+            .take_while(|(a, b)| a == b)
+            .count();
+
+        let inserted: String = new_chars[prefix..new_chars.len() - suffix].iter().collect();
+        let mut operation = TextOperation::new();
+        operation
+            .retain(prefix)
+            .insert(&inserted)
+            .delete(old_chars.len() - suffix - prefix)
+            .retain(suffix);
+        operation
+    }
+
+    pub fn apply(&self, text: &str) -> Result<String, OtError> {
+        let actual = text.chars().count();
+        if actual != self.base_len {
+            return Err(OtError::LengthMismatch { expected: self.base_len, actual });
+        }
+
+        let mut chars = text.chars();
+        let mut result = String::with_capacity(text.len());
+        for op in &self.ops {
+            match op {
+                Op::Retain(n) => result.extend(chars.by_ref().take(*n)),
+                Op::Insert(s) => result.push_str(s),
+                Op::Delete(n) => {
+                    chars.by_ref().take(*n).for_each(drop);
+                }
+            }
+        }
+        Ok(result)
+    }
+
+    /// Combines `self` followed by `other` into one operation with the same effect.
+    pub fn compose(&self, other: &TextOperation) -> Result<TextOperation, OtError> {
+        if self.target_len != other.base_len {
+            return Err(OtError::LengthMismatch { expected: other.base_len, actual: self.target_len });
+        }
+
+        let mut result = TextOperation::new();
+        let mut a_ops = self.ops.iter().cloned();
+        let mut b_ops = other.ops.iter().cloned();
+        let mut a = a_ops.next();
+        let mut b = b_ops.next();
+
+        loop {
+            match (a.take(), b.take()) {
+                (None, None) => break,
+                (Some(Op::Delete(n)), b_op) => {
+                    result.delete(n);
+                    a = a_ops.next();
+                    b = b_op;
+                }
+                (a_op, Some(Op::Insert(s))) => {
+                    result.insert(&s);
+                    a = a_op;
+                    b = b_ops.next();
+                }
+                (Some(Op::Retain(x)), Some(Op::Retain(y))) => {
+                    let n = x.min(y);
+                    result.retain(n);
+                    (a, b) = (remainder(Op::Retain(x), n, &mut a_ops), remainder(Op::Retain(y), n, &mut b_ops));
+                }
+                (Some(Op::Insert(s)), Some(Op::Delete(y))) => {
+                    let x = s.chars().count();
+                    let n = x.min(y);
+                    (a, b) = (remainder(Op::Insert(s), n, &mut a_ops), remainder(Op::Delete(y), n, &mut b_ops));
+                }
+                (Some(Op::Insert(s)), Some(Op::Retain(y))) => {
+                    let x = s.chars().count();
+                    let n = x.min(y);
+                    result.insert(&s.chars().take(n).collect::<String>());
+                    (a, b) = (remainder(Op::Insert(s), n, &mut a_ops), remainder(Op::Retain(y), n, &mut b_ops));
+                }
+                (Some(Op::Retain(x)), Some(Op::Delete(y))) => {
+                    let n = x.min(y);
+                    result.delete(n);
+                    (a, b) = (remainder(Op::Retain(x), n, &mut a_ops), remainder(Op::Delete(y), n, &mut b_ops));
+                }
+                (None, Some(_)) | (Some(_), None) => {
+                    return Err(OtError::LengthMismatch { expected: other.base_len, actual: self.target_len });
+                }
+            }
+        }
+
+        Ok(result)
+    }
+
+    /// Rebases two operations made against the same document onto each other,
+    /// returning `(a', b')` such that `b' ∘ a == a' ∘ b`. Ties between inserts go to `a`.
+    pub fn transform(a: &TextOperation, b: &TextOperation) -> Result<(TextOperation, TextOperation), OtError> {
+        if a.base_len != b.base_len {
+            return Err(OtError::LengthMismatch { expected: a.base_len, actual: b.base_len });
+        }
+
+        let mut a_prime = TextOperation::new();
+        let mut b_prime = TextOperation::new();
+        let mut a_ops = a.ops.iter().cloned();
+        let mut b_ops = b.ops.iter().cloned();
+        let mut op_a = a_ops.next();
+        let mut op_b = b_ops.next();
+
+        loop {
+            match (op_a.take(), op_b.take()) {
+                (None, None) => break,
+                (Some(Op::Insert(s)), b_op) => {
+                    let n = s.chars().count();
+                    a_prime.insert(&s);
+                    b_prime.retain(n);
+                    op_a = a_ops.next();
+                    op_b = b_op;
+                }
+                (a_op, Some(Op::Insert(s))) => {
+                    let n = s.chars().count();
+                    a_prime.retain(n);
+                    b_prime.insert(&s);
+                    op_a = a_op;
+                    op_b = b_ops.next();
+                }
+                (Some(Op::Retain(x)), Some(Op::Retain(y))) => {
+                    let n = x.min(y);
+                    a_prime.retain(n);
+                    b_prime.retain(n);
+                    (op_a, op_b) = (remainder(Op::Retain(x), n, &mut a_ops), remainder(Op::Retain(y), n, &mut b_ops));
+                }
+                (Some(Op::Delete(x)), Some(Op::Delete(y))) => {
+                    let n = x.min(y);
+                    (op_a, op_b) = (remainder(Op::Delete(x), n, &mut a_ops), remainder(Op::Delete(y), n, &mut b_ops));
+                }
+                (Some(Op::Delete(x)), Some(Op::Retain(y))) => {
+                    let n = x.min(y);
+                    a_prime.delete(n);
+                    (op_a, op_b) = (remainder(Op::Delete(x), n, &mut a_ops), remainder(Op::Retain(y), n, &mut b_ops));
+                }
+                (Some(Op::Retain(x)), Some(Op::Delete(y))) => {
+                    let n = x.min(y);
+                    b_prime.delete(n);
+                    (op_a, op_b) = (remainder(Op::Retain(x), n, &mut a_ops), remainder(Op::Delete(y), n, &mut b_ops));
+                }
+                (None, Some(_)) | (Some(_), None) => {
+                    return Err(OtError::LengthMismatch { expected: a.base_len, actual: b.base_len });
+                }
+            }
+        }
+
+        Ok((a_prime, b_prime))
+    }
+}
+
+// Returns what is left of `op` after consuming `n` characters of it, or the next
+// component when it has been used up entirely.
+fn remainder(op: Op, n: usize, rest: &mut impl Iterator<Item = Op>) -> Option<Op> {
+    match op {
+        Op::Retain(x) if x > n => Some(Op::Retain(x - n)),
+        Op::Delete(x) if x > n => Some(Op::Delete(x - n)),
+        Op::Insert(s) if s.chars().count() > n => Some(Op::Insert(s.chars().skip(n).collect())),
+        _ => rest.next(),
+    }
+}
+
+/// Server copy of one file: the merged text, its revision and the recent
+/// operations needed to rebase late-arriving client edits.
+#[derive(Debug, Clone)]
+pub struct Document {
+    pub content: String,
+    pub revision: u64,
+    /// The saved revision of the file this document was last loaded from or
+    /// saved as (`File::revision`), and its text. Saves only land on top of it.
+    saved: u64,
+    saved_content: String,
+    history: VecDeque<TextOperation>,
+    dirty: bool,
+    /// Users who edited since the last save.
+    authors: Vec<String>,
+    /// Being saved before it is forgotten; loads wait until it is gone.
+    closing: bool,
+}
+
+impl Document {
+    pub fn new(content: String, saved: u64) -> Self {
+        Self {
+            saved_content: content.clone(),
+            content,
+            revision: 0,
+            saved,
+            history: VecDeque::new(),
+            dirty: false,
+            authors: Vec::new(),
+            closing: false,
+        }
+    }
+
+    fn oldest_revision(&self) -> u64 {
+        self.revision - self.history.len() as u64
+    }
+
+    /// Rebases an operation made against `revision` over everything accepted
+    /// since, applies it, and returns the operation as it was actually applied.
+    pub fn apply(&mut self, revision: u64, operation: TextOperation) -> Result<TextOperation, OtError> {
+        if revision > self.revision {
+            return Err(OtError::RevisionInFuture { revision, current: self.revision });
+        }
+        let oldest = self.oldest_revision();
+        if revision < oldest {
+            return Err(OtError::RevisionTooOld { revision, oldest });
+        }
+
+        let mut operation = operation;
+        for concurrent in self.history.range((revision - oldest) as usize..) {
+            operation = TextOperation::transform(&operation, concurrent)?.0;
+        }
+
+        self.content = operation.apply(&self.content)?;
+        self.revision += 1;
+        self.dirty = true;
+        self.history.push_back(operation.clone());
+        if self.history.len() > MAX_HISTORY {
+            self.history.pop_front();
+        }
+
+        Ok(operation)
+    }
+
+    fn record_author(&mut self, author: &str) {
+        if !self.authors.iter().any(|a| a == author) {
+            self.authors.push(author.to_string());
+        }
+    }
+
+    // Puts back what a failed save took, so the next flush tries again.
+    fn unsaved(&mut self, authors: &[String]) {
+        self.dirty = true;
+        authors.iter().for_each(|a| self.record_author(a));
+    }
+
+    /// Takes in `content`, saved as `revision` by someone else since this
+    /// document was loaded or last saved, as an edit on top of the live text.
+    pub fn merge_saved(&mut self, revision: u64, content: String) -> Result<TextOperation, OtError> {
+        let ours = TextOperation::diff(&self.saved_content, &self.content);
+        let theirs = TextOperation::diff(&self.saved_content, &content);
+        let (_, theirs) = TextOperation::transform(&ours, &theirs)?;
+        let applied = self.apply(self.revision, theirs)?;
+        self.saved = revision;
+        self.saved_content = content;
+        Ok(applied)
+    }
+}
+
+/// An accepted edit, ready to be acknowledged and broadcast.
+pub struct AppliedEdit {
+    pub revision: u64,
+    pub operation: TextOperation,
+    pub content: String,
+}
+
+/// Open documents keyed by file id, loaded from storage on first use and
+/// written back by [`spawn_persistence`].
+pub struct DocumentStore {
+    storage: Arc<dyn Storage>,
+    documents: Mutex<HashMap<String, Document>>,
+    /// Held while flushing or closing, so a close cannot forget a document
+    /// while a flush is still writing it.
+    persisting: Mutex<()>,
+    /// Wakes loads waiting on a closing document.
+    closed: Notify,
+}
+
+impl DocumentStore {
+    pub fn new(storage: Arc<dyn Storage>) -> Self {
+        Self { storage, documents: Mutex::new(HashMap::new()), persisting: Mutex::new(()), closed: Notify::new() }
+    }
+
+    async fn load(&self, file_id: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+        loop {
+            let closed = {
+                let documents = self.documents.lock().await;
+                match documents.get(file_id) {
+                    // Created under the lock, so the wakeup from `close` cannot be missed.
+                    Some(document) if document.closing => self.closed.notified(),
+                    Some(_) => return Ok(()),
+                    None => break,
+                }
+            };
+            closed.await;
+        }
+
+        let file = self
+            .storage
+            .get_file_by_id(file_id)
+            .await?
+            .ok_or_else(|| format!("File {} not found", file_id))?;
+
+        self.documents
+            .lock()
+            .await
+            .entry(file_id.to_string())
+            .or_insert_with(|| Document::new(file.content, file.revision));
+        Ok(())
+    }
+
+    /// Returns the current revision and text of a file.
+    pub async fn snapshot(&self, file_id: &str) -> Result<(u64, String), Box<dyn Error + Send + Sync>> {
+        self.load(file_id).await?;
+        let documents = self.documents.lock().await;
+        let document = documents.get(file_id).ok_or("Document was closed")?;
+        Ok((document.revision, document.content.clone()))
+    }
+
+    pub async fn apply(
+        &self,
+        file_id: &str,
+        revision: u64,
+        operation: TextOperation,
+        author: &str,
+    ) -> Result<AppliedEdit, Box<dyn Error + Send + Sync>> {
+        self.load(file_id).await?;
+        let mut documents = self.documents.lock().await;
+        let document = documents.get_mut(file_id).ok_or("Document was closed")?;
+        let operation = document.apply(revision, operation)?;
+        document.record_author(author);
+        Ok(AppliedEdit { revision: document.revision, operation, content: document.content.clone() })
+    }
+
+    /// Applies a whole-content replacement on top of the latest revision.
+    pub async fn replace(&self, file_id: &str, content: &str, author: &str) -> Result<AppliedEdit, Box<dyn Error + Send + Sync>> {
+        self.load(file_id).await?;
+        self.replace_if_open(file_id, content, author)
+            .await?
+            .ok_or_else(|| "Document was closed".into())
+    }
+
+    /// Like [`DocumentStore::replace`], but leaves files nobody has open untouched and returns `None`.
+    pub async fn replace_if_open(&self, file_id: &str, content: &str, author: &str) -> Result<Option<AppliedEdit>, Box<dyn Error + Send + Sync>> {
+        let mut documents = self.documents.lock().await;
+        let Some(document) = documents.get_mut(file_id) else {
+            return Ok(None);
+        };
+        let revision = document.revision;
+        let operation = document.apply(revision, TextOperation::diff(&document.content, content))?;
+        document.record_author(author);
+        Ok(Some(AppliedEdit { revision: document.revision, operation, content: document.content.clone() }))
+    }
+
+    // Saves `content` on top of the revision the document was built from. If
+    // the file was saved elsewhere meanwhile, that text is merged into the
+    // document instead, which stays dirty, and the merge is returned.
+    async fn persist(&self, file_id: &str, base: u64, content: &str, authors: &[String]) -> Result<Option<AppliedEdit>, Box<dyn Error + Send + Sync>> {
+        if let Some(revision) = self.storage.update_file_content_from(file_id, base, content, authors).await? {
+            if let Some(document) = self.documents.lock().await.get_mut(file_id) {
+                document.saved = revision;
+                document.saved_content = content.to_string();
+            }
+            return Ok(None);
+        }
+
+        let file = self.storage.get_file_by_id(file_id).await?.ok_or_else(|| format!("File {} not found", file_id))?;
+        let mut documents = self.documents.lock().await;
+        let Some(document) = documents.get_mut(file_id) else {
+            return Ok(None);
+        };
+        document.unsaved(authors);
+        let operation = document.merge_saved(file.revision, file.content)?;
+        println!("🔀 File {} was saved elsewhere; merged into the open document", file_id);
+        Ok(Some(AppliedEdit { revision: document.revision, operation, content: document.content.clone() }))
+    }
+
+    /// Writes every document changed since the last flush on top of the
+    /// revision it was loaded from. Documents whose file has been deleted are
+    /// dropped. Returns the saves made elsewhere that were merged into open
+    /// documents instead, for the caller to send to their editors.
+    pub async fn flush(&self) -> Vec<(String, AppliedEdit)> {
+        let _persisting = self.persisting.lock().await;
+        let pending: Vec<(String, u64, String, Vec<String>)> = self
+            .documents
+            .lock()
+            .await
+            .iter_mut()
+            .filter(|(_, document)| document.dirty)
+            .map(|(file_id, document)| {
+                document.dirty = false;
+                (file_id.clone(), document.saved, document.content.clone(), std::mem::take(&mut document.authors))
+            })
+            .collect();
+
+        let mut merged = Vec::new();
+        for (file_id, base, content, authors) in pending {
+            match self.persist(&file_id, base, &content, &authors).await {
+                Ok(Some(edit)) => merged.push((file_id, edit)),
+                Ok(None) => {}
+                Err(e) => {
+                    // A deleted file can never be saved; stop trying.
+                    if let Ok(None) = self.storage.get_file_by_id(&file_id).await {
+                        println!("🗑️ Dropping edits to deleted file {}", file_id);
+                        self.discard(&file_id).await;
+                        continue;
+                    }
+                    eprintln!("❌ Failed to persist file {}: {}", file_id, e);
+                    if let Some(document) = self.documents.lock().await.get_mut(&file_id) {
+                        document.unsaved(&authors);
+                    }
+                }
+            }
+        }
+        merged
+    }
+
+    /// The live revision and text of a file, if someone has it open.
+    pub async fn peek(&self, file_id: &str) -> Option<(u64, String)> {
+        let documents = self.documents.lock().await;
+        documents.get(file_id).map(|document| (document.revision, document.content.clone()))
+    }
+
+    /// Forgets a document without saving it, for files that have been deleted.
+    pub async fn discard(&self, file_id: &str) {
+        self.documents.lock().await.remove(file_id);
+    }
+
+    /// Persists and forgets a document once nobody is editing it. The document
+    /// stays in place until it is saved, so someone joining meanwhile waits for
+    /// the save rather than loading the old text. If saving fails it is kept
+    /// open for the next flush.
+    pub async fn close(&self, file_id: &str) {
+        let _persisting = self.persisting.lock().await;
+        // Edits that land while saving are saved on the next pass.
+        loop {
+            let (base, content, authors) = {
+                let mut documents = self.documents.lock().await;
+                let Some(document) = documents.get_mut(file_id) else {
+                    return;
+                };
+                if !document.dirty {
+                    documents.remove(file_id);
+                    self.closed.notify_waiters();
+                    return;
+                }
+                document.closing = true;
+                document.dirty = false;
+                (document.saved, document.content.clone(), std::mem::take(&mut document.authors))
+            };
+
+            // Nobody is left to send a merged save to; it is saved on the next pass.
+            if let Err(e) = self.persist(file_id, base, &content, &authors).await {
+                eprintln!("❌ Failed to persist file {}: {}", file_id, e);
+                if let Some(document) = self.documents.lock().await.get_mut(file_id) {
+                    document.closing = false;
+                    document.unsaved(&authors);
+                }
+                self.closed.notify_waiters();
+                return;
+            }
+        }
+    }
+}
+
+/// Periodically flushes merged document text back to storage, sending any
+/// saves merged in from elsewhere to the documents' editors.
+pub fn spawn_persistence(store: Arc<DocumentStore>, hub: Arc<CollabHub>, every: Duration) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(every);
+        loop {
+            interval.tick().await;
+            for (file_id, edit) in store.flush().await {
+                hub.broadcast_edit(&file_id, Uuid::nil(), edit).await;
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::test_support::app::TestApp;
+
+    fn op(ops: Vec<Op>) -> TextOperation {
+        ops.try_into().unwrap()
+    }
+
+    /// Concurrent edits of "hello world", each pair made against that text.
+    fn concurrent_pairs() -> Vec<(TextOperation, TextOperation)> {
+        use Op::{Delete, Insert, Retain};
+        vec![
+            // Inserts at the same place.
+            (op(vec![Retain(5), Insert(",".into()), Retain(6)]), op(vec![Retain(5), Insert("!".into()), Retain(6)])),
+            // An insert inside a deleted range.
+            (op(vec![Retain(2), Insert("y".into()), Retain(9)]), op(vec![Delete(5), Retain(6)])),
+            // Overlapping deletes.
+            (op(vec![Retain(3), Delete(5), Retain(3)]), op(vec![Retain(6), Delete(5)])),
+            // Everything replaced on one side.
+            (op(vec![Delete(11), Insert("bye".into())]), op(vec![Retain(11), Insert("!".into())])),
+        ]
+    }
+
+    #[test]
+    fn transformed_operations_converge() {
+        let text = "hello world";
+        for (a, b) in concurrent_pairs() {
+            let (a_prime, b_prime) = TextOperation::transform(&a, &b).unwrap();
+            let after_b_then_a = a_prime.apply(&b.apply(text).unwrap()).unwrap();
+            let after_a_then_b = b_prime.apply(&a.apply(text).unwrap()).unwrap();
+            assert_eq!(after_b_then_a, after_a_then_b, "{:?} against {:?}", a, b);
+        }
+
+        // Ties between inserts go to the first operation.
+        let (a, b) = &concurrent_pairs()[0];
+        let (a_prime, _) = TextOperation::transform(a, b).unwrap();
+        assert_eq!(a_prime.apply(&b.apply(text).unwrap()).unwrap(), "hello,! world");
+    }
+
+    #[test]
+    fn composed_operations_have_the_same_effect() {
+        let text = "hello world";
+        let first = TextOperation::diff(text, "hello there world");
+        let second = TextOperation::diff("hello there world", "well, there world");
+        let composed = first.compose(&second).unwrap();
+        assert_eq!(composed.apply(text).unwrap(), second.apply(&first.apply(text).unwrap()).unwrap());
+
+        let unrelated = TextOperation::diff("abc", "abcd");
+        assert_eq!(first.compose(&unrelated), Err(OtError::LengthMismatch { expected: 3, actual: 17 }));
+    }
+
+    #[test]
+    fn operations_whose_lengths_overflow_are_refused() {
+        use Op::{Delete, Insert, Retain};
+        assert_eq!(TextOperation::try_from(vec![Retain(usize::MAX), Retain(1)]), Err(OtError::TooLong));
+        assert_eq!(TextOperation::try_from(vec![Retain(1), Delete(usize::MAX)]), Err(OtError::TooLong));
+        assert_eq!(TextOperation::try_from(vec![Retain(usize::MAX), Insert("x".into())]), Err(OtError::TooLong));
+
+        let json = format!(r#"[{{"retain":{}}},{{"delete":1}}]"#, usize::MAX);
+        let error = serde_json::from_str::<TextOperation>(&json).unwrap_err();
+        assert!(error.to_string().contains("operation is too long"), "{}", error);
+    }
+
+    #[test]
+    fn stale_edits_are_rebased_over_newer_ones() {
+        let mut document = Document::new("fn main() {}".into(), 0);
+        document.apply(0, TextOperation::diff("fn main() {}", "pub fn main() {}")).unwrap();
+        document.apply(1, TextOperation::diff("pub fn main() {}", "pub fn main() { run(); }")).unwrap();
+
+        // Made against the first revision, before both edits above.
+        let applied = document.apply(0, TextOperation::diff("fn main() {}", "fn start() {}")).unwrap();
+        assert_eq!(document.content, "pub fn start() { run(); }");
+        assert_eq!(document.revision, 3);
+        assert_eq!(applied.apply("pub fn main() { run(); }").unwrap(), document.content);
+
+        assert_eq!(
+            document.apply(4, TextOperation::diff(&document.content.clone(), "")),
+            Err(OtError::RevisionInFuture { revision: 4, current: 3 })
+        );
+    }
+
+    #[test]
+    fn offsets_count_characters_not_bytes_or_utf16_units() {
+        // "é" is two bytes; "😀" is four bytes and two UTF-16 code units. Each is one character.
+        let text = "é😀a";
+        let mut insert = TextOperation::new();
+        insert.retain(2).insert("ß").retain(1);
+        assert_eq!(insert.apply(text).unwrap(), "é😀ßa");
+        let mut delete = TextOperation::new();
+        delete.retain(1).delete(1).retain(1);
+        assert_eq!(delete.apply(text).unwrap(), "éa");
+
+        let (insert_prime, delete_prime) = TextOperation::transform(&insert, &delete).unwrap();
+        assert_eq!(insert_prime.apply("éa").unwrap(), "éßa");
+        assert_eq!(delete_prime.apply("é😀ßa").unwrap(), "éßa");
+
+        assert_eq!(TextOperation::diff("a😀b", "a😃b").apply("a😀b").unwrap(), "a😃b");
+        // Counted in UTF-16 units the operation is one character too long.
+        let mut utf16 = TextOperation::new();
+        utf16.retain(text.encode_utf16().count());
+        assert_eq!(utf16.apply(text), Err(OtError::LengthMismatch { expected: 4, actual: 3 }));
+    }
+
+    #[tokio::test]
+    async fn flushing_drops_documents_of_deleted_files() {
+        let app = TestApp::new().await;
+        let (storage, documents) = (&app.storage, &app.documents);
+        let owner = Uuid::new_v4();
+        let project_id = app.project(owner, &[]).await;
+        let file_id = storage.create_file("main.py".into(), owner, project_id, None).await.unwrap().unwrap().to_string();
+
+        let (revision, content) = documents.snapshot(&file_id).await.unwrap();
+        documents.apply(&file_id, revision, TextOperation::diff(&content, "print(1)\n"), "ada").await.unwrap();
+        assert!(storage.delete_file(&file_id).await.unwrap());
+
+        documents.flush().await;
+        assert!(documents.peek(&file_id).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn saves_made_while_a_document_loads_are_merged_rather_than_overwritten() {
+        let app = TestApp::new().await;
+        let (storage, documents) = (&app.storage, &app.documents);
+        let owner = Uuid::new_v4();
+        let project_id = app.project(owner, &[]).await;
+        let file_id = storage.create_file("main.py".into(), owner, project_id, None).await.unwrap().unwrap().to_string();
+        storage.update_file_content(&file_id, "x = 1\n", &["ada".into()]).await.unwrap();
+
+        // A REST save that found nothing open yet, landing after the load read the file.
+        let (revision, content) = documents.snapshot(&file_id).await.unwrap();
+        storage.update_file_content(&file_id, "x = 1\ny = 2\n", &["grace".into()]).await.unwrap();
+        documents.apply(&file_id, revision, TextOperation::diff(&content, "# x\nx = 1\n"), "ada").await.unwrap();
+
+        let merged = documents.flush().await;
+        assert_eq!(merged.len(), 1);
+        assert_eq!(merged[0].1.content, "# x\nx = 1\ny = 2\n");
+        assert_eq!(storage.get_file_by_id(&file_id).await.unwrap().unwrap().content, "x = 1\ny = 2\n", "nothing was overwritten");
+        assert!(documents.flush().await.is_empty());
+        let file = storage.get_file_by_id(&file_id).await.unwrap().unwrap();
+        assert_eq!((file.revision, file.content.as_str()), (3, "# x\nx = 1\ny = 2\n"));
+    }
+
+    #[tokio::test]
+    async fn joining_while_a_document_closes_sees_the_saved_text() {
+        let app = TestApp::new().await;
+        let (storage, documents) = (&app.storage, &app.documents);
+        let owner = Uuid::new_v4();
+        let project_id = app.project(owner, &[]).await;
+        let file_id = storage.create_file("main.py".into(), owner, project_id, None).await.unwrap().unwrap().to_string();
+
+        let (revision, content) = documents.snapshot(&file_id).await.unwrap();
+        documents.apply(&file_id, revision, TextOperation::diff(&content, "print(1)\n"), "ada").await.unwrap();
+
+        let ((), joined) = tokio::join!(documents.close(&file_id), documents.snapshot(&file_id));
+        assert_eq!(joined.unwrap().1, "print(1)\n");
+        assert_eq!(storage.get_file_by_id(&file_id).await.unwrap().unwrap().content, "print(1)\n");
+    }
+}
@@ -1,20 +1,122 @@
 use crate::connection::auth::get_access_token;
 // use anyhow::Result;
-use reqwest::Client;
+use reqwest::{Client, StatusCode};
+use serde_json::{json, Value};
+use std::error::Error;
+
+const FIREBASE_PROJECT_ID: &str = "dcode-7b1a0";
+const FIRESTORE_BASE_URL: &str = "https://firestore.googleapis.com/v1";
+/// Most writes Firestore accepts in one commit.
+const MAX_COMMIT_WRITES: usize = 500;
+const BATCH_ATTEMPTS: usize = 5;
 
 #[derive(Clone)]
 pub struct FirebaseService {
     pub access_token: String,
     pub client: Client,
+    pub project_id: String,
+    /// Firestore REST root, overridable with `FIRESTORE_BASE_URL` (e.g. for an emulator).
+    pub base_url: String,
 }
 
 impl FirebaseService {
     pub async fn new() -> Self {
         let access_token = get_access_token().await.expect("Failed to get access token");
-        let client = Client::new();
+        let base_url = std::env::var("FIRESTORE_BASE_URL").unwrap_or_else(|_| FIRESTORE_BASE_URL.to_string());
         println!("✅ Firestore REST client initialized");
 
-        Self { access_token, client }
+        Self::with_base_url(&base_url, FIREBASE_PROJECT_ID, &access_token)
+    }
+
+    /// Builds a client against any Firestore-compatible REST endpoint without fetching a token.
+    pub fn with_base_url(base_url: &str, project_id: &str, access_token: &str) -> Self {
+        Self {
+            access_token: access_token.to_string(),
+            client: Client::new(),
+            project_id: project_id.to_string(),
+            base_url: base_url.trim_end_matches('/').to_string(),
+        }
+    }
+
+    /// Root of the Firestore REST API for this project's default database.
+    pub fn documents_url(&self) -> String {
+        format!("{}/projects/{}/databases/(default)/documents", self.base_url, self.project_id)
+    }
+
+    /// Full resource name of a document, as used inside batched writes.
+    pub fn document_name(&self, collection: &str, id: &str) -> String {
+        format!("projects/{}/databases/(default)/documents/{}/{}", self.project_id, collection, id)
+    }
+
+    /// Fetches a document as Firestore returns it, with its `fields` and the
+    /// `updateTime` to use as a precondition when writing it back.
+    pub async fn get_document(&self, collection: &str, id: &str) -> Result<Option<Value>, Box<dyn Error + Send + Sync>> {
+        let url = format!("{}/{}/{}", self.documents_url(), collection, id);
+
+        let response = self.client.get(&url)
+            .bearer_auth(&self.access_token)
+            .send()
+            .await?;
+
+        match response.status() {
+            StatusCode::NOT_FOUND => Ok(None),
+            status if status.is_success() => Ok(Some(response.json::<Value>().await?)),
+            _ => {
+                let error = response.text().await.unwrap_or_default();
+                Err(error.into())
+            }
+        }
+    }
+
+    /// Applies `writes` in one atomic commit. Returns `false` when a
+    /// precondition failed because something else wrote first, so the caller
+    /// can read again and retry.
+    pub async fn commit(&self, writes: Vec<Value>) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        let url = format!("{}:commit", self.documents_url());
+
+        let response = self.client.post(&url)
+            .bearer_auth(&self.access_token)
+            .json(&json!({ "writes": writes }))
+            .send()
+            .await?;
+
+        if response.status().is_success() {
+            return Ok(true);
+        }
+        let error = response.text().await.unwrap_or_default();
+        let status = serde_json::from_str::<Value>(&error).ok().and_then(|body| body["error"]["status"].as_str().map(String::from));
+        match status.as_deref() {
+            Some("FAILED_PRECONDITION" | "ALREADY_EXISTS" | "ABORTED") => Ok(false),
+            _ => Err(error.into()),
+        }
+    }
+
+    /// Commits writes that are safe to repeat, such as deletes, with `last`
+    /// landing after everything in `first`. They go in one atomic commit when
+    /// they fit; otherwise `first` goes in batches and `last` in a commit of
+    /// its own, so a failure part way leaves `last` undone and the whole call
+    /// can simply be made again. Batches that Firestore aborts are retried.
+    pub async fn commit_repeatable(&self, first: Vec<Value>, last: Vec<Value>) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let batches: Vec<Vec<Value>> = if first.len() + last.len() <= MAX_COMMIT_WRITES {
+            vec![[first, last].concat()]
+        } else {
+            first.chunks(MAX_COMMIT_WRITES).map(<[Value]>::to_vec).chain([last]).collect()
+        };
+
+        for batch in batches {
+            let mut committed = false;
+            for _ in 0..BATCH_ATTEMPTS {
+                if self.commit(batch.clone()).await? {
+                    committed = true;
+                    break;
+                }
+                println!("🔁 Batch of {} writes was aborted; retrying", batch.len());
+            }
+            if !committed {
+                return Err(format!("A batch of {} writes kept being aborted", batch.len()).into());
+            }
+        }
+        Ok(())
     }
 
 /*     pub async fn test_connection(&self) {
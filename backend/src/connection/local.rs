@@ -0,0 +1,538 @@
+use async_trait::async_trait;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use sled::transaction::{ConflictableTransactionError, ConflictableTransactionResult, TransactionError, TransactionalTree};
+use sled::Transactional;
+use uuid::Uuid;
+
+use crate::connection::storage::{Storage, StorageResult};
+use crate::model::files::{file_type_from_name, File};
+use crate::model::folder::{entry_name_key, Folder};
+use crate::model::invite::{Invite, Redemption};
+use crate::model::project::{MemberChange, MemberUpdate, Project};
+use crate::model::revision::{now_millis, FileRevision};
+use crate::model::test_report::TestReport;
+use crate::model::user::{UserCred, UserDetails};
+
+#[derive(Debug, Serialize, Deserialize)]
+struct StoredUser {
+    details: UserDetails,
+    password: String,
+}
+
+/// Embedded storage backed by a sled database on disk, for running the
+/// backend without Firestore. Records are kept as JSON, one tree per collection.
+pub struct LocalStorage {
+    users: sled::Tree,
+    projects: sled::Tree,
+    files: sled::Tree,
+    folders: sled::Tree,
+    revisions: sled::Tree,
+    invites: sled::Tree,
+    test_reports: sled::Tree,
+    /// Which file or folder holds each name; keyed by `entry_name_key`.
+    entry_names: sled::Tree,
+}
+
+// Zero-padded so a prefix scan over one file's revisions comes back in order.
+fn revision_key(file_id: &str, revision: u64) -> String {
+    format!("{}/{:020}", file_id, revision)
+}
+
+fn get<T: DeserializeOwned>(tree: &sled::Tree, key: &str) -> StorageResult<Option<T>> {
+    match tree.get(key)? {
+        Some(bytes) => Ok(Some(serde_json::from_slice(&bytes)?)),
+        None => Ok(None),
+    }
+}
+
+fn put<T: Serialize>(tree: &sled::Tree, key: &str, value: &T) -> StorageResult<()> {
+    tree.insert(key, serde_json::to_vec(value)?)?;
+    Ok(())
+}
+
+fn filter<T: DeserializeOwned>(tree: &sled::Tree, matches: impl Fn(&T) -> bool) -> StorageResult<Vec<T>> {
+    let mut found = Vec::new();
+    for entry in tree.iter() {
+        let (_, bytes) = entry?;
+        let value: T = serde_json::from_slice(&bytes)?;
+        if matches(&value) {
+            found.push(value);
+        }
+    }
+    Ok(found)
+}
+
+fn abort<T>(message: String) -> ConflictableTransactionResult<T, String> {
+    Err(ConflictableTransactionError::Abort(message))
+}
+
+fn decode<T: DeserializeOwned>(bytes: &[u8]) -> ConflictableTransactionResult<T, String> {
+    serde_json::from_slice(bytes).or_else(|e| abort(e.to_string()))
+}
+
+fn encode<T: Serialize>(value: &T) -> ConflictableTransactionResult<Vec<u8>, String> {
+    serde_json::to_vec(value).or_else(|e| abort(e.to_string()))
+}
+
+// Moves an entry's name from `old` to `new` unless someone else holds `new`.
+fn move_name(names: &TransactionalTree, entry_id: Uuid, old: &str, new: &str) -> ConflictableTransactionResult<bool, String> {
+    if old == new {
+        return Ok(true);
+    }
+    if names.get(new)?.is_some_and(|holder| holder != entry_id.to_string().as_bytes()) {
+        return Ok(false);
+    }
+    names.remove(old)?;
+    names.insert(new, entry_id.to_string().as_bytes())?;
+    Ok(true)
+}
+
+fn transaction_error(e: TransactionError<String>) -> Box<dyn std::error::Error + Send + Sync> {
+    match e {
+        TransactionError::Abort(message) => message.into(),
+        TransactionError::Storage(e) => Box::new(e),
+    }
+}
+
+fn find<T: DeserializeOwned>(tree: &sled::Tree, matches: impl Fn(&T) -> bool) -> StorageResult<Option<T>> {
+    for entry in tree.iter() {
+        let (_, bytes) = entry?;
+        let value: T = serde_json::from_slice(&bytes)?;
+        if matches(&value) {
+            return Ok(Some(value));
+        }
+    }
+    Ok(None)
+}
+
+impl LocalStorage {
+    pub fn open(path: &str) -> StorageResult<Self> {
+        let db = sled::open(path)?;
+        Ok(Self {
+            users: db.open_tree("users")?,
+            projects: db.open_tree("projects")?,
+            files: db.open_tree("files")?,
+            folders: db.open_tree("folders")?,
+            revisions: db.open_tree("file_revisions")?,
+            invites: db.open_tree("invites")?,
+            test_reports: db.open_tree("test_reports")?,
+            entry_names: db.open_tree("entry_names")?,
+        })
+    }
+
+    // Gives a file the name and folder `place` picks, moving its name reservation with it.
+    fn place_file(&self, file_id: &str, place: impl Fn(&File) -> (String, Option<Uuid>)) -> StorageResult<bool> {
+        (&self.files, &self.entry_names)
+            .transaction(|(files, names)| {
+                let Some(bytes) = files.get(file_id)? else {
+                    return abort("File not found".to_string());
+                };
+                let mut file: File = decode(&bytes)?;
+                let (name, folder) = place(&file);
+                let old = entry_name_key(file.project_id, file.folder, &file.name);
+                if !move_name(names, file.id, &old, &entry_name_key(file.project_id, folder, &name))? {
+                    return Ok(false);
+                }
+
+                file.file_type = file_type_from_name(&name);
+                file.name = name;
+                file.folder = folder;
+                files.insert(file_id.as_bytes(), encode(&file)?)?;
+                Ok(true)
+            })
+            .map_err(transaction_error)
+    }
+
+    fn save_content(&self, file_id: &str, base_revision: Option<u64>, new_content: &str, authors: &[String]) -> StorageResult<Option<u64>> {
+        // The file and its new revision are written together, so concurrent
+        // saves each get a revision of their own.
+        (&self.files, &self.revisions)
+            .transaction(|(files, revisions)| {
+                let Some(bytes) = files.get(file_id)? else {
+                    return abort("File not found".to_string());
+                };
+                let mut file: File = match serde_json::from_slice(&bytes) {
+                    Ok(file) => file,
+                    Err(e) => return abort(e.to_string()),
+                };
+                if base_revision.is_some_and(|base| base != file.revision) {
+                    return Ok(None);
+                }
+                file.revision += 1;
+                file.content = new_content.to_string();
+                let revision = FileRevision {
+                    file_id: file.id,
+                    revision: file.revision,
+                    authors: authors.to_vec(),
+                    timestamp: now_millis(),
+                    content: new_content.to_string(),
+                };
+                let (file_bytes, revision_bytes) = match (serde_json::to_vec(&file), serde_json::to_vec(&revision)) {
+                    (Ok(file_bytes), Ok(revision_bytes)) => (file_bytes, revision_bytes),
+                    (Err(e), _) | (_, Err(e)) => return abort(e.to_string()),
+                };
+
+                files.insert(file_id.as_bytes(), file_bytes)?;
+                revisions.insert(revision_key(file_id, revision.revision).as_bytes(), revision_bytes)?;
+                Ok(Some(revision.revision))
+            })
+            .map_err(transaction_error)
+    }
+
+    fn update_project<F: FnOnce(&mut Project)>(&self, project_id: &str, update: F) -> StorageResult<()> {
+        let mut project: Project = get(&self.projects, project_id)?.ok_or("Project not found")?;
+        update(&mut project);
+        put(&self.projects, project_id, &project)
+    }
+}
+
+#[async_trait]
+impl Storage for LocalStorage {
+    async fn create_user(&self, details: &UserDetails, password_hash: &str) -> StorageResult<()> {
+        let user = StoredUser { details: details.clone(), password: password_hash.to_string() };
+        put(&self.users, &details.id.to_string(), &user)
+    }
+
+    async fn get_user_by_id(&self, id: &str) -> StorageResult<Option<UserDetails>> {
+        Ok(get::<StoredUser>(&self.users, id)?.map(|u| u.details))
+    }
+
+    async fn get_user_by_username(&self, username: &str) -> StorageResult<Option<UserDetails>> {
+        Ok(find(&self.users, |u: &StoredUser| u.details.username == username)?.map(|u| u.details))
+    }
+
+    async fn get_user_by_email(&self, email: &str) -> StorageResult<Option<UserDetails>> {
+        Ok(find(&self.users, |u: &StoredUser| u.details.email == email)?.map(|u| u.details))
+    }
+
+    async fn get_user_credentials(&self, username: &str) -> StorageResult<Option<UserCred>> {
+        Ok(find(&self.users, |u: &StoredUser| u.details.username == username)?
+            .map(|u| UserCred { id: u.details.id, password: u.password }))
+    }
+
+    async fn update_user_profile(&self, user_id: &str, display_name: &str, username: &str, email: &str) -> StorageResult<()> {
+        let mut user: StoredUser = get(&self.users, user_id)?.ok_or("User not found")?;
+        user.details.display_name = display_name.to_string();
+        user.details.username = username.to_string();
+        user.details.email = email.to_string();
+        put(&self.users, user_id, &user)
+    }
+
+    async fn update_user_password(&self, user_id: &str, password_hash: &str) -> StorageResult<()> {
+        let mut user: StoredUser = get(&self.users, user_id)?.ok_or("User not found")?;
+        user.password = password_hash.to_string();
+        put(&self.users, user_id, &user)
+    }
+
+    async fn create_project(&self, project: &Project) -> StorageResult<()> {
+        put(&self.projects, &project.id.to_string(), project)
+    }
+
+    async fn get_project(&self, project_id: &str) -> StorageResult<Option<Project>> {
+        get(&self.projects, project_id)
+    }
+
+    async fn update_project_metadata(&self, project_id: &str, name: &str, description: &str) -> StorageResult<()> {
+        self.update_project(project_id, |project| {
+            project.name = name.to_string();
+            project.description = description.to_string();
+        })
+    }
+
+    async fn update_member(&self, project_id: &str, user_id: Uuid, change: MemberChange) -> StorageResult<MemberUpdate> {
+        // Read and written in one transaction, so a concurrent redeem is kept.
+        self.projects
+            .transaction(|projects| {
+                let Some(bytes) = projects.get(project_id)? else {
+                    return Ok(MemberUpdate::ProjectNotFound);
+                };
+                let mut project: Project = match serde_json::from_slice(&bytes) {
+                    Ok(project) => project,
+                    Err(e) => return abort(e.to_string()),
+                };
+                if !project.change_member(user_id, change) {
+                    return Ok(MemberUpdate::NotMember);
+                }
+                let project_bytes = match serde_json::to_vec(&project) {
+                    Ok(bytes) => bytes,
+                    Err(e) => return abort(e.to_string()),
+                };
+                projects.insert(project_id.as_bytes(), project_bytes)?;
+                Ok(MemberUpdate::Updated(project))
+            })
+            .map_err(transaction_error)
+    }
+
+    async fn delete_project(&self, project_id: &str) -> StorageResult<bool> {
+        let files = filter(&self.files, |file: &File| file.project_id.to_string() == project_id)?;
+        let folders = self.list_folders(project_id).await?;
+        let invites = self.list_invites(project_id).await?;
+        let mut revisions = Vec::new();
+        for file in &files {
+            for key in self.revisions.scan_prefix(format!("{}/", file.id)).keys() {
+                revisions.push(key?);
+            }
+        }
+
+        (&self.projects, &self.files, &self.revisions, &self.folders, &self.entry_names, &self.invites, &self.test_reports, &self.users)
+            .transaction(|(projects, file_tree, revision_tree, folder_tree, names, invite_tree, test_reports, users)| {
+                let Some(bytes) = projects.remove(project_id.as_bytes())? else {
+                    return Ok(false);
+                };
+                let project: Project = decode(&bytes)?;
+                for file in &files {
+                    file_tree.remove(file.id.to_string().as_bytes())?;
+                    names.remove(entry_name_key(file.project_id, file.folder, &file.name).as_bytes())?;
+                }
+                for key in &revisions {
+                    revision_tree.remove(key)?;
+                }
+                for folder in &folders {
+                    folder_tree.remove(folder.id.to_string().as_bytes())?;
+                    names.remove(entry_name_key(folder.project_id, folder.parent, &folder.name).as_bytes())?;
+                }
+                for invite in &invites {
+                    invite_tree.remove(invite.code.as_bytes())?;
+                }
+                test_reports.remove(project_id.as_bytes())?;
+                for user_id in project.members.iter().chain([&project.owner]) {
+                    let user_key = user_id.to_string();
+                    let Some(bytes) = users.get(&user_key)? else { continue };
+                    let mut user: StoredUser = decode(&bytes)?;
+                    user.details.projects.retain(|id| *id != project.id);
+                    users.insert(user_key.as_bytes(), encode(&user)?)?;
+                }
+                Ok(true)
+            })
+            .map_err(transaction_error)
+    }
+
+    async fn create_file(&self, name: String, owner: Uuid, project_id: Uuid, folder: Option<Uuid>) -> StorageResult<Option<Uuid>> {
+        let file = File {
+            id: Uuid::new_v4(),
+            file_type: file_type_from_name(&name),
+            name,
+            content: String::new(),
+            owner,
+            project_id,
+            folder,
+            revision: 0,
+        };
+        let file_bytes = serde_json::to_vec(&file)?;
+
+        let name_key = entry_name_key(project_id, folder, &file.name);
+
+        // All trees change in one transaction, so no write survives without the others.
+        (&self.files, &self.projects, &self.entry_names)
+            .transaction(|(files, projects, names)| {
+                let key = project_id.to_string();
+                let Some(bytes) = projects.get(&key)? else {
+                    return abort("Project not found".to_string());
+                };
+                let mut project: Project = decode(&bytes)?;
+                if names.get(&name_key)?.is_some() {
+                    return Ok(None);
+                }
+                project.files.push(file.id);
+
+                files.insert(file.id.to_string().as_bytes(), file_bytes.as_slice())?;
+                projects.insert(key.as_bytes(), encode(&project)?)?;
+                names.insert(name_key.as_bytes(), file.id.to_string().as_bytes())?;
+                Ok(Some(file.id))
+            })
+            .map_err(transaction_error)
+    }
+
+    async fn get_file_by_id(&self, file_id: &str) -> StorageResult<Option<File>> {
+        get(&self.files, file_id)
+    }
+
+    async fn list_project_files(&self, project_id: &str) -> StorageResult<Vec<File>> {
+        filter(&self.files, |f: &File| f.project_id.to_string() == project_id)
+    }
+
+    async fn update_file_content(&self, file_id: &str, new_content: &str, authors: &[String]) -> StorageResult<u64> {
+        let saved = self.save_content(file_id, None, new_content, authors)?;
+        Ok(saved.expect("saves without a base revision always land"))
+    }
+
+    async fn update_file_content_from(&self, file_id: &str, base_revision: u64, new_content: &str, authors: &[String]) -> StorageResult<Option<u64>> {
+        self.save_content(file_id, Some(base_revision), new_content, authors)
+    }
+
+    async fn rename_file(&self, file_id: &str, name: &str) -> StorageResult<bool> {
+        self.place_file(file_id, |file| (name.to_string(), file.folder))
+    }
+
+    async fn move_file(&self, file_id: &str, folder: Option<Uuid>) -> StorageResult<bool> {
+        self.place_file(file_id, |file| (file.name.clone(), folder))
+    }
+
+    async fn delete_file(&self, file_id: &str) -> StorageResult<bool> {
+        let revisions = self.revisions.scan_prefix(format!("{}/", file_id)).keys().collect::<Result<Vec<_>, _>>()?;
+        (&self.files, &self.projects, &self.entry_names, &self.revisions)
+            .transaction(|(files, projects, names, revision_tree)| {
+                let Some(bytes) = files.remove(file_id.as_bytes())? else {
+                    return Ok(false);
+                };
+                let file: File = decode(&bytes)?;
+                let key = file.project_id.to_string();
+                if let Some(bytes) = projects.get(&key)? {
+                    let mut project: Project = decode(&bytes)?;
+                    project.files.retain(|id| *id != file.id);
+                    projects.insert(key.as_bytes(), encode(&project)?)?;
+                }
+                names.remove(entry_name_key(file.project_id, file.folder, &file.name).as_bytes())?;
+                for key in &revisions {
+                    revision_tree.remove(key)?;
+                }
+                Ok(true)
+            })
+            .map_err(transaction_error)
+    }
+
+    async fn create_folder(&self, folder: &Folder) -> StorageResult<bool> {
+        let name_key = entry_name_key(folder.project_id, folder.parent, &folder.name);
+        (&self.folders, &self.entry_names)
+            .transaction(|(folders, names)| {
+                if names.get(&name_key)?.is_some() {
+                    return Ok(false);
+                }
+                folders.insert(folder.id.to_string().as_bytes(), encode(folder)?)?;
+                names.insert(name_key.as_bytes(), folder.id.to_string().as_bytes())?;
+                Ok(true)
+            })
+            .map_err(transaction_error)
+    }
+
+    async fn list_folders(&self, project_id: &str) -> StorageResult<Vec<Folder>> {
+        filter(&self.folders, |f: &Folder| f.project_id.to_string() == project_id)
+    }
+
+    async fn update_folder(&self, folder_id: &str, name: &str, parent: Option<Uuid>) -> StorageResult<bool> {
+        (&self.folders, &self.entry_names)
+            .transaction(|(folders, names)| {
+                let Some(bytes) = folders.get(folder_id)? else {
+                    return abort("Folder not found".to_string());
+                };
+                let mut folder: Folder = decode(&bytes)?;
+                let old = entry_name_key(folder.project_id, folder.parent, &folder.name);
+                if !move_name(names, folder.id, &old, &entry_name_key(folder.project_id, parent, name))? {
+                    return Ok(false);
+                }
+
+                folder.name = name.to_string();
+                folder.parent = parent;
+                folders.insert(folder_id.as_bytes(), encode(&folder)?)?;
+                Ok(true)
+            })
+            .map_err(transaction_error)
+    }
+
+    async fn delete_folder(&self, folder_id: &str) -> StorageResult<bool> {
+        (&self.folders, &self.entry_names)
+            .transaction(|(folders, names)| {
+                let Some(bytes) = folders.remove(folder_id.as_bytes())? else {
+                    return Ok(false);
+                };
+                let folder: Folder = decode(&bytes)?;
+                names.remove(entry_name_key(folder.project_id, folder.parent, &folder.name).as_bytes())?;
+                Ok(true)
+            })
+            .map_err(transaction_error)
+    }
+
+    async fn get_file_revision(&self, file_id: &str, revision: u64) -> StorageResult<Option<FileRevision>> {
+        get(&self.revisions, &revision_key(file_id, revision))
+    }
+
+    async fn list_file_revisions(&self, file_id: &str) -> StorageResult<Vec<FileRevision>> {
+        self.revisions
+            .scan_prefix(format!("{}/", file_id))
+            .map(|entry| Ok(serde_json::from_slice(&entry?.1)?))
+            .collect()
+    }
+
+    async fn create_invite(&self, invite: &Invite) -> StorageResult<()> {
+        put(&self.invites, &invite.code, invite)
+    }
+
+    async fn get_invite(&self, code: &str) -> StorageResult<Option<Invite>> {
+        get(&self.invites, code)
+    }
+
+    async fn list_invites(&self, project_id: &str) -> StorageResult<Vec<Invite>> {
+        let mut invites = filter(&self.invites, |i: &Invite| i.project_id.to_string() == project_id)?;
+        invites.sort_by_key(|invite| std::cmp::Reverse(invite.created_at));
+        Ok(invites)
+    }
+
+    async fn redeem_invite(&self, code: &str, user_id: Uuid, now: u64) -> StorageResult<Redemption> {
+        (&self.invites, &self.projects, &self.users)
+            .transaction(|(invites, projects, users)| {
+                let Some(bytes) = invites.get(code)? else {
+                    return Ok(Redemption::InviteNotFound);
+                };
+                let mut invite: Invite = match serde_json::from_slice(&bytes) {
+                    Ok(invite) => invite,
+                    Err(e) => return abort(e.to_string()),
+                };
+                if invite.is_expired(now) {
+                    return Ok(Redemption::Expired);
+                }
+                if invite.is_used_up() {
+                    return Ok(Redemption::UsedUp);
+                }
+
+                let project_key = invite.project_id.to_string();
+                let Some(bytes) = projects.get(&project_key)? else {
+                    return Ok(Redemption::ProjectNotFound);
+                };
+                let mut project: Project = match serde_json::from_slice(&bytes) {
+                    Ok(project) => project,
+                    Err(e) => return abort(e.to_string()),
+                };
+                let user_key = user_id.to_string();
+                let Some(bytes) = users.get(&user_key)? else {
+                    return Ok(Redemption::UserNotFound);
+                };
+                let mut user: StoredUser = match serde_json::from_slice(&bytes) {
+                    Ok(user) => user,
+                    Err(e) => return abort(e.to_string()),
+                };
+                if let Some(role) = project.role_of(user_id) {
+                    return Ok(Redemption::AlreadyMember { project_id: project.id, role });
+                }
+
+                invite.uses += 1;
+                project.members.push(user_id);
+                project.roles.insert(user_id, invite.role);
+                if !user.details.projects.contains(&project.id) {
+                    user.details.projects.push(project.id);
+                }
+                let (invite_bytes, project_bytes, user_bytes) =
+                    match (serde_json::to_vec(&invite), serde_json::to_vec(&project), serde_json::to_vec(&user)) {
+                        (Ok(invite_bytes), Ok(project_bytes), Ok(user_bytes)) => (invite_bytes, project_bytes, user_bytes),
+                        (Err(e), _, _) | (_, Err(e), _) | (_, _, Err(e)) => return abort(e.to_string()),
+                    };
+
+                invites.insert(code.as_bytes(), invite_bytes)?;
+                projects.insert(project_key.as_bytes(), project_bytes)?;
+                users.insert(user_key.as_bytes(), user_bytes)?;
+                Ok(Redemption::Joined { project_id: project.id, role: invite.role })
+            })
+            .map_err(transaction_error)
+    }
+
+    async fn delete_invite(&self, code: &str) -> StorageResult<bool> {
+        Ok(self.invites.remove(code)?.is_some())
+    }
+
+    async fn save_test_report(&self, report: &TestReport) -> StorageResult<()> {
+        put(&self.test_reports, &report.project_id.to_string(), report)
+    }
+
+    async fn get_test_report(&self, project_id: &str) -> StorageResult<Option<TestReport>> {
+        get(&self.test_reports, project_id)
+    }
+}
@@ -0,0 +1,115 @@
+use argon2::password_hash::{rand_core::OsRng, PasswordHash, SaltString};
+use argon2::{Algorithm, Argon2, Params, PasswordHasher as _, PasswordVerifier as _, Version};
+use std::error::Error;
+use subtle::ConstantTimeEq;
+
+/// Outcome of checking a login attempt against a stored credential.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Verification {
+    Invalid,
+    /// The password matched. `needs_rehash` is set when the stored value is a
+    /// legacy plaintext record or was hashed with different parameters.
+    Valid { needs_rehash: bool },
+}
+
+/// Argon2id hashing for user passwords. Each hash gets its own random salt and
+/// is stored as a PHC string (`$argon2id$v=19$m=...,t=...,p=...$salt$hash`),
+/// so the parameters travel with the record.
+pub struct PasswordHasher {
+    params: Params,
+}
+
+impl PasswordHasher {
+    pub fn new(params: Params) -> Self {
+        Self { params }
+    }
+
+    /// Reads `ARGON2_MEMORY_KIB`, `ARGON2_ITERATIONS` and `ARGON2_PARALLELISM`,
+    /// falling back to the argon2 crate defaults (19 MiB, 2 passes, 1 lane).
+    pub fn from_env() -> Result<Self, Box<dyn Error + Send + Sync>> {
+        fn var(name: &str, default: u32) -> Result<u32, Box<dyn Error + Send + Sync>> {
+            match std::env::var(name) {
+                Ok(value) => value.parse().map_err(|_| format!("{} must be a number, got '{}'", name, value).into()),
+                Err(_) => Ok(default),
+            }
+        }
+
+        let params = Params::new(
+            var("ARGON2_MEMORY_KIB", Params::DEFAULT_M_COST)?,
+            var("ARGON2_ITERATIONS", Params::DEFAULT_T_COST)?,
+            var("ARGON2_PARALLELISM", Params::DEFAULT_P_COST)?,
+            None,
+        )
+        .map_err(|e| format!("Invalid Argon2 parameters: {}", e))?;
+
+        Ok(Self::new(params))
+    }
+
+    fn argon2(&self) -> Argon2<'_> {
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, self.params.clone())
+    }
+
+    pub fn hash(&self, password: &str) -> Result<String, Box<dyn Error + Send + Sync>> {
+        let salt = SaltString::generate(&mut OsRng);
+        let hash = self
+            .argon2()
+            .hash_password(password.as_bytes(), &salt)
+            .map_err(|e| format!("Failed to hash password: {}", e))?;
+        Ok(hash.to_string())
+    }
+
+    /// Checks `password` against a stored PHC hash, or against a plaintext
+    /// value left over from before passwords were hashed. Both comparisons
+    /// run in constant time.
+    pub fn verify(&self, password: &str, stored: &str) -> Verification {
+        let Ok(hash) = PasswordHash::new(stored) else {
+            return if bool::from(password.as_bytes().ct_eq(stored.as_bytes())) {
+                Verification::Valid { needs_rehash: true }
+            } else {
+                Verification::Invalid
+            };
+        };
+
+        if self.argon2().verify_password(password.as_bytes(), &hash).is_err() {
+            return Verification::Invalid;
+        }
+
+        let current = hash.algorithm.as_str() == Algorithm::Argon2id.ident().as_str()
+            && Params::try_from(&hash).is_ok_and(|params| {
+                params.m_cost() == self.params.m_cost()
+                    && params.t_cost() == self.params.t_cost()
+                    && params.p_cost() == self.params.p_cost()
+            });
+        Verification::Valid { needs_rehash: !current }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hasher(t_cost: u32) -> PasswordHasher {
+        PasswordHasher::new(Params::new(1024, t_cost, 1, None).unwrap())
+    }
+
+    #[test]
+    fn hashes_are_salted_argon2id() {
+        let hasher = hasher(1);
+        let first = hasher.hash("hunter2").unwrap();
+        let second = hasher.hash("hunter2").unwrap();
+
+        assert!(first.starts_with("$argon2id$"), "{}", first);
+        assert_ne!(first, second);
+        assert_eq!(hasher.verify("hunter2", &first), Verification::Valid { needs_rehash: false });
+        assert_eq!(hasher.verify("hunter3", &first), Verification::Invalid);
+    }
+
+    #[test]
+    fn plaintext_and_outdated_hashes_need_rehash() {
+        assert_eq!(hasher(1).verify("hunter2", "hunter2"), Verification::Valid { needs_rehash: true });
+        assert_eq!(hasher(1).verify("hunter2", "hunter"), Verification::Invalid);
+
+        let old = hasher(1).hash("hunter2").unwrap();
+        assert_eq!(hasher(2).verify("hunter2", &old), Verification::Valid { needs_rehash: true });
+    }
+}
@@ -1,22 +1,168 @@
-use anyhow::Result;
-use reqwest::Client;
-use serde_json::json;
-
-pub struct RealtimeDatabaseService {
-    pub client: Client,
-    pub project_id: String,
-    pub access_token: String,
-}
-
-impl RealtimeDatabaseService {
-    pub async fn new(project_id: &str, access_token: &str) -> Self {
-        let client = Client::new();
-        Self {
-            client,
-            project_id: project_id.to_string(),
-            access_token: access_token.to_string(),
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use tokio::sync::{broadcast, Mutex};
+use uuid::Uuid;
+
+use crate::connection::document::{AppliedEdit, TextOperation};
+
+// Events broadcast to a room are buffered per subscriber; a client that falls
+// this far behind is sent the whole document again.
+const ROOM_CHANNEL_CAPACITY: usize = 256;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ActiveUser {
+    pub user_id: String,
+    pub user_name: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FileUpdate {
+    pub file_id: String,
+    pub content: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OperationPayload {
+    pub file_id: String,
+    /// Revision the operation was made against (client) or produced (server).
+    pub revision: u64,
+    pub operation: TextOperation,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DocumentSnapshot {
+    pub file_id: String,
+    pub revision: u64,
+    pub content: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Ack {
+    pub file_id: String,
+    pub revision: u64,
+}
+
+/// Tells a client one of its messages was refused.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CollabError {
+    pub file_id: String,
+    pub message: String,
+}
+
+/// Messages sent by the editor, framed as `{ "event": ..., "data": ... }`.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "event", content = "data")]
+pub enum ClientEvent {
+    /// The joining user's id comes from the connection's access token; any
+    /// `userId` the client sends is ignored.
+    #[serde(rename = "join-file", rename_all = "camelCase")]
+    Join {
+        project_id: String,
+        file_id: String,
+        user_name: String,
+    },
+    #[serde(rename = "leave-file", rename_all = "camelCase")]
+    Leave {
+        file_id: String,
+    },
+    #[serde(rename = "update-file", alias = "file-updated")]
+    Update(FileUpdate),
+    #[serde(rename = "operation")]
+    Operation(OperationPayload),
+}
+
+/// Messages pushed to every editor in a file room.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", content = "data", rename_all = "kebab-case")]
+pub enum ServerEvent {
+    FileUpdated(FileUpdate),
+    UserJoined(ActiveUser),
+    UserLeft(ActiveUser),
+    ActiveUsers(Vec<ActiveUser>),
+    Document(DocumentSnapshot),
+    Operation(OperationPayload),
+    Ack(Ack),
+    Error(CollabError),
+}
+
+/// An event together with the connection that caused it, so a sender can skip its own echo.
+pub type RoomMessage = (Uuid, ServerEvent);
+
+struct Room {
+    users: HashMap<Uuid, ActiveUser>,
+    tx: broadcast::Sender<RoomMessage>,
+}
+
+/// Tracks who is connected to each file and fans events out to them.
+#[derive(Default)]
+pub struct CollabHub {
+    rooms: Mutex<HashMap<String, Room>>,
+}
+
+impl CollabHub {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a connection to a file room, announces it to the others and
+    /// returns a receiver for the room plus the current presence list.
+    pub async fn join(
+        &self,
+        file_id: &str,
+        conn_id: Uuid,
+        user: ActiveUser,
+    ) -> (broadcast::Receiver<RoomMessage>, Vec<ActiveUser>) {
+        let mut rooms = self.rooms.lock().await;
+        let room = rooms.entry(file_id.to_string()).or_insert_with(|| Room {
+            users: HashMap::new(),
+            tx: broadcast::channel(ROOM_CHANNEL_CAPACITY).0,
+        });
+
+        let rx = room.tx.subscribe();
+        room.users.insert(conn_id, user.clone());
+        let _ = room.tx.send((conn_id, ServerEvent::UserJoined(user)));
+
+        (rx, room.users.values().cloned().collect())
+    }
+
+    /// Removes a connection from a file room, dropping the room once it is empty.
+    /// Returns `true` when this was the last connection.
+    pub async fn leave(&self, file_id: &str, conn_id: Uuid) -> bool {
+        let mut rooms = self.rooms.lock().await;
+        let Some(room) = rooms.get_mut(file_id) else {
+            return false;
+        };
+        let Some(user) = room.users.remove(&conn_id) else {
+            return false;
+        };
+
+        if room.users.is_empty() {
+            rooms.remove(file_id);
+            true
+        } else {
+            let _ = room.tx.send((conn_id, ServerEvent::UserLeft(user)));
+            false
         }
     }
 
+    pub async fn broadcast(&self, file_id: &str, conn_id: Uuid, event: ServerEvent) {
+        if let Some(room) = self.rooms.lock().await.get(file_id) {
+            let _ = room.tx.send((conn_id, event));
+        }
+    }
 
+    /// Sends the rebased operation to OT-aware editors and the merged text to everyone else.
+    pub async fn broadcast_edit(&self, file_id: &str, conn_id: Uuid, edit: AppliedEdit) {
+        let operation = OperationPayload { file_id: file_id.to_string(), revision: edit.revision, operation: edit.operation };
+        self.broadcast(file_id, conn_id, ServerEvent::Operation(operation)).await;
+
+        let update = FileUpdate { file_id: file_id.to_string(), content: edit.content };
+        self.broadcast(file_id, conn_id, ServerEvent::FileUpdated(update)).await;
+    }
 }
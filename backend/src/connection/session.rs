@@ -0,0 +1,226 @@
+use axum::{
+    extract::FromRequestParts,
+    http::{header::{AUTHORIZATION, UPGRADE}, request::Parts, StatusCode},
+    Json,
+};
+use jsonwebtoken::{decode, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use rand_core::{OsRng, RngCore};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::{Arc, Mutex};
+use uuid::Uuid;
+
+use crate::model::revision::now_millis;
+
+const ACCESS_TOKEN_TTL_SECS: u64 = 15 * 60;
+const REFRESH_TOKEN_TTL_SECS: u64 = 7 * 24 * 60 * 60;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TokenKind {
+    Access,
+    Refresh,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Claims {
+    pub sub: Uuid,
+    pub username: String,
+    pub kind: TokenKind,
+    pub jti: Uuid,
+    pub iat: u64,
+    pub exp: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TokenPair {
+    pub access_token: String,
+    pub refresh_token: String,
+    pub token_type: &'static str,
+    /// Lifetime of the access token in seconds.
+    pub expires_in: u64,
+}
+
+/// Issues and checks HS256-signed session tokens. Access tokens are short-lived
+/// and stateless; refresh tokens are single use and can be revoked on logout.
+pub struct TokenService {
+    encoding: EncodingKey,
+    decoding: DecodingKey,
+    // jti -> expiry, so entries can be dropped once the token would be rejected anyway
+    revoked: Mutex<HashMap<Uuid, u64>>,
+}
+
+fn now_secs() -> u64 {
+    now_millis() / 1000
+}
+
+impl TokenService {
+    pub fn new(secret: &[u8]) -> Self {
+        Self {
+            encoding: EncodingKey::from_secret(secret),
+            decoding: DecodingKey::from_secret(secret),
+            revoked: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Signs with `JWT_SECRET`. Without it a random key is used, which means
+    /// every session ends when the server restarts.
+    pub fn from_env() -> Self {
+        match std::env::var("JWT_SECRET") {
+            Ok(secret) if !secret.is_empty() => Self::new(secret.as_bytes()),
+            _ => {
+                eprintln!("⚠️ JWT_SECRET is not set; using a random key for this run");
+                let mut secret = [0u8; 32];
+                OsRng.fill_bytes(&mut secret);
+                Self::new(&secret)
+            }
+        }
+    }
+
+    fn sign(&self, user_id: Uuid, username: &str, kind: TokenKind, ttl: u64) -> Result<String, Box<dyn Error + Send + Sync>> {
+        let iat = now_secs();
+        let claims = Claims { sub: user_id, username: username.to_string(), kind, jti: Uuid::new_v4(), iat, exp: iat + ttl };
+        Ok(encode(&Header::new(Algorithm::HS256), &claims, &self.encoding)?)
+    }
+
+    pub fn issue(&self, user_id: Uuid, username: &str) -> Result<TokenPair, Box<dyn Error + Send + Sync>> {
+        Ok(TokenPair {
+            access_token: self.sign(user_id, username, TokenKind::Access, ACCESS_TOKEN_TTL_SECS)?,
+            refresh_token: self.sign(user_id, username, TokenKind::Refresh, REFRESH_TOKEN_TTL_SECS)?,
+            token_type: "Bearer",
+            expires_in: ACCESS_TOKEN_TTL_SECS,
+        })
+    }
+
+    /// Decodes `token`, rejecting bad signatures, expired tokens, the wrong
+    /// kind of token and revoked refresh tokens.
+    pub fn verify(&self, token: &str, kind: TokenKind) -> Option<Claims> {
+        let mut validation = Validation::new(Algorithm::HS256);
+        validation.leeway = 0;
+        let claims = decode::<Claims>(token, &self.decoding, &validation).ok()?.claims;
+
+        if claims.kind != kind || self.revoked.lock().unwrap().contains_key(&claims.jti) {
+            return None;
+        }
+        Some(claims)
+    }
+
+    /// Marks a token as unusable until it expires. Returns false if it
+    /// already was.
+    pub fn revoke(&self, claims: &Claims) -> bool {
+        let now = now_secs();
+        let mut revoked = self.revoked.lock().unwrap();
+        revoked.retain(|_, exp| *exp >= now);
+        revoked.insert(claims.jti, claims.exp).is_none()
+    }
+
+    /// Exchanges a refresh token for a new pair. The old refresh token is
+    /// revoked, so each one can be used once, even by concurrent requests.
+    pub fn refresh(&self, refresh_token: &str) -> Option<TokenPair> {
+        let claims = self.verify(refresh_token, TokenKind::Refresh)?;
+        if !self.revoke(&claims) {
+            return None;
+        }
+        self.issue(claims.sub, &claims.username).ok()
+    }
+}
+
+/// The caller, as proven by an access token. Read from `Authorization: Bearer`,
+/// or from an `access_token` query parameter for WebSocket upgrades, which
+/// browsers cannot attach headers to.
+#[derive(Debug, Clone)]
+pub struct AuthUser {
+    pub user_id: Uuid,
+    pub username: String,
+}
+
+fn unauthorized(message: &str) -> (StatusCode, Json<Value>) {
+    (StatusCode::UNAUTHORIZED, Json(json!({ "error": message })))
+}
+
+fn bearer_token(parts: &Parts) -> Option<&str> {
+    if let Some(header) = parts.headers.get(AUTHORIZATION) {
+        return header.to_str().ok()?.strip_prefix("Bearer ");
+    }
+    // Tokens in URLs end up in logs, so plain requests must use the header.
+    let upgrade = parts.headers.get(UPGRADE)?;
+    if !upgrade.as_bytes().eq_ignore_ascii_case(b"websocket") {
+        return None;
+    }
+    parts
+        .uri
+        .query()?
+        .split('&')
+        .find_map(|pair| pair.strip_prefix("access_token="))
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for AuthUser {
+    type Rejection = (StatusCode, Json<Value>);
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let tokens = parts.extensions.get::<Arc<TokenService>>().ok_or((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(json!({ "error": "Token service is not configured" })),
+        ))?;
+
+        let token = bearer_token(parts).ok_or_else(|| unauthorized("Missing access token"))?;
+        let claims = tokens
+            .verify(token, TokenKind::Access)
+            .ok_or_else(|| unauthorized("Invalid or expired access token"))?;
+
+        Ok(AuthUser { user_id: claims.sub, username: claims.username })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn refresh_tokens_are_single_use() {
+        let tokens = TokenService::new(b"secret");
+        let pair = tokens.issue(Uuid::new_v4(), "ada").unwrap();
+
+        assert!(tokens.verify(&pair.access_token, TokenKind::Access).is_some());
+        assert!(tokens.verify(&pair.access_token, TokenKind::Refresh).is_none(), "kinds are not interchangeable");
+
+        let next = tokens.refresh(&pair.refresh_token).expect("first refresh succeeds");
+        assert!(tokens.refresh(&pair.refresh_token).is_none(), "old refresh token was revoked");
+        assert!(tokens.verify(&next.refresh_token, TokenKind::Refresh).is_some());
+    }
+
+    #[test]
+    fn concurrent_refreshes_get_one_pair() {
+        let tokens = TokenService::new(b"secret");
+        let pair = tokens.issue(Uuid::new_v4(), "ada").unwrap();
+
+        let refreshed = std::thread::scope(|scope| {
+            let attempts: Vec<_> = (0..8).map(|_| scope.spawn(|| tokens.refresh(&pair.refresh_token))).collect();
+            attempts.into_iter().filter_map(|attempt| attempt.join().unwrap()).count()
+        });
+        assert_eq!(refreshed, 1);
+    }
+
+    #[test]
+    fn query_tokens_only_open_websockets() {
+        let parts = |upgrade: Option<&str>| {
+            let mut request = axum::http::Request::builder().uri("/files/1?access_token=abc");
+            if let Some(upgrade) = upgrade {
+                request = request.header(UPGRADE, upgrade);
+            }
+            request.body(()).unwrap().into_parts().0
+        };
+        assert_eq!(bearer_token(&parts(Some("websocket"))), Some("abc"));
+        assert_eq!(bearer_token(&parts(Some("WebSocket"))), Some("abc"));
+        assert_eq!(bearer_token(&parts(Some("h2c"))), None);
+        assert_eq!(bearer_token(&parts(None)), None);
+    }
+
+    #[test]
+    fn tokens_from_another_key_are_rejected() {
+        let pair = TokenService::new(b"one").issue(Uuid::new_v4(), "ada").unwrap();
+        assert!(TokenService::new(b"two").verify(&pair.access_token, TokenKind::Access).is_none());
+    }
+}
@@ -0,0 +1,471 @@
+use async_trait::async_trait;
+use std::error::Error;
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::connection::firebase::FirebaseService;
+use crate::connection::local::LocalStorage;
+use crate::model::files::File;
+use crate::model::folder::Folder;
+use crate::model::invite::{Invite, Redemption};
+use crate::model::project::{MemberChange, MemberUpdate, Project};
+use crate::model::revision::FileRevision;
+use crate::model::test_report::TestReport;
+use crate::model::user::{UserCred, UserDetails};
+
+pub type StorageResult<T> = Result<T, Box<dyn Error + Send + Sync>>;
+
+/// Persistence for users, projects and files. Implemented by the Firestore
+/// client and by an embedded local store for offline development.
+#[async_trait]
+pub trait Storage: Send + Sync {
+    // Users
+    /// `password_hash` is stored as given; hashing happens before it gets here.
+    async fn create_user(&self, details: &UserDetails, password_hash: &str) -> StorageResult<()>;
+    async fn get_user_by_id(&self, id: &str) -> StorageResult<Option<UserDetails>>;
+    async fn get_user_by_username(&self, username: &str) -> StorageResult<Option<UserDetails>>;
+    async fn get_user_by_email(&self, email: &str) -> StorageResult<Option<UserDetails>>;
+    async fn get_user_credentials(&self, username: &str) -> StorageResult<Option<UserCred>>;
+    async fn update_user_password(&self, user_id: &str, password_hash: &str) -> StorageResult<()>;
+    async fn update_user_profile(&self, user_id: &str, display_name: &str, username: &str, email: &str) -> StorageResult<()>;
+
+    // Projects
+    async fn create_project(&self, project: &Project) -> StorageResult<()>;
+    async fn get_project(&self, project_id: &str) -> StorageResult<Option<Project>>;
+    async fn update_project_metadata(&self, project_id: &str, name: &str, description: &str) -> StorageResult<()>;
+    /// Sets a member's role or removes them, reading and writing the project
+    /// as one atomic step so members joining meanwhile are kept.
+    async fn update_member(&self, project_id: &str, user_id: Uuid, change: MemberChange) -> StorageResult<MemberUpdate>;
+    /// Deletes the project with its files, folders and test report. Returns `false` if it did not exist.
+    async fn delete_project(&self, project_id: &str) -> StorageResult<bool>;
+
+    // Files
+    /// Creates an empty file and links it into the project's `files` as one
+    /// atomic write. `folder` is the parent folder, `None` for the project root.
+    /// Names are reserved in the same write: returns `None` if a file or
+    /// folder in `folder` already has it.
+    async fn create_file(&self, name: String, owner: Uuid, project_id: Uuid, folder: Option<Uuid>) -> StorageResult<Option<Uuid>>;
+    async fn get_file_by_id(&self, file_id: &str) -> StorageResult<Option<File>>;
+    async fn list_project_files(&self, project_id: &str) -> StorageResult<Vec<File>>;
+    /// Saves new content as the next revision and returns its number.
+    async fn update_file_content(&self, file_id: &str, new_content: &str, authors: &[String]) -> StorageResult<u64>;
+    /// Like `update_file_content`, but only if the file is still at
+    /// `base_revision`; returns `None`, saving nothing, once it has moved on.
+    async fn update_file_content_from(&self, file_id: &str, base_revision: u64, new_content: &str, authors: &[String]) -> StorageResult<Option<u64>>;
+    /// Returns `false` if the name is already taken in the file's folder.
+    async fn rename_file(&self, file_id: &str, name: &str) -> StorageResult<bool>;
+    /// Returns `false` if the file's name is already taken in `folder`.
+    async fn move_file(&self, file_id: &str, folder: Option<Uuid>) -> StorageResult<bool>;
+    /// Deletes the file with its saved revisions and unlinks it from the
+    /// project's `files` in the same write. Returns `false` if it did not exist.
+    async fn delete_file(&self, file_id: &str) -> StorageResult<bool>;
+
+    // Folders
+    /// Returns `false` if the name is already taken in the parent folder.
+    async fn create_folder(&self, folder: &Folder) -> StorageResult<bool>;
+    async fn list_folders(&self, project_id: &str) -> StorageResult<Vec<Folder>>;
+    /// Sets a folder's name and parent together; used for both rename and move.
+    /// Returns `false` if the name is already taken in `parent`.
+    async fn update_folder(&self, folder_id: &str, name: &str, parent: Option<Uuid>) -> StorageResult<bool>;
+    /// Deletes only the folder record; callers remove its contents first.
+    async fn delete_folder(&self, folder_id: &str) -> StorageResult<bool>;
+
+    // File history
+    async fn get_file_revision(&self, file_id: &str, revision: u64) -> StorageResult<Option<FileRevision>>;
+    async fn list_file_revisions(&self, file_id: &str) -> StorageResult<Vec<FileRevision>>;
+
+    // Invites
+    async fn create_invite(&self, invite: &Invite) -> StorageResult<()>;
+    async fn get_invite(&self, code: &str) -> StorageResult<Option<Invite>>;
+    async fn list_invites(&self, project_id: &str) -> StorageResult<Vec<Invite>>;
+    /// Counts one use of the invite, adds the user to its project with the
+    /// invite's role and lists the project on the user, as one atomic step.
+    async fn redeem_invite(&self, code: &str, user_id: Uuid, now: u64) -> StorageResult<Redemption>;
+    /// Returns `false` if there was no such invite.
+    async fn delete_invite(&self, code: &str) -> StorageResult<bool>;
+
+    // Test runs
+    /// Replaces the project's last test report.
+    async fn save_test_report(&self, report: &TestReport) -> StorageResult<()>;
+    async fn get_test_report(&self, project_id: &str) -> StorageResult<Option<TestReport>>;
+}
+
+/// Picks the storage backend from `STORAGE_BACKEND` (`firestore`, the default, or `local`).
+/// The local store lives at `LOCAL_STORAGE_PATH`, defaulting to `data/local.db`.
+pub async fn connect() -> StorageResult<Arc<dyn Storage>> {
+    match std::env::var("STORAGE_BACKEND").as_deref() {
+        Ok("local") => {
+            let path = std::env::var("LOCAL_STORAGE_PATH").unwrap_or_else(|_| "data/local.db".to_string());
+            println!("✅ Using local storage at {}", path);
+            Ok(Arc::new(LocalStorage::open(&path)?))
+        }
+        Ok("firestore") | Err(_) => Ok(Arc::new(FirebaseService::new().await)),
+        Ok(other) => Err(format!("Unknown STORAGE_BACKEND '{}'", other).into()),
+    }
+}
+
+#[async_trait]
+impl Storage for FirebaseService {
+    async fn create_user(&self, details: &UserDetails, password_hash: &str) -> StorageResult<()> {
+        FirebaseService::create_user(self, details, password_hash).await
+    }
+
+    async fn get_user_by_id(&self, id: &str) -> StorageResult<Option<UserDetails>> {
+        FirebaseService::get_user_by_id(self, id).await
+    }
+
+    async fn get_user_by_username(&self, username: &str) -> StorageResult<Option<UserDetails>> {
+        FirebaseService::get_user_by_username(self, username).await
+    }
+
+    async fn get_user_by_email(&self, email: &str) -> StorageResult<Option<UserDetails>> {
+        FirebaseService::get_user_by_email(self, email).await
+    }
+
+    async fn get_user_credentials(&self, username: &str) -> StorageResult<Option<UserCred>> {
+        FirebaseService::get_user_credentials(self, username).await
+    }
+
+    async fn update_user_password(&self, user_id: &str, password_hash: &str) -> StorageResult<()> {
+        FirebaseService::update_user_password(self, user_id, password_hash).await
+    }
+
+    async fn update_user_profile(&self, user_id: &str, display_name: &str, username: &str, email: &str) -> StorageResult<()> {
+        FirebaseService::update_user_profile(self, user_id, display_name, username, email).await
+    }
+
+    async fn create_project(&self, project: &Project) -> StorageResult<()> {
+        FirebaseService::create_project(self, project).await
+    }
+
+    async fn get_project(&self, project_id: &str) -> StorageResult<Option<Project>> {
+        FirebaseService::get_project(self, project_id).await
+    }
+
+    async fn update_project_metadata(&self, project_id: &str, name: &str, description: &str) -> StorageResult<()> {
+        FirebaseService::update_project_metadata(self, project_id, name, description).await
+    }
+
+    async fn update_member(&self, project_id: &str, user_id: Uuid, change: MemberChange) -> StorageResult<MemberUpdate> {
+        FirebaseService::update_member(self, project_id, user_id, change).await
+    }
+
+    async fn delete_project(&self, project_id: &str) -> StorageResult<bool> {
+        FirebaseService::delete_project(self, project_id).await
+    }
+
+    async fn create_file(&self, name: String, owner: Uuid, project_id: Uuid, folder: Option<Uuid>) -> StorageResult<Option<Uuid>> {
+        FirebaseService::create_file(self, name, owner, project_id, folder).await
+    }
+
+    async fn get_file_by_id(&self, file_id: &str) -> StorageResult<Option<File>> {
+        FirebaseService::get_file_by_id(self, file_id).await
+    }
+
+    async fn list_project_files(&self, project_id: &str) -> StorageResult<Vec<File>> {
+        FirebaseService::list_project_files(self, project_id).await
+    }
+
+    async fn update_file_content(&self, file_id: &str, new_content: &str, authors: &[String]) -> StorageResult<u64> {
+        FirebaseService::update_file_content(self, file_id, new_content, authors).await
+    }
+
+    async fn update_file_content_from(&self, file_id: &str, base_revision: u64, new_content: &str, authors: &[String]) -> StorageResult<Option<u64>> {
+        FirebaseService::update_file_content_from(self, file_id, base_revision, new_content, authors).await
+    }
+
+    async fn rename_file(&self, file_id: &str, name: &str) -> StorageResult<bool> {
+        FirebaseService::rename_file(self, file_id, name).await
+    }
+
+    async fn move_file(&self, file_id: &str, folder: Option<Uuid>) -> StorageResult<bool> {
+        FirebaseService::move_file(self, file_id, folder).await
+    }
+
+    async fn delete_file(&self, file_id: &str) -> StorageResult<bool> {
+        FirebaseService::delete_file(self, file_id).await
+    }
+
+    async fn create_folder(&self, folder: &Folder) -> StorageResult<bool> {
+        FirebaseService::create_folder(self, folder).await
+    }
+
+    async fn list_folders(&self, project_id: &str) -> StorageResult<Vec<Folder>> {
+        FirebaseService::list_folders(self, project_id).await
+    }
+
+    async fn update_folder(&self, folder_id: &str, name: &str, parent: Option<Uuid>) -> StorageResult<bool> {
+        FirebaseService::update_folder(self, folder_id, name, parent).await
+    }
+
+    async fn delete_folder(&self, folder_id: &str) -> StorageResult<bool> {
+        FirebaseService::delete_folder(self, folder_id).await
+    }
+
+    async fn get_file_revision(&self, file_id: &str, revision: u64) -> StorageResult<Option<FileRevision>> {
+        FirebaseService::get_file_revision(self, file_id, revision).await
+    }
+
+    async fn list_file_revisions(&self, file_id: &str) -> StorageResult<Vec<FileRevision>> {
+        FirebaseService::list_file_revisions(self, file_id).await
+    }
+
+    async fn create_invite(&self, invite: &Invite) -> StorageResult<()> {
+        FirebaseService::create_invite(self, invite).await
+    }
+
+    async fn get_invite(&self, code: &str) -> StorageResult<Option<Invite>> {
+        FirebaseService::get_invite(self, code).await
+    }
+
+    async fn list_invites(&self, project_id: &str) -> StorageResult<Vec<Invite>> {
+        FirebaseService::list_invites(self, project_id).await
+    }
+
+    async fn redeem_invite(&self, code: &str, user_id: Uuid, now: u64) -> StorageResult<Redemption> {
+        FirebaseService::redeem_invite(self, code, user_id, now).await
+    }
+
+    async fn delete_invite(&self, code: &str) -> StorageResult<bool> {
+        FirebaseService::delete_invite(self, code).await
+    }
+
+    async fn save_test_report(&self, report: &TestReport) -> StorageResult<()> {
+        FirebaseService::save_test_report(self, report).await
+    }
+
+    async fn get_test_report(&self, project_id: &str) -> StorageResult<Option<TestReport>> {
+        FirebaseService::get_test_report(self, project_id).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::executor::runner::Stage;
+    use crate::model::project::Role;
+    use crate::test_support::{auth, firestore};
+
+    async fn new_user(storage: &dyn Storage, name: &str) -> Uuid {
+        let details = UserDetails {
+            id: Uuid::new_v4(),
+            display_name: name.into(),
+            username: name.into(),
+            email: format!("{}@example.com", name),
+            projects: vec![],
+        };
+        storage.create_user(&details, "hash").await.unwrap();
+        details.id
+    }
+
+    /// Exercises every method the same way against a backend, so both stay interchangeable.
+    async fn conforms(storage: Arc<dyn Storage>) {
+        let details = UserDetails {
+            id: Uuid::new_v4(),
+            display_name: "Ada".into(),
+            username: "ada".into(),
+            email: "ada@example.com".into(),
+            projects: vec![],
+        };
+        storage.create_user(&details, "hash").await.unwrap();
+        let id = details.id.to_string();
+        assert_eq!(storage.get_user_by_username("ada").await.unwrap().unwrap().id, details.id);
+        assert_eq!(storage.get_user_by_email("ada@example.com").await.unwrap().unwrap().id, details.id);
+        assert!(storage.get_user_by_username("grace").await.unwrap().is_none());
+        storage.update_user_password(&id, "new hash").await.unwrap();
+        assert_eq!(storage.get_user_credentials("ada").await.unwrap().unwrap().password, "new hash");
+        storage.update_user_profile(&id, "Ada L.", "lovelace", "ada@lovelace.dev").await.unwrap();
+        let project_id = auth::project_with_members(storage.as_ref(), details.id, &[]).await;
+        let user = storage.get_user_by_id(&id).await.unwrap().unwrap();
+        assert_eq!((user.username.as_str(), user.email.as_str()), ("lovelace", "ada@lovelace.dev"));
+
+        let project_key = project_id.to_string();
+        storage.update_project_metadata(&project_key, "renamed", "about").await.unwrap();
+        let project = storage.get_project(&project_key).await.unwrap().unwrap();
+        assert_eq!((project.name.as_str(), project.description.as_str()), ("renamed", "about"));
+
+        assert!(storage.create_file("orphan.py".into(), details.id, Uuid::new_v4(), None).await.is_err());
+        let folder = Folder { id: Uuid::new_v4(), project_id, parent: None, name: "src".into(), created_by: details.id };
+        assert!(storage.create_folder(&folder).await.unwrap());
+        assert!(storage.update_folder(&folder.id.to_string(), "lib", None).await.unwrap());
+        let folders = storage.list_folders(&project_key).await.unwrap();
+        assert_eq!(folders.iter().map(|f| f.name.as_str()).collect::<Vec<_>>(), ["lib"]);
+
+        let file_id = storage.create_file("main.py".into(), details.id, project_id, Some(folder.id)).await.unwrap().unwrap();
+        let other_id = storage.create_file("util.py".into(), details.id, project_id, None).await.unwrap().unwrap();
+        let file_key = file_id.to_string();
+        assert_eq!(storage.update_file_content(&file_key, "print(1)", &["ada".into()]).await.unwrap(), 1);
+        assert_eq!(storage.update_file_content(&file_key, "print(2)", &[]).await.unwrap(), 2);
+        assert!(storage.rename_file(&file_key, "main.rs").await.unwrap());
+        assert!(storage.move_file(&file_key, None).await.unwrap());
+        let file = storage.get_file_by_id(&file_key).await.unwrap().unwrap();
+        assert_eq!((file.name.as_str(), file.file_type.as_str(), file.folder, file.revision), ("main.rs", "Rust", None, 2));
+        assert_eq!(storage.list_project_files(&project_key).await.unwrap().len(), 2);
+        let revisions = storage.list_file_revisions(&file_key).await.unwrap();
+        assert_eq!(revisions.iter().map(|r| (r.revision, r.content.as_str())).collect::<Vec<_>>(), [(1, "print(1)"), (2, "print(2)")]);
+        assert_eq!(storage.get_file_revision(&file_key, 1).await.unwrap().unwrap().authors, ["ada"]);
+
+        // Names are unique per folder, across files and folders, even when racing.
+        let taken = Folder { id: Uuid::new_v4(), parent: Some(folder.id), name: "util.py".into(), ..folder.clone() };
+        assert!(!storage.create_folder(&Folder { parent: None, ..taken.clone() }).await.unwrap());
+        assert!(storage.create_folder(&taken).await.unwrap());
+        assert_eq!(storage.create_file("lib".into(), details.id, project_id, None).await.unwrap(), None);
+        assert!(!storage.rename_file(&file_key, "util.py").await.unwrap());
+        assert!(!storage.update_folder(&taken.id.to_string(), "util.py", None).await.unwrap());
+        assert!(storage.rename_file(&file_key, "main.rs").await.unwrap());
+        let creates = (0..3).map(|_| {
+            let storage = Arc::clone(&storage);
+            async move { storage.create_file("race.py".into(), details.id, project_id, None).await.unwrap() }
+        });
+        let created: Vec<Uuid> = futures_util::future::join_all(creates).await.into_iter().flatten().collect();
+        assert_eq!(created.len(), 1);
+        assert!(storage.delete_file(&created[0].to_string()).await.unwrap());
+        assert!(storage.delete_folder(&taken.id.to_string()).await.unwrap());
+        // Deleting releases the name.
+        assert!(storage.rename_file(&file_key, "race.py").await.unwrap());
+        assert!(storage.move_file(&file_key, Some(folder.id)).await.unwrap());
+        assert!(storage.rename_file(&file_key, "util.py").await.unwrap());
+        assert!(storage.move_file(&file_key, None).await.is_ok_and(|moved| !moved));
+        assert!(storage.rename_file(&file_key, "main.rs").await.unwrap());
+        assert!(storage.move_file(&file_key, None).await.unwrap());
+        assert_eq!(storage.list_project_files(&project_key).await.unwrap().len(), 2);
+
+        // Saves racing each other all land, each as a revision of its own.
+        let saves = (0..4).map(|n| {
+            let (storage, file_key) = (Arc::clone(&storage), file_key.clone());
+            async move { storage.update_file_content(&file_key, &format!("print({})", n), &[]).await.unwrap() }
+        });
+        let mut saved = futures_util::future::join_all(saves).await;
+        saved.sort();
+        assert_eq!(saved, [3, 4, 5, 6]);
+        assert_eq!(storage.list_file_revisions(&file_key).await.unwrap().len(), 6);
+
+        assert!(storage.delete_file(&file_key).await.unwrap());
+        assert!(!storage.delete_file(&file_key).await.unwrap());
+        assert!(storage.list_file_revisions(&file_key).await.unwrap().is_empty());
+        assert!(storage.get_file_revision(&file_key, 2).await.unwrap().is_none());
+        assert_eq!(storage.get_project(&project_key).await.unwrap().unwrap().files, [other_id]);
+
+        let invite = |code: &str, created_at| Invite {
+            code: code.into(),
+            project_id,
+            role: Role::Editor,
+            created_by: details.id,
+            created_at,
+            expires_at: u64::MAX,
+            max_uses: Some(2),
+            uses: 0,
+        };
+        storage.create_invite(&invite("older", 1)).await.unwrap();
+        storage.create_invite(&invite("newer", 2)).await.unwrap();
+        assert_eq!(storage.redeem_invite("older", Uuid::new_v4(), 0).await.unwrap(), Redemption::UserNotFound);
+        let guest = new_user(storage.as_ref(), "guest").await;
+        assert_eq!(storage.redeem_invite("older", guest, 0).await.unwrap(), Redemption::Joined { project_id, role: Role::Editor });
+        let joined = storage.get_user_by_id(&guest.to_string()).await.unwrap().unwrap();
+        assert_eq!(joined.projects, [project_id]);
+        assert_eq!(storage.redeem_invite("older", guest, 0).await.unwrap(), Redemption::AlreadyMember { project_id, role: Role::Editor });
+        assert_eq!(storage.redeem_invite("older", Uuid::new_v4(), u64::MAX).await.unwrap(), Redemption::Expired);
+        assert_eq!(storage.redeem_invite("missing", guest, 0).await.unwrap(), Redemption::InviteNotFound);
+        // Two people racing for the last use: exactly one of them gets in.
+        let racers = [new_user(storage.as_ref(), "first").await, new_user(storage.as_ref(), "second").await];
+        let redeems = racers.map(|user_id| {
+            let storage = Arc::clone(&storage);
+            async move { storage.redeem_invite("older", user_id, 0).await.unwrap() }
+        });
+        let mut outcomes = futures_util::future::join_all(redeems).await;
+        outcomes.sort_by_key(|outcome| *outcome == Redemption::UsedUp);
+        assert_eq!(outcomes, [Redemption::Joined { project_id, role: Role::Editor }, Redemption::UsedUp]);
+        assert_eq!(storage.get_invite("older").await.unwrap().unwrap().uses, 2);
+        let project = storage.get_project(&project_key).await.unwrap().unwrap();
+        assert_eq!(project.role_of(guest), Some(Role::Editor));
+        assert_eq!(racers.iter().filter(|id| project.role_of(**id).is_some()).count(), 1);
+
+        let stranger = new_user(storage.as_ref(), "stranger").await;
+        assert!(matches!(storage.update_member(&project_key, stranger, MemberChange::Remove).await.unwrap(), MemberUpdate::NotMember));
+        assert!(matches!(
+            storage.update_member(&Uuid::new_v4().to_string(), guest, MemberChange::Remove).await.unwrap(),
+            MemberUpdate::ProjectNotFound
+        ));
+        let MemberUpdate::Updated(project) = storage.update_member(&project_key, guest, MemberChange::SetRole(Role::Viewer)).await.unwrap() else {
+            panic!("guest is a member");
+        };
+        assert_eq!(project.role_of(guest), Some(Role::Viewer));
+        // A role change racing someone joining keeps both.
+        let (changed, joined) = futures_util::join!(
+            storage.update_member(&project_key, guest, MemberChange::SetRole(Role::Editor)),
+            storage.redeem_invite("newer", stranger, 0),
+        );
+        assert!(matches!(changed.unwrap(), MemberUpdate::Updated(_)));
+        assert_eq!(joined.unwrap(), Redemption::Joined { project_id, role: Role::Editor });
+        let project = storage.get_project(&project_key).await.unwrap().unwrap();
+        assert_eq!((project.role_of(guest), project.role_of(stranger)), (Some(Role::Editor), Some(Role::Editor)));
+        assert!(matches!(storage.update_member(&project_key, stranger, MemberChange::Remove).await.unwrap(), MemberUpdate::Updated(_)));
+        let project = storage.get_project(&project_key).await.unwrap().unwrap();
+        assert_eq!((project.role_of(stranger), project.roles.contains_key(&stranger)), (None, false));
+        let codes: Vec<String> = storage.list_invites(&project_key).await.unwrap().into_iter().map(|i| i.code).collect();
+        assert_eq!(codes, ["newer", "older"]);
+        assert!(storage.delete_invite("older").await.unwrap());
+        assert!(!storage.delete_invite("older").await.unwrap());
+
+        let report = TestReport {
+            project_id,
+            run_by: details.id,
+            finished_at: 1,
+            language: "python".into(),
+            entry: None,
+            stage: Stage::Run,
+            exit_code: 0,
+            timed_out: false,
+            passed: 1,
+            failed: 0,
+            skipped: 0,
+            wall_time_ms: 5,
+            tests: vec![],
+            diagnostics: vec![],
+        };
+        storage.save_test_report(&report).await.unwrap();
+        assert_eq!(storage.get_test_report(&project_key).await.unwrap().unwrap().passed, 1);
+
+        let other_key = other_id.to_string();
+        storage.update_file_content(&other_key, "x = 1", &[]).await.unwrap();
+        assert_eq!(storage.update_file_content_from(&other_key, 0, "x = 0", &[]).await.unwrap(), None);
+        assert_eq!(storage.update_file_content_from(&other_key, 1, "x = 2", &[]).await.unwrap(), Some(2));
+        assert!(storage.delete_project(&project_key).await.unwrap());
+        assert!(!storage.delete_project(&project_key).await.unwrap());
+        assert!(storage.get_file_by_id(&other_key).await.unwrap().is_none());
+        assert!(storage.list_file_revisions(&other_key).await.unwrap().is_empty());
+        assert!(storage.list_folders(&project_key).await.unwrap().is_empty());
+        assert!(storage.get_test_report(&project_key).await.unwrap().is_none());
+        assert!(storage.list_invites(&project_key).await.unwrap().is_empty());
+        assert!(storage.get_user_by_id(&guest.to_string()).await.unwrap().unwrap().projects.is_empty());
+    }
+
+    #[tokio::test]
+    async fn firestore_conforms() {
+        conforms(firestore::firebase().await).await;
+    }
+
+    #[tokio::test]
+    async fn firestore_deletes_files_with_more_revisions_than_fit_in_a_commit() {
+        let storage = firestore::firebase().await;
+        let owner = new_user(storage.as_ref(), "ada").await;
+        let project_id = auth::project_with_members(storage.as_ref(), owner, &[]).await;
+        let file_id = storage.create_file("long.py".into(), owner, project_id, None).await.unwrap().unwrap().to_string();
+        for revision in 0..520 {
+            storage.update_file_content(&file_id, &format!("x = {}", revision), &[]).await.unwrap();
+        }
+
+        assert!(storage.delete_file(&file_id).await.unwrap());
+        assert!(storage.get_file_by_id(&file_id).await.unwrap().is_none());
+        assert!(storage.list_file_revisions(&file_id).await.unwrap().is_empty());
+        assert!(storage.create_file("long.py".into(), owner, project_id, None).await.unwrap().is_some(), "the name was released");
+    }
+
+    #[tokio::test]
+    async fn local_storage_conforms() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("local.db");
+        conforms(Arc::new(LocalStorage::open(path.to_str().unwrap()).unwrap())).await;
+    }
+}
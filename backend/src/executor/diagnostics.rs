@@ -0,0 +1,333 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::borrow::Cow;
+use std::path::Path;
+
+/// One compiler or runtime message tied to a place in the project. Shaped like
+/// an AI review `Suggestion` so the editor can underline both the same way.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Diagnostic {
+    /// Project-relative path.
+    pub file: String,
+    pub line: usize,
+    pub column: Option<usize>,
+    /// `error`, `warning` or `note`.
+    pub severity: String,
+    pub message: String,
+    /// The tool's name for the problem, e.g. `E0308`, `-Wunused-variable`,
+    /// `TS2322` or `ZeroDivisionError`.
+    pub code: Option<String>,
+}
+
+/// How a toolchain reports problems.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// `file:line[:column]: severity: message`, from gcc, clang and javac.
+    Gcc,
+    /// rustc `--error-format=json` lines, bare or wrapped by cargo `--message-format=json`.
+    Rustc,
+    /// Uncaught exception tracebacks.
+    Python,
+    /// `file(line,column): severity TS1234: message`, from `tsc --pretty false`.
+    Tsc,
+}
+
+impl Format {
+    /// Diagnostics for project files found in `output` of a tool run in `workdir`.
+    /// Messages about files outside the project (system headers, the standard
+    /// library) are dropped since there is nothing to underline.
+    pub fn parse(self, output: &str, workdir: &Path) -> Vec<Diagnostic> {
+        match self {
+            Format::Gcc => output.lines().filter_map(|line| parse_gcc_line(line, workdir)).collect(),
+            Format::Rustc => output.lines().filter_map(|line| parse_rustc_line(line, workdir)).collect(),
+            Format::Python => parse_python(output, workdir),
+            Format::Tsc => output.lines().filter_map(|line| parse_tsc_line(line, workdir)).collect(),
+        }
+    }
+
+    /// `output` as a person would want to read it. Only rustc's JSON needs
+    /// turning back into text; expects whole lines.
+    pub fn readable(self, output: &str) -> Cow<'_, str> {
+        match self {
+            Format::Rustc => Cow::Owned(output.lines().filter_map(render_rustc_line).collect()),
+            _ => Cow::Borrowed(output),
+        }
+    }
+}
+
+/// Turns a path printed by a tool running in `workdir` into a project path,
+/// or `None` when it points outside the project.
+fn project_path(path: &str, workdir: &Path) -> Option<String> {
+    let workdir = workdir.to_string_lossy();
+    let path = match path.strip_prefix(workdir.as_ref()) {
+        Some(rest) => rest.trim_start_matches('/'),
+        None if path.starts_with('/') || path.starts_with('<') => return None,
+        None => path,
+    };
+    Some(path.trim_start_matches("./").to_string())
+}
+
+fn severity(level: &str) -> Option<&'static str> {
+    match level {
+        "error" | "fatal error" => Some("error"),
+        "warning" => Some("warning"),
+        "note" | "help" | "message" => Some("note"),
+        _ => None,
+    }
+}
+
+// Anything else gcc prints (context lines, carets, linker output) is skipped.
+fn parse_gcc_line(line: &str, workdir: &Path) -> Option<Diagnostic> {
+    let mut parts = line.splitn(5, ':');
+    let file = parts.next()?.trim();
+    let line_number = parts.next()?.trim().parse().ok()?;
+
+    let mut rest: Vec<&str> = parts.collect();
+    let column = rest.first().and_then(|part| part.trim().parse().ok());
+    if column.is_some() {
+        rest.remove(0);
+    }
+    let rest = rest.join(":");
+    let (level, message) = rest.trim_start().split_once(':')?;
+    let severity = severity(level.trim())?;
+
+    // gcc names the warning that fired at the end: `... [-Wunused-variable]`.
+    let message = message.trim();
+    let (message, code) = match message.strip_suffix(']').and_then(|m| m.rsplit_once(" [")) {
+        Some((text, flag)) if flag.starts_with('-') => (text, Some(flag.to_string())),
+        _ => (message, None),
+    };
+
+    Some(Diagnostic {
+        file: project_path(file, workdir)?,
+        line: line_number,
+        column,
+        severity: severity.to_string(),
+        message: message.to_string(),
+        code,
+    })
+}
+
+/// The compiler message on a rustc JSON line, unwrapping cargo's envelope.
+fn rustc_message(line: &str) -> Option<Value> {
+    let value: Value = serde_json::from_str(line.trim()).ok()?;
+    match value.get("reason") {
+        Some(_) => value.get("message").cloned(),
+        None => Some(value),
+    }
+}
+
+fn parse_rustc_line(line: &str, workdir: &Path) -> Option<Diagnostic> {
+    let message = rustc_message(line)?;
+    let severity = severity(message["level"].as_str()?)?;
+    let span = message["spans"].as_array()?.iter().find(|span| span["is_primary"].as_bool() == Some(true))?;
+
+    Some(Diagnostic {
+        file: project_path(span["file_name"].as_str()?, workdir)?,
+        line: span["line_start"].as_u64()? as usize,
+        column: span["column_start"].as_u64().map(|column| column as usize),
+        severity: severity.to_string(),
+        message: message["message"].as_str()?.to_string(),
+        code: message["code"]["code"].as_str().map(str::to_string),
+    })
+}
+
+/// A rustc message's own rendering; other JSON (cargo build progress) is
+/// dropped and plain lines are kept.
+fn render_rustc_line(line: &str) -> Option<String> {
+    if serde_json::from_str::<Value>(line.trim()).is_err() {
+        return Some(format!("{}\n", line));
+    }
+    rustc_message(line)?["rendered"].as_str().map(str::to_string)
+}
+
+/// Each exception in a traceback (chained ones included) is reported at the
+/// innermost frame that belongs to the project.
+fn parse_python(output: &str, workdir: &Path) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    let mut frame: Option<(String, usize)> = None;
+    let mut in_traceback = false;
+
+    for line in output.lines() {
+        if line.starts_with("Traceback (most recent call last)") {
+            in_traceback = true;
+            frame = None;
+            continue;
+        }
+        if let Some(rest) = line.trim_start().strip_prefix("File \"") {
+            let Some((path, rest)) = rest.split_once('"') else { continue };
+            let line_number = rest
+                .strip_prefix(", line ")
+                .map(|rest| rest.split(|c: char| !c.is_ascii_digit()).next().unwrap_or(""))
+                .and_then(|number| number.parse().ok());
+            if let (Some(path), Some(line_number)) = (project_path(path, workdir), line_number) {
+                frame = Some((path, line_number));
+            }
+            // Syntax errors are reported without a "Traceback" header.
+            in_traceback = true;
+            continue;
+        }
+        if !in_traceback || line.is_empty() || line.starts_with(char::is_whitespace) {
+            continue;
+        }
+
+        // The first unindented line after the frames names the exception.
+        let (name, detail) = line.split_once(':').unwrap_or((line, ""));
+        let is_exception = name.split('.').all(|part| !part.is_empty() && part.chars().all(|c| c.is_alphanumeric() || c == '_'));
+        if let (true, Some((file, line_number))) = (is_exception, frame.take()) {
+            let name = name.rsplit('.').next().unwrap_or(name);
+            diagnostics.push(Diagnostic {
+                file,
+                line: line_number,
+                column: None,
+                severity: "error".into(),
+                message: if detail.trim().is_empty() { name.to_string() } else { format!("{}: {}", name, detail.trim()) },
+                code: Some(name.to_string()),
+            });
+        }
+        in_traceback = false;
+    }
+    diagnostics
+}
+
+fn parse_tsc_line(line: &str, workdir: &Path) -> Option<Diagnostic> {
+    let (location, rest) = line.split_once("): ")?;
+    let (file, position) = location.rsplit_once('(')?;
+    let (line_number, column) = position.split_once(',')?;
+    let (level, rest) = rest.split_once(' ')?;
+    let (code, message) = rest.split_once(": ")?;
+    if !code.starts_with("TS") {
+        return None;
+    }
+
+    Some(Diagnostic {
+        file: project_path(file.trim(), workdir)?,
+        line: line_number.parse().ok()?,
+        column: column.parse().ok(),
+        severity: severity(level)?.to_string(),
+        message: message.trim().to_string(),
+        code: Some(code.to_string()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type Summary<'a> = (&'a str, usize, Option<usize>, &'a str, Option<&'a str>);
+
+    fn summary(diagnostics: &[Diagnostic]) -> Vec<Summary<'_>> {
+        diagnostics
+            .iter()
+            .map(|d| (d.file.as_str(), d.line, d.column, d.severity.as_str(), d.code.as_deref()))
+            .collect()
+    }
+
+    #[test]
+    fn gcc_and_javac_messages_are_parsed() {
+        let workdir = Path::new("/tmp/codecollab-run-x");
+        let output = "\
+src/util.c: In function 'add':
+src/util.c:3:12: error: expected ';' before '}' token
+    3 |   return a + b
+      |            ^
+/tmp/codecollab-run-x/main.c:7:5: warning: unused variable 'x' [-Wunused-variable]
+./include/util.h:1:1: note: declared here
+/usr/include/stdio.h:12:1: note: in a system header
+Main.java:4: error: ';' expected
+/usr/bin/ld: main.o: in function `main': undefined reference to `missing'
+";
+        let diagnostics = Format::Gcc.parse(output, workdir);
+        assert_eq!(
+            summary(&diagnostics),
+            vec![
+                ("src/util.c", 3, Some(12), "error", None),
+                ("main.c", 7, Some(5), "warning", Some("-Wunused-variable")),
+                ("include/util.h", 1, Some(1), "note", None),
+                ("Main.java", 4, None, "error", None),
+            ]
+        );
+        assert_eq!(diagnostics[1].message, "unused variable 'x'");
+    }
+
+    #[test]
+    fn rustc_json_is_parsed_and_rendered() {
+        let workdir = Path::new("/tmp/codecollab-run-x");
+        let mismatch = r#"{"$message_type":"diagnostic","message":"mismatched types","code":{"code":"E0308","explanation":"..."},"level":"error","spans":[{"file_name":"src/main.rs","line_start":2,"column_start":18,"is_primary":true}],"children":[],"rendered":"error[E0308]: mismatched types\n"}"#;
+        let unused = r#"{"message":"unused variable: `x`","code":{"code":"unused_variables","explanation":null},"level":"warning","spans":[{"file_name":"src/lib.rs","line_start":5,"column_start":9,"is_primary":true}],"children":[],"rendered":"warning: unused variable: `x`\n"}"#;
+        let output = format!(
+            "{}\n{{\"reason\":\"compiler-message\",\"package_id\":\"app\",\"message\":{}}}\n{}\n{}\n",
+            mismatch,
+            unused,
+            r#"{"$message_type":"diagnostic","message":"aborting due to 1 previous error","code":null,"level":"error","spans":[],"children":[],"rendered":"error: aborting due to 1 previous error\n"}"#,
+            r#"{"reason":"build-finished","success":false}"#,
+        );
+
+        let diagnostics = Format::Rustc.parse(&output, workdir);
+        assert_eq!(
+            summary(&diagnostics),
+            vec![
+                ("src/main.rs", 2, Some(18), "error", Some("E0308")),
+                ("src/lib.rs", 5, Some(9), "warning", Some("unused_variables")),
+            ]
+        );
+        assert_eq!(diagnostics[0].message, "mismatched types");
+        assert_eq!(
+            Format::Rustc.readable(&format!("{}error: could not compile `app`\n", output)),
+            "error[E0308]: mismatched types\nwarning: unused variable: `x`\nerror: aborting due to 1 previous error\nerror: could not compile `app`\n"
+        );
+    }
+
+    #[test]
+    fn python_tracebacks_point_at_the_innermost_project_frame() {
+        let workdir = Path::new("/tmp/codecollab-run-x");
+        let output = r#"Traceback (most recent call last):
+  File "<frozen runpy>", line 198, in _run_module_as_main
+  File "/tmp/codecollab-run-x/app/main.py", line 3, in <module>
+    print(divide(1, 0))
+  File "/tmp/codecollab-run-x/pkg/maths.py", line 2, in divide
+    return a / b
+           ~~^~~
+ZeroDivisionError: division by zero
+
+During handling of the above exception, another exception occurred:
+
+Traceback (most recent call last):
+  File "/tmp/codecollab-run-x/app/main.py", line 5, in <module>
+    json.loads("{")
+  File "/usr/lib/python3.11/json/__init__.py", line 346, in loads
+    return _default_decoder.decode(s)
+json.decoder.JSONDecodeError: Expecting property name enclosed in double quotes: line 1 column 2 (char 1)
+  File "/tmp/codecollab-run-x/broken.py", line 1
+    print(
+         ^
+SyntaxError: '(' was never closed
+"#;
+        let diagnostics = Format::Python.parse(output, workdir);
+        assert_eq!(
+            summary(&diagnostics),
+            vec![
+                ("pkg/maths.py", 2, None, "error", Some("ZeroDivisionError")),
+                ("app/main.py", 5, None, "error", Some("JSONDecodeError")),
+                ("broken.py", 1, None, "error", Some("SyntaxError")),
+            ]
+        );
+        assert_eq!(diagnostics[0].message, "ZeroDivisionError: division by zero");
+    }
+
+    #[test]
+    fn tsc_messages_are_parsed() {
+        let workdir = Path::new("/tmp/codecollab-run-x");
+        let output = "\
+src/index.ts(3,7): error TS2322: Type 'string' is not assignable to type 'number'.
+/tmp/codecollab-run-x/util.ts(10,1): warning TS6133: 'x' is declared but its value is never read.
+Found 2 errors in 2 files.
+";
+        let diagnostics = Format::Tsc.parse(output, workdir);
+        assert_eq!(
+            summary(&diagnostics),
+            vec![("src/index.ts", 3, Some(7), "error", Some("TS2322")), ("util.ts", 10, Some(1), "warning", Some("TS6133"))]
+        );
+        assert_eq!(diagnostics[0].message, "Type 'string' is not assignable to type 'number'.");
+    }
+}
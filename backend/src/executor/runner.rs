@@ -0,0 +1,388 @@
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::sync::Semaphore;
+
+use crate::executor::diagnostics::Diagnostic;
+use crate::executor::sandbox::{Isolation, Limits, Output, Sandbox, Workspace};
+use crate::executor::testing::{Harness, REPORT_FILE};
+use crate::executor::toolchain::{Plan, Toolchain};
+use crate::model::test_report::TestResult;
+
+/// Which step a run stopped at: a failed compile never reaches `Run`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Stage {
+    Compile,
+    Run,
+}
+
+/// The result of compiling and running one submission.
+#[derive(Debug)]
+pub struct Execution {
+    pub stage: Stage,
+    pub stdout: String,
+    pub stderr: String,
+    pub exit_code: i32,
+    pub signal: Option<i32>,
+    pub timed_out: bool,
+    pub output_truncated: bool,
+    pub compile_time_ms: Option<u64>,
+    pub wall_time_ms: u64,
+    pub cpu_time_ms: u64,
+    pub max_memory_kb: u64,
+    /// Compiler messages, including warnings from a build that succeeded, or
+    /// the uncaught error of an interpreted program.
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl Execution {
+    fn new(stage: Stage, output: Output, compile_time_ms: Option<u64>, diagnostics: Vec<Diagnostic>) -> Self {
+        Self {
+            stage,
+            stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+            stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+            exit_code: output.usage.exit_code,
+            signal: output.usage.signal,
+            timed_out: output.usage.timed_out,
+            output_truncated: output.output_truncated,
+            compile_time_ms,
+            wall_time_ms: output.usage.wall_time_ms,
+            cpu_time_ms: output.usage.cpu_time_ms,
+            max_memory_kb: output.usage.max_memory_kb,
+            diagnostics,
+        }
+    }
+}
+
+/// A test run: how the build and the test process went, and what each test did.
+#[derive(Debug)]
+pub struct TestRun {
+    pub execution: Execution,
+    pub tests: Vec<TestResult>,
+}
+
+/// Compiles and runs submitted code in the sandbox, a bounded number of
+/// submissions at a time.
+pub struct Executor {
+    sandbox: Sandbox,
+    limits: Limits,
+    /// Wall-clock limit for interactive sessions, which mostly wait on their user.
+    session_timeout: Duration,
+    permits: Semaphore,
+}
+
+impl Executor {
+    pub fn new(sandbox: Sandbox, limits: Limits, max_concurrent: usize) -> Self {
+        Self {
+            sandbox,
+            limits,
+            session_timeout: limits.wall_clock.max(Duration::from_secs(300)),
+            permits: Semaphore::new(max_concurrent.max(1)),
+        }
+    }
+
+    pub fn with_session_timeout(mut self, timeout: Duration) -> Self {
+        self.session_timeout = timeout;
+        self
+    }
+
+    /// Reads `EXECUTOR_HELPER` (the `codecollab-sandbox` binary, by default
+    /// next to the server's), `EXECUTOR_ISOLATION` (`namespaces` or `none`), `EXECUTOR_CPU_SECONDS`,
+    /// `EXECUTOR_TIMEOUT_SECONDS`, `EXECUTOR_MEMORY_MB`, `EXECUTOR_OUTPUT_KB`,
+    /// `EXECUTOR_MAX_PROCESSES`, `EXECUTOR_MAX_CONCURRENT` and
+    /// `EXECUTOR_SESSION_TIMEOUT_SECONDS`.
+    pub fn from_env() -> Result<Self, Box<dyn Error + Send + Sync>> {
+        fn var(name: &str, default: u64) -> Result<u64, Box<dyn Error + Send + Sync>> {
+            match std::env::var(name) {
+                Ok(value) => value.parse().map_err(|_| format!("{} must be a number, got '{}'", name, value).into()),
+                Err(_) => Ok(default),
+            }
+        }
+
+        let helper = match std::env::var_os("EXECUTOR_HELPER") {
+            Some(helper) => PathBuf::from(helper),
+            None => std::env::current_exe()?.with_file_name("codecollab-sandbox"),
+        };
+        let isolation = match std::env::var("EXECUTOR_ISOLATION").as_deref() {
+            Ok("namespaces") | Err(_) => Isolation::Namespaces,
+            Ok("none") => {
+                eprintln!("⚠️ EXECUTOR_ISOLATION=none: submitted code runs with network and filesystem access");
+                Isolation::None
+            }
+            Ok(other) => return Err(format!("EXECUTOR_ISOLATION must be 'namespaces' or 'none', got '{}'", other).into()),
+        };
+        let limits = Limits {
+            cpu_seconds: var("EXECUTOR_CPU_SECONDS", 5)?,
+            memory_bytes: var("EXECUTOR_MEMORY_MB", 512)? * 1024 * 1024,
+            wall_clock: Duration::from_secs(var("EXECUTOR_TIMEOUT_SECONDS", 10)?),
+            output_bytes: var("EXECUTOR_OUTPUT_KB", 1024)? as usize * 1024,
+            processes: var("EXECUTOR_MAX_PROCESSES", 64)?,
+            file_bytes: 16 * 1024 * 1024,
+        };
+
+        let executor = Self::new(Sandbox::new(helper, isolation), limits, var("EXECUTOR_MAX_CONCURRENT", 4)? as usize);
+        Ok(executor.with_session_timeout(Duration::from_secs(var("EXECUTOR_SESSION_TIMEOUT_SECONDS", 300)?)))
+    }
+
+    pub fn sandbox(&self) -> &Sandbox {
+        &self.sandbox
+    }
+
+    /// Limits for running a program built with `toolchain`.
+    pub fn run_limits(&self, toolchain: &Toolchain) -> Limits {
+        Limits { memory_bytes: toolchain.address_space.unwrap_or(self.limits.memory_bytes), ..self.limits }
+    }
+
+    /// Limits for an interactive run: the same budget, but more time to wait for input.
+    pub fn session_limits(&self, toolchain: &Toolchain) -> Limits {
+        Limits { wall_clock: self.session_timeout, ..self.run_limits(toolchain) }
+    }
+
+    /// Compilers are trusted more than the programs they build, but still bounded.
+    pub fn compile_limits(&self, toolchain: &Toolchain) -> Limits {
+        let run = self.run_limits(toolchain);
+        Limits {
+            cpu_seconds: run.cpu_seconds.max(30),
+            memory_bytes: run.memory_bytes.max(2 * 1024 * 1024 * 1024),
+            wall_clock: run.wall_clock.max(Duration::from_secs(60)),
+            file_bytes: 256 * 1024 * 1024,
+            ..run
+        }
+    }
+
+    /// Waits for a free execution slot; hold the permit for the whole run.
+    pub async fn permit(&self) -> Result<tokio::sync::SemaphorePermit<'_>, Box<dyn Error + Send + Sync>> {
+        Ok(self.permits.acquire().await?)
+    }
+
+    /// Runs the plan's compile step in `workspace`, which must already hold
+    /// the sources. Returns `None` when there is nothing to compile.
+    pub async fn compile(&self, toolchain: &Toolchain, plan: &Plan, workspace: &Workspace) -> Result<Option<Execution>, Box<dyn Error + Send + Sync>> {
+        let Some(command) = &plan.compile else { return Ok(None) };
+        let output = self
+            .sandbox
+            .run(command, workspace, self.compile_limits(toolchain), b"")
+            .await
+            .map_err(|e| format!("Could not start {}: {}", command[0], e))?;
+        let compile_time_ms = Some(output.usage.wall_time_ms);
+        let mut execution = Execution::new(Stage::Compile, output, compile_time_ms, Vec::new());
+        if let Some(format) = toolchain.diagnostics {
+            // Some compilers (tsc, cargo) report on stdout.
+            execution.diagnostics = format.parse(&format!("{}\n{}", execution.stderr, execution.stdout), workspace.path());
+            execution.stdout = format.readable(&execution.stdout).into_owned();
+            execution.stderr = format.readable(&execution.stderr).into_owned();
+        }
+        Ok(Some(execution))
+    }
+
+    /// Saves `code` as the toolchain's source file, compiles it if needed and
+    /// runs it with `stdin`.
+    pub async fn execute(&self, toolchain: &Toolchain, code: &str, stdin: &str) -> Result<Execution, Box<dyn Error + Send + Sync>> {
+        let files = [(toolchain.source.to_string(), code.to_string())];
+        self.execute_files(toolchain, toolchain.source, &files, stdin).await
+    }
+
+    /// Lays `files` (project path and content) out in a fresh directory,
+    /// builds them and runs `entry` with `stdin`.
+    pub async fn execute_files(
+        &self,
+        toolchain: &Toolchain,
+        entry: &str,
+        files: &[(String, String)],
+        stdin: &str,
+    ) -> Result<Execution, Box<dyn Error + Send + Sync>> {
+        let _permit = self.permit().await?;
+        let workspace = self.sandbox.workspace()?;
+        self.sandbox.materialize(&workspace, files).await?;
+        let paths: Vec<&str> = files.iter().map(|(path, _)| path.as_str()).collect();
+        let plan = (toolchain.plan)(entry, &paths);
+        self.build_and_run(toolchain, &plan, &workspace, stdin).await
+    }
+
+    /// Runs a test `plan` over `files` and reads back each test's result.
+    pub async fn run_tests(
+        &self,
+        toolchain: &Toolchain,
+        harness: &Harness,
+        plan: &Plan,
+        files: &[(String, String)],
+    ) -> Result<TestRun, Box<dyn Error + Send + Sync>> {
+        let _permit = self.permit().await?;
+        let workspace = self.sandbox.workspace()?;
+        let files: Vec<(String, String)> = files.iter().cloned().chain(harness.support_files()).collect();
+        self.sandbox.materialize(&workspace, &files).await?;
+
+        let execution = self.build_and_run(toolchain, plan, &workspace, "").await?;
+        let tests = match (execution.stage, harness.report.uses_file()) {
+            (Stage::Compile, _) => Vec::new(),
+            (Stage::Run, true) => {
+                let report = tokio::fs::read_to_string(workspace.path().join(REPORT_FILE)).await.unwrap_or_default();
+                harness.report.parse(&report)
+            }
+            (Stage::Run, false) => harness.report.parse(&execution.stdout),
+        };
+        Ok(TestRun { execution, tests })
+    }
+
+    /// Compiles (when the plan says to) and runs in a `workspace` that already
+    /// holds the sources. A failed compile is returned as is.
+    async fn build_and_run(&self, toolchain: &Toolchain, plan: &Plan, workspace: &Workspace, stdin: &str) -> Result<Execution, Box<dyn Error + Send + Sync>> {
+        let (compile_time_ms, diagnostics) = match self.compile(toolchain, plan, workspace).await? {
+            Some(failed) if failed.exit_code != 0 => return Ok(failed),
+            Some(compiled) => (compiled.compile_time_ms, compiled.diagnostics),
+            None => (None, Vec::new()),
+        };
+
+        let output = self
+            .sandbox
+            .run(&plan.run, workspace, self.run_limits(toolchain), stdin.as_bytes())
+            .await
+            .map_err(|e| format!("Could not start {}: {}", plan.run[0], e))?;
+        let mut execution = Execution::new(Stage::Run, output, compile_time_ms, diagnostics);
+        if let (None, Some(format)) = (&plan.compile, toolchain.diagnostics) {
+            execution.diagnostics = format.parse(&execution.stderr, workspace.path());
+        }
+        Ok(execution)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::sandbox;
+
+    fn executor() -> Executor {
+        let limits = Limits {
+            cpu_seconds: 2,
+            memory_bytes: 256 * 1024 * 1024,
+            wall_clock: Duration::from_secs(3),
+            output_bytes: 4096,
+            processes: 64,
+            file_bytes: 1024 * 1024,
+        };
+        Executor::new(Sandbox::new(sandbox::helper(), Isolation::Namespaces), limits, 2)
+    }
+
+    async fn python(executor: &Executor, code: &str, stdin: &str) -> Execution {
+        executor.execute(Toolchain::find("Python").unwrap(), code, stdin).await.expect("python runs")
+    }
+
+    #[tokio::test]
+    async fn runs_code_with_stdin_and_reports_usage() {
+        let executor = executor();
+        let run = python(&executor, "import sys\nprint(sys.stdin.read().upper())\nsys.exit(3)", "hello").await;
+        assert_eq!(run.stage, Stage::Run);
+        assert_eq!(run.stdout, "HELLO\n");
+        assert_eq!(run.exit_code, 3);
+        assert!(run.max_memory_kb > 0);
+
+        let c = Toolchain::find("c").unwrap();
+        let broken = executor.execute(c, "int main( { return 0; }", "").await.unwrap();
+        assert_eq!(broken.stage, Stage::Compile);
+        assert_ne!(broken.exit_code, 0);
+        assert!(broken.stderr.contains("error"));
+        assert_eq!((broken.diagnostics[0].file.as_str(), broken.diagnostics[0].line), ("main.c", 1));
+
+        let crash = python(&executor, "def divide(a, b):\n    return a / b\n\nprint(divide(1, 0))", "").await;
+        let error = &crash.diagnostics[0];
+        assert_eq!((error.file.as_str(), error.line, error.code.as_deref()), ("main.py", 2, Some("ZeroDivisionError")));
+
+        let built = executor.execute(c, "#include <stdio.h>\nint main(void) { puts(\"hi\"); return 0; }", "").await.unwrap();
+        assert_eq!((built.stage, built.stdout.as_str(), built.exit_code), (Stage::Run, "hi\n", 0));
+        assert!(built.compile_time_ms.is_some());
+    }
+
+    #[tokio::test]
+    async fn limits_stop_runaway_programs() {
+        let executor = executor();
+
+        let sleeper = python(&executor, "import time\ntime.sleep(30)", "").await;
+        assert!(sleeper.timed_out);
+        assert_eq!(sleeper.signal, Some(libc::SIGKILL));
+        assert!(sleeper.wall_time_ms < 10_000);
+
+        let spinner = python(&executor, "while True: pass", "").await;
+        assert!(spinner.cpu_time_ms >= 1000);
+        assert!(spinner.signal.is_some());
+
+        let chatty = python(&executor, "while True: print('x' * 100)", "").await;
+        assert!(chatty.output_truncated);
+        assert_eq!(chatty.stdout.len(), 4096);
+
+        let hog = python(&executor, "data = bytearray(1024 * 1024 * 1024)", "").await;
+        assert!(hog.stderr.contains("MemoryError"));
+    }
+
+    #[tokio::test]
+    async fn programs_cannot_reach_the_network_or_write_outside_their_directory() {
+        let executor = executor();
+        let code = "import socket\ntry:\n    socket.create_connection(('1.1.1.1', 53), timeout=2)\n    print('connected')\nexcept OSError as e:\n    print('offline')";
+        assert_eq!(python(&executor, code, "").await.stdout, "offline\n");
+
+        let outside = std::env::temp_dir().join(format!("sandbox-escape-{}", std::process::id()));
+        let code = format!(
+            "open('inside.txt', 'w').write('ok')\ntry:\n    open({:?}, 'w').write('escaped')\nexcept OSError:\n    print('blocked')",
+            outside.to_string_lossy()
+        );
+        let run = python(&executor, &code, "").await;
+        assert_eq!(run.stdout, "blocked\n", "{}", run.stderr);
+        assert!(!outside.exists());
+    }
+
+    #[tokio::test]
+    async fn programs_see_only_their_own_processes_and_the_toolchains() {
+        let executor = executor();
+        let code = "import ctypes, os, subprocess\n\
+            print(os.getpid())\n\
+            print(sorted(int(pid) for pid in os.listdir('/proc') if pid.isdigit()))\n\
+            print('var' in os.listdir('/'))\n\
+            libc = ctypes.CDLL(None, use_errno=True)\n\
+            print(libc.ptrace(0, 0, 0, 0), ctypes.get_errno())\n\
+            print(subprocess.run(['echo', 'child'], capture_output=True).stdout.decode().strip())";
+        let run = python(&executor, code, "").await;
+        let lines: Vec<&str> = run.stdout.lines().collect();
+        assert_eq!(lines, ["2", "[1, 2]", "False", "-1 1", "child"], "{}", run.stderr);
+    }
+
+    #[tokio::test]
+    async fn every_workspace_has_a_user_of_its_own() {
+        use std::os::unix::fs::MetadataExt;
+
+        let executor = executor();
+        let sandbox = executor.sandbox();
+        let [first, second] = [(), ()].map(|_| sandbox.workspace().unwrap());
+        for workspace in [&first, &second] {
+            sandbox.materialize(workspace, &[("main.py".to_string(), String::new())]).await.unwrap();
+        }
+        let [first_uid, second_uid] = [&first, &second].map(|workspace| std::fs::metadata(workspace.path().join("main.py")).unwrap().uid());
+        assert_ne!(first_uid, second_uid);
+        // SAFETY: geteuid has no preconditions and cannot fail.
+        let server = unsafe { libc::geteuid() };
+        assert!(first_uid != server && second_uid != server);
+
+        // Dropping a workspace clears out what its user left behind, off the async worker.
+        let dir = first.path().parent().unwrap().to_path_buf();
+        drop(first);
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(10);
+        while dir.exists() && std::time::Instant::now() < deadline {
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+        assert!(!dir.exists());
+    }
+
+    #[test]
+    fn the_helper_only_hands_workspaces_to_sandbox_users() {
+        let workspace = tempfile::tempdir().unwrap();
+        std::fs::create_dir(workspace.path().join("work")).unwrap();
+        let output = std::process::Command::new(sandbox::helper())
+            .arg("prepare")
+            .arg(workspace.path())
+            .arg("0")
+            .output()
+            .unwrap();
+        assert_eq!(output.status.code(), Some(125));
+        assert!(String::from_utf8_lossy(&output.stderr).contains("0 is not a sandbox user"));
+    }
+}
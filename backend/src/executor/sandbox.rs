@@ -0,0 +1,483 @@
+use std::collections::BTreeSet;
+use std::fs::Permissions;
+use std::io;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Component, Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tempfile::TempDir;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+use tokio::process::{ChildStderr, ChildStdin, ChildStdout};
+
+/// Resource limits for one sandboxed process and everything it starts.
+#[derive(Debug, Clone, Copy)]
+pub struct Limits {
+    pub cpu_seconds: u64,
+    /// Address-space limit per process.
+    pub memory_bytes: u64,
+    pub wall_clock: Duration,
+    /// Combined stdout and stderr kept before the process is killed.
+    pub output_bytes: usize,
+    pub processes: u64,
+    /// Largest file the process may write.
+    pub file_bytes: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Isolation {
+    /// Fresh user, mount, PID, network, IPC and UTS namespaces: no network,
+    /// no other processes in sight, and a root holding only the toolchains
+    /// (read-only) and the working directory.
+    Namespaces,
+    /// Resource limits and the system call filter only, for hosts without
+    /// unprivileged user namespaces.
+    None,
+}
+
+/// Sandboxed programs run as users from this range, one per workspace, so
+/// runs never share the server's user, each other's files or a process
+/// count (`RLIMIT_NPROC` is per user). The helper refuses any other user.
+const FIRST_SANDBOX_UID: u32 = 1_000_000;
+const SANDBOX_UIDS: u32 = 65_536;
+
+/// Where to look for the next free sandbox user, and the users handed out.
+static LEASED_UIDS: Mutex<(u32, BTreeSet<u32>)> = Mutex::new((0, BTreeSet::new()));
+
+/// How many generations below the helper the program runs: with namespaces,
+/// a parent waiting on its behalf and its PID namespace's init sit in between.
+const NAMESPACED_DEPTH: u32 = 3;
+
+/// The helper's exit status and stderr prefix when it failed itself, before
+/// the program ever started.
+const HELPER_FAILED: i32 = 125;
+const HELPER_ERROR_PREFIX: &[u8] = b"codecollab-sandbox: ";
+
+/// How a sandboxed process ended and what it used.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Usage {
+    /// The exit status, or 128 + the signal number when it was killed.
+    pub exit_code: i32,
+    pub signal: Option<i32>,
+    pub timed_out: bool,
+    pub wall_time_ms: u64,
+    pub cpu_time_ms: u64,
+    /// Peak resident memory of the process itself, sampled while it runs.
+    pub max_memory_kb: u64,
+}
+
+/// Everything a finished process printed, capped at `Limits::output_bytes`.
+#[derive(Debug)]
+pub struct Output {
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+    pub output_truncated: bool,
+    pub usage: Usage,
+}
+
+/// A fresh working directory and the user that programs run in it run as.
+/// The helper empties it, and the user goes back to the pool, when the
+/// workspace is dropped.
+pub struct Workspace {
+    /// Only taken by `drop`, which hands it to the cleanup.
+    dir: Option<TempDir>,
+    work: PathBuf,
+    uid: u32,
+    helper: PathBuf,
+}
+
+impl Workspace {
+    pub fn path(&self) -> &Path {
+        &self.work
+    }
+
+    fn root(&self) -> &Path {
+        self.dir.as_ref().expect("a workspace keeps its directory until dropped").path()
+    }
+}
+
+impl Drop for Workspace {
+    fn drop(&mut self) {
+        let Some(dir) = self.dir.take() else {
+            return;
+        };
+        let (helper, uid) = (self.helper.clone(), self.uid);
+        let clean = move || {
+            // The sandbox user's files are beyond the server's reach.
+            let _ = helper_command(&helper).arg("clean").arg(dir.path()).status();
+            drop(dir);
+            // Only now can no file or process of the old run be left to the next one.
+            let mut leased = LEASED_UIDS.lock().unwrap_or_else(|e| e.into_inner());
+            leased.1.remove(&uid);
+        };
+        // Waiting on the helper would stall an async worker.
+        match tokio::runtime::Handle::try_current() {
+            Ok(runtime) => drop(runtime.spawn_blocking(clean)),
+            Err(_) => clean(),
+        }
+    }
+}
+
+/// Hands out a sandbox user no other workspace holds, starting after the last
+/// one handed out so a user is not reused straight away.
+fn lease_uid() -> io::Result<u32> {
+    let mut leased = LEASED_UIDS.lock().unwrap_or_else(|e| e.into_inner());
+    let (next, taken) = &mut *leased;
+    for offset in 0..SANDBOX_UIDS {
+        let slot = (*next + offset) % SANDBOX_UIDS;
+        if taken.insert(FIRST_SANDBOX_UID + slot) {
+            *next = (slot + 1) % SANDBOX_UIDS;
+            return Ok(FIRST_SANDBOX_UID + slot);
+        }
+    }
+    Err(io::Error::other("Every sandbox user is in use"))
+}
+
+fn helper_command(helper: &Path) -> Command {
+    let mut command = Command::new(helper);
+    command.env_clear().stdin(Stdio::null()).stdout(Stdio::null()).stderr(Stdio::null());
+    command
+}
+
+/// Runs sandboxed programs through `codecollab-sandbox`, the setuid helper
+/// that does everything needing root, so the server itself runs unprivileged.
+pub struct Sandbox {
+    helper: PathBuf,
+    isolation: Isolation,
+}
+
+impl Sandbox {
+    pub fn new(helper: PathBuf, isolation: Isolation) -> Self {
+        Self { helper, isolation }
+    }
+
+    /// A fresh, empty working directory for a sandbox user of its own.
+    pub fn workspace(&self) -> io::Result<Workspace> {
+        let dir = tempfile::Builder::new().prefix("codecollab-run-").tempdir()?;
+        // Sandbox users must reach `work` to bind it into their root.
+        std::fs::set_permissions(dir.path(), Permissions::from_mode(0o711))?;
+        let work = dir.path().join("work");
+        std::fs::create_dir(&work)?;
+        Ok(Workspace { dir: Some(dir), work, uid: lease_uid()?, helper: self.helper.clone() })
+    }
+
+    /// Writes `files` (relative path and content) into the workspace,
+    /// creating the folders they sit in, and hands them to the sandbox user so
+    /// builds can write next to their sources. Afterwards the server can no
+    /// longer write there, so this is called once per workspace.
+    pub async fn materialize(&self, workspace: &Workspace, files: &[(String, String)]) -> io::Result<()> {
+        let workdir = workspace.path();
+        for (path, content) in files {
+            let relative = Path::new(path);
+            if !relative.components().all(|component| matches!(component, Component::Normal(_))) {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Invalid file path '{}'", path)));
+            }
+            let target = workdir.join(relative);
+            if let Some(parent) = target.parent() {
+                tokio::fs::create_dir_all(parent).await?;
+            }
+            tokio::fs::write(&target, content).await?;
+        }
+
+        let mut prepare = tokio::process::Command::from(helper_command(&self.helper));
+        prepare.arg("prepare").arg(workspace.root()).arg(workspace.uid.to_string()).stderr(Stdio::piped());
+        let output = prepare.output().await?;
+        if !output.status.success() {
+            return Err(helper_error(&output.stderr));
+        }
+        Ok(())
+    }
+
+    /// Starts `argv` in the workspace under `limits` with piped stdio and a
+    /// clean environment, through the helper. The helper kills the program
+    /// and everything it started when asked to, or when it exits.
+    pub fn spawn(&self, argv: &[String], workspace: &Workspace, limits: Limits) -> io::Result<Process> {
+        if argv.is_empty() {
+            return Err(io::Error::other("empty command"));
+        }
+        let mut command = helper_command(&self.helper);
+        command.arg("run").arg(workspace.root());
+        command.args(["--uid".to_string(), workspace.uid.to_string()]);
+        command.args(["--isolation", match self.isolation {
+            Isolation::Namespaces => "namespaces",
+            Isolation::None => "none",
+        }]);
+        command.args(["--cpu-seconds".to_string(), limits.cpu_seconds.to_string()]);
+        command.args(["--memory-bytes".to_string(), limits.memory_bytes.to_string()]);
+        command.args(["--file-bytes".to_string(), limits.file_bytes.to_string()]);
+        command.args(["--processes".to_string(), limits.processes.to_string()]);
+        for (name, value) in environment(workspace.path()) {
+            command.arg("--env").arg(format!("{}={}", name, value));
+        }
+        command.arg("--").args(argv);
+        command.stdin(Stdio::piped()).stdout(Stdio::piped()).stderr(Stdio::piped());
+
+        let mut child = command.spawn()?;
+        let depth = match self.isolation {
+            Isolation::Namespaces => NAMESPACED_DEPTH,
+            Isolation::None => 1,
+        };
+        let process = Process {
+            killer: Killer { pid: child.id() as libc::pid_t, depth, reaped: Arc::new(Mutex::new(false)) },
+            stdin: child.stdin.take().map(ChildStdin::from_std).transpose()?,
+            stdout: child.stdout.take().map(ChildStdout::from_std).transpose()?,
+            stderr: child.stderr.take().map(ChildStderr::from_std).transpose()?,
+            started: Instant::now(),
+            limits,
+            waiting: false,
+        };
+        // Dropping a std child neither kills nor reaps it; `Process::wait` does the reaping.
+        drop(child);
+        Ok(process)
+    }
+
+    /// Runs `argv` to completion, feeding it `stdin` and collecting its output.
+    /// Fails if the helper could not start the program.
+    pub async fn run(&self, argv: &[String], workspace: &Workspace, limits: Limits, stdin: &[u8]) -> io::Result<Output> {
+        let mut process = self.spawn(argv, workspace, limits)?;
+        let killer = process.killer();
+
+        if let Some(mut pipe) = process.stdin.take() {
+            let input = stdin.to_vec();
+            // Programs that never read their input must not stall the run.
+            tokio::spawn(async move {
+                let _ = pipe.write_all(&input).await;
+            });
+        }
+        let (stdout, stderr) = (process.stdout.take(), process.stderr.take());
+
+        let (mut out, mut err) = (Vec::new(), Vec::new());
+        let collect = collect(stdout, stderr, &mut out, &mut err, limits.output_bytes, &killer);
+        // Stray descendants can hold the pipes open after the process exits,
+        // so collection gets the same deadline as the process plus a grace period.
+        let collect = tokio::time::timeout(limits.wall_clock + Duration::from_secs(1), collect);
+        let (usage, output_truncated) = tokio::join!(process.wait(), collect);
+
+        let usage = usage?;
+        if usage.exit_code == HELPER_FAILED && err.starts_with(HELPER_ERROR_PREFIX) {
+            return Err(helper_error(&err));
+        }
+        Ok(Output {
+            stdout: out,
+            stderr: err,
+            output_truncated: output_truncated.unwrap_or(Ok(false))?,
+            usage,
+        })
+    }
+}
+
+/// The helper's own complaint, without its prefix.
+fn helper_error(stderr: &[u8]) -> io::Error {
+    let message = String::from_utf8_lossy(stderr.strip_prefix(HELPER_ERROR_PREFIX).unwrap_or(stderr));
+    match message.trim() {
+        "" => io::Error::other("The sandbox helper failed"),
+        message => io::Error::other(message.to_string()),
+    }
+}
+
+/// The variables sandboxed programs see. `HOME` and `TMPDIR` point at the
+/// working directory, the only place they can write.
+fn environment(workdir: &Path) -> Vec<(String, String)> {
+    let mut vars = vec![
+        ("PATH".to_string(), std::env::var("PATH").unwrap_or_else(|_| "/usr/local/bin:/usr/bin:/bin".into())),
+        ("HOME".to_string(), workdir.to_string_lossy().into_owned()),
+        ("TMPDIR".to_string(), workdir.to_string_lossy().into_owned()),
+        ("LANG".to_string(), "C.UTF-8".to_string()),
+    ];
+    for name in ["RUSTUP_HOME", "RUSTUP_TOOLCHAIN", "CARGO_HOME", "JAVA_HOME"] {
+        if let Ok(value) = std::env::var(name) {
+            vars.push((name.to_string(), value));
+        }
+    }
+    vars
+}
+
+/// Asks the helper to kill a sandboxed program, but only while the helper is
+/// unreaped so its pid cannot have been handed to someone else. The program
+/// itself runs as a sandbox user the server may not signal.
+#[derive(Clone)]
+pub struct Killer {
+    pid: libc::pid_t,
+    /// How many generations below the helper the program runs.
+    depth: u32,
+    reaped: Arc<Mutex<bool>>,
+}
+
+impl Killer {
+    /// The program's peak resident set so far, from `/proc`. `wait4`'s
+    /// `ru_maxrss` is no use here: it also counts the helper's forked copies.
+    fn resident_peak_kb(&self) -> u64 {
+        let reaped = self.reaped.lock().unwrap_or_else(|e| e.into_inner());
+        if *reaped {
+            return 0;
+        }
+        let mut pid = self.pid;
+        for _ in 0..self.depth {
+            let children = std::fs::read_to_string(format!("/proc/{}/task/{}/children", pid, pid)).unwrap_or_default();
+            match children.split_whitespace().next().and_then(|child| child.parse().ok()) {
+                Some(child) => pid = child,
+                None => return 0,
+            }
+        }
+        std::fs::read_to_string(format!("/proc/{}/status", pid))
+            .ok()
+            .and_then(|status| {
+                let line = status.lines().find(|line| line.starts_with("VmHWM:"))?;
+                line.split_whitespace().nth(1)?.parse().ok()
+            })
+            .unwrap_or(0)
+    }
+
+    pub fn kill(&self) {
+        let reaped = self.reaped.lock().unwrap_or_else(|e| e.into_inner());
+        if !*reaped {
+            // SAFETY: signalling our own child; failures (already gone) are harmless.
+            unsafe { libc::kill(self.pid, libc::SIGTERM) };
+        }
+    }
+}
+
+/// A running sandboxed process. Take the pipes before calling `wait`.
+pub struct Process {
+    killer: Killer,
+    pub stdin: Option<ChildStdin>,
+    pub stdout: Option<ChildStdout>,
+    pub stderr: Option<ChildStderr>,
+    started: Instant,
+    limits: Limits,
+    waiting: bool,
+}
+
+impl Drop for Process {
+    /// An abandoned process must not outlive the request that started it.
+    fn drop(&mut self) {
+        self.killer.kill();
+        if !self.waiting {
+            let killer = self.killer.clone();
+            std::thread::spawn(move || reap(&killer));
+        }
+    }
+}
+
+impl Process {
+    pub fn killer(&self) -> Killer {
+        self.killer.clone()
+    }
+
+    /// Waits for the process to exit, killing it once the wall-clock limit
+    /// runs out. The helper kills whatever it left running.
+    pub async fn wait(mut self) -> io::Result<Usage> {
+        self.waiting = true;
+        let killer = self.killer.clone();
+        let mut waiter = tokio::task::spawn_blocking(move || reap(&killer));
+        let deadline = tokio::time::sleep(self.limits.wall_clock);
+        tokio::pin!(deadline);
+        let mut sampler = tokio::time::interval(Duration::from_millis(10));
+        let (mut timed_out, mut peak_kb) = (false, 0);
+
+        let result = loop {
+            tokio::select! {
+                result = &mut waiter => break result,
+                _ = &mut deadline, if !timed_out => {
+                    self.killer.kill();
+                    timed_out = true;
+                }
+                _ = sampler.tick() => peak_kb = peak_kb.max(self.killer.resident_peak_kb()),
+            }
+        };
+        let (status, rusage) = result.map_err(io::Error::other)??;
+
+        let signal = libc::WIFSIGNALED(status).then(|| libc::WTERMSIG(status));
+        let exit_code = match signal {
+            Some(signal) => 128 + signal,
+            None => libc::WEXITSTATUS(status),
+        };
+        let millis = |time: libc::timeval| time.tv_sec as u64 * 1000 + time.tv_usec as u64 / 1000;
+        Ok(Usage {
+            exit_code,
+            signal,
+            timed_out,
+            wall_time_ms: self.started.elapsed().as_millis() as u64,
+            cpu_time_ms: millis(rusage.ru_utime) + millis(rusage.ru_stime),
+            max_memory_kb: peak_kb,
+        })
+    }
+}
+
+/// Blocks until the helper exits (it has killed whatever the program left
+/// running by then), then reaps it. The lock is only taken for the reaping,
+/// so `kill` works while this waits.
+fn reap(killer: &Killer) -> io::Result<(libc::c_int, libc::rusage)> {
+    // SAFETY: waitid/wait4 only write into the zeroed structs passed to them.
+    unsafe {
+        let mut info: libc::siginfo_t = std::mem::zeroed();
+        while libc::waitid(libc::P_PID, killer.pid as libc::id_t, &mut info, libc::WEXITED | libc::WNOWAIT) < 0 {
+            let error = io::Error::last_os_error();
+            if error.kind() != io::ErrorKind::Interrupted {
+                return Err(error);
+            }
+        }
+
+        let mut reaped = killer.reaped.lock().unwrap_or_else(|e| e.into_inner());
+        let mut status = 0;
+        let mut usage: libc::rusage = std::mem::zeroed();
+        while libc::wait4(killer.pid, &mut status, 0, &mut usage) < 0 {
+            let error = io::Error::last_os_error();
+            if error.kind() != io::ErrorKind::Interrupted {
+                return Err(error);
+            }
+        }
+        *reaped = true;
+        Ok((status, usage))
+    }
+}
+
+/// Reads from `pipe`, or waits forever once it has been closed and taken away.
+pub async fn read_from(pipe: &mut Option<impl AsyncRead + Unpin>, buffer: &mut [u8]) -> io::Result<usize> {
+    match pipe {
+        Some(pipe) => pipe.read(buffer).await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Reads both pipes until they close, keeping at most `limit` bytes between
+/// them. Going over the limit kills the process; returns whether that happened.
+async fn collect(
+    mut stdout: Option<ChildStdout>,
+    mut stderr: Option<ChildStderr>,
+    out: &mut Vec<u8>,
+    err: &mut Vec<u8>,
+    limit: usize,
+    killer: &Killer,
+) -> io::Result<bool> {
+    let mut truncated = false;
+    let (mut out_chunk, mut err_chunk) = ([0u8; 8192], [0u8; 8192]);
+
+    while stdout.is_some() || stderr.is_some() {
+        let (read, from_stdout) = tokio::select! {
+            read = read_from(&mut stdout, &mut out_chunk) => {
+                let read = read?;
+                if read == 0 { stdout = None; }
+                (read, true)
+            }
+            read = read_from(&mut stderr, &mut err_chunk) => {
+                let read = read?;
+                if read == 0 { stderr = None; }
+                (read, false)
+            }
+        };
+
+        let room = limit.saturating_sub(out.len() + err.len());
+        if read > room && !truncated {
+            truncated = true;
+            killer.kill();
+        }
+        let kept = read.min(room);
+        if from_stdout {
+            out.extend_from_slice(&out_chunk[..kept]);
+        } else {
+            err.extend_from_slice(&err_chunk[..kept]);
+        }
+    }
+    Ok(truncated)
+}
@@ -0,0 +1,424 @@
+use serde::Deserialize;
+
+use crate::executor::toolchain::{Plan, Toolchain};
+use crate::model::test_report::{TestOutcome, TestResult};
+
+/// Where a harness that reports through a file writes it, in the working directory.
+pub const REPORT_FILE: &str = ".codecollab-tests.json";
+
+/// The built test program; projects often have a `tests` folder already.
+const TEST_BINARY: &str = ".codecollab-tests";
+
+/// Per-test output kept in a report; the full streams come back with the run.
+const MAX_TEST_OUTPUT: usize = 8 * 1024;
+
+/// How to run a project's tests in one language and read back the results.
+#[derive(Debug, Clone, Copy)]
+pub struct Harness {
+    /// The `Toolchain::language` whose compiler and limits the tests use.
+    pub language: &'static str,
+    /// Commands for the whole suite, or for one test file given as `entry`.
+    /// Fails with a message when the tests cannot be located.
+    pub plan: fn(entry: Option<&str>, files: &[&str]) -> Result<Plan, String>,
+    pub report: Report,
+}
+
+/// Where a harness's per-test results come from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Report {
+    /// libtest's `test name ... ok` lines on stdout, from `cargo test` or `rustc --test`.
+    Libtest,
+    /// Test Anything Protocol on stdout: `ok 1 - name`, `not ok 2 - name`.
+    Tap,
+    /// JSON lines in `REPORT_FILE`, written by `PYTEST_PLUGIN`.
+    Pytest,
+    /// jest's `--json` report in `REPORT_FILE`.
+    Jest,
+}
+
+pub const HARNESSES: &[Harness] = &[
+    Harness { language: "python", plan: pytest, report: Report::Pytest },
+    Harness { language: "rust", plan: cargo_test, report: Report::Libtest },
+    Harness { language: "c", plan: c_harness, report: Report::Tap },
+    Harness { language: "javascript", plan: jest, report: Report::Jest },
+];
+
+/// Loaded with `pytest -p`; records each test's outcome as a JSON line.
+const PYTEST_PLUGIN: (&str, &str) = (
+    "codecollab_pytest.py",
+    r#"import json
+
+
+def pytest_runtest_logreport(report):
+    if report.when != "call" and report.passed:
+        return
+    output = report.capstdout + report.capstderr
+    if report.failed:
+        output += report.longreprtext
+    with open(".codecollab-tests.json", "a") as results:
+        results.write(json.dumps({
+            "name": report.nodeid,
+            "outcome": report.outcome,
+            "duration": report.duration,
+            "output": output,
+        }) + "\n")
+"#,
+);
+
+impl Harness {
+    pub fn find(toolchain: &Toolchain) -> Option<&'static Harness> {
+        HARNESSES.iter().find(|harness| harness.language == toolchain.language)
+    }
+
+    /// Guesses the test framework from the project's files.
+    pub fn detect(files: &[&str]) -> Option<&'static Harness> {
+        let name = |path: &&str| path.rsplit('/').next().unwrap_or(path).to_string();
+        let language = if files.contains(&"Cargo.toml") {
+            "rust"
+        } else if files.iter().map(name).any(|n| n.ends_with(".py") && (n.starts_with("test_") || n.ends_with("_test.py"))) {
+            "python"
+        } else if files.iter().map(name).any(|n| n.ends_with(".test.js") || n.ends_with(".spec.js")) {
+            "javascript"
+        } else if files.iter().map(name).any(|n| is_c_test(&n)) {
+            "c"
+        } else {
+            return None;
+        };
+        HARNESSES.iter().find(|harness| harness.language == language)
+    }
+
+    /// Files the harness needs next to the project's own.
+    pub fn support_files(&self) -> Vec<(String, String)> {
+        match self.report {
+            Report::Pytest => vec![(PYTEST_PLUGIN.0.to_string(), PYTEST_PLUGIN.1.to_string())],
+            _ => Vec::new(),
+        }
+    }
+}
+
+impl Report {
+    /// Whether results are read from `REPORT_FILE` rather than stdout.
+    pub fn uses_file(self) -> bool {
+        matches!(self, Report::Pytest | Report::Jest)
+    }
+
+    /// Per-test results from the run's stdout or the report file's contents.
+    pub fn parse(self, output: &str) -> Vec<TestResult> {
+        let mut results = match self {
+            Report::Libtest => parse_libtest(output),
+            Report::Tap => parse_tap(output),
+            Report::Pytest => parse_pytest(output),
+            Report::Jest => parse_jest(output),
+        };
+        for result in &mut results {
+            truncate(&mut result.output);
+        }
+        results
+    }
+}
+
+fn command(parts: &[&str]) -> Vec<String> {
+    parts.iter().map(|part| part.to_string()).collect()
+}
+
+fn pytest(entry: Option<&str>, _files: &[&str]) -> Result<Plan, String> {
+    let mut run = command(&["python3", "-E", "-s", "-m", "pytest", "-q", "-p", "codecollab_pytest", "-p", "no:cacheprovider"]);
+    run.extend(entry.map(str::to_string));
+    Ok(Plan { compile: None, run })
+}
+
+/// Cargo projects run every test target; otherwise `entry` is built as a test crate.
+fn cargo_test(entry: Option<&str>, files: &[&str]) -> Result<Plan, String> {
+    if files.contains(&"Cargo.toml") {
+        let mut run = command(&["cargo", "test", "--offline", "--no-fail-fast"]);
+        // A file stem names the test target or filters test names.
+        if let Some(filter) = entry.and_then(|entry| entry.rsplit('/').next()).map(|name| name.trim_end_matches(".rs")) {
+            run.push(filter.to_string());
+        }
+        return Ok(Plan { compile: Some(command(&["cargo", "test", "--no-run", "--offline", "--message-format=json"])), run });
+    }
+    let entry = entry.ok_or("Rust tests outside a Cargo project need an entry file")?;
+    Ok(Plan {
+        compile: Some(command(&["rustc", "--edition", "2021", "--error-format=json", "--test", "-o", TEST_BINARY, entry])),
+        run: vec![format!("./{}", TEST_BINARY)],
+    })
+}
+
+fn is_c_test(name: &str) -> bool {
+    name.ends_with(".c") && (name.starts_with("test_") || name.ends_with("_test.c"))
+}
+
+/// The entry (a test program printing TAP) is linked with the project's other
+/// sources, leaving out the other test programs and any `main.c`, whose
+/// `main` would clash with the harness's.
+fn c_harness(entry: Option<&str>, files: &[&str]) -> Result<Plan, String> {
+    let entry = match entry {
+        Some(entry) => entry,
+        None => {
+            let mut tests = files.iter().filter(|path| is_c_test(path.rsplit('/').next().unwrap_or(path)));
+            match (tests.next(), tests.next()) {
+                (Some(only), None) => only,
+                (None, _) => return Err("No C test program found; name it test_*.c or *_test.c".into()),
+                _ => return Err("Several C test programs found; pick one as the entry".into()),
+            }
+        }
+    };
+
+    let mut compile = command(&["gcc", "-O2", "-Wall", "-o", TEST_BINARY, entry]);
+    let mut sources: Vec<&str> = files
+        .iter()
+        .copied()
+        .filter(|path| path.ends_with(".c") && *path != entry)
+        .filter(|path| {
+            let name = path.rsplit('/').next().unwrap_or(path);
+            name != "main.c" && !is_c_test(name)
+        })
+        .collect();
+    sources.sort();
+    compile.extend(sources.iter().map(|path| path.to_string()));
+    compile.push("-lm".into());
+    Ok(Plan { compile: Some(compile), run: vec![format!("./{}", TEST_BINARY)] })
+}
+
+fn jest(entry: Option<&str>, _files: &[&str]) -> Result<Plan, String> {
+    let report = format!("--outputFile={}", REPORT_FILE);
+    let mut run = command(&["jest", "--ci", "--runInBand", "--json", &report]);
+    run.extend(entry.map(str::to_string));
+    Ok(Plan { compile: None, run })
+}
+
+fn truncate(output: &mut String) {
+    if output.len() > MAX_TEST_OUTPUT {
+        let mut end = MAX_TEST_OUTPUT;
+        while !output.is_char_boundary(end) {
+            end -= 1;
+        }
+        output.truncate(end);
+        output.push_str("\n[output truncated]");
+    }
+}
+
+fn parse_libtest(output: &str) -> Vec<TestResult> {
+    let mut results: Vec<TestResult> = Vec::new();
+    let mut capturing: Option<usize> = None;
+
+    for line in output.lines() {
+        if let Some(rest) = line.strip_prefix("test ") {
+            if let Some((name, status)) = rest.rsplit_once(" ... ") {
+                let outcome = match status.trim() {
+                    "ok" => TestOutcome::Passed,
+                    "FAILED" => TestOutcome::Failed,
+                    status if status.starts_with("ignored") => TestOutcome::Skipped,
+                    _ => continue,
+                };
+                results.push(TestResult { name: name.to_string(), outcome, duration_ms: None, output: String::new() });
+                continue;
+            }
+        }
+        // Failures print their captured output as `---- name stdout ----` sections.
+        if let Some(name) = line.strip_prefix("---- ").and_then(|rest| rest.strip_suffix(" stdout ----")) {
+            capturing = results.iter().rposition(|result| result.name == name);
+            continue;
+        }
+        if line == "failures:" || line.starts_with("test result:") {
+            capturing = None;
+            continue;
+        }
+        if let Some(index) = capturing {
+            let output = &mut results[index].output;
+            output.push_str(line);
+            output.push('\n');
+        }
+    }
+    for result in &mut results {
+        result.output = result.output.trim_end().to_string();
+    }
+    results
+}
+
+fn parse_tap(output: &str) -> Vec<TestResult> {
+    let mut results: Vec<TestResult> = Vec::new();
+
+    for line in output.lines() {
+        let (passed, rest) = if let Some(rest) = line.strip_prefix("not ok") {
+            (false, rest)
+        } else if let Some(rest) = line.strip_prefix("ok") {
+            (true, rest)
+        } else {
+            // Comments after a test are its diagnostics.
+            if let (Some(comment), Some(last)) = (line.strip_prefix('#'), results.last_mut()) {
+                if !last.output.is_empty() {
+                    last.output.push('\n');
+                }
+                last.output.push_str(comment.trim());
+            }
+            continue;
+        };
+
+        let rest = rest.trim_start().trim_start_matches(|c: char| c.is_ascii_digit()).trim_start();
+        let (description, directive) = match rest.split_once('#') {
+            Some((description, directive)) => (description, directive.trim().to_ascii_uppercase()),
+            None => (rest, String::new()),
+        };
+        let outcome = if directive.starts_with("SKIP") || directive.starts_with("TODO") {
+            TestOutcome::Skipped
+        } else if passed {
+            TestOutcome::Passed
+        } else {
+            TestOutcome::Failed
+        };
+        let name = description.trim().trim_start_matches('-').trim();
+        let name = if name.is_empty() { format!("test {}", results.len() + 1) } else { name.to_string() };
+        results.push(TestResult { name, outcome, duration_ms: None, output: String::new() });
+    }
+    results
+}
+
+#[derive(Deserialize)]
+struct PytestLine {
+    name: String,
+    outcome: String,
+    duration: f64,
+    output: String,
+}
+
+fn parse_pytest(report: &str) -> Vec<TestResult> {
+    report
+        .lines()
+        .filter_map(|line| serde_json::from_str::<PytestLine>(line).ok())
+        .map(|line| TestResult {
+            name: line.name,
+            outcome: match line.outcome.as_str() {
+                "passed" => TestOutcome::Passed,
+                "failed" => TestOutcome::Failed,
+                _ => TestOutcome::Skipped,
+            },
+            duration_ms: Some((line.duration * 1000.0).round() as u64),
+            output: line.output,
+        })
+        .collect()
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct JestReport {
+    test_results: Vec<JestFile>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct JestFile {
+    assertion_results: Vec<JestAssertion>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct JestAssertion {
+    full_name: String,
+    status: String,
+    duration: Option<f64>,
+    #[serde(default)]
+    failure_messages: Vec<String>,
+}
+
+fn parse_jest(report: &str) -> Vec<TestResult> {
+    let Ok(report) = serde_json::from_str::<JestReport>(report) else { return Vec::new() };
+    report
+        .test_results
+        .into_iter()
+        .flat_map(|file| file.assertion_results)
+        .map(|assertion| TestResult {
+            name: assertion.full_name,
+            outcome: match assertion.status.as_str() {
+                "passed" => TestOutcome::Passed,
+                "failed" => TestOutcome::Failed,
+                _ => TestOutcome::Skipped,
+            },
+            duration_ms: assertion.duration.map(|ms| ms.round() as u64),
+            output: assertion.failure_messages.join("\n"),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn outcomes(results: &[TestResult]) -> Vec<(&str, TestOutcome)> {
+        results.iter().map(|result| (result.name.as_str(), result.outcome)).collect()
+    }
+
+    #[test]
+    fn libtest_and_tap_output_is_parsed() {
+        let libtest = "\
+running 3 tests
+test maths::adds ... ok
+test maths::divides ... FAILED
+test maths::slow ... ignored, takes a minute
+
+failures:
+
+---- maths::divides stdout ----
+thread 'maths::divides' panicked at src/lib.rs:9:9:
+attempt to divide by zero
+
+
+failures:
+    maths::divides
+
+test result: FAILED. 1 passed; 1 failed; 1 ignored; 0 measured; 0 filtered out
+";
+        let results = Report::Libtest.parse(libtest);
+        assert_eq!(
+            outcomes(&results),
+            vec![("maths::adds", TestOutcome::Passed), ("maths::divides", TestOutcome::Failed), ("maths::slow", TestOutcome::Skipped)]
+        );
+        assert_eq!(results[1].output, "thread 'maths::divides' panicked at src/lib.rs:9:9:\nattempt to divide by zero");
+
+        let tap = "1..4\nok 1 - adds\nnot ok 2 - divides\n# expected 2, got 0\nok 3 # SKIP no floats yet\nnot ok 4 - rounds # TODO\n";
+        let results = Report::Tap.parse(tap);
+        assert_eq!(
+            outcomes(&results),
+            vec![
+                ("adds", TestOutcome::Passed),
+                ("divides", TestOutcome::Failed),
+                ("test 3", TestOutcome::Skipped),
+                ("rounds", TestOutcome::Skipped),
+            ]
+        );
+        assert_eq!(results[1].output, "expected 2, got 0");
+    }
+
+    #[test]
+    fn pytest_and_jest_reports_are_parsed() {
+        let pytest = r#"{"name": "tests/test_maths.py::test_adds", "outcome": "passed", "duration": 0.0021, "output": ""}
+{"name": "tests/test_maths.py::test_divides", "outcome": "failed", "duration": 0.5, "output": "ZeroDivisionError"}
+{"name": "tests/test_maths.py::test_later", "outcome": "skipped", "duration": 0.0, "output": ""}
+"#;
+        let results = Report::Pytest.parse(pytest);
+        assert_eq!(outcomes(&results)[1], ("tests/test_maths.py::test_divides", TestOutcome::Failed));
+        assert_eq!((results[0].duration_ms, results[1].duration_ms), (Some(2), Some(500)));
+        assert_eq!(results[2].outcome, TestOutcome::Skipped);
+
+        let jest = r#"{"numTotalTests": 2, "testResults": [{"name": "/w/sum.test.js", "assertionResults": [
+            {"fullName": "sum adds", "status": "passed", "duration": 3, "failureMessages": []},
+            {"fullName": "sum overflows", "status": "pending", "duration": null, "failureMessages": []},
+            {"fullName": "sum fails", "status": "failed", "duration": 12, "failureMessages": ["expected 3", "received 4"]}
+        ]}]}"#;
+        let results = Report::Jest.parse(jest);
+        assert_eq!(
+            outcomes(&results),
+            vec![("sum adds", TestOutcome::Passed), ("sum overflows", TestOutcome::Skipped), ("sum fails", TestOutcome::Failed)]
+        );
+        assert_eq!(results[2].output, "expected 3\nreceived 4");
+    }
+
+    #[test]
+    fn c_harness_links_everything_but_other_programs() {
+        let files = ["main.c", "lib/maths.c", "tests/test_maths.c", "tests/test_io.c", "lib/maths.h"];
+        let plan = c_harness(Some("tests/test_maths.c"), &files).unwrap();
+        assert_eq!(plan.compile.unwrap()[5..], ["tests/test_maths.c", "lib/maths.c", "-lm"]);
+        assert!(c_harness(None, &files).is_err());
+        assert_eq!(Harness::detect(&files).map(|harness| harness.language), Some("c"));
+    }
+}
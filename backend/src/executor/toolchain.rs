@@ -0,0 +1,132 @@
+use crate::executor::diagnostics::Format;
+
+/// How to build and run a program in one language, from a single submitted
+/// file or from a whole project laid out in the sandbox's working directory.
+#[derive(Debug, Clone, Copy)]
+pub struct Toolchain {
+    pub language: &'static str,
+    /// Other names clients use for the language.
+    pub aliases: &'static [&'static str],
+    /// File name for single-file submissions.
+    pub source: &'static str,
+    /// Commands for an entry file, given every file path in the project.
+    pub plan: fn(entry: &str, files: &[&str]) -> Plan,
+    /// Address-space limit to use instead of the configured one, for runtimes
+    /// that reserve far more virtual memory than they ever touch.
+    pub address_space: Option<u64>,
+    /// How the compiler (or, without a compile step, the program) reports errors.
+    pub diagnostics: Option<Format>,
+}
+
+/// The commands for one build, run from the working directory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Plan {
+    pub compile: Option<Vec<String>>,
+    pub run: Vec<String>,
+}
+
+const GIB: u64 = 1024 * 1024 * 1024;
+
+pub const TOOLCHAINS: &[Toolchain] = &[
+    Toolchain { language: "python", aliases: &["py", "python3"], source: "main.py", plan: python, address_space: None, diagnostics: Some(Format::Python) },
+    Toolchain { language: "c", aliases: &[], source: "main.c", plan: c, address_space: None, diagnostics: Some(Format::Gcc) },
+    Toolchain { language: "cpp", aliases: &["c++"], source: "main.cpp", plan: cpp, address_space: None, diagnostics: Some(Format::Gcc) },
+    Toolchain { language: "rust", aliases: &["rs"], source: "main.rs", plan: rust, address_space: None, diagnostics: Some(Format::Rustc) },
+    Toolchain { language: "javascript", aliases: &["js", "node"], source: "main.js", plan: javascript, address_space: Some(GIB), diagnostics: None },
+    Toolchain { language: "typescript", aliases: &["ts"], source: "main.ts", plan: typescript, address_space: Some(GIB), diagnostics: Some(Format::Tsc) },
+    Toolchain { language: "java", aliases: &[], source: "Main.java", plan: java, address_space: Some(GIB), diagnostics: Some(Format::Gcc) },
+];
+
+impl Toolchain {
+    /// Looks a toolchain up by language name or alias, ignoring case.
+    pub fn find(language: &str) -> Option<&'static Toolchain> {
+        let language = language.trim().to_lowercase();
+        TOOLCHAINS
+            .iter()
+            .find(|toolchain| toolchain.language == language || toolchain.aliases.contains(&language.as_str()))
+    }
+
+    /// The plan for a single submission saved as `self.source`.
+    pub fn single_file(&self) -> Plan {
+        (self.plan)(self.source, &[self.source])
+    }
+}
+
+fn command(parts: &[&str]) -> Vec<String> {
+    parts.iter().map(|part| part.to_string()).collect()
+}
+
+/// `files` with one of `extensions`, in a stable order.
+fn sources(files: &[&str], extensions: &[&str]) -> Vec<String> {
+    let mut found: Vec<String> = files
+        .iter()
+        .filter(|path| path.rsplit_once('.').is_some_and(|(_, ext)| extensions.contains(&ext)))
+        .map(|path| path.to_string())
+        .collect();
+    found.sort();
+    found
+}
+
+/// `pkg/mod.py` → `pkg.mod`, so packages import each other the usual way.
+fn python(entry: &str, _files: &[&str]) -> Plan {
+    let module = entry.strip_suffix(".py").unwrap_or(entry).replace('/', ".");
+    Plan { compile: None, run: command(&["python3", "-E", "-s", "-m", &module]) }
+}
+
+// Every translation unit is linked together; headers are found relative to their includers.
+fn c(_entry: &str, files: &[&str]) -> Plan {
+    let mut compile = command(&["gcc", "-O2", "-Wall", "-o", "main"]);
+    compile.extend(sources(files, &["c"]));
+    compile.push("-lm".into());
+    Plan { compile: Some(compile), run: command(&["./main"]) }
+}
+
+fn cpp(_entry: &str, files: &[&str]) -> Plan {
+    let mut compile = command(&["g++", "-O2", "-Wall", "-std=c++17", "-o", "main"]);
+    compile.extend(sources(files, &["cpp", "cc", "cxx"]));
+    Plan { compile: Some(compile), run: command(&["./main"]) }
+}
+
+/// Cargo projects are built with cargo (offline: the sandbox has no network);
+/// anything else is compiled from the entry file, which pulls in its `mod`s.
+/// Both report errors as JSON for `Format::Rustc`.
+fn rust(entry: &str, files: &[&str]) -> Plan {
+    if files.contains(&"Cargo.toml") {
+        return Plan {
+            compile: Some(command(&["cargo", "build", "--offline", "--quiet", "--message-format=json"])),
+            run: command(&["cargo", "run", "--offline", "--quiet"]),
+        };
+    }
+    Plan {
+        compile: Some(command(&["rustc", "--edition", "2021", "--error-format=json", "-O", "-o", "main", entry])),
+        run: command(&["./main"]),
+    }
+}
+
+fn javascript(entry: &str, _files: &[&str]) -> Plan {
+    Plan { compile: None, run: command(&["node", entry]) }
+}
+
+/// Each `.ts` file compiles to the `.js` file next to it, which node then runs.
+fn typescript(entry: &str, files: &[&str]) -> Plan {
+    let mut compile = command(&["tsc", "--pretty", "false", "--target", "es2020", "--module", "commonjs"]);
+    compile.extend(sources(files, &["ts"]));
+    let script = format!("{}.js", entry.strip_suffix(".ts").unwrap_or(entry));
+    Plan { compile: Some(compile), run: command(&["node", &script]) }
+}
+
+const JVM_FLAGS: &[&str] = &["-Xmx256m", "-XX:+UseSerialGC", "-XX:ReservedCodeCacheSize=32m", "-XX:CompressedClassSpaceSize=64m"];
+
+/// The entry's path names its class, so `com/example/App.java` runs `com.example.App`.
+fn java(entry: &str, files: &[&str]) -> Plan {
+    let mut compile = vec!["javac".to_string()];
+    compile.extend(JVM_FLAGS.iter().map(|flag| format!("-J{}", flag)));
+    compile.extend(command(&["-d", "."]));
+    compile.extend(sources(files, &["java"]));
+
+    let class = entry.strip_suffix(".java").unwrap_or(entry).replace('/', ".");
+    let mut run = vec!["java".to_string()];
+    run.extend(command(JVM_FLAGS));
+    run.extend(command(&["-cp", ".", &class]));
+    Plan { compile: Some(compile), run }
+}
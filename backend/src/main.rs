@@ -1,5 +1,5 @@
-use axum::Router;
-use std::{net::SocketAddr, sync::Arc};
+use axum::{Extension, Router};
+use std::{net::SocketAddr, path::PathBuf, sync::Arc, time::Duration};
 use tokio::net::TcpListener;
 use tower_http::cors::CorsLayer;
 // use http::header::{AUTHORIZATION, CONTENT_TYPE, ACCEPT};
@@ -10,34 +10,88 @@ use http::{Method, header::{AUTHORIZATION, CONTENT_TYPE, ACCEPT}};
 mod model;
 mod connection {
     pub mod auth;
+    pub mod document;
     pub mod firebase;
-    pub mod real_time; 
+    pub mod local;
+    pub mod password;
+    pub mod real_time;
+    pub mod session;
+    pub mod storage;
+}
+mod ai {
+    pub mod diff;
+    pub mod project_review;
+    pub mod provider;
+    pub mod review;
+}
+mod executor {
+    pub mod diagnostics;
+    pub mod runner;
+    pub mod sandbox;
+    pub mod testing;
+    pub mod toolchain;
 }
 mod routers {
+    pub mod access;
+    pub mod archive;
+    pub mod build;
     pub mod login;
     pub mod project;
     pub mod ai_review;
+    pub mod collab;
+    pub mod history;
+    pub mod invite;
+    pub mod files;
+    pub mod folders;
+    pub mod git;
+    pub mod run;
+    pub mod user;
 }
 
 
 
-use connection::firebase::FirebaseService;
-use crate::connection::real_time::RealtimeDatabaseService;
+use crate::connection::document::{spawn_persistence, DocumentStore};
+use crate::connection::password::PasswordHasher;
+use crate::connection::session::TokenService;
+use crate::connection::real_time::CollabHub;
+use crate::connection::storage;
+use crate::executor::runner::Executor;
 
 use routers::login::auth_routes;
 use routers::project::project_routes;
-use routers::ai_review::ai_review_routes;
+use routers::ai_review::{ai_fix_routes, ai_review_routes, file_review_routes, project_review_routes};
+use routers::collab::collab_routes;
+use routers::history::history_routes;
+use routers::invite::invite_routes;
+use routers::files::file_routes;
+use routers::folders::folder_routes;
+use routers::archive::archive_routes;
+use routers::git::git_routes;
+use routers::build::build_routes;
+use routers::run::run_routes;
+use routers::user::user_routes;
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    // ✅ Initialize Firebase
-    let firebase = Arc::new(FirebaseService::new().await);
+    // ✅ Initialize storage (Firestore by default, or the local store with STORAGE_BACKEND=local)
+    let storage = storage::connect().await.map_err(|e| anyhow::anyhow!(e))?;
+    let passwords = Arc::new(PasswordHasher::from_env().map_err(|e| anyhow::anyhow!(e))?);
+    let tokens = Arc::new(TokenService::from_env());
+    // ✅ Sandbox for running submitted code
+    let executor = Arc::new(Executor::from_env().map_err(|e| anyhow::anyhow!(e))?);
+    // ✅ Model used for code reviews (Ollama by default, see AI_PROVIDER)
+    let ai = ai::provider::from_env().map_err(|e| anyhow::anyhow!(e))?;
+    // ✅ Background reviews of whole projects (see AI_REVIEW_WORKERS and AI_CONTEXT_CHARS)
+    let reviews = Arc::new(ai::project_review::ReviewJobs::from_env(Arc::clone(&ai)).map_err(|e| anyhow::anyhow!(e))?);
+
+    // ✅ Real-time collaboration rooms, one per open file
+    let hub = Arc::new(CollabHub::new());
+    let documents = Arc::new(DocumentStore::new(Arc::clone(&storage)));
+    spawn_persistence(Arc::clone(&documents), Arc::clone(&hub), Duration::from_secs(5));
 
-    println!("Access token: {}", firebase.access_token);
+    // ✅ Local git repositories can only be imported from / exported to below this directory
+    let git_repos_root = std::env::var("GIT_REPOS_ROOT").ok().map(PathBuf::from);
 
-    let realtime_db = RealtimeDatabaseService::new("dcode-7b1a0", &firebase.access_token).await;
-    println!("Realtime Database Service initialized with project ID: {}", realtime_db.project_id);
-    println!("Access token: {}", realtime_db.access_token);
     // ✅ CORS setup
     let cors = CorsLayer::new()
     .allow_origin([
@@ -52,9 +106,23 @@ async fn main() -> anyhow::Result<()> {
 
     // ✅ Build Axum app
     let app = Router::new()
-        .nest("/auth", auth_routes(Arc::clone(&firebase)))
-        .nest("/project", project_routes(Arc::clone(&firebase)))
-        .nest("/ai", ai_review_routes())
+        .nest("/auth", auth_routes(Arc::clone(&storage), passwords, Arc::clone(&tokens)))
+        .nest("/project", project_routes(Arc::clone(&storage)))
+        .nest("/project/{project_id}/files", file_routes(Arc::clone(&storage), Arc::clone(&hub), Arc::clone(&documents)))
+        .nest("/project/{project_id}", folder_routes(Arc::clone(&storage), Arc::clone(&documents)))
+        .nest("/project/{project_id}", archive_routes(Arc::clone(&storage), Arc::clone(&documents)))
+        .nest("/project/{project_id}", git_routes(Arc::clone(&storage), Arc::clone(&documents), git_repos_root))
+        .nest("/project/{project_id}", build_routes(Arc::clone(&storage), Arc::clone(&documents), Arc::clone(&executor)))
+        .nest("/project/{project_id}", project_review_routes(Arc::clone(&storage), Arc::clone(&documents), reviews))
+        .nest("/invites", invite_routes(Arc::clone(&storage)))
+        .nest("/users", user_routes(Arc::clone(&storage)))
+        .nest("/ai", ai_review_routes(Arc::clone(&ai), Arc::clone(&executor)))
+        .nest("/ws", collab_routes(Arc::clone(&storage), Arc::clone(&hub), Arc::clone(&documents)))
+        .nest("/ws", run_routes(executor))
+        .nest("/files", history_routes(Arc::clone(&storage), Arc::clone(&hub), Arc::clone(&documents)))
+        .nest("/files", ai_fix_routes(Arc::clone(&storage), Arc::clone(&hub), Arc::clone(&documents)))
+        .nest("/files", file_review_routes(Arc::clone(&storage), Arc::clone(&documents), ai))
+        .layer(Extension(tokens))
         .layer(cors);
 
     let addr = SocketAddr::from(([0, 0, 0, 0], 5000));
@@ -65,3 +133,12 @@ async fn main() -> anyhow::Result<()> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod test_support {
+    pub mod app;
+    pub mod auth;
+    pub mod firestore;
+    pub mod http;
+    pub mod sandbox;
+}
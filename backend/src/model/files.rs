@@ -2,9 +2,14 @@ use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
 use uuid::Uuid;
 use crate::connection::firebase::FirebaseService;
+use crate::model::folder::{optional_uuid, optional_uuid_value};
+use crate::model::revision::{now_millis, FileRevision};
 use reqwest::StatusCode;
 use std::error::Error;
 
+/// Tries at saving content while other saves keep getting in first.
+const SAVE_ATTEMPTS: usize = 5;
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct File {
     pub id: Uuid,
@@ -13,60 +18,100 @@ pub struct File {
     pub file_type: String,
     pub owner: Uuid,
     pub project_id: Uuid,
+    /// Folder the file sits in; `None` for the project root.
+    #[serde(default)]
+    pub folder: Option<Uuid>,
+    /// Number of saved revisions; see `model::revision`.
+    #[serde(default)]
+    pub revision: u64,
+}
+
+/// Infers the language label stored in `File::file_type` from a file name's extension.
+pub fn file_type_from_name(name: &str) -> String {
+    match name.rsplit('.').next().unwrap_or("") {
+        "c" => "C",
+        "cpp" => "C++",
+        "py" => "Python",
+        "rs" => "Rust",
+        "js" => "JavaScript",
+        "ts" => "TypeScript",
+        "html" => "HTML",
+        "css" => "CSS",
+        ext => ext,
+    }.to_string()
+}
+
+fn parse_file(fields: &Value) -> Result<File, Box<dyn Error + Send + Sync>> {
+    Ok(File {
+        id: Uuid::parse_str(fields["id"]["stringValue"].as_str().unwrap_or(""))
+            .map_err(|e| Box::new(e) as Box<dyn Error + Send + Sync>)?,
+        name: fields["name"]["stringValue"].as_str().unwrap_or("").to_string(),
+        content: fields["content"]["stringValue"].as_str().unwrap_or("").to_string(),
+        file_type: fields["file_type"]["stringValue"].as_str().unwrap_or("").to_string(),
+        owner: Uuid::parse_str(fields["owner"]["stringValue"].as_str().unwrap_or(""))
+            .map_err(|e| Box::new(e) as Box<dyn Error + Send + Sync>)?,
+        project_id: Uuid::parse_str(fields["project_id"]["stringValue"].as_str().unwrap_or(""))
+            .map_err(|e| Box::new(e) as Box<dyn Error + Send + Sync>)?,
+        folder: optional_uuid(&fields["folder"]),
+        revision: fields["revision"]["integerValue"].as_str().and_then(|r| r.parse().ok()).unwrap_or(0),
+    })
 }
 
 impl FirebaseService {
-    pub async fn create_file(&self, name: String, owner: Uuid, project_id: Uuid) -> Result<Uuid, Box<dyn Error + Send + Sync>> {
+    /// Creates an empty file, appends it to its project's `files` and reserves
+    /// its name in a single commit, so a file is never left unlinked (or
+    /// linked to a project that does not exist) when one part fails. Returns
+    /// `None` if the name is already taken in `folder`.
+    pub async fn create_file(&self, name: String, owner: Uuid, project_id: Uuid, folder: Option<Uuid>) -> Result<Option<Uuid>, Box<dyn Error + Send + Sync>> {
         let file_id = Uuid::new_v4();
-        let content = "".to_string();
-
-        let file_type = match name.rsplit('.').next().unwrap_or("") {
-            "c" => "C",
-            "cpp" => "C++",
-            "py" => "Python",
-            "rs" => "Rust",
-            "js" => "JavaScript",
-            "ts" => "TypeScript",
-            "html" => "HTML",
-            "css" => "CSS",
-            ext => ext,
-        }.to_string();
-
-        let url = format!(
-            "https://firestore.googleapis.com/v1/projects/{}/databases/(default)/documents/files?documentId={}",
-            "your-firebase-project-id", file_id
-        );
+        let file_type = file_type_from_name(&name);
 
-        let body = json!({
-            "fields": {
-                "id": { "stringValue": file_id.to_string() },
-                "name": { "stringValue": name },
-                "content": { "stringValue": content },
-                "file_type": { "stringValue": file_type },
-                "owner": { "stringValue": owner.to_string() },
-                "project_id": { "stringValue": project_id.to_string() }
-            }
-        });
+        for _ in 0..SAVE_ATTEMPTS {
+            let writes = vec![
+                json!({
+                    "update": {
+                        "name": self.document_name("files", &file_id.to_string()),
+                        "fields": {
+                            "id": { "stringValue": file_id.to_string() },
+                            "name": { "stringValue": name },
+                            "content": { "stringValue": "" },
+                            "file_type": { "stringValue": file_type },
+                            "owner": { "stringValue": owner.to_string() },
+                            "project_id": { "stringValue": project_id.to_string() },
+                            "folder": optional_uuid_value(folder),
+                            "revision": { "integerValue": "0" }
+                        }
+                    },
+                    "currentDocument": { "exists": false }
+                }),
+                json!({
+                    "transform": {
+                        "document": self.document_name("projects", &project_id.to_string()),
+                        "fieldTransforms": [{
+                            "fieldPath": "files",
+                            "appendMissingElements": { "values": [{ "stringValue": file_id.to_string() }] }
+                        }]
+                    },
+                    "currentDocument": { "exists": true }
+                }),
+                self.reserve_name_write(project_id, folder, &name, file_id),
+            ];
 
-        let response = self.client.post(&url)
-            .bearer_auth(&self.access_token)
-            .json(&body)
-            .send()
-            .await?;
-
-        if response.status().is_success() {
-            Ok(file_id)
-        } else {
-            let error_text = response.text().await.unwrap_or_default();
-            Err(error_text.into())
+            if self.commit(writes).await? {
+                println!("✅ File {} created in project {}", file_id, project_id);
+                return Ok(Some(file_id));
+            }
+            if self.name_holder(project_id, folder, &name).await?.is_some() {
+                return Ok(None);
+            }
+            println!("🔁 File '{}' could not be created; retrying", name);
         }
+
+        Err(format!("File '{}' could not be created", name).into())
     }
 
     pub async fn get_file_by_id(&self, file_id: &str) -> Result<Option<File>, Box<dyn Error + Send + Sync>> {
-        let url = format!(
-            "https://firestore.googleapis.com/v1/projects/{}/databases/(default)/documents/files/{}",
-            "your-firebase-project-id", file_id
-        );
+        let url = format!("{}/files/{}", self.documents_url(), file_id);
 
         let response = self.client.get(&url)
             .bearer_auth(&self.access_token)
@@ -78,40 +123,22 @@ impl FirebaseService {
         }
 
         let json = response.json::<Value>().await?;
-        let fields = &json["fields"];
-
-        let file = File {
-            id: Uuid::parse_str(fields["id"]["stringValue"].as_str().unwrap_or(""))
-                .map_err(|e| Box::new(e) as Box<dyn Error + Send + Sync>)?,
-            name: fields["name"]["stringValue"].as_str().unwrap_or("").to_string(),
-            content: fields["content"]["stringValue"].as_str().unwrap_or("").to_string(),
-            file_type: fields["file_type"]["stringValue"].as_str().unwrap_or("").to_string(),
-            owner: Uuid::parse_str(fields["owner"]["stringValue"].as_str().unwrap_or(""))
-                .map_err(|e| Box::new(e) as Box<dyn Error + Send + Sync>)?,
-            project_id: Uuid::parse_str(fields["project_id"]["stringValue"].as_str().unwrap_or(""))
-                .map_err(|e| Box::new(e) as Box<dyn Error + Send + Sync>)?,
-        };
-
-        Ok(Some(file))
+        Ok(Some(parse_file(&json["fields"])?))
     }
 
-    pub async fn get_file_by_name(&self, name: &str) -> Result<Option<File>, Box<dyn Error + Send + Sync>> {
-        let url = format!(
-            "https://firestore.googleapis.com/v1/projects/{}/databases/(default)/documents:runQuery",
-            "your-firebase-project-id"
-        );
+    pub async fn list_project_files(&self, project_id: &str) -> Result<Vec<File>, Box<dyn Error + Send + Sync>> {
+        let url = format!("{}:runQuery", self.documents_url());
 
         let body = json!({
             "structuredQuery": {
                 "from": [{ "collectionId": "files" }],
                 "where": {
                     "fieldFilter": {
-                        "field": { "fieldPath": "name" },
+                        "field": { "fieldPath": "project_id" },
                         "op": "EQUAL",
-                        "value": { "stringValue": name }
+                        "value": { "stringValue": project_id }
                     }
-                },
-                "limit": 1
+                }
             }
         });
 
@@ -121,74 +148,152 @@ impl FirebaseService {
             .send()
             .await?;
 
+        if !response.status().is_success() {
+            let error = response.text().await.unwrap_or_default();
+            return Err(error.into());
+        }
+
         let results = response.json::<Vec<Value>>().await?;
+        results
+            .iter()
+            .filter_map(|r| r.get("document"))
+            .map(|doc| parse_file(&doc["fields"]))
+            .collect()
+    }
 
-        if let Some(doc) = results.first().and_then(|r| r.get("document")) {
-            let fields = &doc["fields"];
-            let file = File {
-                id: Uuid::parse_str(fields["id"]["stringValue"].as_str().unwrap_or(""))
-                    .map_err(|e| Box::new(e) as Box<dyn Error + Send + Sync>)?,
-                name: fields["name"]["stringValue"].as_str().unwrap_or("").to_string(),
-                content: fields["content"]["stringValue"].as_str().unwrap_or("").to_string(),
-                file_type: fields["file_type"]["stringValue"].as_str().unwrap_or("").to_string(),
-                owner: Uuid::parse_str(fields["owner"]["stringValue"].as_str().unwrap_or(""))
-                    .map_err(|e| Box::new(e) as Box<dyn Error + Send + Sync>)?,
-                project_id: Uuid::parse_str(fields["project_id"]["stringValue"].as_str().unwrap_or(""))
-                    .map_err(|e| Box::new(e) as Box<dyn Error + Send + Sync>)?,
-            };
-            Ok(Some(file))
-        } else {
-            Ok(None)
-        }
+    /// Saves new content and records it as the next revision of the file, in
+    /// one commit that only lands if the file is unchanged since it was read.
+    /// When another save gets in first, this retries on top of it. Returns
+    /// the revision number that was written.
+    pub async fn update_file_content(&self, file_id: &str, new_content: &str, authors: &[String]) -> Result<u64, Box<dyn Error + Send + Sync>> {
+        let saved = self.save_content(file_id, None, new_content, authors).await?;
+        Ok(saved.expect("saves without a base revision always land"))
     }
 
-    pub async fn update_file_content(&self, file_id: &str, new_content: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
-        let url = format!(
-            "https://firestore.googleapis.com/v1/projects/{}/databases/(default)/documents/files/{}?updateMask.fieldPaths=content",
-            "your-firebase-project-id", file_id
-        );
+    /// Like `update_file_content`, but only saves over `base_revision`.
+    /// Returns `None`, saving nothing, once the file has moved past it.
+    pub async fn update_file_content_from(&self, file_id: &str, base_revision: u64, new_content: &str, authors: &[String]) -> Result<Option<u64>, Box<dyn Error + Send + Sync>> {
+        self.save_content(file_id, Some(base_revision), new_content, authors).await
+    }
 
-        let body = json!({
-            "fields": {
-                "content": { "stringValue": new_content }
+    async fn save_content(&self, file_id: &str, base_revision: Option<u64>, new_content: &str, authors: &[String]) -> Result<Option<u64>, Box<dyn Error + Send + Sync>> {
+        for _ in 0..SAVE_ATTEMPTS {
+            let document = self.get_document("files", file_id).await?.ok_or("File not found")?;
+            let file = parse_file(&document["fields"])?;
+            let update_time = document["updateTime"].as_str().ok_or("File has no update time")?;
+            if base_revision.is_some_and(|base| base != file.revision) {
+                return Ok(None);
             }
-        });
 
-        let response = self.client.patch(&url)
-            .bearer_auth(&self.access_token)
-            .json(&body)
-            .send()
-            .await?;
+            let revision = FileRevision {
+                file_id: file.id,
+                revision: file.revision + 1,
+                authors: authors.to_vec(),
+                timestamp: now_millis(),
+                content: new_content.to_string(),
+            };
+            let writes = vec![
+                json!({
+                    "update": {
+                        "name": self.document_name("files", file_id),
+                        "fields": {
+                            "content": { "stringValue": new_content },
+                            "revision": { "integerValue": revision.revision.to_string() }
+                        }
+                    },
+                    "updateMask": { "fieldPaths": ["content", "revision"] },
+                    "currentDocument": { "updateTime": update_time }
+                }),
+                self.file_revision_write(&revision),
+            ];
 
-        if response.status().is_success() {
-            Ok(())
-        } else {
-            let error = response.text().await.unwrap_or_default();
-            Err(error.into())
+            if self.commit(writes).await? {
+                return Ok(Some(revision.revision));
+            }
+            println!("🔁 File {} changed while saving; retrying", file_id);
         }
+
+        Err(format!("File {} kept changing while it was being saved", file_id).into())
     }
 
-    pub async fn delete_file(&self, file_id: &str) -> Result<bool, Box<dyn Error + Send + Sync>> {
-        if self.get_file_by_id(file_id).await?.is_none() {
-            return Ok(false);
-        }
+    /// Renames a file, re-deriving its `file_type` from the new extension.
+    /// Returns `false` if the name is already taken in the file's folder.
+    pub async fn rename_file(&self, file_id: &str, name: &str) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        self.place_file(file_id, |file| (name.to_string(), file.folder)).await
+    }
 
-        let url = format!(
-            "https://firestore.googleapis.com/v1/projects/{}/databases/(default)/documents/files/{}",
-            "your-firebase-project-id", file_id
-        );
+    /// Returns `false` if the file's name is already taken in `folder`.
+    pub async fn move_file(&self, file_id: &str, folder: Option<Uuid>) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        self.place_file(file_id, |file| (file.name.clone(), folder)).await
+    }
 
-        let response = self.client.delete(&url)
-            .bearer_auth(&self.access_token)
-            .send()
-            .await?;
+    // Gives a file the name and folder `place` picks, releasing its old name and
+    // reserving the new one in the same commit as the file itself.
+    async fn place_file(&self, file_id: &str, place: impl Fn(&File) -> (String, Option<Uuid>)) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        for _ in 0..SAVE_ATTEMPTS {
+            let document = self.get_document("files", file_id).await?.ok_or("File not found")?;
+            let file = parse_file(&document["fields"])?;
+            let update_time = document["updateTime"].as_str().ok_or("File has no update time")?;
+            let (name, folder) = place(&file);
 
-        match response.status() {
-            StatusCode::NO_CONTENT | StatusCode::OK => Ok(true),
-            _ => {
-                let error = response.text().await.unwrap_or_default();
-                Err(error.into())
+            let mut writes = vec![json!({
+                "update": {
+                    "name": self.document_name("files", file_id),
+                    "fields": {
+                        "name": { "stringValue": name },
+                        "file_type": { "stringValue": file_type_from_name(&name) },
+                        "folder": optional_uuid_value(folder)
+                    }
+                },
+                "updateMask": { "fieldPaths": ["name", "file_type", "folder"] },
+                "currentDocument": { "updateTime": update_time }
+            })];
+            if (file.folder, file.name.as_str()) != (folder, name.as_str()) {
+                writes.push(self.release_name_write(file.project_id, file.folder, &file.name));
+                writes.push(self.reserve_name_write(file.project_id, folder, &name, file.id));
+            }
+
+            if self.commit(writes).await? {
+                return Ok(true);
             }
+            if self.name_holder(file.project_id, folder, &name).await?.is_some_and(|id| id != file.id) {
+                return Ok(false);
+            }
+            println!("🔁 File {} changed while placing it; retrying", file_id);
+        }
+
+        Err(format!("File {} kept changing while it was being moved", file_id).into())
+    }
+
+    /// Deletes the file, its revisions and its name in one commit. A file
+    /// with more revisions than fit in a commit loses them first, and the
+    /// file itself last, so a delete that fails part way can be repeated.
+    pub async fn delete_file(&self, file_id: &str) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        let Some(file) = self.get_file_by_id(file_id).await? else {
+            return Ok(false);
+        };
+
+        let revisions = self.file_revision_deletes(file_id).await?;
+        let mut writes = vec![
+            json!({ "delete": self.document_name("files", file_id) }),
+            self.release_name_write(file.project_id, file.folder, &file.name),
+        ];
+        let project_id = file.project_id.to_string();
+        // A transform would create a missing project, so it is only unlinked from an existing one.
+        if self.get_document("projects", &project_id).await?.is_some() {
+            writes.push(json!({
+                "transform": {
+                    "document": self.document_name("projects", &project_id),
+                    "fieldTransforms": [{
+                        "fieldPath": "files",
+                        "removeAllFromArray": { "values": [{ "stringValue": file_id }] }
+                    }]
+                }
+            }));
         }
+        self.commit_repeatable(revisions, writes).await?;
+
+        println!("🗑️ File {} deleted", file_id);
+        Ok(true)
     }
 }
@@ -0,0 +1,324 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use uuid::Uuid;
+use crate::connection::firebase::FirebaseService;
+use crate::model::files::File;
+use reqwest::StatusCode;
+use std::error::Error;
+
+/// A directory inside a project. Folders and files point at their parent
+/// folder; `None` means the project root.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Folder {
+    pub id: Uuid,
+    pub project_id: Uuid,
+    pub parent: Option<Uuid>,
+    pub name: String,
+    pub created_by: Uuid,
+}
+
+/// Whether `name` can be used for a file or folder: one non-empty path
+/// segment without surrounding whitespace.
+pub fn valid_entry_name(name: &str) -> bool {
+    name.trim() == name && !name.is_empty() && name != "." && name != ".." && !name.contains('/')
+}
+
+/// One entry of a project's source tree as returned by the tree listing.
+#[derive(Debug, Serialize)]
+pub struct TreeNode {
+    pub id: Uuid,
+    pub name: String,
+    pub path: String,
+    #[serde(flatten)]
+    pub kind: NodeKind,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum NodeKind {
+    Folder { children: Vec<TreeNode> },
+    File { file_type: String, revision: u64 },
+}
+
+/// The folders and files of one project, for path checks and tree listings.
+pub struct ProjectTree {
+    pub folders: Vec<Folder>,
+    pub files: Vec<File>,
+}
+
+impl ProjectTree {
+    pub fn folder(&self, id: Uuid) -> Option<&Folder> {
+        self.folders.iter().find(|folder| folder.id == id)
+    }
+
+    /// The folder called `name` directly inside `parent`.
+    pub fn child_folder(&self, parent: Option<Uuid>, name: &str) -> Option<&Folder> {
+        self.folders.iter().find(|f| f.parent == parent && f.name == name)
+    }
+
+    /// Whether a file or folder other than `except` is already called `name` in `parent`.
+    pub fn name_taken(&self, parent: Option<Uuid>, name: &str, except: Option<Uuid>) -> bool {
+        let clashes = |id: Uuid, entry_parent: Option<Uuid>, entry_name: &str| {
+            Some(id) != except && entry_parent == parent && entry_name == name
+        };
+        self.folders.iter().any(|f| clashes(f.id, f.parent, &f.name))
+            || self.files.iter().any(|f| clashes(f.id, f.folder, &f.name))
+    }
+
+    /// `id` and every folder nested anywhere below it.
+    pub fn subtree(&self, id: Uuid) -> Vec<Uuid> {
+        let mut found = vec![id];
+        let mut next = 0;
+        while next < found.len() {
+            let current = found[next];
+            found.extend(self.folders.iter().filter(|f| f.parent == Some(current)).map(|f| f.id));
+            next += 1;
+        }
+        found
+    }
+
+    /// Slash-separated path of a folder from the project root.
+    pub fn folder_path(&self, id: Uuid) -> String {
+        let mut segments = Vec::new();
+        let mut current = Some(id);
+        // Bounded so a corrupted parent cycle cannot loop forever.
+        for _ in 0..=self.folders.len() {
+            let Some(folder) = current.and_then(|id| self.folder(id)) else { break };
+            segments.push(folder.name.as_str());
+            current = folder.parent;
+        }
+        segments.reverse();
+        segments.join("/")
+    }
+
+    pub fn file_path(&self, file: &File) -> String {
+        match file.folder {
+            Some(folder) => format!("{}/{}", self.folder_path(folder), file.name),
+            None => file.name.clone(),
+        }
+    }
+
+    /// The nested listing under `parent`: folders first, then files, each sorted by name.
+    pub fn nodes(&self, parent: Option<Uuid>) -> Vec<TreeNode> {
+        let mut folders: Vec<&Folder> = self.folders.iter().filter(|f| f.parent == parent).collect();
+        folders.sort_by(|a, b| a.name.cmp(&b.name));
+        let mut files: Vec<&File> = self.files.iter().filter(|f| f.folder == parent).collect();
+        files.sort_by(|a, b| a.name.cmp(&b.name));
+
+        let folders = folders.into_iter().map(|folder| TreeNode {
+            id: folder.id,
+            name: folder.name.clone(),
+            path: self.folder_path(folder.id),
+            kind: NodeKind::Folder { children: self.nodes(Some(folder.id)) },
+        });
+        let files = files.into_iter().map(|file| TreeNode {
+            id: file.id,
+            name: file.name.clone(),
+            path: self.file_path(file),
+            kind: NodeKind::File { file_type: file.file_type.clone(), revision: file.revision },
+        });
+        folders.chain(files).collect()
+    }
+}
+
+/// Tries at writing an entry while other writes keep getting in first.
+const ENTRY_ATTEMPTS: usize = 5;
+
+/// Id of the record that reserves `name` inside `parent` of a project. It is
+/// derived from all three, so two entries racing for one name write the same
+/// record and only the first one lands.
+pub fn entry_name_key(project_id: Uuid, parent: Option<Uuid>, name: &str) -> String {
+    let parent = parent.map(|id| id.to_string()).unwrap_or_default();
+    Uuid::new_v5(&project_id, format!("{}/{}", parent, name).as_bytes()).to_string()
+}
+
+pub(crate) fn optional_uuid(value: &Value) -> Option<Uuid> {
+    value["stringValue"].as_str().and_then(|id| Uuid::parse_str(id).ok())
+}
+
+pub(crate) fn optional_uuid_value(id: Option<Uuid>) -> Value {
+    match id {
+        Some(id) => json!({ "stringValue": id.to_string() }),
+        None => json!({ "nullValue": null }),
+    }
+}
+
+fn parse_folder(fields: &Value) -> Result<Folder, Box<dyn Error + Send + Sync>> {
+    Ok(Folder {
+        id: Uuid::parse_str(fields["id"]["stringValue"].as_str().unwrap_or(""))?,
+        project_id: Uuid::parse_str(fields["project_id"]["stringValue"].as_str().unwrap_or(""))?,
+        parent: optional_uuid(&fields["parent"]),
+        name: fields["name"]["stringValue"].as_str().unwrap_or("").to_string(),
+        created_by: Uuid::parse_str(fields["created_by"]["stringValue"].as_str().unwrap_or(""))?,
+    })
+}
+
+impl FirebaseService {
+    /// A write that claims `name` in `parent` for `entry_id`; the commit fails if it is taken.
+    pub(crate) fn reserve_name_write(&self, project_id: Uuid, parent: Option<Uuid>, name: &str, entry_id: Uuid) -> Value {
+        json!({
+            "update": {
+                "name": self.document_name("entry_names", &entry_name_key(project_id, parent, name)),
+                "fields": {
+                    "project_id": { "stringValue": project_id.to_string() },
+                    "entry_id": { "stringValue": entry_id.to_string() }
+                }
+            },
+            "currentDocument": { "exists": false }
+        })
+    }
+
+    pub(crate) fn release_name_write(&self, project_id: Uuid, parent: Option<Uuid>, name: &str) -> Value {
+        json!({ "delete": self.document_name("entry_names", &entry_name_key(project_id, parent, name)) })
+    }
+
+    /// The file or folder holding `name` in `parent`, if any.
+    pub(crate) async fn name_holder(&self, project_id: Uuid, parent: Option<Uuid>, name: &str) -> Result<Option<Uuid>, Box<dyn Error + Send + Sync>> {
+        let document = self.get_document("entry_names", &entry_name_key(project_id, parent, name)).await?;
+        Ok(document.and_then(|document| optional_uuid(&document["fields"]["entry_id"])))
+    }
+
+    /// Creates a folder and reserves its name in one commit. Returns `false`
+    /// if the name is already taken in the parent folder.
+    pub async fn create_folder(&self, folder: &Folder) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        for _ in 0..ENTRY_ATTEMPTS {
+            let writes = vec![
+                json!({
+                    "update": {
+                        "name": self.document_name("folders", &folder.id.to_string()),
+                        "fields": {
+                            "id": { "stringValue": folder.id.to_string() },
+                            "project_id": { "stringValue": folder.project_id.to_string() },
+                            "parent": optional_uuid_value(folder.parent),
+                            "name": { "stringValue": folder.name },
+                            "created_by": { "stringValue": folder.created_by.to_string() }
+                        }
+                    },
+                    "currentDocument": { "exists": false }
+                }),
+                self.reserve_name_write(folder.project_id, folder.parent, &folder.name, folder.id),
+            ];
+
+            if self.commit(writes).await? {
+                println!("✅ Folder '{}' created", folder.name);
+                return Ok(true);
+            }
+            if self.name_holder(folder.project_id, folder.parent, &folder.name).await?.is_some() {
+                return Ok(false);
+            }
+            println!("🔁 Folder '{}' could not be created; retrying", folder.name);
+        }
+
+        Err(format!("Folder '{}' could not be created", folder.name).into())
+    }
+
+    pub async fn get_folder(&self, folder_id: &str) -> Result<Option<Folder>, Box<dyn Error + Send + Sync>> {
+        let url = format!("{}/folders/{}", self.documents_url(), folder_id);
+
+        let response = self.client.get(&url)
+            .bearer_auth(&self.access_token)
+            .send()
+            .await?;
+
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        if !response.status().is_success() {
+            let error = response.text().await.unwrap_or_default();
+            return Err(error.into());
+        }
+
+        let json = response.json::<Value>().await?;
+        Ok(Some(parse_folder(&json["fields"])?))
+    }
+
+    pub async fn list_folders(&self, project_id: &str) -> Result<Vec<Folder>, Box<dyn Error + Send + Sync>> {
+        let url = format!("{}:runQuery", self.documents_url());
+
+        let body = json!({
+            "structuredQuery": {
+                "from": [{ "collectionId": "folders" }],
+                "where": {
+                    "fieldFilter": {
+                        "field": { "fieldPath": "project_id" },
+                        "op": "EQUAL",
+                        "value": { "stringValue": project_id }
+                    }
+                }
+            }
+        });
+
+        let response = self.client.post(&url)
+            .bearer_auth(&self.access_token)
+            .json(&body)
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            let error = response.text().await.unwrap_or_default();
+            return Err(error.into());
+        }
+
+        let results = response.json::<Vec<Value>>().await?;
+        results
+            .iter()
+            .filter_map(|r| r.get("document"))
+            .map(|doc| parse_folder(&doc["fields"]))
+            .collect()
+    }
+
+    /// Renames and/or moves a folder; its contents follow because they point at its id.
+    /// The old name is released and the new one reserved in the same commit.
+    /// Returns `false` if the new name is already taken in `parent`.
+    pub async fn update_folder(&self, folder_id: &str, name: &str, parent: Option<Uuid>) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        for _ in 0..ENTRY_ATTEMPTS {
+            let document = self.get_document("folders", folder_id).await?.ok_or("Folder not found")?;
+            let folder = parse_folder(&document["fields"])?;
+            let update_time = document["updateTime"].as_str().ok_or("Folder has no update time")?;
+
+            let mut writes = vec![json!({
+                "update": {
+                    "name": self.document_name("folders", folder_id),
+                    "fields": {
+                        "name": { "stringValue": name },
+                        "parent": optional_uuid_value(parent)
+                    }
+                },
+                "updateMask": { "fieldPaths": ["name", "parent"] },
+                "currentDocument": { "updateTime": update_time }
+            })];
+            if (folder.parent, folder.name.as_str()) != (parent, name) {
+                writes.push(self.release_name_write(folder.project_id, folder.parent, &folder.name));
+                writes.push(self.reserve_name_write(folder.project_id, parent, name, folder.id));
+            }
+
+            if self.commit(writes).await? {
+                return Ok(true);
+            }
+            if self.name_holder(folder.project_id, parent, name).await?.is_some_and(|id| id != folder.id) {
+                return Ok(false);
+            }
+            println!("🔁 Folder {} changed while updating it; retrying", folder_id);
+        }
+
+        Err(format!("Folder {} kept changing while it was being updated", folder_id).into())
+    }
+
+    /// Deletes the folder record and releases its name.
+    pub async fn delete_folder(&self, folder_id: &str) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        let Some(folder) = self.get_folder(folder_id).await? else {
+            return Ok(false);
+        };
+
+        let writes = vec![
+            json!({ "delete": self.document_name("folders", folder_id) }),
+            self.release_name_write(folder.project_id, folder.parent, &folder.name),
+        ];
+        if !self.commit(writes).await? {
+            return Err(format!("Folder {} could not be deleted", folder_id).into());
+        }
+
+        println!("🗑️ Folder {} deleted", folder_id);
+        Ok(true)
+    }
+}
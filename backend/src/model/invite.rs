@@ -0,0 +1,270 @@
+use rand_core::{OsRng, RngCore};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use uuid::Uuid;
+use crate::connection::firebase::FirebaseService;
+use crate::model::project::{parse_project, role_map, Role};
+use reqwest::StatusCode;
+use std::error::Error;
+
+// 32 symbols without look-alikes (0/O, 1/I), so a byte maps onto it without bias.
+const CODE_ALPHABET: &[u8; 32] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+const CODE_LENGTH: usize = 10;
+const REDEEM_ATTEMPTS: usize = 5;
+
+/// A code that lets whoever holds it join a project with a given role.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Invite {
+    pub code: String,
+    pub project_id: Uuid,
+    pub role: Role,
+    pub created_by: Uuid,
+    /// Milliseconds since the Unix epoch.
+    pub created_at: u64,
+    pub expires_at: u64,
+    /// `None` means the code can be redeemed any number of times until it expires.
+    pub max_uses: Option<u32>,
+    pub uses: u32,
+}
+
+impl Invite {
+    pub fn generate_code() -> String {
+        let mut bytes = [0u8; CODE_LENGTH];
+        OsRng.fill_bytes(&mut bytes);
+        bytes.iter().map(|b| CODE_ALPHABET[(b & 31) as usize] as char).collect()
+    }
+
+    pub fn is_expired(&self, now: u64) -> bool {
+        now >= self.expires_at
+    }
+
+    pub fn is_used_up(&self) -> bool {
+        self.max_uses.is_some_and(|max| self.uses >= max)
+    }
+}
+
+/// Outcome of redeeming an invite code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Redemption {
+    /// The user joined with `role` and the code was used once.
+    Joined { project_id: Uuid, role: Role },
+    /// The user was already a member; their role is unchanged and no use was counted.
+    AlreadyMember { project_id: Uuid, role: Role },
+    Expired,
+    UsedUp,
+    InviteNotFound,
+    ProjectNotFound,
+    UserNotFound,
+}
+
+fn parse_invite(fields: &Value) -> Result<Invite, Box<dyn Error + Send + Sync>> {
+    let integer = |name: &str| fields[name]["integerValue"].as_str().and_then(|v| v.parse::<u64>().ok());
+
+    Ok(Invite {
+        code: fields["code"]["stringValue"].as_str().unwrap_or("").to_string(),
+        project_id: Uuid::parse_str(fields["project_id"]["stringValue"].as_str().unwrap_or(""))?,
+        role: Role::parse(fields["role"]["stringValue"].as_str().unwrap_or("")).ok_or("Invite has an unknown role")?,
+        created_by: Uuid::parse_str(fields["created_by"]["stringValue"].as_str().unwrap_or(""))?,
+        created_at: integer("created_at").unwrap_or(0),
+        expires_at: integer("expires_at").unwrap_or(0),
+        max_uses: integer("max_uses").map(|v| v as u32),
+        uses: integer("uses").unwrap_or(0) as u32,
+    })
+}
+
+impl FirebaseService {
+    pub async fn create_invite(&self, invite: &Invite) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let url = format!("{}/invites?documentId={}", self.documents_url(), invite.code);
+
+        let max_uses = match invite.max_uses {
+            Some(max) => json!({ "integerValue": max.to_string() }),
+            None => json!({ "nullValue": null }),
+        };
+        let body = json!({
+            "fields": {
+                "code": { "stringValue": invite.code },
+                "project_id": { "stringValue": invite.project_id.to_string() },
+                "role": { "stringValue": invite.role.as_str() },
+                "created_by": { "stringValue": invite.created_by.to_string() },
+                "created_at": { "integerValue": invite.created_at.to_string() },
+                "expires_at": { "integerValue": invite.expires_at.to_string() },
+                "max_uses": max_uses,
+                "uses": { "integerValue": invite.uses.to_string() }
+            }
+        });
+
+        let response = self.client.post(&url)
+            .bearer_auth(&self.access_token)
+            .json(&body)
+            .send()
+            .await?;
+
+        if response.status().is_success() {
+            println!("✅ Invite created for project {}", invite.project_id);
+            Ok(())
+        } else {
+            let error = response.text().await.unwrap_or_default();
+            eprintln!("❌ Failed to create invite: {}", error);
+            Err(error.into())
+        }
+    }
+
+    pub async fn get_invite(&self, code: &str) -> Result<Option<Invite>, Box<dyn Error + Send + Sync>> {
+        let url = format!("{}/invites/{}", self.documents_url(), code);
+
+        let response = self.client.get(&url)
+            .bearer_auth(&self.access_token)
+            .send()
+            .await?;
+
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        if !response.status().is_success() {
+            let error = response.text().await.unwrap_or_default();
+            return Err(error.into());
+        }
+
+        let json = response.json::<Value>().await?;
+        Ok(Some(parse_invite(&json["fields"])?))
+    }
+
+    /// Returns a project's invites, newest first.
+    pub async fn list_invites(&self, project_id: &str) -> Result<Vec<Invite>, Box<dyn Error + Send + Sync>> {
+        let url = format!("{}:runQuery", self.documents_url());
+
+        let body = json!({
+            "structuredQuery": {
+                "from": [{ "collectionId": "invites" }],
+                "where": {
+                    "fieldFilter": {
+                        "field": { "fieldPath": "project_id" },
+                        "op": "EQUAL",
+                        "value": { "stringValue": project_id }
+                    }
+                }
+            }
+        });
+
+        let response = self.client.post(&url)
+            .bearer_auth(&self.access_token)
+            .json(&body)
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            let error = response.text().await.unwrap_or_default();
+            return Err(error.into());
+        }
+
+        let results = response.json::<Vec<Value>>().await?;
+        let mut invites = results
+            .iter()
+            .filter_map(|r| r.get("document"))
+            .map(|doc| parse_invite(&doc["fields"]))
+            .collect::<Result<Vec<_>, _>>()?;
+        invites.sort_by_key(|invite| std::cmp::Reverse(invite.created_at));
+
+        Ok(invites)
+    }
+
+    /// Counts one use of the invite, adds the user to its project and lists
+    /// the project on the user in a single commit. The invite and project are
+    /// guarded by their `updateTime`, so two people redeeming the last use at
+    /// once cannot both get in.
+    pub async fn redeem_invite(&self, code: &str, user_id: Uuid, now: u64) -> Result<Redemption, Box<dyn Error + Send + Sync>> {
+        for _ in 0..REDEEM_ATTEMPTS {
+            let Some(invite_document) = self.get_document("invites", code).await? else {
+                return Ok(Redemption::InviteNotFound);
+            };
+            let invite = parse_invite(&invite_document["fields"])?;
+            if invite.is_expired(now) {
+                return Ok(Redemption::Expired);
+            }
+            if invite.is_used_up() {
+                return Ok(Redemption::UsedUp);
+            }
+
+            let project_id = invite.project_id.to_string();
+            let Some(project_document) = self.get_document("projects", &project_id).await? else {
+                return Ok(Redemption::ProjectNotFound);
+            };
+            let mut project = parse_project(&project_document["fields"])?;
+            if self.get_document("users", &user_id.to_string()).await?.is_none() {
+                return Ok(Redemption::UserNotFound);
+            }
+            // Existing members keep their role and don't use up the code.
+            if let Some(role) = project.role_of(user_id) {
+                return Ok(Redemption::AlreadyMember { project_id: project.id, role });
+            }
+            project.roles.insert(user_id, invite.role);
+
+            let invite_time = invite_document["updateTime"].as_str().ok_or("Invite has no update time")?;
+            let project_time = project_document["updateTime"].as_str().ok_or("Project has no update time")?;
+            let writes = vec![
+                json!({
+                    "update": {
+                        "name": self.document_name("invites", code),
+                        "fields": {
+                            "uses": { "integerValue": (invite.uses + 1).to_string() }
+                        }
+                    },
+                    "updateMask": { "fieldPaths": ["uses"] },
+                    "currentDocument": { "updateTime": invite_time }
+                }),
+                json!({
+                    "update": {
+                        "name": self.document_name("projects", &project_id),
+                        "fields": {
+                            "roles": role_map(&project.roles)
+                        }
+                    },
+                    "updateMask": { "fieldPaths": ["roles"] },
+                    "updateTransforms": [{
+                        "fieldPath": "members",
+                        "appendMissingElements": { "values": [{ "stringValue": user_id.to_string() }] }
+                    }],
+                    "currentDocument": { "updateTime": project_time }
+                }),
+                json!({
+                    "transform": {
+                        "document": self.document_name("users", &user_id.to_string()),
+                        "fieldTransforms": [{
+                            "fieldPath": "projects",
+                            "appendMissingElements": { "values": [{ "stringValue": project_id }] }
+                        }]
+                    },
+                    "currentDocument": { "exists": true }
+                }),
+            ];
+
+            if self.commit(writes).await? {
+                return Ok(Redemption::Joined { project_id: project.id, role: invite.role });
+            }
+            println!("🔁 Invite {} changed while redeeming; retrying", code);
+        }
+
+        Err(format!("Invite {} kept changing while it was being redeemed", code).into())
+    }
+
+    pub async fn delete_invite(&self, code: &str) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        if self.get_invite(code).await?.is_none() {
+            return Ok(false);
+        }
+
+        let url = format!("{}/invites/{}", self.documents_url(), code);
+
+        let response = self.client.delete(&url)
+            .bearer_auth(&self.access_token)
+            .send()
+            .await?;
+
+        if response.status().is_success() {
+            println!("🗑️ Invite {} revoked", code);
+            Ok(true)
+        } else {
+            let error = response.text().await.unwrap_or_default();
+            Err(error.into())
+        }
+    }
+}
@@ -1,3 +1,7 @@
 pub mod user;
 pub mod project;
 pub mod files;
+pub mod folder;
+pub mod revision;
+pub mod invite;
+pub mod test_report;
@@ -1,7 +1,58 @@
 use serde::{Serialize, Deserialize};
 use uuid::Uuid;
 use crate::connection::firebase::FirebaseService;
-use serde_json::json;
+use reqwest::StatusCode;
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+use std::error::Error;
+
+const MEMBER_UPDATE_ATTEMPTS: usize = 5;
+
+/// What a member may do in a project.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    Owner,
+    Editor,
+    Viewer,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Permission {
+    /// See the project, its files and their history.
+    Read,
+    /// Change files and project details.
+    Write,
+    /// Manage members and delete the project.
+    Manage,
+}
+
+impl Role {
+    pub fn allows(self, permission: Permission) -> bool {
+        match permission {
+            Permission::Read => true,
+            Permission::Write => self != Role::Viewer,
+            Permission::Manage => self == Role::Owner,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Role::Owner => "owner",
+            Role::Editor => "editor",
+            Role::Viewer => "viewer",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Role> {
+        match value {
+            "owner" => Some(Role::Owner),
+            "editor" => Some(Role::Editor),
+            "viewer" => Some(Role::Viewer),
+            _ => None,
+        }
+    }
+}
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Project {
@@ -10,78 +61,163 @@ pub struct Project {
     pub description: String,
     pub owner: Uuid,
     pub members: Vec<Uuid>,
+    /// Role of each entry in `members`. Members recorded before roles existed
+    /// have no entry and count as editors.
+    #[serde(default)]
+    pub roles: HashMap<Uuid, Role>,
     pub files: Vec<Uuid>,
 }
 
-impl FirebaseService {
-    pub async fn create_file(&self, name: String, owner: Uuid, project_id: Uuid) -> Result<Uuid, reqwest::Error> {
-        let file_id = Uuid::new_v4();
-        let content = "".to_string();
-
-        let file_type = match name.rsplit('.').next().unwrap_or("") {
-            "c" => "C",
-            "cpp" => "C++",
-            "py" => "Python",
-            "rs" => "Rust",
-            "js" => "JavaScript",
-            "ts" => "TypeScript",
-            "html" => "HTML",
-            "css" => "CSS",
-            ext => ext,
-        }.to_string();
+impl Project {
+    /// The user's role, or `None` if they are not a member.
+    pub fn role_of(&self, user_id: Uuid) -> Option<Role> {
+        if user_id == self.owner {
+            return Some(Role::Owner);
+        }
+        if !self.members.contains(&user_id) {
+            return None;
+        }
+        Some(self.roles.get(&user_id).copied().unwrap_or(Role::Editor))
+    }
 
-        let url = format!(
-            "https://firestore.googleapis.com/v1/projects/{}/databases/(default)/documents/files?documentId={}",
-            "your-firebase-project-id",
-            file_id
-        );
+    /// Applies `change` to a member. Returns `false` if they are not one.
+    pub fn change_member(&mut self, user_id: Uuid, change: MemberChange) -> bool {
+        if !self.members.contains(&user_id) {
+            return false;
+        }
+        match change {
+            MemberChange::SetRole(role) => {
+                self.roles.insert(user_id, role);
+            }
+            MemberChange::Remove => {
+                self.members.retain(|id| *id != user_id);
+                self.roles.remove(&user_id);
+            }
+        }
+        true
+    }
+}
+
+/// A change to one member of a project.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemberChange {
+    SetRole(Role),
+    Remove,
+}
+
+/// Outcome of changing a member.
+#[derive(Debug, Clone)]
+pub enum MemberUpdate {
+    /// The change was saved; the project as it now stands.
+    Updated(Project),
+    NotMember,
+    ProjectNotFound,
+}
+
+fn uuid_array(values: &Value) -> Vec<Uuid> {
+    values["arrayValue"]["values"]
+        .as_array()
+        .map(|values| {
+            values
+                .iter()
+                .filter_map(|v| v["stringValue"].as_str().and_then(|id| Uuid::parse_str(id).ok()))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn uuid_values(ids: &[Uuid]) -> Vec<Value> {
+    ids.iter().map(|id| json!({ "stringValue": id.to_string() })).collect()
+}
+
+pub(crate) fn role_map(roles: &HashMap<Uuid, Role>) -> Value {
+    let fields: Map<String, Value> = roles
+        .iter()
+        .map(|(id, role)| (id.to_string(), json!({ "stringValue": role.as_str() })))
+        .collect();
+    json!({ "mapValue": { "fields": fields } })
+}
+
+fn parse_roles(value: &Value) -> HashMap<Uuid, Role> {
+    value["mapValue"]["fields"]
+        .as_object()
+        .map(|fields| {
+            fields
+                .iter()
+                .filter_map(|(id, role)| {
+                    Some((Uuid::parse_str(id).ok()?, Role::parse(role["stringValue"].as_str()?)?))
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+pub(crate) fn parse_project(fields: &Value) -> Result<Project, Box<dyn Error + Send + Sync>> {
+    Ok(Project {
+        id: Uuid::parse_str(fields["id"]["stringValue"].as_str().unwrap_or(""))?,
+        name: fields["name"]["stringValue"].as_str().unwrap_or("").to_string(),
+        description: fields["description"]["stringValue"].as_str().unwrap_or("").to_string(),
+        owner: Uuid::parse_str(fields["owner"]["stringValue"].as_str().unwrap_or(""))?,
+        members: uuid_array(&fields["members"]),
+        roles: parse_roles(&fields["roles"]),
+        files: uuid_array(&fields["files"]),
+    })
+}
+
+impl FirebaseService {
+    pub async fn create_project(&self, project: &Project) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let url = format!("{}/projects?documentId={}", self.documents_url(), project.id);
 
         let body = json!({
             "fields": {
-                "id": { "stringValue": file_id.to_string() },
-                "name": { "stringValue": name },
-                "content": { "stringValue": content },
-                "file_type": { "stringValue": file_type },
-                "owner": { "stringValue": owner.to_string() },
-                "project_id": { "stringValue": project_id.to_string() }
+                "id": { "stringValue": project.id.to_string() },
+                "name": { "stringValue": project.name },
+                "description": { "stringValue": project.description },
+                "owner": { "stringValue": project.owner.to_string() },
+                "members": { "arrayValue": { "values": uuid_values(&project.members) } },
+                "roles": role_map(&project.roles),
+                "files": { "arrayValue": { "values": uuid_values(&project.files) } }
             }
         });
 
-        let response = self
-            .client
-            .post(&url)
+        let response = self.client.post(&url)
             .bearer_auth(&self.access_token)
             .json(&body)
-            .send()
-            .await?;
+            .send().await?;
 
         if response.status().is_success() {
-            println!("✅ File created");
+            println!("✅ Project created");
+            Ok(())
         } else {
-            eprintln!("❌ Create failed: {}", response.text().await.unwrap());
+            let error = response.text().await.unwrap_or_default();
+            eprintln!("❌ Create failed: {}", error);
+            Err(error.into())
         }
-
-        Ok(file_id)
     }
 
-    pub async fn get_project(&self, project_id: &str) -> Result<serde_json::Value, reqwest::Error> {
-        let url = format!(
-            "https://firestore.googleapis.com/v1/projects/{}/databases/(default)/documents/projects/{}",
-            "your-firebase-project-id", project_id
-        );
+    pub async fn get_project(&self, project_id: &str) -> Result<Option<Project>, Box<dyn Error + Send + Sync>> {
+        let url = format!("{}/projects/{}", self.documents_url(), project_id);
 
         let response = self.client.get(&url)
             .bearer_auth(&self.access_token)
             .send().await?;
 
-        let body = response.json::<serde_json::Value>().await?;
-        Ok(body)
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        if !response.status().is_success() {
+            let error = response.text().await.unwrap_or_default();
+            return Err(error.into());
+        }
+
+        let body = response.json::<Value>().await?;
+        Ok(Some(parse_project(&body["fields"])?))
     }
 
-    pub async fn update_project_metadata(&self, project_id: &str, name: &str, description: &str) -> Result<(), reqwest::Error> {
+    pub async fn update_project_metadata(&self, project_id: &str, name: &str, description: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
         let url = format!(
-            "https://firestore.googleapis.com/v1/projects/{}/databases/(default)/documents/projects/{}?updateMask.fieldPaths=name&updateMask.fieldPaths=description",
-            "your-firebase-project-id", project_id
+            "{}/projects/{}?updateMask.fieldPaths=name&updateMask.fieldPaths=description",
+            self.documents_url(), project_id
         );
 
         let body = json!({
@@ -98,110 +234,98 @@ impl FirebaseService {
 
         if response.status().is_success() {
             println!("✅ Project metadata updated");
+            Ok(())
         } else {
-            eprintln!("❌ Update failed: {}", response.text().await.unwrap());
+            let error = response.text().await.unwrap_or_default();
+            eprintln!("❌ Update failed: {}", error);
+            Err(error.into())
         }
-
-        Ok(())
     }
 
-    pub async fn update_project_members(&self, project_id: &str, members: &[Uuid]) -> Result<(), reqwest::Error> {
-        let url = format!(
-            "https://firestore.googleapis.com/v1/projects/{}/databases/(default)/documents/projects/{}?updateMask.fieldPaths=members",
-            "your-firebase-project-id", project_id
-        );
-
-        let body = json!({
-            "fields": {
-                "members": { "arrayValue": { "values": members.iter().map(|m| json!({ "stringValue": m.to_string() })).collect::<Vec<_>>() } }
+    /// Changes one member, writing the member list and their roles together
+    /// so they cannot drift apart. The write is guarded by the project's
+    /// `updateTime`, so someone joining at the same moment is not lost.
+    pub async fn update_member(&self, project_id: &str, user_id: Uuid, change: MemberChange) -> Result<MemberUpdate, Box<dyn Error + Send + Sync>> {
+        for _ in 0..MEMBER_UPDATE_ATTEMPTS {
+            let Some(document) = self.get_document("projects", project_id).await? else {
+                return Ok(MemberUpdate::ProjectNotFound);
+            };
+            let mut project = parse_project(&document["fields"])?;
+            if !project.change_member(user_id, change) {
+                return Ok(MemberUpdate::NotMember);
             }
-        });
 
-        let response = self.client.patch(&url)
-            .bearer_auth(&self.access_token)
-            .json(&body)
-            .send().await?;
-
-        if response.status().is_success() {
-            println!("✅ Members updated");
-        } else {
-            eprintln!("❌ Update failed: {}", response.text().await.unwrap());
+            let update_time = document["updateTime"].as_str().ok_or("Project has no update time")?;
+            let write = json!({
+                "update": {
+                    "name": self.document_name("projects", project_id),
+                    "fields": {
+                        "members": { "arrayValue": { "values": uuid_values(&project.members) } },
+                        "roles": role_map(&project.roles)
+                    }
+                },
+                "updateMask": { "fieldPaths": ["members", "roles"] },
+                "currentDocument": { "updateTime": update_time }
+            });
+
+            if self.commit(vec![write]).await? {
+                println!("✅ Project members updated");
+                return Ok(MemberUpdate::Updated(project));
+            }
+            println!("🔁 Project {} changed while updating a member; retrying", project_id);
         }
 
-        Ok(())
+        Err(format!("Project {} kept changing while a member was being updated", project_id).into())
     }
 
-    pub async fn delete_project(&self, project_id: &str, file_ids: &[Uuid]) -> Result<(), reqwest::Error> {
-        // First delete all files linked to the project
-        for file_id in file_ids {
-            self.delete_file(&file_id.to_string()).await?;
+    /// Deletes a project together with every file, folder, invite and test
+    /// report in it, and takes it off its members' project lists, in one
+    /// commit. A project too big for one commit has its contents deleted in
+    /// batches first and the project itself last, so a delete that fails part
+    /// way can be repeated.
+    pub async fn delete_project(&self, project_id: &str) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        let Some(project) = self.get_project(project_id).await? else {
+            return Ok(false);
+        };
+
+        let mut contents = Vec::new();
+        for file in self.list_project_files(project_id).await? {
+            contents.extend(self.file_revision_deletes(&file.id.to_string()).await?);
+            contents.push(json!({ "delete": self.document_name("files", &file.id.to_string()) }));
+            contents.push(self.release_name_write(file.project_id, file.folder, &file.name));
         }
-
-        let url = format!(
-            "https://firestore.googleapis.com/v1/projects/{}/databases/(default)/documents/projects/{}",
-            "your-firebase-project-id", project_id
-        );
-
-        let response = self.client.delete(&url)
-            .bearer_auth(&self.access_token)
-            .send().await?;
-
-        if response.status().is_success() {
-            println!("🗑️ Project deleted");
-        } else {
-            eprintln!("❌ Delete failed: {}", response.text().await.unwrap());
+        for folder in self.list_folders(project_id).await? {
+            contents.push(json!({ "delete": self.document_name("folders", &folder.id.to_string()) }));
+            contents.push(self.release_name_write(folder.project_id, folder.parent, &folder.name));
         }
-
-        Ok(())
-    }
-
-    pub async fn list_all_projects(&self) -> Result<serde_json::Value, reqwest::Error> {
-        let url = format!(
-            "https://firestore.googleapis.com/v1/projects/{}/databases/(default)/documents/projects",
-            "your-firebase-project-id"
-        );
-
-        let response = self.client.get(&url)
-            .bearer_auth(&self.access_token)
-            .send().await?;
-
-        let body = response.json::<serde_json::Value>().await?;
-        Ok(body)
-    }
-
-    pub async fn add_file_to_project(&self, project_id: &str, file_id: Uuid) -> Result<(), reqwest::Error> {
-        let mut project = self.get_project(project_id).await?;
-        let files = project["fields"]["files"]["arrayValue"]["values"]
-            .as_array_mut().unwrap_or(&mut vec![]);
-
-        files.push(json!({ "stringValue": file_id.to_string() }));
-
-        self.update_project_field(project_id, "files", files.clone()).await
-    }
-
-    pub async fn update_project_field(&self, project_id: &str, field: &str, values: Vec<serde_json::Value>) -> Result<(), reqwest::Error> {
-        let url = format!(
-            "https://firestore.googleapis.com/v1/projects/{}/databases/(default)/documents/projects/{}?updateMask.fieldPaths={}",
-            "your-firebase-project-id", project_id, field
-        );
-
-        let body = json!({
-            "fields": {
-                field: { "arrayValue": { "values": values } }
+        for invite in self.list_invites(project_id).await? {
+            contents.push(json!({ "delete": self.document_name("invites", &invite.code) }));
+        }
+        contents.push(json!({ "delete": self.document_name("test_reports", project_id) }));
+
+        let mut last = Vec::new();
+        let mut users = project.members.clone();
+        users.push(project.owner);
+        users.sort();
+        users.dedup();
+        for user_id in users {
+            // A transform would create a missing user, so only existing ones are touched.
+            if self.get_document("users", &user_id.to_string()).await?.is_some() {
+                last.push(json!({
+                    "transform": {
+                        "document": self.document_name("users", &user_id.to_string()),
+                        "fieldTransforms": [{
+                            "fieldPath": "projects",
+                            "removeAllFromArray": { "values": [{ "stringValue": project_id }] }
+                        }]
+                    }
+                }));
             }
-        });
-
-        let response = self.client.patch(&url)
-            .bearer_auth(&self.access_token)
-            .json(&body)
-            .send().await?;
-
-        if response.status().is_success() {
-            println!("✅ Project field '{}' updated", field);
-        } else {
-            eprintln!("❌ Update field failed: {}", response.text().await.unwrap());
         }
+        last.push(json!({ "delete": self.document_name("projects", project_id) }));
 
-        Ok(())
+        self.commit_repeatable(contents, last).await?;
+        println!("🗑️ Project deleted");
+        Ok(true)
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,144 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use uuid::Uuid;
+use crate::connection::firebase::FirebaseService;
+use reqwest::StatusCode;
+use std::error::Error;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A saved snapshot of a file, written every time its content is persisted.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct FileRevision {
+    pub file_id: Uuid,
+    pub revision: u64,
+    /// Users whose edits went into this revision.
+    pub authors: Vec<String>,
+    /// Milliseconds since the Unix epoch.
+    pub timestamp: u64,
+    pub content: String,
+}
+
+/// Revision metadata without the content, for listings.
+#[derive(Debug, Serialize, Clone)]
+pub struct RevisionSummary {
+    pub revision: u64,
+    pub authors: Vec<String>,
+    pub timestamp: u64,
+}
+
+impl From<&FileRevision> for RevisionSummary {
+    fn from(revision: &FileRevision) -> Self {
+        Self {
+            revision: revision.revision,
+            authors: revision.authors.clone(),
+            timestamp: revision.timestamp,
+        }
+    }
+}
+
+pub fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+fn revision_doc_id(file_id: &str, revision: u64) -> String {
+    format!("{}_{}", file_id, revision)
+}
+
+fn parse_revision(fields: &Value) -> Result<FileRevision, Box<dyn Error + Send + Sync>> {
+    let integer = |name: &str| {
+        fields[name]["integerValue"].as_str().and_then(|v| v.parse().ok()).unwrap_or(0)
+    };
+
+    Ok(FileRevision {
+        file_id: Uuid::parse_str(fields["file_id"]["stringValue"].as_str().unwrap_or(""))?,
+        revision: integer("revision"),
+        authors: fields["authors"]["arrayValue"]["values"]
+            .as_array()
+            .map(|values| values.iter().filter_map(|v| v["stringValue"].as_str().map(String::from)).collect())
+            .unwrap_or_default(),
+        timestamp: integer("timestamp"),
+        content: fields["content"]["stringValue"].as_str().unwrap_or("").to_string(),
+    })
+}
+
+impl FirebaseService {
+    /// The write that stores `revision`, for committing together with the
+    /// file it belongs to. Fails if that revision was already saved.
+    pub fn file_revision_write(&self, revision: &FileRevision) -> Value {
+        json!({
+            "update": {
+                "name": self.document_name("file_revisions", &revision_doc_id(&revision.file_id.to_string(), revision.revision)),
+                "fields": {
+                    "file_id": { "stringValue": revision.file_id.to_string() },
+                    "revision": { "integerValue": revision.revision.to_string() },
+                    "authors": { "arrayValue": { "values": revision.authors.iter().map(|a| json!({ "stringValue": a })).collect::<Vec<_>>() } },
+                    "timestamp": { "integerValue": revision.timestamp.to_string() },
+                    "content": { "stringValue": revision.content }
+                }
+            },
+            "currentDocument": { "exists": false }
+        })
+    }
+
+    pub async fn get_file_revision(&self, file_id: &str, revision: u64) -> Result<Option<FileRevision>, Box<dyn Error + Send + Sync>> {
+        let url = format!("{}/file_revisions/{}", self.documents_url(), revision_doc_id(file_id, revision));
+
+        let response = self.client.get(&url)
+            .bearer_auth(&self.access_token)
+            .send()
+            .await?;
+
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+
+        let json = response.json::<Value>().await?;
+        Ok(Some(parse_revision(&json["fields"])?))
+    }
+
+    /// Returns every saved revision of a file, oldest first.
+    pub async fn list_file_revisions(&self, file_id: &str) -> Result<Vec<FileRevision>, Box<dyn Error + Send + Sync>> {
+        let url = format!("{}:runQuery", self.documents_url());
+
+        let body = json!({
+            "structuredQuery": {
+                "from": [{ "collectionId": "file_revisions" }],
+                "where": {
+                    "fieldFilter": {
+                        "field": { "fieldPath": "file_id" },
+                        "op": "EQUAL",
+                        "value": { "stringValue": file_id }
+                    }
+                }
+            }
+        });
+
+        let response = self.client.post(&url)
+            .bearer_auth(&self.access_token)
+            .json(&body)
+            .send()
+            .await?;
+
+        let results = response.json::<Vec<Value>>().await?;
+        let mut revisions = results
+            .iter()
+            .filter_map(|r| r.get("document"))
+            .map(|doc| parse_revision(&doc["fields"]))
+            .collect::<Result<Vec<_>, _>>()?;
+        revisions.sort_by_key(|r| r.revision);
+
+        Ok(revisions)
+    }
+
+    /// The writes that delete every saved revision of a file.
+    pub async fn file_revision_deletes(&self, file_id: &str) -> Result<Vec<Value>, Box<dyn Error + Send + Sync>> {
+        let revisions = self.list_file_revisions(file_id).await?;
+        Ok(revisions
+            .iter()
+            .map(|revision| json!({ "delete": self.document_name("file_revisions", &revision_doc_id(file_id, revision.revision)) }))
+            .collect())
+    }
+}
@@ -0,0 +1,100 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use uuid::Uuid;
+use crate::connection::firebase::FirebaseService;
+use crate::executor::diagnostics::Diagnostic;
+use crate::executor::runner::Stage;
+use reqwest::StatusCode;
+use std::error::Error;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TestOutcome {
+    Passed,
+    Failed,
+    /// Skipped, ignored or marked as to-do.
+    Skipped,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TestResult {
+    pub name: String,
+    pub outcome: TestOutcome,
+    /// `None` when the test framework does not time tests.
+    pub duration_ms: Option<u64>,
+    /// What the test printed and, for failures, why it failed.
+    pub output: String,
+}
+
+/// The last test run of a project, kept so every member can see it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TestReport {
+    pub project_id: Uuid,
+    pub run_by: Uuid,
+    /// Milliseconds since the Unix epoch.
+    pub finished_at: u64,
+    pub language: String,
+    /// The test file that was run, if the run was limited to one.
+    pub entry: Option<String>,
+    /// `compile` when the tests did not build.
+    pub stage: Stage,
+    pub exit_code: i32,
+    pub timed_out: bool,
+    pub passed: usize,
+    pub failed: usize,
+    pub skipped: usize,
+    pub wall_time_ms: u64,
+    pub tests: Vec<TestResult>,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl FirebaseService {
+    /// Replaces the project's stored report. The report is kept as one JSON
+    /// string rather than as nested Firestore fields.
+    pub async fn save_test_report(&self, report: &TestReport) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let url = format!("{}/test_reports/{}", self.documents_url(), report.project_id);
+
+        let body = json!({
+            "fields": {
+                "project_id": { "stringValue": report.project_id.to_string() },
+                "report": { "stringValue": serde_json::to_string(report)? }
+            }
+        });
+
+        let response = self.client.patch(&url)
+            .bearer_auth(&self.access_token)
+            .json(&body)
+            .send()
+            .await?;
+
+        if response.status().is_success() {
+            println!("✅ Test report saved for project {}", report.project_id);
+            Ok(())
+        } else {
+            let error = response.text().await.unwrap_or_default();
+            eprintln!("❌ Failed to save test report: {}", error);
+            Err(error.into())
+        }
+    }
+
+    pub async fn get_test_report(&self, project_id: &str) -> Result<Option<TestReport>, Box<dyn Error + Send + Sync>> {
+        let url = format!("{}/test_reports/{}", self.documents_url(), project_id);
+
+        let response = self.client.get(&url)
+            .bearer_auth(&self.access_token)
+            .send()
+            .await?;
+
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        if !response.status().is_success() {
+            let error = response.text().await.unwrap_or_default();
+            return Err(error.into());
+        }
+
+        let json = response.json::<Value>().await?;
+        let report = json["fields"]["report"]["stringValue"].as_str().ok_or("Test report has no content")?;
+        Ok(Some(serde_json::from_str(report)?))
+    }
+}
@@ -1,7 +1,8 @@
 use serde::{Serialize, Deserialize};
 use uuid::Uuid;
 use crate::connection::firebase::FirebaseService;
-use serde_json::json;
+use serde_json::{json, Value};
+use std::error::Error;
 
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -19,102 +20,96 @@ pub struct UserDetails {
     pub projects: Vec<Uuid>,
 }
 
-impl FirebaseService {
-    pub async fn create_user(
-        &self,
-        display_name: String,
-        username: String,
-        email: String,
-        password: String,
-    ) -> Result<Uuid, reqwest::Error> {
-        let user_id = Uuid::new_v4();
-
-        // 1. First, create the user_credentials document
-        let creds_url = format!(
-            "https://firestore.googleapis.com/v1/projects/{}/databases/(default)/documents/user_credentials?documentId={}",
-            "your-firebase-project-id",
-            user_id
-        );
+/// What other users may see of an account.
+#[derive(Debug, Serialize, Clone)]
+pub struct PublicProfile {
+    pub id: Uuid,
+    pub username: String,
+    pub display_name: String,
+}
 
-        let creds_body = json!({
-            "fields": {
-                "id": { "stringValue": user_id.to_string() },
-                "email": { "stringValue": email.clone() },  // Duplicate email for login lookup
-                "password": { "stringValue": password }
-            }
-        });
+impl From<UserDetails> for PublicProfile {
+    fn from(details: UserDetails) -> Self {
+        Self { id: details.id, username: details.username, display_name: details.display_name }
+    }
+}
 
-        let creds_response = self
-            .client
-            .post(&creds_url)
-            .bearer_auth(&self.access_token)
-            .json(&creds_body)
-            .send()
-            .await?;
+pub fn parse_user_details(fields: &Value) -> UserDetails {
+    let id_str = fields["id"]["stringValue"].as_str().unwrap_or_default();
+    let id = Uuid::parse_str(id_str).unwrap_or_else(|_| Uuid::nil());
+
+    let projects = fields["projects"]["arrayValue"]["values"]
+        .as_array()
+        .map(|values| {
+            values
+                .iter()
+                .filter_map(|v| v["stringValue"].as_str().and_then(|id_str| Uuid::parse_str(id_str).ok()))
+                .collect()
+        })
+        .unwrap_or_default();
+
+    UserDetails {
+        id,
+        display_name: fields["display_name"]["stringValue"].as_str().unwrap_or_default().to_string(),
+        username: fields["username"]["stringValue"].as_str().unwrap_or_default().to_string(),
+        email: fields["email"]["stringValue"].as_str().unwrap_or_default().to_string(),
+        projects,
+    }
+}
 
-        if !creds_response.status().is_success() {
-            eprintln!("❌ Failed to create user credentials: {}", creds_response.text().await.unwrap());
-            return Err(reqwest::Error::from(std::io::Error::new(
-                std::io::ErrorKind::Other,
-                "Failed to create user credentials",
-            )));
-        }
+fn parse_user_cred(fields: &Value) -> UserCred {
+    UserCred {
+        id: Uuid::parse_str(fields["id"]["stringValue"].as_str().unwrap_or_default()).unwrap_or_else(|_| Uuid::nil()),
+        password: fields["password"]["stringValue"].as_str().unwrap_or_default().to_string(),
+    }
+}
 
-        // 2. Then, create the user_details document
-        let details_url = format!(
-            "https://firestore.googleapis.com/v1/projects/{}/databases/(default)/documents/user_details?documentId={}",
-            "your-firebase-project-id",
-            user_id
-        );
+impl FirebaseService {
+    /// Stores a user's profile and password hash in a single `users` document.
+    pub async fn create_user(&self, details: &UserDetails, password_hash: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let url = format!("{}/users?documentId={}", self.documents_url(), details.id);
 
-        let details_body = json!({
+        let body = json!({
             "fields": {
-                "id": { "stringValue": user_id.to_string() },
-                "display_name": { "stringValue": display_name },
-                "username": { "stringValue": username },
-                "email": { "stringValue": email },
-                "projects": { "arrayValue": { "values": [] } }
+                "id": { "stringValue": details.id.to_string() },
+                "display_name": { "stringValue": details.display_name },
+                "username": { "stringValue": details.username },
+                "email": { "stringValue": details.email },
+                "projects": { "arrayValue": { "values": details.projects.iter().map(|p| json!({ "stringValue": p.to_string() })).collect::<Vec<_>>() } },
+                "password": { "stringValue": password_hash }
             }
         });
 
-        let details_response = self
+        let response = self
             .client
-            .post(&details_url)
+            .post(&url)
             .bearer_auth(&self.access_token)
-            .json(&details_body)
+            .json(&body)
             .send()
             .await?;
 
-        if details_response.status().is_success() {
-            println!("✅ User created: {}", email);
+        if response.status().is_success() {
+            println!("✅ User created: {}", details.email);
+            Ok(())
         } else {
-            // If user_details creation fails, attempt to clean up the credentials document
-            eprintln!("❌ Failed to create user details: {}", details_response.text().await.unwrap());
-            self.delete_user_credentials(&user_id.to_string()).await.ok();
-            return Err(reqwest::Error::from(std::io::Error::new(
-                std::io::ErrorKind::Other,
-                "Failed to create user details",
-            )));
+            let error = response.text().await.unwrap_or_default();
+            eprintln!("❌ Failed to create user: {}", error);
+            Err(error.into())
         }
-
-        Ok(user_id)
-    }
     }
 
-    pub async fn get_user_by_email(&self, email: &str) -> Result<Option<serde_json::Value>, reqwest::Error> {
-        let url = format!(
-            "https://firestore.googleapis.com/v1/projects/{}/databases/(default)/documents:runQuery",
-            "your-firebase-project-id"
-        );
+    // Returns the `fields` of the first user whose `field` equals `value`.
+    async fn query_user(&self, field: &str, value: &str) -> Result<Option<Value>, Box<dyn Error + Send + Sync>> {
+        let url = format!("{}:runQuery", self.documents_url());
 
         let query = json!({
             "structuredQuery": {
                 "from": [{ "collectionId": "users" }],
                 "where": {
                     "fieldFilter": {
-                        "field": { "fieldPath": "email" },
+                        "field": { "fieldPath": field },
                         "op": "EQUAL",
-                        "value": { "stringValue": email }
+                        "value": { "stringValue": value }
                     }
                 },
                 "limit": 1
@@ -127,89 +122,83 @@ impl FirebaseService {
             .send()
             .await?;
 
-        let json: Vec<serde_json::Value> = response.json().await?;
-        if let Some(document) = json.first().and_then(|d| d.get("document").cloned()) {
-            Ok(Some(document))
-        } else {
-            Ok(None)
+        if !response.status().is_success() {
+            let error = response.text().await.unwrap_or_default();
+            return Err(error.into());
         }
+
+        let json: Vec<Value> = response.json().await?;
+        Ok(json.first().and_then(|d| d.get("document")).map(|doc| doc["fields"].clone()))
     }
 
-    pub async fn check_user_password(&self, email: &str, password: &str) -> Result<bool, reqwest::Error> {
-        if let Some(user_doc) = self.get_user_by_email(email).await? {
-            if let Some(stored) = user_doc["fields"]["password"]["stringValue"].as_str() {
-                return Ok(stored == password);
-            }
-        }
-        Ok(false)
+    pub async fn get_user_by_email(&self, email: &str) -> Result<Option<UserDetails>, Box<dyn Error + Send + Sync>> {
+        Ok(self.query_user("email", email).await?.as_ref().map(parse_user_details))
+    }
+
+    pub async fn get_user_by_id(&self, id: &str) -> Result<Option<UserDetails>, Box<dyn Error + Send + Sync>> {
+        Ok(self.query_user("id", id).await?.as_ref().map(parse_user_details))
     }
 
-    pub async fn get_user_by_id(&self, id: &str) -> Result<Option<serde_json::Value>, reqwest::Error> {
+    pub async fn get_user_by_username(&self, username: &str) -> Result<Option<UserDetails>, Box<dyn Error + Send + Sync>> {
+        Ok(self.query_user("username", username).await?.as_ref().map(parse_user_details))
+    }
+
+    pub async fn get_user_credentials(&self, username: &str) -> Result<Option<UserCred>, Box<dyn Error + Send + Sync>> {
+        Ok(self.query_user("username", username).await?.as_ref().map(parse_user_cred))
+    }
+
+    pub async fn update_user_profile(&self, user_id: &str, display_name: &str, username: &str, email: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
         let url = format!(
-            "https://firestore.googleapis.com/v1/projects/{}/databases/(default)/documents:runQuery",
-            "your-firebase-project-id"
+            "{}/users/{}?updateMask.fieldPaths=display_name&updateMask.fieldPaths=username&updateMask.fieldPaths=email",
+            self.documents_url(), user_id
         );
 
-        let query = json!({
-            "structuredQuery": {
-                "from": [{ "collectionId": "users" }],
-                "where": {
-                    "fieldFilter": {
-                        "field": { "fieldPath": "id" },
-                        "op": "EQUAL",
-                        "value": { "stringValue": id }
-                    }
-                },
-                "limit": 1
+        let body = json!({
+            "fields": {
+                "display_name": { "stringValue": display_name },
+                "username": { "stringValue": username },
+                "email": { "stringValue": email }
             }
         });
 
-        let response = self.client.post(&url)
+        let response = self.client.patch(&url)
             .bearer_auth(&self.access_token)
-            .json(&query)
+            .json(&body)
             .send()
             .await?;
 
-        let json: Vec<serde_json::Value> = response.json().await?;
-        if let Some(document) = json.first().and_then(|d| d.get("document").cloned()) {
-            Ok(Some(document))
+        if response.status().is_success() {
+            println!("✅ Profile updated: {}", username);
+            Ok(())
         } else {
-            Ok(None)
+            let error = response.text().await.unwrap_or_default();
+            eprintln!("❌ Failed to update profile: {}", error);
+            Err(error.into())
         }
     }
 
-    pub async fn get_user_by_username(&self, username: &str) -> Result<Option<serde_json::Value>, reqwest::Error> {
-        let url = format!(
-            "https://firestore.googleapis.com/v1/projects/{}/databases/(default)/documents:runQuery",
-            "your-firebase-project-id"
-        );
+    /// Replaces the stored password hash, e.g. after a rehash on login.
+    pub async fn update_user_password(&self, user_id: &str, password_hash: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let url = format!("{}/users/{}?updateMask.fieldPaths=password", self.documents_url(), user_id);
 
-        let query = json!({
-            "structuredQuery": {
-                "from": [{ "collectionId": "users" }],
-                "where": {
-                    "fieldFilter": {
-                        "field": { "fieldPath": "username" },
-                        "op": "EQUAL",
-                        "value": { "stringValue": username }
-                    }
-                },
-                "limit": 1
+        let body = json!({
+            "fields": {
+                "password": { "stringValue": password_hash }
             }
         });
 
-        let response = self.client.post(&url)
+        let response = self.client.patch(&url)
             .bearer_auth(&self.access_token)
-            .json(&query)
+            .json(&body)
             .send()
             .await?;
 
-        let json: Vec<serde_json::Value> = response.json().await?;
-        if let Some(document) = json.first().and_then(|d| d.get("document").cloned()) {
-            Ok(Some(document))
+        if response.status().is_success() {
+            Ok(())
         } else {
-            Ok(None)
+            let error = response.text().await.unwrap_or_default();
+            eprintln!("❌ Failed to update password: {}", error);
+            Err(error.into())
         }
     }
 }
-
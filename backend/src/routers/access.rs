@@ -0,0 +1,86 @@
+use axum::{
+    extract::{Path, Request},
+    http::{Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde_json::json;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::connection::session::AuthUser;
+use crate::connection::storage::Storage;
+use crate::model::project::{Permission, Project, Role};
+
+/// The caller's standing in the project a request touches. Added to the
+/// request extensions by [`require_access`] for handlers that need it.
+#[derive(Debug, Clone)]
+pub struct ProjectAccess {
+    pub project: Project,
+    pub role: Role,
+}
+
+/// Reads are open to every member; anything else needs write access.
+pub fn by_method(method: &Method) -> Permission {
+    if method == Method::GET || method == Method::HEAD {
+        Permission::Read
+    } else {
+        Permission::Write
+    }
+}
+
+/// Every member may use the routes, whatever the method. For routes that
+/// leave the project's files untouched, like running or reviewing them.
+pub fn read_only(_: &Method) -> Permission {
+    Permission::Read
+}
+
+fn error(status: StatusCode, message: &str) -> Response {
+    (status, Json(json!({ "error": message }))).into_response()
+}
+
+async fn find_project(storage: &dyn Storage, params: &HashMap<String, String>) -> Result<Option<Project>, Response> {
+    let internal = |e: Box<dyn std::error::Error + Send + Sync>| error(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string());
+
+    if let Some(project_id) = params.get("project_id") {
+        return storage.get_project(project_id).await.map_err(internal);
+    }
+
+    let Some(file_id) = params.get("file_id") else {
+        return Err(error(StatusCode::INTERNAL_SERVER_ERROR, "Route has no project or file to authorize"));
+    };
+    match storage.get_file_by_id(file_id).await.map_err(internal)? {
+        Some(file) => storage.get_project(&file.project_id.to_string()).await.map_err(internal),
+        None => Ok(None),
+    }
+}
+
+/// Route layer that resolves the project named by the `project_id` path
+/// parameter (or owning the `file_id` one) and checks the caller's role
+/// against `policy`. Non-members get 403 even for reads.
+pub async fn require_access(
+    storage: Arc<dyn Storage>,
+    policy: fn(&Method) -> Permission,
+    user: AuthUser,
+    Path(params): Path<HashMap<String, String>>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let project = match find_project(storage.as_ref(), &params).await {
+        Ok(Some(project)) => project,
+        Ok(None) => return error(StatusCode::NOT_FOUND, "Project not found"),
+        Err(response) => return response,
+    };
+
+    let Some(role) = project.role_of(user.user_id) else {
+        return error(StatusCode::FORBIDDEN, "You are not a member of this project");
+    };
+    if !role.allows(policy(request.method())) {
+        let message = format!("A project {} cannot do this", role.as_str());
+        return error(StatusCode::FORBIDDEN, &message);
+    }
+
+    request.extensions_mut().insert(ProjectAccess { project, role });
+    next.run(request).await
+}
@@ -1,79 +1,600 @@
-use axum::{routing::post, Json, Router};
+use axum::{
+    extract::Path,
+    http::StatusCode,
+    middleware,
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse,
+    },
+    routing::{get, post},
+    Extension, Json, Router,
+};
+use futures_util::stream::{self, Stream};
 use serde::{Deserialize, Serialize};
-use reqwest;
+use serde_json::json;
+use std::convert::Infallible;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use uuid::Uuid;
 
-#[derive(Deserialize)]
-struct ReviewRequest {
-    language: String,
-    code: String,
-}
-
-#[derive(Serialize, Deserialize)]
-pub struct Suggestion {
-    line: usize,
-    message: String,
-    severity: Option<String>,
-    fix: Option<String>,
-}
+use crate::ai::diff::DiffReviewRequest;
+use crate::ai::project_review::{ReviewJobs, SourceFile};
+use crate::ai::provider::AiProvider;
+use crate::ai::review::{ReviewEvent, ReviewRequest, ReviewResponse, Suggestion};
+use crate::connection::document::{DocumentStore, TextOperation};
+use crate::connection::real_time::CollabHub;
+use crate::connection::session::AuthUser;
+use crate::connection::storage::Storage;
+use crate::executor::diagnostics::Diagnostic;
+use crate::executor::runner::{Execution, Executor, Stage};
+use crate::executor::toolchain::Toolchain;
+use crate::routers::access::{by_method, read_only, require_access, ProjectAccess};
+use crate::routers::folders::load_tree;
 
 #[derive(Serialize, Deserialize)]
-pub struct ReviewResponse {
-    suggestions: Vec<Suggestion>,
-}
-
-#[derive(Deserialize)]
 pub struct CompileRequest {
     pub language: String,
     pub code: String,
+    /// Fed to the program's standard input.
+    #[serde(default)]
+    pub stdin: String,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize)]
 pub struct CompileResponse {
     pub stdout: String,
     pub stderr: String,
     pub exit_code: i32,
+    /// `compile` when the build failed and the program never ran.
+    pub stage: Stage,
+    /// Set when the program was killed by a signal (including limit breaches).
+    pub signal: Option<i32>,
+    pub timed_out: bool,
+    pub output_truncated: bool,
+    pub compile_time_ms: Option<u64>,
+    pub wall_time_ms: u64,
+    pub cpu_time_ms: u64,
+    pub max_memory_kb: u64,
+    /// Compiler errors and warnings, with project-relative paths.
+    pub diagnostics: Vec<Diagnostic>,
 }
 
-async fn review_code_handler(Json(req): Json<ReviewRequest>) -> Result<Json<ReviewResponse>, (axum::http::StatusCode, String)> {
-    let ai_url = "http://localhost:8001/review";
-    let client = reqwest::Client::new();
-    let resp = client
-        .post(ai_url)
-        .json(&req)
-        .send()
-        .await
-        .map_err(|e| (axum::http::StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
-    let status = resp.status();
-    let body = resp.text().await.unwrap_or_else(|_| "{\"suggestions\":[]}".to_string());
-    if status.is_success() {
-        let parsed: ReviewResponse = serde_json::from_str(&body).unwrap_or(ReviewResponse { suggestions: vec![] });
-        Ok(Json(parsed))
-    } else {
-        Err((status, body))
-    }
-}
-
-async fn compile_code_handler(Json(req): Json<CompileRequest>) -> Result<Json<CompileResponse>, (axum::http::StatusCode, String)> {
-    let ai_url = "http://localhost:8001/compile";
-    let client = reqwest::Client::new();
-    let resp = client
-        .post(ai_url)
-        .json(&req)
-        .send()
+impl From<Execution> for CompileResponse {
+    fn from(run: Execution) -> Self {
+        Self {
+            stdout: run.stdout,
+            stderr: run.stderr,
+            exit_code: run.exit_code,
+            stage: run.stage,
+            signal: run.signal,
+            timed_out: run.timed_out,
+            output_truncated: run.output_truncated,
+            compile_time_ms: run.compile_time_ms,
+            wall_time_ms: run.wall_time_ms,
+            cpu_time_ms: run.cpu_time_ms,
+            max_memory_kb: run.max_memory_kb,
+            diagnostics: run.diagnostics,
+        }
+    }
+}
+
+async fn review_code_handler(ai: Arc<dyn AiProvider>, _user: AuthUser, Json(req): Json<ReviewRequest>) -> Result<Json<ReviewResponse>, (axum::http::StatusCode, String)> {
+    match ai.review(&req).await {
+        Ok(review) => Ok(Json(review.with_edits(&req.code, req.revision))),
+        Err(e) => {
+            eprintln!("❌ AI review failed: {}", e);
+            Err((StatusCode::BAD_GATEWAY, e.to_string()))
+        }
+    }
+}
+
+/// Reviews only what changed since a base revision; suggestions point at the current code.
+async fn review_diff_handler(ai: Arc<dyn AiProvider>, _user: AuthUser, Json(req): Json<DiffReviewRequest>) -> Result<Json<ReviewResponse>, (axum::http::StatusCode, String)> {
+    if req.base_revision.is_some() {
+        return Err((StatusCode::BAD_REQUEST, "Review a stored file's changes since `base_revision` at /files/{file_id}/review/diff".to_string()));
+    }
+    review_diff(ai.as_ref(), &req).await
+}
+
+/// Reviews what changed in a stored file since `base_revision`, reading the
+/// file as collaborators currently see it.
+async fn review_file_diff_handler(
+    storage: Arc<dyn Storage>,
+    documents: Arc<DocumentStore>,
+    ai: Arc<dyn AiProvider>,
+    Path(file_id): Path<Uuid>,
+    Json(req): Json<DiffReviewRequest>,
+) -> Result<Json<ReviewResponse>, (axum::http::StatusCode, String)> {
+    let internal = |e: Box<dyn std::error::Error + Send + Sync>| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string());
+    let Some(base_revision) = req.base_revision else {
+        return Err((StatusCode::BAD_REQUEST, "Send the `base_revision` to review the changes since".to_string()));
+    };
+    let file_id = file_id.to_string();
+    let file = storage.get_file_by_id(&file_id).await.map_err(internal)?.ok_or((StatusCode::NOT_FOUND, "File not found".to_string()))?;
+    let base = storage
+        .get_file_revision(&file_id, base_revision)
         .await
-        .map_err(|e| (axum::http::StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
-    let status = resp.status();
-    let body = resp.text().await.unwrap_or_else(|_| "{\"stdout\":\"\",\"stderr\":\"\",\"exit_code\":1}".to_string());
-    if status.is_success() {
-        let parsed: CompileResponse = serde_json::from_str(&body).unwrap_or(CompileResponse { stdout: "".to_string(), stderr: "Error parsing response".to_string(), exit_code: 1 });
-        Ok(Json(parsed))
-    } else {
-        Err((status, body))
+        .map_err(internal)?
+        .ok_or((StatusCode::NOT_FOUND, "Revision not found".to_string()))?;
+
+    let (code, revision) = match documents.peek(&file_id).await {
+        Some((_, live)) if live != file.content => (live, None),
+        _ => (file.content, Some(file.revision)),
+    };
+    let language = if req.language.is_empty() { file.file_type } else { req.language };
+    let req = DiffReviewRequest { language, code, revision, base: Some(base.content), diff: None, ..req };
+    review_diff(ai.as_ref(), &req).await
+}
+
+async fn review_diff(ai: &dyn AiProvider, req: &DiffReviewRequest) -> Result<Json<ReviewResponse>, (axum::http::StatusCode, String)> {
+    let excerpt = req.excerpt().map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    if excerpt.is_empty() {
+        return Ok(Json(ReviewResponse::default()));
+    }
+    match ai.review_diff(&req.language, &req.code, &excerpt).await {
+        Ok(review) => Ok(Json(review.with_edits(&req.code, req.revision))),
+        Err(e) => {
+            eprintln!("❌ AI review failed: {}", e);
+            Err((StatusCode::BAD_GATEWAY, e.to_string()))
+        }
     }
 }
 
-pub fn ai_review_routes() -> Router {
+/// Streams the review as server-sent events: `suggestion` for each suggestion
+/// as the model writes it, `progress` now and then, and finally `done` with
+/// the whole review or `error`.
+async fn review_stream_handler(ai: Arc<dyn AiProvider>, _user: AuthUser, Json(req): Json<ReviewRequest>) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let (events, received) = mpsc::unbounded_channel();
+    let (code, revision) = (req.code.clone(), req.revision);
+    tokio::spawn(async move {
+        let last = match ai.review_stream(&req, &events).await {
+            Ok(review) => ReviewEvent::Done(review),
+            Err(e) => {
+                eprintln!("❌ AI review failed: {}", e);
+                ReviewEvent::Error(e.to_string())
+            }
+        };
+        let _ = events.send(last);
+    });
+
+    let stream = stream::unfold((received, code), move |(mut received, code)| async move {
+        let event = match received.recv().await? {
+            ReviewEvent::Progress(progress) => Event::default().event("progress").json_data(progress),
+            ReviewEvent::Suggestion(suggestion) => Event::default().event("suggestion").json_data(suggestion.with_edit(&code, revision)),
+            ReviewEvent::Done(review) => Event::default().event("done").json_data(review.with_edits(&code, revision)),
+            ReviewEvent::Error(message) => Event::default().event("error").json_data(json!({ "error": message })),
+        };
+        Some((Ok(event.expect("review events serialize")), (received, code)))
+    });
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
+
+/// Applies a suggestion's edit to a file. Open files are edited through the
+/// live document like any collaborator's change. If the file is no longer at
+/// the revision the review was made against, the edit is refused with 409
+/// unless the lines it replaces still read the same at the same place.
+async fn apply_fix_handler(
+    storage: Arc<dyn Storage>,
+    hub: Arc<CollabHub>,
+    documents: Arc<DocumentStore>,
+    user: AuthUser,
+    Path(file_id): Path<Uuid>,
+    Json(suggestion): Json<Suggestion>,
+) -> impl IntoResponse {
+    let Some(edit) = suggestion.edit else {
+        return (StatusCode::BAD_REQUEST, Json(json!({"error": "This suggestion has no fix to apply"}))).into_response();
+    };
+    let file_id = file_id.to_string();
+    let author = user.user_id.to_string();
+    let conflict = |message: String| (StatusCode::CONFLICT, Json(json!({"error": message}))).into_response();
+    let invalid = |message: String| (StatusCode::BAD_REQUEST, Json(json!({"error": message}))).into_response();
+    let line = edit.start_line;
+
+    let file = match storage.get_file_by_id(&file_id).await {
+        Ok(Some(file)) => file,
+        Ok(None) => return (StatusCode::NOT_FOUND, Json(json!({"error": "File not found"}))).into_response(),
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({"error": e.to_string()}))).into_response(),
+    };
+
+    if let Some((revision, content)) = documents.peek(&file_id).await {
+        // Unsaved changes mean the live text is no saved revision at all.
+        let saved = (content == file.content).then_some(file.revision);
+        if let Err(message) = edit.check(&content) {
+            return invalid(message);
+        }
+        let fixed = match edit.apply(&content, saved) {
+            Ok(fixed) => fixed,
+            Err(message) => return conflict(message),
+        };
+        // Edits that land between the peek and here are rebased over, as for any client.
+        return match documents.apply(&file_id, revision, TextOperation::diff(&content, &fixed), &author).await {
+            Ok(applied) => {
+                hub.broadcast_edit(&file_id, Uuid::nil(), applied).await;
+                (StatusCode::OK, Json(json!({"message": "Fix applied", "line": line}))).into_response()
+            }
+            Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({"error": e.to_string()}))).into_response(),
+        };
+    }
+
+    if let Err(message) = edit.check(&file.content) {
+        return invalid(message);
+    }
+    let fixed = match edit.apply(&file.content, Some(file.revision)) {
+        Ok(fixed) => fixed,
+        Err(message) => return conflict(message),
+    };
+    match storage.update_file_content(&file_id, &fixed, &[author]).await {
+        Ok(revision) => (StatusCode::OK, Json(json!({"message": "Fix applied", "line": line, "revision": revision}))).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({"error": e.to_string()}))).into_response(),
+    }
+}
+
+/// Starts reviewing every file of the project as collaborators currently see it.
+async fn start_project_review_handler(
+    storage: Arc<dyn Storage>,
+    documents: Arc<DocumentStore>,
+    reviews: Arc<ReviewJobs>,
+    user: AuthUser,
+    Extension(access): Extension<ProjectAccess>,
+) -> impl IntoResponse {
+    let tree = match load_tree(storage.as_ref(), &access.project).await {
+        Ok(tree) => tree,
+        Err(response) => return response,
+    };
+    let mut files = Vec::with_capacity(tree.files.len());
+    for file in &tree.files {
+        let (content, revision) = match documents.peek(&file.id.to_string()).await {
+            Some((_, live)) if live != file.content => (live, None),
+            _ => (file.content.clone(), Some(file.revision)),
+        };
+        files.push(SourceFile { id: file.id, path: tree.file_path(file), language: file.file_type.clone(), content, revision });
+    }
+    match reviews.start(access.project.id, user.user_id, files) {
+        Ok(job) => (StatusCode::ACCEPTED, Json(job)).into_response(),
+        Err(running) => (
+            StatusCode::CONFLICT,
+            Json(json!({"error": "A review of this project is already running", "job_id": running})),
+        )
+            .into_response(),
+    }
+}
+
+async fn project_review_handler(
+    reviews: Arc<ReviewJobs>,
+    Extension(access): Extension<ProjectAccess>,
+    Path((_, job_id)): Path<(Uuid, Uuid)>,
+) -> impl IntoResponse {
+    match reviews.get(job_id) {
+        Some(job) if job.project_id == access.project.id => (StatusCode::OK, Json(job)).into_response(),
+        _ => (StatusCode::NOT_FOUND, Json(json!({"error": "Review job not found"}))).into_response(),
+    }
+}
+
+async fn compile_code_handler(executor: Arc<Executor>, _user: AuthUser, Json(req): Json<CompileRequest>) -> Result<Json<CompileResponse>, (axum::http::StatusCode, String)> {
+    let toolchain = Toolchain::find(&req.language)
+        .ok_or_else(|| (StatusCode::BAD_REQUEST, format!("Unsupported language: {}", req.language)))?;
+    let run = executor
+        .execute(toolchain, &req.code, &req.stdin)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    Ok(Json(run.into()))
+}
+
+pub fn ai_review_routes(ai: Arc<dyn AiProvider>, executor: Arc<Executor>) -> Router {
+    let (streaming, diffs) = (Arc::clone(&ai), Arc::clone(&ai));
+    Router::new()
+        .route("/review", post(move |user, payload| review_code_handler(ai, user, payload)))
+        .route("/review/stream", post(move |user, payload| review_stream_handler(streaming, user, payload)))
+        .route("/review/diff", post(move |user, payload| review_diff_handler(diffs, user, payload)))
+        .route("/compile", post(move |user, payload| compile_code_handler(executor, user, payload)))
+}
+
+/// Applying review fixes to stored files, nested under `/files`.
+pub fn ai_fix_routes(storage: Arc<dyn Storage>, hub: Arc<CollabHub>, documents: Arc<DocumentStore>) -> Router {
+    Router::new()
+        .route(
+            "/{file_id}/apply-fix",
+            post({
+                let storage = Arc::clone(&storage);
+                move |user, path, payload| apply_fix_handler(storage, hub, documents, user, path, payload)
+            }),
+        )
+        .route_layer(middleware::from_fn(move |user, path, request, next| {
+            require_access(Arc::clone(&storage), by_method, user, path, request, next)
+        }))
+}
+
+/// Reviewing stored files, nested under `/files`. Open to every member.
+pub fn file_review_routes(storage: Arc<dyn Storage>, documents: Arc<DocumentStore>, ai: Arc<dyn AiProvider>) -> Router {
     Router::new()
-        .route("/review", post(review_code_handler))
-        .route("/compile", post(compile_code_handler))
-} 
\ No newline at end of file
+        .route(
+            "/{file_id}/review/diff",
+            post({
+                let storage = Arc::clone(&storage);
+                move |path, payload| review_file_diff_handler(storage, documents, ai, path, payload)
+            }),
+        )
+        .route_layer(middleware::from_fn(move |user, path, request, next| {
+            require_access(Arc::clone(&storage), read_only, user, path, request, next)
+        }))
+}
+
+/// Project-wide reviews, nested under `/project/{project_id}`.
+pub fn project_review_routes(storage: Arc<dyn Storage>, documents: Arc<DocumentStore>, reviews: Arc<ReviewJobs>) -> Router {
+    Router::new()
+        .route(
+            "/reviews",
+            post({
+                let (storage, reviews) = (Arc::clone(&storage), Arc::clone(&reviews));
+                move |user, access| start_project_review_handler(storage, documents, reviews, user, access)
+            }),
+        )
+        .route("/reviews/{job_id}", get(move |access, path| project_review_handler(reviews, access, path)))
+        .route_layer(middleware::from_fn(move |user, path, request, next| {
+            require_access(Arc::clone(&storage), by_method, user, path, request, next)
+        }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_trait::async_trait;
+    use axum::http::{Method, StatusCode};
+    use std::time::Duration;
+    use uuid::Uuid;
+
+    use crate::ai::provider::{AiResult, ChatMessage};
+    use crate::executor::sandbox::{Isolation, Limits, Sandbox};
+    use crate::test_support::sandbox;
+    use crate::model::project::Role;
+    use crate::routers::history::history_routes;
+    use crate::test_support::{app::TestApp, http::{send_as, send_bytes}};
+
+    /// Writes a canned reply a few characters at a time.
+    struct Typist(&'static str);
+
+    #[async_trait]
+    impl AiProvider for Typist {
+        fn name(&self) -> &'static str {
+            "typist"
+        }
+
+        async fn chat(&self, _messages: &[ChatMessage]) -> AiResult<String> {
+            Ok(self.0.to_string())
+        }
+
+        async fn chat_stream(&self, _messages: &[ChatMessage], chunks: &mpsc::UnboundedSender<String>) -> AiResult<String> {
+            let chars: Vec<char> = self.0.chars().collect();
+            for piece in chars.chunks(5) {
+                chunks.send(piece.iter().collect())?;
+            }
+            Ok(self.0.to_string())
+        }
+    }
+
+    /// The SSE body as `(event, data)` pairs.
+    fn events(body: &[u8]) -> Vec<(String, serde_json::Value)> {
+        String::from_utf8_lossy(body)
+            .split("\n\n")
+            .filter_map(|block| {
+                let field = |name: &str| block.lines().find_map(|line| line.strip_prefix(name)).map(str::to_string);
+                Some((field("event: ")?, serde_json::from_str(&field("data: ")?).ok()?))
+            })
+            .collect()
+    }
+
+    /// The AI routes with `reply` as the model's answer, and a token to call them with.
+    async fn router(reply: &'static str) -> (Router, String) {
+        let limits = Limits {
+            cpu_seconds: 1,
+            memory_bytes: 64 * 1024 * 1024,
+            wall_clock: Duration::from_secs(1),
+            output_bytes: 1024,
+            processes: 8,
+            file_bytes: 1024,
+        };
+        let executor = Arc::new(Executor::new(Sandbox::new(sandbox::helper(), Isolation::Namespaces), limits, 1));
+        let app = TestApp::new().await;
+        (app.serve(ai_review_routes(Arc::new(Typist(reply)), executor)), app.token(Uuid::new_v4()))
+    }
+
+    #[tokio::test]
+    async fn reviews_stream_each_suggestion_then_the_result() {
+        let reply = r#"{"suggestions": [{"line": 1, "message": "Name the constant", "severity": "info", "fix": null}, {"line": 2, "message": "Division by zero", "severity": "error", "fix": "print(x)"}]}"#;
+        let (router, token) = router(reply).await;
+
+        let body = serde_json::json!({ "language": "python", "code": "x = 1\nprint(x / 0)" }).to_string().into_bytes();
+        let (status, response) = send_bytes(&router, Some(&token), Method::POST, "/review/stream", "application/json", body).await;
+        assert_eq!(status, StatusCode::OK);
+        let events = events(&response);
+        let names: Vec<&str> = events.iter().map(|(name, _)| name.as_str()).filter(|name| *name != "progress").collect();
+        assert_eq!(names, ["suggestion", "suggestion", "done"]);
+        assert_eq!(events[0].1["message"], "Name the constant");
+        assert_eq!(events.last().unwrap().1["suggestions"][1]["fix"], "print(x)");
+
+        let (status, _) = send_bytes(&router, None, Method::POST, "/review/stream", "application/json", b"{}".to_vec()).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn diff_reviews_point_at_the_current_file() {
+        // Excerpt line 3 is the added `print(x / 0)`, line 4 of the file; the imports are out of context.
+        let (router, token) = router(r#"[{"line": 3, "message": "Division by zero", "severity": "error", "fix": "print(x)"}]"#).await;
+        let base = "import os\nimport sys\nx = 1\nprint(x)\n";
+        let code = "import os\nimport sys\nx = 1\nprint(x / 0)\n";
+        let send = |body: serde_json::Value| send_as(&router, Some(&token), Method::POST, "/review/diff", Some(body));
+
+        let (status, body) = send(serde_json::json!({ "language": "python", "code": code, "revision": 5, "base": base, "context": 1 })).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["suggestions"], serde_json::json!([{
+                "line": 4,
+                "message": "Division by zero",
+                "severity": "error",
+                "fix": "print(x)",
+                "edit": { "start_line": 4, "end_line": 4, "original": "print(x / 0)", "replacement": "print(x)", "revision": 5 }
+            }]));
+
+        let (_, body) = send(serde_json::json!({ "language": "python", "code": code, "base": code })).await;
+        assert_eq!(body["suggestions"], serde_json::json!([]), "an unchanged file is not sent to the model");
+
+        let (status, body) = send(serde_json::json!({ "language": "python", "code": code })).await;
+        assert_eq!((status, body), (StatusCode::BAD_REQUEST, serde_json::Value::Null));
+        let (status, _) = send(serde_json::json!({ "language": "python", "code": code, "base_revision": 1 })).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "stored files are reviewed under /files");
+    }
+
+    #[tokio::test]
+    async fn stored_files_are_reviewed_since_a_saved_revision() {
+        let app = TestApp::new().await;
+        let reply = r#"[{"line": 3, "message": "Division by zero", "severity": "error", "fix": "print(x)"}]"#;
+        let routes = file_review_routes(Arc::clone(&app.storage), Arc::clone(&app.documents), Arc::new(Typist(reply)));
+        let router = app.serve(Router::new().nest("/files", routes));
+
+        let (owner, viewer) = (Uuid::new_v4(), Uuid::new_v4());
+        let project_id = app.project(owner, &[(viewer, Role::Viewer)]).await;
+        let file_id = app.storage.create_file("main.py".into(), owner, project_id, None).await.unwrap().unwrap().to_string();
+        for code in ["x = 1\nprint(x)\n", "x = 1\nprint(x / 0)\n"] {
+            app.storage.update_file_content(&file_id, code, &[owner.to_string()]).await.unwrap();
+        }
+        let uri = format!("/files/{}/review/diff", file_id);
+        let review = |user, body| {
+            let (router, uri, token) = (router.clone(), uri.clone(), app.token(user));
+            async move { send_as(&router, Some(&token), Method::POST, &uri, Some(body)).await }
+        };
+
+        // Excerpt line 3 is the added `print(x / 0)`, line 2 of the saved revision 2.
+        let (status, body) = review(viewer, json!({ "base_revision": 1 })).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["suggestions"][0]["line"], 2);
+        assert_eq!(body["suggestions"][0]["edit"]["revision"], 2);
+
+        // An open file is reviewed as collaborators see it, which is no saved revision.
+        app.documents.replace(&file_id, "x = 1\nprint(x / 0)\n# live\n", &owner.to_string()).await.unwrap();
+        let (_, body) = review(viewer, json!({ "base_revision": 1 })).await;
+        assert_eq!(body["suggestions"][0]["edit"]["original"], "print(x / 0)");
+        assert_eq!(body["suggestions"][0]["edit"]["revision"], serde_json::Value::Null);
+
+        let (status, _) = review(viewer, json!({ "base_revision": 9 })).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let (status, _) = review(viewer, json!({})).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let (status, _) = review(Uuid::new_v4(), json!({ "base_revision": 1 })).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn fixes_apply_to_stored_and_open_files_unless_the_code_moved_on() {
+        let app = TestApp::new().await;
+        let hub = Arc::new(CollabHub::new());
+        let routes = Router::new()
+            .nest("/files", history_routes(Arc::clone(&app.storage), Arc::clone(&hub), Arc::clone(&app.documents)))
+            .nest("/files", ai_fix_routes(Arc::clone(&app.storage), hub, Arc::clone(&app.documents)));
+        let router = app.serve(routes);
+
+        let (owner, viewer) = (Uuid::new_v4(), Uuid::new_v4());
+        let project_id = app.project(owner, &[(viewer, Role::Viewer)]).await;
+        let file_id = app.storage.create_file("main.py".into(), owner, project_id, None).await.unwrap().unwrap().to_string();
+        let code = "def area(r):\n    return 3.14 * r * r\n";
+        app.storage.update_file_content(&file_id, code, &[owner.to_string()]).await.unwrap();
+
+        let fix = |reviewed: &str, revision, line, fix: &str| {
+            let suggestion = Suggestion { line, message: "Use math.pi".into(), severity: None, fix: Some(fix.into()), edit: None };
+            serde_json::to_value(suggestion.with_edit(reviewed, revision)).unwrap()
+        };
+        let uri = format!("/files/{}/apply-fix", file_id);
+        let token = app.token(owner);
+        let apply = |body| send_as(&router, Some(&token), Method::POST, &uri, Some(body));
+
+        let (status, body) = apply(fix(code, Some(1), 2, "return math.pi * r * r")).await;
+        assert_eq!((status, &body["revision"]), (StatusCode::OK, &json!(2)));
+        let stored = app.storage.get_file_by_id(&file_id).await.unwrap().unwrap().content;
+        assert_eq!(stored, "def area(r):\n    return math.pi * r * r\n");
+
+        let (status, body) = apply(fix(code, Some(1), 2, "return r ** 2 * 3.14")).await;
+        let changed = "The file changed since the review (revision 1, now 2) and line 2 no longer matches";
+        assert_eq!((status, &body["error"]), (StatusCode::CONFLICT, &json!(changed)));
+
+        // With the file open, the fix goes through the live document; lines that moved are not chased.
+        let live = format!("import math\n{}", stored);
+        app.documents.replace(&file_id, &live, "grace").await.unwrap();
+        let (status, body) = apply(fix(&stored, Some(2), 1, "def area(radius):")).await;
+        assert_eq!((status, &body["error"]), (StatusCode::CONFLICT, &json!("Line 1 changed since the review")));
+        let (status, body) = apply(fix(&live, None, 2, "def area(radius):")).await;
+        assert_eq!((status, &body["line"]), (StatusCode::OK, &json!(2)));
+        let (_, live) = app.documents.peek(&file_id).await.unwrap();
+        assert_eq!(live, "import math\ndef area(radius):\n    return math.pi * r * r\n");
+
+        let viewer_token = app.token(viewer);
+        let (status, _) = send_as(&router, Some(&viewer_token), Method::POST, &uri, Some(fix(&live, None, 1, "def f(r):"))).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let (status, _) = apply(json!({ "line": 1, "message": "No fix", "severity": null, "fix": null })).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let mut far = fix(&live, None, 1, "def f(r):");
+        far["edit"]["start_line"] = json!(usize::MAX);
+        far["edit"]["end_line"] = json!(usize::MAX);
+        let (status, body) = apply(far).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{}", body);
+    }
+
+    /// Flags the first line of whatever it is shown, and fails on code mentioning `boom`.
+    struct FirstLine;
+
+    #[async_trait]
+    impl AiProvider for FirstLine {
+        fn name(&self) -> &'static str {
+            "first line"
+        }
+
+        async fn chat(&self, messages: &[ChatMessage]) -> AiResult<String> {
+            if messages[1].content.contains("boom") {
+                return Err("the model crashed".into());
+            }
+            Ok(r#"[{"line": 1, "message": "Look here", "severity": "info", "fix": null}]"#.to_string())
+        }
+    }
+
+    #[tokio::test]
+    async fn project_reviews_chunk_files_and_report_per_file() {
+        let app = TestApp::new().await;
+        // Room for two of the ten-character lines below per chunk.
+        let reviews = Arc::new(ReviewJobs::new(Arc::new(FirstLine), 2, 25));
+        let routes = Router::new().nest("/project/{project_id}", project_review_routes(Arc::clone(&app.storage), Arc::clone(&app.documents), reviews));
+        let router = app.serve(routes);
+
+        let (owner, viewer) = (Uuid::new_v4(), Uuid::new_v4());
+        let project_id = app.project(owner, &[(viewer, Role::Viewer)]).await;
+        for (name, content) in [("a.py", "x = 1 + 1\ny = 2 + 2\nz = 3 + 3\n"), ("b.py", "boom()\n"), ("empty.py", "")] {
+            let file_id = app.storage.create_file(name.into(), owner, project_id, None).await.unwrap().unwrap();
+            app.storage.update_file_content(&file_id.to_string(), content, &[owner.to_string()]).await.unwrap();
+        }
+
+        let start = format!("/project/{}/reviews", project_id);
+        let (status, _) = send_as(&router, Some(&app.token(viewer)), Method::POST, &start, None).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let token = app.token(owner);
+        let (status, job) = send_as(&router, Some(&token), Method::POST, &start, None).await;
+        assert_eq!((status, &job["chunks"]), (StatusCode::ACCEPTED, &json!(3)));
+        let uri = format!("/project/{}/reviews/{}", project_id, job["id"].as_str().unwrap());
+
+        let mut report = json!(null);
+        for _ in 0..250 {
+            report = send_as(&router, Some(&token), Method::GET, &uri, None).await.1;
+            if report["status"] == "done" {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert_eq!((&report["status"], &report["chunks_done"]), (&json!("done"), &json!(3)));
+        let files = report["files"].as_array().unwrap();
+        assert_eq!(files.iter().map(|file| file["path"].as_str().unwrap()).collect::<Vec<_>>(), ["a.py", "b.py"]);
+        let lines: Vec<&serde_json::Value> = files[0]["suggestions"].as_array().unwrap().iter().map(|s| &s["line"]).collect();
+        assert_eq!((&files[0]["chunks"], lines), (&json!(2), vec![&json!(1), &json!(3)]));
+        assert_eq!(files[1]["errors"], json!(["the model crashed"]));
+
+        let stranger = Uuid::new_v4();
+        let other_project = app.project(stranger, &[]).await;
+        let uri = format!("/project/{}/reviews/{}", other_project, job["id"].as_str().unwrap());
+        let (status, _) = send_as(&router, Some(&app.token(stranger)), Method::GET, &uri, None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+}
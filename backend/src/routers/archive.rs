@@ -0,0 +1,527 @@
+use axum::{
+    body::{Body, Bytes},
+    extract::{DefaultBodyLimit, Json},
+    http::{header, StatusCode},
+    middleware,
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Extension, Router,
+};
+use serde::Serialize;
+use serde_json::json;
+use std::io::{self, Cursor, Read, Seek, SeekFrom, Write};
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use uuid::Uuid;
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
+
+use crate::connection::document::DocumentStore;
+use crate::connection::session::AuthUser;
+use crate::connection::storage::Storage;
+use crate::model::folder::{valid_entry_name, Folder, ProjectTree};
+use crate::routers::access::{by_method, require_access, ProjectAccess};
+use crate::routers::folders::load_tree;
+
+/// Largest zip upload accepted by the import route.
+pub(crate) const MAX_ARCHIVE_BYTES: usize = 10 * 1024 * 1024;
+/// Most entries (files and directories) an imported archive may hold.
+const MAX_ARCHIVE_ENTRIES: usize = 1000;
+/// Largest single file, and largest total, once decompressed.
+const MAX_ENTRY_BYTES: u64 = 1024 * 1024;
+const MAX_UNPACKED_BYTES: u64 = 50 * 1024 * 1024;
+
+/// One file read out of an uploaded archive.
+pub(crate) struct ArchiveEntry {
+    /// Path segments, e.g. `["src", "main.rs"]`.
+    path: Vec<String>,
+    /// `None` for a directory entry.
+    content: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SkippedEntry {
+    pub path: String,
+    pub reason: String,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct ImportReport {
+    pub created_files: Vec<String>,
+    pub created_folders: Vec<String>,
+    pub skipped: Vec<SkippedEntry>,
+}
+
+/// Zip export and import of a whole project, nested under `/project/{project_id}`.
+pub fn archive_routes(storage: Arc<dyn Storage>, documents: Arc<DocumentStore>) -> Router {
+    Router::new()
+        .route(
+            "/export",
+            get({
+                let storage = Arc::clone(&storage);
+                move |access| export_handler(storage, documents, access)
+            }),
+        )
+        .route(
+            "/import",
+            post({
+                let storage = Arc::clone(&storage);
+                move |user, access, body| import_handler(storage, user, access, body)
+            })
+            .layer(DefaultBodyLimit::max(MAX_ARCHIVE_BYTES)),
+        )
+        .route_layer(middleware::from_fn(move |user, path, request, next| {
+            require_access(Arc::clone(&storage), by_method, user, path, request, next)
+        }))
+}
+
+fn error(status: StatusCode, message: &str) -> Response {
+    (status, Json(json!({ "error": message }))).into_response()
+}
+
+/// Sends a zip to the response body as it is written. `ZipWriter` seeks back
+/// over the entry it is writing to fill in its sizes, so that entry is held
+/// until the writer flushes it on starting the next one; everything before it
+/// has already been sent.
+struct ChunkSender {
+    chunks: mpsc::Sender<io::Result<Bytes>>,
+    pending: Vec<u8>,
+    /// Archive offset of `pending[0]`.
+    sent: u64,
+    /// Write position within `pending`.
+    cursor: usize,
+}
+
+impl ChunkSender {
+    fn new(chunks: mpsc::Sender<io::Result<Bytes>>) -> Self {
+        Self { chunks, pending: Vec::new(), sent: 0, cursor: 0 }
+    }
+}
+
+impl Write for ChunkSender {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let overlap = buf.len().min(self.pending.len() - self.cursor);
+        self.pending[self.cursor..self.cursor + overlap].copy_from_slice(&buf[..overlap]);
+        self.pending.extend_from_slice(&buf[overlap..]);
+        self.cursor += buf.len();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        let chunk = Bytes::from(std::mem::take(&mut self.pending));
+        self.sent += chunk.len() as u64;
+        self.cursor = 0;
+        // Fails once the client has gone away, which stops the export.
+        self.chunks
+            .blocking_send(Ok(chunk))
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "Export download was cancelled"))
+    }
+}
+
+impl Seek for ChunkSender {
+    fn seek(&mut self, position: SeekFrom) -> io::Result<u64> {
+        let end = self.sent + self.pending.len() as u64;
+        let target = match position {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => end.checked_add_signed(offset),
+            SeekFrom::Current(offset) => (self.sent + self.cursor as u64).checked_add_signed(offset),
+        };
+        match target {
+            Some(target) if (self.sent..=end).contains(&target) => {
+                self.cursor = (target - self.sent) as usize;
+                Ok(target)
+            }
+            _ => Err(io::Error::new(io::ErrorKind::InvalidInput, "Cannot seek into data that was already sent")),
+        }
+    }
+}
+
+// `ZipWriter` only offers flushing per entry to writers it could read back
+// from, which it never does unless asked to copy an entry.
+impl Read for ChunkSender {
+    fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "An export cannot be read back"))
+    }
+}
+
+/// Packs every folder and file of the tree into a zip, keyed by project path.
+fn pack(tree: &ProjectTree, contents: &[(Uuid, String)], out: ChunkSender) -> zip::result::ZipResult<()> {
+    let mut writer = ZipWriter::new(out);
+    writer.set_flush_on_finish_file(true);
+    let options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+
+    let mut folders: Vec<String> = tree.folders.iter().map(|folder| tree.folder_path(folder.id)).collect();
+    folders.sort();
+    for path in folders {
+        writer.add_directory(format!("{}/", path), options)?;
+    }
+
+    let mut files: Vec<(String, &str)> = contents
+        .iter()
+        .filter_map(|(id, content)| {
+            let file = tree.files.iter().find(|file| file.id == *id)?;
+            Some((tree.file_path(file), content.as_str()))
+        })
+        .collect();
+    files.sort();
+    for (path, content) in files {
+        writer.start_file(path, options)?;
+        writer.write_all(content.as_bytes())?;
+    }
+
+    writer.finish()?.flush()?;
+    Ok(())
+}
+
+/// Reads an uploaded archive, enforcing the entry and size limits. Entries
+/// whose names escape the archive root are refused outright.
+pub(crate) fn unpack(bytes: &[u8]) -> Result<(Vec<ArchiveEntry>, Vec<SkippedEntry>), String> {
+    let mut archive = ZipArchive::new(Cursor::new(bytes)).map_err(|e| format!("Not a valid zip archive: {}", e))?;
+    if archive.len() > MAX_ARCHIVE_ENTRIES {
+        return Err(format!("Archive has {} entries; the limit is {}", archive.len(), MAX_ARCHIVE_ENTRIES));
+    }
+
+    let mut entries = Vec::new();
+    let mut skipped = Vec::new();
+    let mut unpacked = 0u64;
+    for index in 0..archive.len() {
+        let entry = archive.by_index(index).map_err(|e| e.to_string())?;
+        let name = entry.name().to_string();
+        let Some(path) = entry.enclosed_name() else {
+            return Err(format!("Entry '{}' points outside the archive", name));
+        };
+        let path: Vec<String> = path
+            .components()
+            .map(|part| part.as_os_str().to_string_lossy().into_owned())
+            .collect();
+        if path.is_empty() || path.first().is_some_and(|root| root == "__MACOSX") {
+            continue;
+        }
+        if !path.iter().all(|segment| valid_entry_name(segment)) {
+            skipped.push(SkippedEntry { path: name, reason: "Invalid name".into() });
+            continue;
+        }
+
+        if entry.is_dir() {
+            entries.push(ArchiveEntry { path, content: None });
+            continue;
+        }
+        if entry.size() > MAX_ENTRY_BYTES {
+            skipped.push(SkippedEntry { path: name, reason: "File is too large".into() });
+            continue;
+        }
+
+        // The declared size can lie, so cap what is actually read as well.
+        let mut data = Vec::new();
+        entry.take(MAX_ENTRY_BYTES + 1).read_to_end(&mut data).map_err(|e| e.to_string())?;
+        if data.len() as u64 > MAX_ENTRY_BYTES {
+            skipped.push(SkippedEntry { path: name, reason: "File is too large".into() });
+            continue;
+        }
+        unpacked += data.len() as u64;
+        if unpacked > MAX_UNPACKED_BYTES {
+            return Err(format!("Archive unpacks to more than {} bytes", MAX_UNPACKED_BYTES));
+        }
+
+        match String::from_utf8(data) {
+            Ok(content) => entries.push(ArchiveEntry { path, content: Some(content) }),
+            Err(_) => skipped.push(SkippedEntry { path: name, reason: "Not a text file".into() }),
+        }
+    }
+
+    Ok((entries, skipped))
+}
+
+async fn export_handler(
+    storage: Arc<dyn Storage>,
+    documents: Arc<DocumentStore>,
+    Extension(access): Extension<ProjectAccess>,
+) -> impl IntoResponse {
+    let tree = match load_tree(storage.as_ref(), &access.project).await {
+        Ok(tree) => tree,
+        Err(response) => return response,
+    };
+
+    // Open files are exported as collaborators currently see them.
+    let mut contents = Vec::with_capacity(tree.files.len());
+    for file in &tree.files {
+        let content = match documents.peek(&file.id.to_string()).await {
+            Some((_, live)) => live,
+            None => file.content.clone(),
+        };
+        contents.push((file.id, content));
+    }
+
+    // The headers go out before the archive is complete, so a failure part way
+    // through can only cut the download short.
+    let (chunks, received) = mpsc::channel(8);
+    tokio::task::spawn_blocking(move || {
+        if let Err(e) = pack(&tree, &contents, ChunkSender::new(chunks.clone())) {
+            eprintln!("❌ Export failed: {}", e);
+            let _ = chunks.blocking_send(Err(io::Error::other(e.to_string())));
+        }
+    });
+    let archive = Body::from_stream(futures_util::stream::unfold(received, |mut received| async move {
+        received.recv().await.map(|chunk| (chunk, received))
+    }));
+
+    let name: String = access
+        .project
+        .name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c } else { '_' })
+        .collect();
+    let disposition = format!("attachment; filename=\"{}.zip\"", if name.is_empty() { "project" } else { &name });
+
+    (
+        StatusCode::OK,
+        [(header::CONTENT_TYPE, "application/zip".to_string()), (header::CONTENT_DISPOSITION, disposition)],
+        archive,
+    )
+        .into_response()
+}
+
+/// Creates folders and files for every entry of an uploaded zip.
+async fn import_handler(
+    storage: Arc<dyn Storage>,
+    user: AuthUser,
+    Extension(access): Extension<ProjectAccess>,
+    body: Bytes,
+) -> impl IntoResponse {
+    let unpacked = tokio::task::spawn_blocking(move || unpack(&body)).await;
+    let (entries, skipped) = match unpacked {
+        Ok(Ok(result)) => result,
+        Ok(Err(message)) => return error(StatusCode::BAD_REQUEST, &message),
+        Err(e) => return error(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+    };
+
+    match import_entries(storage.as_ref(), &user, &access, entries, skipped).await {
+        Ok(report) => (StatusCode::CREATED, Json(report)).into_response(),
+        Err(response) => response,
+    }
+}
+
+/// Writes unpacked entries into the project, creating missing folders on the
+/// way. Paths that already exist are left alone and reported as skipped.
+pub(crate) async fn import_entries(
+    storage: &dyn Storage,
+    user: &AuthUser,
+    access: &ProjectAccess,
+    entries: Vec<ArchiveEntry>,
+    skipped: Vec<SkippedEntry>,
+) -> Result<ImportReport, Response> {
+    let mut tree = load_tree(storage, &access.project).await?;
+    let project_id = access.project.id;
+    let internal = |e: Box<dyn std::error::Error + Send + Sync>| error(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string());
+    let mut report = ImportReport { skipped, ..Default::default() };
+
+    for entry in entries {
+        let display = entry.path.join("/");
+        let (folders, name) = match entry.content {
+            Some(_) => entry.path.split_at(entry.path.len() - 1),
+            None => (entry.path.as_slice(), &[][..]),
+        };
+
+        // Walk down the folders, creating any that are missing.
+        let mut parent: Option<Uuid> = None;
+        let mut blocked = false;
+        for segment in folders {
+            if let Some(existing) = tree.child_folder(parent, segment) {
+                parent = Some(existing.id);
+                continue;
+            }
+            if tree.name_taken(parent, segment, None) {
+                blocked = true;
+                break;
+            }
+            let folder = Folder {
+                id: Uuid::new_v4(),
+                project_id,
+                parent,
+                name: segment.clone(),
+                created_by: user.user_id,
+            };
+            if !storage.create_folder(&folder).await.map_err(internal)? {
+                blocked = true;
+                break;
+            }
+            let id = folder.id;
+            tree.folders.push(folder);
+            report.created_folders.push(tree.folder_path(id));
+            parent = Some(id);
+        }
+        if blocked {
+            report.skipped.push(SkippedEntry { path: display, reason: "A file is in the way of this path".into() });
+            continue;
+        }
+
+        let (Some(content), [name]) = (entry.content, name) else { continue };
+        if tree.name_taken(parent, name, None) {
+            report.skipped.push(SkippedEntry { path: display, reason: "Already exists".into() });
+            continue;
+        }
+
+        let Some(file_id) = storage.create_file(name.clone(), user.user_id, project_id, parent).await.map_err(internal)? else {
+            report.skipped.push(SkippedEntry { path: display, reason: "Already exists".into() });
+            continue;
+        };
+        if !content.is_empty() {
+            storage.update_file_content(&file_id.to_string(), &content, &[user.user_id.to_string()]).await.map_err(internal)?;
+        }
+        if let Some(file) = storage.get_file_by_id(&file_id.to_string()).await.map_err(internal)? {
+            tree.files.push(file);
+        }
+        report.created_files.push(display);
+    }
+
+    println!(
+        "📦 Imported {} files and {} folders into project {}",
+        report.created_files.len(), report.created_folders.len(), project_id
+    );
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::Method;
+
+    use crate::model::project::Role;
+    use crate::test_support::{app::TestApp, http::send_bytes};
+
+    fn zip_of(entries: &[(&str, &str)]) -> Vec<u8> {
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, content) in entries {
+            if name.ends_with('/') {
+                writer.add_directory(*name, SimpleFileOptions::default()).unwrap();
+            } else {
+                writer.start_file(*name, SimpleFileOptions::default()).unwrap();
+                writer.write_all(content.as_bytes()).unwrap();
+            }
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    fn read_zip(bytes: &[u8]) -> Vec<(String, String)> {
+        let mut archive = ZipArchive::new(Cursor::new(bytes)).unwrap();
+        (0..archive.len())
+            .map(|i| {
+                let mut entry = archive.by_index(i).unwrap();
+                let mut content = String::new();
+                entry.read_to_string(&mut content).unwrap();
+                (entry.name().to_string(), content)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn exports_are_sent_one_entry_at_a_time() {
+        let (chunks, mut received) = mpsc::channel(16);
+        let mut writer = ZipWriter::new(ChunkSender::new(chunks));
+        writer.set_flush_on_finish_file(true);
+        for name in ["a.txt", "b.txt", "c.txt"] {
+            writer.start_file(name, SimpleFileOptions::default()).unwrap();
+            writer.write_all(name.repeat(100).as_bytes()).unwrap();
+        }
+        let mut out = writer.finish().unwrap();
+        out.flush().unwrap();
+        assert!(out.seek(SeekFrom::Start(0)).is_err(), "sent bytes cannot be rewritten");
+        drop(out);
+
+        let mut archive = Vec::new();
+        let mut count = 0;
+        while let Ok(chunk) = received.try_recv() {
+            archive.extend_from_slice(&chunk.unwrap());
+            count += 1;
+        }
+        assert!(count >= 3, "each entry is sent once it is finished, got {} chunks", count);
+        let names: Vec<String> = read_zip(&archive).into_iter().map(|(name, _)| name).collect();
+        assert_eq!(names, ["a.txt", "b.txt", "c.txt"]);
+    }
+
+    #[tokio::test]
+    async fn import_then_export_round_trips_paths_and_content() {
+        let app = TestApp::new().await;
+        let routes = Router::new().nest("/project/{project_id}", archive_routes(Arc::clone(&app.storage), Arc::clone(&app.documents)));
+        let router = app.serve(routes);
+
+        let (owner, viewer) = (Uuid::new_v4(), Uuid::new_v4());
+        let project_id = app.project(owner, &[(viewer, Role::Viewer)]).await;
+        let token = app.token(owner);
+        let import = format!("/project/{}/import", project_id);
+
+        let upload = zip_of(&[
+            ("src/", ""),
+            ("src/main.c", "int main() { return 0; }\n"),
+            ("src/util/helpers.py", "def helper():\n    pass\n"),
+            ("README", ""),
+            ("../escape.txt", "nope"),
+        ]);
+        let (status, _) = send_bytes(&router, Some(&token), Method::POST, &import, "application/zip", upload.clone()).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "entries outside the archive root are refused");
+
+        let upload = zip_of(&[
+            ("src/", ""),
+            ("src/main.c", "int main() { return 0; }\n"),
+            ("src/util/helpers.py", "def helper():\n    pass\n"),
+            ("README", ""),
+        ]);
+        let viewer_token = app.token(viewer);
+        let (status, _) = send_bytes(&router, Some(&viewer_token), Method::POST, &import, "application/zip", upload.clone()).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        let (status, body) = send_bytes(&router, Some(&token), Method::POST, &import, "application/zip", upload.clone()).await;
+        assert_eq!(status, StatusCode::CREATED);
+        let report: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(report["created_files"], json!(["src/main.c", "src/util/helpers.py", "README"]));
+        assert_eq!(report["created_folders"], json!(["src", "src/util"]));
+
+        let files = app.storage.list_project_files(&project_id.to_string()).await.unwrap();
+        let helper = files.iter().find(|f| f.name == "helpers.py").unwrap();
+        assert_eq!(helper.file_type, "Python");
+
+        // Importing the same archive again leaves the existing files alone.
+        let (_, body) = send_bytes(&router, Some(&token), Method::POST, &import, "application/zip", upload).await;
+        let report: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(report["created_files"], json!([]));
+        assert_eq!(report["skipped"].as_array().unwrap().len(), 3);
+
+        let (status, body) = send_bytes(&router, Some(&viewer_token), Method::GET, &format!("/project/{}/export", project_id), "application/zip", vec![]).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            read_zip(&body),
+            [
+                ("src/".to_string(), String::new()),
+                ("src/util/".to_string(), String::new()),
+                ("README".to_string(), String::new()),
+                ("src/main.c".to_string(), "int main() { return 0; }\n".to_string()),
+                ("src/util/helpers.py".to_string(), "def helper():\n    pass\n".to_string()),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn oversized_archives_are_rejected() {
+        let app = TestApp::new().await;
+        let routes = Router::new().nest("/project/{project_id}", archive_routes(Arc::clone(&app.storage), Arc::clone(&app.documents)));
+        let router = app.serve(routes);
+
+        let owner = Uuid::new_v4();
+        let project_id = app.project(owner, &[]).await;
+        let token = app.token(owner);
+        let import = format!("/project/{}/import", project_id);
+
+        let names: Vec<String> = (0..=MAX_ARCHIVE_ENTRIES).map(|i| format!("f{}.txt", i)).collect();
+        let entries: Vec<(&str, &str)> = names.iter().map(|name| (name.as_str(), "x")).collect();
+        let (status, _) = send_bytes(&router, Some(&token), Method::POST, &import, "application/zip", zip_of(&entries)).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let (status, _) = send_bytes(&router, Some(&token), Method::POST, &import, "application/zip", vec![0; MAX_ARCHIVE_BYTES + 1]).await;
+        assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+
+        assert!(app.storage.list_project_files(&project_id.to_string()).await.unwrap().is_empty());
+    }
+}
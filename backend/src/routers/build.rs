@@ -0,0 +1,337 @@
+use axum::{
+    extract::Json,
+    http::StatusCode,
+    middleware,
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Extension, Router,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::sync::Arc;
+
+use crate::connection::document::DocumentStore;
+use crate::connection::session::AuthUser;
+use crate::connection::storage::Storage;
+use crate::executor::runner::Executor;
+use crate::executor::testing::Harness;
+use crate::executor::toolchain::Toolchain;
+use crate::model::folder::ProjectTree;
+use crate::model::revision::now_millis;
+use crate::model::test_report::{TestOutcome, TestReport};
+use crate::routers::access::{by_method, read_only, require_access, ProjectAccess};
+use crate::routers::ai_review::CompileResponse;
+use crate::routers::folders::load_tree;
+
+#[derive(Debug, Deserialize)]
+pub struct RunProjectRequest {
+    /// Project path of the file to run, e.g. `src/main.c`.
+    pub entry: String,
+    /// Defaults to the entry file's language.
+    #[serde(default)]
+    pub language: Option<String>,
+    #[serde(default)]
+    pub stdin: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RunTestsRequest {
+    /// One test file to run; the whole suite by default.
+    #[serde(default)]
+    pub entry: Option<String>,
+    /// Defaults to a guess from the project's files.
+    #[serde(default)]
+    pub language: Option<String>,
+}
+
+/// The stored report plus the raw output of the run, which is not kept.
+#[derive(Debug, Serialize)]
+pub struct TestRunResponse {
+    #[serde(flatten)]
+    pub report: TestReport,
+    pub stdout: String,
+    pub stderr: String,
+    pub output_truncated: bool,
+}
+
+/// Builds, runs and tests a whole project, nested under `/project/{project_id}`.
+pub fn build_routes(storage: Arc<dyn Storage>, documents: Arc<DocumentStore>, executor: Arc<Executor>) -> Router {
+    let running = Router::new()
+        .route(
+            "/run",
+            post({
+                let (storage, documents, executor) = (Arc::clone(&storage), Arc::clone(&documents), Arc::clone(&executor));
+                move |access, payload| run_project_handler(storage, documents, executor, access, payload)
+            }),
+        )
+        .route(
+            "/tests",
+            get({
+                let storage = Arc::clone(&storage);
+                move |access| test_report_handler(storage, access)
+            }),
+        )
+        .route_layer(middleware::from_fn({
+            let storage = Arc::clone(&storage);
+            move |user, path, request, next| require_access(Arc::clone(&storage), read_only, user, path, request, next)
+        }));
+    // A test run replaces the project's stored report, so it needs write access.
+    let testing = Router::new()
+        .route(
+            "/tests",
+            post({
+                let storage = Arc::clone(&storage);
+                move |user, access, payload| run_tests_handler(storage, documents, executor, user, access, payload)
+            }),
+        )
+        .route_layer(middleware::from_fn(move |user, path, request, next| {
+            require_access(Arc::clone(&storage), by_method, user, path, request, next)
+        }));
+    running.merge(testing)
+}
+
+fn error(status: StatusCode, message: &str) -> Response {
+    (status, Json(json!({ "error": message }))).into_response()
+}
+
+async fn run_project_handler(
+    storage: Arc<dyn Storage>,
+    documents: Arc<DocumentStore>,
+    executor: Arc<Executor>,
+    Extension(access): Extension<ProjectAccess>,
+    Json(payload): Json<RunProjectRequest>,
+) -> impl IntoResponse {
+    let tree = match load_tree(storage.as_ref(), &access.project).await {
+        Ok(tree) => tree,
+        Err(response) => return response,
+    };
+
+    let entry = payload.entry.trim_matches('/');
+    let Some(entry_file) = tree.files.iter().find(|file| tree.file_path(file) == entry) else {
+        return error(StatusCode::NOT_FOUND, "Entry file not found");
+    };
+    let language = payload.language.as_deref().unwrap_or(&entry_file.file_type);
+    let Some(toolchain) = Toolchain::find(language) else {
+        return error(StatusCode::BAD_REQUEST, &format!("Unsupported language: {}", language));
+    };
+
+    let files = live_files(&documents, &tree).await;
+    match executor.execute_files(toolchain, entry, &files, &payload.stdin).await {
+        Ok(run) => (StatusCode::OK, Json(CompileResponse::from(run))).into_response(),
+        Err(e) => error(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+    }
+}
+
+async fn run_tests_handler(
+    storage: Arc<dyn Storage>,
+    documents: Arc<DocumentStore>,
+    executor: Arc<Executor>,
+    user: AuthUser,
+    Extension(access): Extension<ProjectAccess>,
+    Json(payload): Json<RunTestsRequest>,
+) -> impl IntoResponse {
+    let tree = match load_tree(storage.as_ref(), &access.project).await {
+        Ok(tree) => tree,
+        Err(response) => return response,
+    };
+    let files = live_files(&documents, &tree).await;
+    let paths: Vec<&str> = files.iter().map(|(path, _)| path.as_str()).collect();
+
+    let entry = payload.entry.as_deref().map(|entry| entry.trim_matches('/'));
+    if entry.is_some_and(|entry| !paths.contains(&entry)) {
+        return error(StatusCode::NOT_FOUND, "Entry file not found");
+    }
+    let harness = match payload.language.as_deref() {
+        Some(language) => match Toolchain::find(language) {
+            Some(toolchain) => Harness::find(toolchain),
+            None => return error(StatusCode::BAD_REQUEST, &format!("Unsupported language: {}", language)),
+        },
+        None => Harness::detect(&paths),
+    };
+    let Some((harness, toolchain)) = harness.and_then(|harness| Some((harness, Toolchain::find(harness.language)?))) else {
+        return error(StatusCode::BAD_REQUEST, "No test runner for this project; set `language` to python, rust, c or javascript");
+    };
+    let plan = match (harness.plan)(entry, &paths) {
+        Ok(plan) => plan,
+        Err(message) => return error(StatusCode::BAD_REQUEST, &message),
+    };
+
+    let run = match executor.run_tests(toolchain, harness, &plan, &files).await {
+        Ok(run) => run,
+        Err(e) => return error(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+    };
+    let count = |outcome| run.tests.iter().filter(|test| test.outcome == outcome).count();
+    let report = TestReport {
+        project_id: access.project.id,
+        run_by: user.user_id,
+        finished_at: now_millis(),
+        language: toolchain.language.to_string(),
+        entry: entry.map(str::to_string),
+        stage: run.execution.stage,
+        exit_code: run.execution.exit_code,
+        timed_out: run.execution.timed_out,
+        passed: count(TestOutcome::Passed),
+        failed: count(TestOutcome::Failed),
+        skipped: count(TestOutcome::Skipped),
+        wall_time_ms: run.execution.wall_time_ms,
+        tests: run.tests,
+        diagnostics: run.execution.diagnostics,
+    };
+    if let Err(e) = storage.save_test_report(&report).await {
+        return error(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string());
+    }
+
+    let response = TestRunResponse {
+        report,
+        stdout: run.execution.stdout,
+        stderr: run.execution.stderr,
+        output_truncated: run.execution.output_truncated,
+    };
+    (StatusCode::OK, Json(response)).into_response()
+}
+
+async fn test_report_handler(storage: Arc<dyn Storage>, Extension(access): Extension<ProjectAccess>) -> impl IntoResponse {
+    match storage.get_test_report(&access.project.id.to_string()).await {
+        Ok(Some(report)) => (StatusCode::OK, Json(report)).into_response(),
+        Ok(None) => error(StatusCode::NOT_FOUND, "No tests have been run yet"),
+        Err(e) => error(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+    }
+}
+
+/// Every file as (project path, content). Open files are taken as they
+/// currently read in the editor, saved or not.
+async fn live_files(documents: &DocumentStore, tree: &ProjectTree) -> Vec<(String, String)> {
+    let mut files = Vec::with_capacity(tree.files.len());
+    for file in &tree.files {
+        let content = match documents.peek(&file.id.to_string()).await {
+            Some((_, live)) => live,
+            None => file.content.clone(),
+        };
+        files.push((tree.file_path(file), content));
+    }
+    files
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::Method;
+    use std::time::Duration;
+    use uuid::Uuid;
+
+    use crate::executor::sandbox::{Isolation, Limits, Sandbox};
+    use crate::test_support::sandbox;
+    use crate::model::folder::Folder;
+    use crate::model::project::Role;
+    use crate::test_support::{app::TestApp, http::send_as};
+
+    fn executor() -> Arc<Executor> {
+        let limits = Limits {
+            cpu_seconds: 5,
+            memory_bytes: 256 * 1024 * 1024,
+            wall_clock: Duration::from_secs(10),
+            output_bytes: 64 * 1024,
+            processes: 64,
+            file_bytes: 1024 * 1024,
+        };
+        Arc::new(Executor::new(Sandbox::new(sandbox::helper(), Isolation::Namespaces), limits, 2))
+    }
+
+    /// Creates `path` (and any missing folders on the way) with `content`.
+    async fn add_file(storage: &dyn Storage, project_id: Uuid, owner: Uuid, path: &str, content: &str) {
+        let (folders, name) = path.rsplit_once('/').map(|(dir, name)| (Some(dir), name)).unwrap_or((None, path));
+        let mut parent = None;
+        for segment in folders.into_iter().flat_map(|dir| dir.split('/')) {
+            let existing = storage.list_folders(&project_id.to_string()).await.unwrap();
+            parent = Some(match existing.iter().find(|f| f.parent == parent && f.name == segment) {
+                Some(folder) => folder.id,
+                None => {
+                    let folder = Folder { id: Uuid::new_v4(), project_id, parent, name: segment.into(), created_by: owner };
+                    storage.create_folder(&folder).await.unwrap();
+                    folder.id
+                }
+            });
+        }
+        let file_id = storage.create_file(name.into(), owner, project_id, parent).await.unwrap().unwrap();
+        storage.update_file_content(&file_id.to_string(), content, &[owner.to_string()]).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn projects_build_from_all_their_files() {
+        let app = TestApp::new().await;
+        let routes = Router::new().nest("/project/{project_id}", build_routes(Arc::clone(&app.storage), Arc::clone(&app.documents), executor()));
+        let router = app.serve(routes);
+
+        let (owner, viewer) = (Uuid::new_v4(), Uuid::new_v4());
+        let project_id = app.project(owner, &[(viewer, Role::Viewer)]).await;
+        add_file(app.storage.as_ref(), project_id, owner, "src/main.c", "#include <stdio.h>\n#include \"../lib/util.h\"\nint main(void) { printf(\"%d\\n\", twice(21)); return 0; }\n").await;
+        add_file(app.storage.as_ref(), project_id, owner, "lib/util.h", "int twice(int x);\n").await;
+        add_file(app.storage.as_ref(), project_id, owner, "lib/util.c", "int twice(int x) { return x * 2; }\n").await;
+        add_file(app.storage.as_ref(), project_id, owner, "pkg/__init__.py", "").await;
+        add_file(app.storage.as_ref(), project_id, owner, "pkg/greet.py", "def hello(name):\n    return 'hello ' + name\n").await;
+        add_file(app.storage.as_ref(), project_id, owner, "app/main.py", "import sys\nfrom pkg.greet import hello\nprint(hello(sys.stdin.read()))\n").await;
+
+        let uri = format!("/project/{}/run", project_id);
+        let token = app.token(viewer);
+        let (status, body) = send_as(&router, Some(&token), Method::POST, &uri, Some(json!({ "entry": "src/main.c" }))).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["stdout"], "42\n");
+
+        let run = json!({ "entry": "app/main.py", "stdin": "ada" });
+        let (_, body) = send_as(&router, Some(&token), Method::POST, &uri, Some(run)).await;
+        assert_eq!(body["stdout"], "hello ada\n", "{}", body["stderr"]);
+
+        add_file(app.storage.as_ref(), project_id, owner, "lib/broken.c", "int broken(void) { return 1 }\n").await;
+        let (_, body) = send_as(&router, Some(&token), Method::POST, &uri, Some(json!({ "entry": "src/main.c" }))).await;
+        assert_eq!(body["stage"], "compile");
+        assert_eq!(body["diagnostics"][0]["file"], "lib/broken.c");
+        assert_eq!(body["diagnostics"][0]["line"], 1);
+        assert_eq!(body["diagnostics"][0]["severity"], "error");
+
+        let (status, _) = send_as(&router, Some(&token), Method::POST, &uri, Some(json!({ "entry": "missing.c" }))).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let outsider = app.token(Uuid::new_v4());
+        let (status, _) = send_as(&router, Some(&outsider), Method::POST, &uri, Some(json!({ "entry": "src/main.c" }))).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn test_runs_are_reported_and_kept() {
+        let app = TestApp::new().await;
+        let routes = Router::new().nest("/project/{project_id}", build_routes(Arc::clone(&app.storage), Arc::clone(&app.documents), executor()));
+        let router = app.serve(routes);
+
+        let (owner, viewer) = (Uuid::new_v4(), Uuid::new_v4());
+        let project_id = app.project(owner, &[(viewer, Role::Viewer)]).await;
+        add_file(app.storage.as_ref(), project_id, owner, "main.c", "int main(void) { return 0; }\n").await;
+        add_file(app.storage.as_ref(), project_id, owner, "lib/maths.c", "int half(int x) { return x / 2; }\n").await;
+        let harness = "#include <stdio.h>\nint half(int x);\nint main(void) {\n    puts(\"1..3\");\n    printf(\"%s 1 - halves evens\\n\", half(4) == 2 ? \"ok\" : \"not ok\");\n    printf(\"%s 2 - rounds odds up\\n\", half(5) == 3 ? \"ok\" : \"not ok\");\n    printf(\"# half(5) == %d\\n\", half(5));\n    puts(\"ok 3 - negatives # SKIP not decided\");\n    return 1;\n}\n";
+        add_file(app.storage.as_ref(), project_id, owner, "tests/test_maths.c", harness).await;
+
+        let uri = format!("/project/{}/tests", project_id);
+        let (owner_token, viewer_token) = (app.token(owner), app.token(viewer));
+        let (status, _) = send_as(&router, Some(&viewer_token), Method::GET, &uri, None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        let (status, _) = send_as(&router, Some(&viewer_token), Method::POST, &uri, Some(json!({}))).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        let (status, body) = send_as(&router, Some(&owner_token), Method::POST, &uri, Some(json!({}))).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        assert_eq!(body["language"], "c");
+        assert_eq!((body["passed"].as_u64(), body["failed"].as_u64(), body["skipped"].as_u64()), (Some(1), Some(1), Some(1)));
+        assert_eq!(body["tests"][1]["name"], "rounds odds up");
+        assert_eq!(body["tests"][1]["outcome"], "failed");
+        assert_eq!(body["tests"][1]["output"], "half(5) == 2");
+        assert!(body["stdout"].as_str().unwrap().starts_with("1..3"));
+
+        let (status, report) = send_as(&router, Some(&viewer_token), Method::GET, &uri, None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(report["run_by"], owner.to_string());
+        assert_eq!(report["tests"], body["tests"]);
+        assert!(report.get("stdout").is_none());
+
+        let (status, body) = send_as(&router, Some(&owner_token), Method::POST, &uri, Some(json!({ "language": "java" }))).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{}", body);
+    }
+}
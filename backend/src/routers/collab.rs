@@ -0,0 +1,336 @@
+use axum::{
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        Path,
+    },
+    middleware,
+    response::IntoResponse,
+    routing::get,
+    Extension, Router,
+};
+use futures_util::{SinkExt, StreamExt};
+use std::sync::Arc;
+use tokio::sync::broadcast::{self, error::RecvError};
+use uuid::Uuid;
+
+use crate::connection::document::DocumentStore;
+use crate::connection::session::AuthUser;
+use crate::connection::storage::Storage;
+use crate::model::project::Permission;
+use crate::routers::access::{by_method, require_access, ProjectAccess};
+use crate::connection::real_time::{
+    Ack, ActiveUser, ClientEvent, CollabError, CollabHub, DocumentSnapshot, RoomMessage, ServerEvent,
+};
+
+pub fn collab_routes(storage: Arc<dyn Storage>, hub: Arc<CollabHub>, documents: Arc<DocumentStore>) -> Router {
+    Router::new()
+        .route(
+            "/files/{file_id}",
+            get({
+                let hub = Arc::clone(&hub);
+                let documents = Arc::clone(&documents);
+                move |user, access, path, ws| ws_handler(hub, documents, user, access, path, ws)
+            }),
+        )
+        .route_layer(middleware::from_fn(move |user, path, request, next| {
+            require_access(Arc::clone(&storage), by_method, user, path, request, next)
+        }))
+}
+
+async fn ws_handler(
+    hub: Arc<CollabHub>,
+    documents: Arc<DocumentStore>,
+    user: AuthUser,
+    Extension(access): Extension<ProjectAccess>,
+    Path(file_id): Path<String>,
+    ws: WebSocketUpgrade,
+) -> impl IntoResponse {
+    let can_edit = access.role.allows(Permission::Write);
+    ws.on_upgrade(move |socket| handle_socket(hub, documents, user, can_edit, file_id, socket))
+}
+
+// Viewers join rooms like everyone else but their edits are refused.
+async fn handle_socket(
+    hub: Arc<CollabHub>,
+    documents: Arc<DocumentStore>,
+    user: AuthUser,
+    can_edit: bool,
+    file_id: String,
+    socket: WebSocket,
+) {
+    let conn_id = Uuid::new_v4();
+    let (mut sender, mut receiver) = socket.split();
+    let mut room_rx: Option<broadcast::Receiver<RoomMessage>> = None;
+    let author = user.user_id.to_string();
+
+    loop {
+        tokio::select! {
+            msg = receiver.next() => {
+                let text = match msg {
+                    Some(Ok(Message::Text(text))) => text,
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                    Some(Ok(_)) => continue,
+                };
+
+                let event = match serde_json::from_str::<ClientEvent>(&text) {
+                    Ok(event) => event,
+                    Err(e) => {
+                        eprintln!("❌ Refusing malformed collab message: {}", e);
+                        let error = CollabError { file_id: file_id.clone(), message: e.to_string() };
+                        if send_event(&mut sender, &ServerEvent::Error(error)).await.is_err() {
+                            break;
+                        }
+                        continue;
+                    }
+                };
+
+                match event {
+                    ClientEvent::Join { project_id, file_id: joined, user_name } => {
+                        if joined != file_id || room_rx.is_some() {
+                            continue;
+                        }
+
+                        println!("👋 {} joined file {} in project {}", user_name, file_id, project_id);
+                        let active = ActiveUser { user_id: author.clone(), user_name };
+                        let (rx, users) = hub.join(&file_id, conn_id, active).await;
+                        room_rx = Some(rx);
+
+                        if send_event(&mut sender, &ServerEvent::ActiveUsers(users)).await.is_err() {
+                            break;
+                        }
+                        if send_snapshot(&mut sender, &documents, &file_id).await.is_err() {
+                            break;
+                        }
+                    }
+                    ClientEvent::Leave { file_id: left, .. } => {
+                        if left == file_id && room_rx.take().is_some() {
+                            leave_room(&hub, &documents, &file_id, conn_id).await;
+                        }
+                    }
+                    ClientEvent::Update(update) => {
+                        if update.file_id != file_id || room_rx.is_none() {
+                            continue;
+                        }
+                        if !can_edit {
+                            if send_snapshot(&mut sender, &documents, &file_id).await.is_err() {
+                                break;
+                            }
+                            continue;
+                        }
+
+                        match documents.replace(&file_id, &update.content, &author).await {
+                            Ok(edit) => hub.broadcast_edit(&file_id, conn_id, edit).await,
+                            Err(e) => eprintln!("❌ Rejected update to file {}: {}", file_id, e),
+                        }
+                    }
+                    ClientEvent::Operation(payload) => {
+                        if payload.file_id != file_id || room_rx.is_none() {
+                            continue;
+                        }
+                        if !can_edit {
+                            if send_snapshot(&mut sender, &documents, &file_id).await.is_err() {
+                                break;
+                            }
+                            continue;
+                        }
+
+                        match documents.apply(&file_id, payload.revision, payload.operation, &author).await {
+                            Ok(edit) => {
+                                let ack = ServerEvent::Ack(Ack { file_id: file_id.clone(), revision: edit.revision });
+                                hub.broadcast_edit(&file_id, conn_id, edit).await;
+                                if send_event(&mut sender, &ack).await.is_err() {
+                                    break;
+                                }
+                            }
+                            Err(e) => {
+                                // The client is out of step with the server; hand it a fresh base.
+                                eprintln!("❌ Rejected operation on file {}: {}", file_id, e);
+                                if send_snapshot(&mut sender, &documents, &file_id).await.is_err() {
+                                    break;
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+            room_msg = next_room_message(&mut room_rx) => {
+                match room_msg {
+                    Ok((from, _)) if from == conn_id => {}
+                    Ok((_, event)) => {
+                        if send_event(&mut sender, &event).await.is_err() {
+                            break;
+                        }
+                    }
+                    Err(RecvError::Lagged(skipped)) => {
+                        // The edits it missed are gone; start over from the tail and the current text.
+                        eprintln!("⚠️ Collab connection {} skipped {} events; resending the document", conn_id, skipped);
+                        room_rx = room_rx.map(|rx| rx.resubscribe());
+                        if send_snapshot(&mut sender, &documents, &file_id).await.is_err() {
+                            break;
+                        }
+                    }
+                    Err(RecvError::Closed) => room_rx = None,
+                }
+            }
+        }
+    }
+
+    if room_rx.is_some() {
+        leave_room(&hub, &documents, &file_id, conn_id).await;
+    }
+}
+
+async fn leave_room(hub: &CollabHub, documents: &DocumentStore, file_id: &str, conn_id: Uuid) {
+    if hub.leave(file_id, conn_id).await {
+        documents.close(file_id).await;
+    }
+}
+
+async fn send_snapshot<S>(sender: &mut S, documents: &DocumentStore, file_id: &str) -> Result<(), axum::Error>
+where
+    S: SinkExt<Message, Error = axum::Error> + Unpin,
+{
+    match documents.snapshot(file_id).await {
+        Ok((revision, content)) => {
+            let snapshot = DocumentSnapshot { file_id: file_id.to_string(), revision, content };
+            send_event(sender, &ServerEvent::Document(snapshot)).await
+        }
+        Err(e) => {
+            eprintln!("❌ Failed to load file {}: {}", file_id, e);
+            Ok(())
+        }
+    }
+}
+
+// Waits on the room channel, or forever when the connection has not joined yet.
+async fn next_room_message(rx: &mut Option<broadcast::Receiver<RoomMessage>>) -> Result<RoomMessage, RecvError> {
+    match rx {
+        Some(rx) => rx.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+async fn send_event<S>(sender: &mut S, event: &ServerEvent) -> Result<(), axum::Error>
+where
+    S: SinkExt<Message, Error = axum::Error> + Unpin,
+{
+    let text = serde_json::to_string(event).expect("ServerEvent is always serializable");
+    sender.send(Message::Text(text.into())).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+    use std::time::Duration;
+    use tokio_tungstenite::tungstenite::Message as WsMessage;
+
+    use crate::connection::real_time::FileUpdate;
+    use crate::model::project::Role;
+    use crate::test_support::app::TestApp;
+
+    type Client = tokio_tungstenite::WebSocketStream<tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>>;
+
+    async fn send(client: &mut Client, event: &str, data: Value) {
+        let frame = json!({ "event": event, "data": data });
+        client.send(WsMessage::Text(frame.to_string().into())).await.unwrap();
+    }
+
+    /// Skips frames until one of kind `event` and returns its data.
+    async fn receive(client: &mut Client, event: &str) -> Value {
+        loop {
+            let frame = tokio::time::timeout(Duration::from_secs(5), client.next()).await.expect("frame in time");
+            if let Some(Ok(WsMessage::Text(text))) = frame {
+                let frame: Value = serde_json::from_str(&text).unwrap();
+                if frame["event"] == event {
+                    return frame["data"].clone();
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn editors_exchange_edits_and_viewers_are_refused() {
+        let app = TestApp::new().await;
+        let routes = Router::new().nest("/ws", collab_routes(Arc::clone(&app.storage), Arc::new(CollabHub::new()), Arc::clone(&app.documents)));
+        let router = app.serve(routes);
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, router).await.unwrap() });
+
+        let (owner, editor, viewer) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+        let members = [(editor, Role::Editor), (viewer, Role::Viewer)];
+        let project_id = app.project(owner, &members).await;
+        let file_id = app.storage.create_file("main.py".into(), owner, project_id, None).await.unwrap().unwrap().to_string();
+        let connect = |user| {
+            let url = format!("ws://{}/ws/files/{}?access_token={}", addr, file_id, app.token(user));
+            async move { tokio_tungstenite::connect_async(url).await.expect("connect").0 }
+        };
+        let join = json!({ "projectId": project_id, "fileId": file_id, "userName": "someone" });
+
+        let mut ada = connect(owner).await;
+        send(&mut ada, "join-file", join.clone()).await;
+        assert_eq!(receive(&mut ada, "document").await["revision"], 0);
+        let mut grace = connect(editor).await;
+        send(&mut grace, "join-file", join.clone()).await;
+        assert_eq!(receive(&mut grace, "document").await["revision"], 0);
+        assert_eq!(receive(&mut ada, "user-joined").await["userId"], editor.to_string());
+
+        let edit = json!({ "fileId": file_id, "revision": 0, "operation": [{ "insert": "print(1)\n" }] });
+        send(&mut ada, "operation", edit).await;
+        assert_eq!(receive(&mut ada, "ack").await["revision"], 1);
+        assert_eq!(receive(&mut grace, "operation").await["operation"], json!([{ "insert": "print(1)\n" }]));
+
+        // Made before Grace saw Ada's edit, so it is rebased over it.
+        let stale = json!({ "fileId": file_id, "revision": 0, "operation": [{ "insert": "# hi\n" }] });
+        send(&mut grace, "operation", stale).await;
+        assert_eq!(receive(&mut grace, "ack").await["revision"], 2);
+        let rebased = receive(&mut ada, "operation").await;
+        assert_eq!((&rebased["revision"], &rebased["operation"]), (&json!(2), &json!([{ "insert": "# hi\n" }, { "retain": 9 }])));
+        assert_eq!(receive(&mut ada, "file-updated").await["content"], "# hi\nprint(1)\n");
+
+        let overflowing = json!({ "fileId": file_id, "revision": 2, "operation": [{ "retain": usize::MAX }, { "retain": 1 }] });
+        send(&mut ada, "operation", overflowing).await;
+        assert!(receive(&mut ada, "error").await["message"].as_str().unwrap().contains("operation is too long"));
+
+        let mut viewer_socket = connect(viewer).await;
+        send(&mut viewer_socket, "join-file", join).await;
+        receive(&mut viewer_socket, "document").await;
+        let refused = json!({ "fileId": file_id, "revision": 2, "operation": [{ "delete": 14 }] });
+        send(&mut viewer_socket, "operation", refused).await;
+        let snapshot = receive(&mut viewer_socket, "document").await;
+        assert_eq!((&snapshot["revision"], &snapshot["content"]), (&json!(2), &json!("# hi\nprint(1)\n")));
+        assert_eq!(app.documents.peek(&file_id).await, Some((2, "# hi\nprint(1)\n".to_string())));
+    }
+
+    #[tokio::test]
+    async fn a_client_that_falls_behind_gets_the_document_again() {
+        let app = TestApp::new().await;
+        let hub = Arc::new(CollabHub::new());
+        let routes = Router::new().nest("/ws", collab_routes(Arc::clone(&app.storage), Arc::clone(&hub), Arc::clone(&app.documents)));
+        let router = app.serve(routes);
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, router).await.unwrap() });
+
+        let owner = Uuid::new_v4();
+        let project_id = app.project(owner, &[]).await;
+        let file_id = app.storage.create_file("main.py".into(), owner, project_id, None).await.unwrap().unwrap().to_string();
+        let url = format!("ws://{}/ws/files/{}?access_token={}", addr, file_id, app.token(owner));
+        let mut client = tokio_tungstenite::connect_async(url).await.expect("connect").0;
+        send(&mut client, "join-file", json!({ "projectId": project_id, "fileId": file_id, "userName": "ada" })).await;
+        assert_eq!(receive(&mut client, "document").await["revision"], 0);
+
+        // Without yielding, so the connection cannot drain the room before it overflows.
+        tokio::task::unconstrained(async {
+            for n in 0..300 {
+                let update = FileUpdate { file_id: file_id.clone(), content: n.to_string() };
+                hub.broadcast(&file_id, Uuid::new_v4(), ServerEvent::FileUpdated(update)).await;
+            }
+        })
+        .await;
+
+        let snapshot = receive(&mut client, "document").await;
+        assert_eq!((&snapshot["revision"], &snapshot["content"]), (&json!(0), &json!("")));
+    }
+}
@@ -1,21 +1,41 @@
 use axum::{
     extract::{Path, Json},
     http::StatusCode,
+    middleware,
     response::IntoResponse,
-    routing::{delete, get, post, put},
-    Router,
+    routing::{get, put},
+    Extension, Router,
 };
 use serde::{Deserialize, Serialize};
+use serde_json::json;
 use std::sync::Arc;
 use uuid::Uuid;
-use crate::connection::firebase::FirebaseService;
+
+use crate::connection::document::DocumentStore;
+use crate::connection::real_time::CollabHub;
+use crate::connection::session::AuthUser;
+use crate::connection::storage::{Storage, StorageResult};
 use crate::model::files::File;
+use crate::routers::access::{by_method, require_access, ProjectAccess};
+use crate::routers::folders::{check_name, check_parent, load_tree, name_taken};
 
 #[derive(Debug, Deserialize)]
 pub struct CreateFileRequest {
     pub name: String,
-    pub owner: Uuid,
-    pub project_id: Uuid,
+    /// Folder to create the file in; the project root when absent.
+    #[serde(default)]
+    pub folder: Option<Uuid>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RenameFileRequest {
+    pub name: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MoveFileRequest {
+    /// Destination folder, or `null` for the project root.
+    pub folder: Option<Uuid>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -29,121 +49,387 @@ pub struct FileResponse {
     pub file_id: Option<Uuid>,
 }
 
-pub fn file_routes(firebase: Arc<FirebaseService>) -> Router {
+/// A file without its content, for listings.
+#[derive(Debug, Serialize)]
+pub struct FileSummary {
+    pub id: Uuid,
+    pub name: String,
+    pub path: String,
+    pub folder: Option<Uuid>,
+    pub file_type: String,
+    pub revision: u64,
+}
+
+/// File CRUD for one project, nested under `/project/{project_id}/files`.
+pub fn file_routes(storage: Arc<dyn Storage>, hub: Arc<CollabHub>, documents: Arc<DocumentStore>) -> Router {
     Router::new()
-        .route("/files/create", post(move |payload| create_file_handler(firebase.clone(), payload)))
-        .route("/files/id/:id", get(move |Path(id)| get_file_by_id_handler(firebase.clone(), id)))
-        .route("/files/name/:name", get(move |Path(name)| get_file_by_name_handler(firebase.clone(), name)))
-        .route("/files/id/:id", delete(move |Path(id)| delete_file_handler(firebase.clone(), id)))
-        .route("/files/id/:id", put(move |Path(id), payload| update_file_content_handler(firebase.clone(), id, payload)))
+        .route(
+            "/",
+            get({
+                let storage = Arc::clone(&storage);
+                move |access| list_files_handler(storage, access)
+            })
+            .post({
+                let storage = Arc::clone(&storage);
+                move |user, access, payload| create_file_handler(storage, user, access, payload)
+            }),
+        )
+        .route(
+            "/{file_id}",
+            get({
+                let storage = Arc::clone(&storage);
+                let documents = Arc::clone(&documents);
+                move |path| get_file_handler(storage, documents, path)
+            })
+            .delete({
+                let storage = Arc::clone(&storage);
+                let documents = Arc::clone(&documents);
+                move |path| delete_file_handler(storage, documents, path)
+            }),
+        )
+        .route(
+            "/{file_id}/name",
+            put({
+                let storage = Arc::clone(&storage);
+                move |access, path, payload| rename_file_handler(storage, access, path, payload)
+            }),
+        )
+        .route(
+            "/{file_id}/folder",
+            put({
+                let storage = Arc::clone(&storage);
+                move |access, path, payload| move_file_handler(storage, access, path, payload)
+            }),
+        )
+        .route(
+            "/{file_id}/content",
+            put({
+                let storage = Arc::clone(&storage);
+                move |user, path, payload| update_file_content_handler(storage, hub, documents, user, path, payload)
+            }),
+        )
+        .route_layer(middleware::from_fn(move |user, path, request, next| {
+            require_access(Arc::clone(&storage), by_method, user, path, request, next)
+        }))
+}
+
+// Loads a file, treating one that belongs to another project as missing.
+async fn project_file(storage: &dyn Storage, project_id: Uuid, file_id: Uuid) -> Result<File, axum::response::Response> {
+    match storage.get_file_by_id(&file_id.to_string()).await {
+        Ok(Some(file)) if file.project_id == project_id => Ok(file),
+        Ok(_) => Err((StatusCode::NOT_FOUND, Json(json!({"error": "File not found"}))).into_response()),
+        Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, Json(json!({"error": e.to_string()}))).into_response()),
+    }
 }
 
 async fn create_file_handler(
-    firebase: Arc<FirebaseService>,
+    storage: Arc<dyn Storage>,
+    user: AuthUser,
+    Extension(access): Extension<ProjectAccess>,
     Json(payload): Json<CreateFileRequest>,
 ) -> impl IntoResponse {
-    // Check if the file already exists by name
-    if let Ok(Some(_)) = firebase.get_file_by_name(&payload.name).await {
-        return (
-            StatusCode::BAD_REQUEST,
-            Json(FileResponse {
-                message: "File with this name already exists".into(),
-                file_id: None,
-            }),
-        ).into_response();
+    let name = payload.name.trim();
+    let tree = match load_tree(storage.as_ref(), &access.project).await {
+        Ok(tree) => tree,
+        Err(response) => return response,
+    };
+    // Names only have to be unique within their folder.
+    if let Some(response) = check_parent(&tree, payload.folder).or_else(|| check_name(&tree, payload.folder, name, None)) {
+        return response;
     }
 
-    // If file does not exist, create the file
-    match firebase.create_file(payload.name, payload.owner, payload.project_id).await {
-        Ok(file_id) => (
+    // Creating the file also links it into the project and reserves its name, in one write
+    match storage.create_file(name.to_string(), user.user_id, access.project.id, payload.folder).await {
+        Ok(Some(file_id)) => (
             StatusCode::CREATED,
             Json(FileResponse {
                 message: "File created successfully".into(),
                 file_id: Some(file_id),
             }),
-        ),
+        ).into_response(),
+        Ok(None) => name_taken(),
         Err(e) => (
             StatusCode::INTERNAL_SERVER_ERROR,
             Json(FileResponse {
                 message: e.to_string(),
                 file_id: None,
             }),
-        ),
+        ).into_response(),
+    }
+}
+
+async fn list_files_handler(storage: Arc<dyn Storage>, Extension(access): Extension<ProjectAccess>) -> impl IntoResponse {
+    let tree = match load_tree(storage.as_ref(), &access.project).await {
+        Ok(tree) => tree,
+        Err(response) => return response,
+    };
+    let mut files: Vec<FileSummary> = tree
+        .files
+        .iter()
+        .map(|file| FileSummary {
+            id: file.id,
+            name: file.name.clone(),
+            path: tree.file_path(file),
+            folder: file.folder,
+            file_type: file.file_type.clone(),
+            revision: file.revision,
+        })
+        .collect();
+    files.sort_by(|a, b| a.path.cmp(&b.path));
+
+    (StatusCode::OK, Json(json!({ "project_id": access.project.id, "files": files }))).into_response()
+}
+
+async fn get_file_handler(
+    storage: Arc<dyn Storage>,
+    documents: Arc<DocumentStore>,
+    Path((project_id, file_id)): Path<(Uuid, Uuid)>,
+) -> impl IntoResponse {
+    let mut file = match project_file(storage.as_ref(), project_id, file_id).await {
+        Ok(file) => file,
+        Err(response) => return response,
+    };
+
+    // Serve what collaborators currently see rather than the last save.
+    if let Some((_, content)) = documents.peek(&file_id.to_string()).await {
+        file.content = content;
     }
+
+    (StatusCode::OK, Json(file)).into_response()
 }
 
+async fn rename_file_handler(
+    storage: Arc<dyn Storage>,
+    Extension(access): Extension<ProjectAccess>,
+    Path((project_id, file_id)): Path<(Uuid, Uuid)>,
+    Json(payload): Json<RenameFileRequest>,
+) -> impl IntoResponse {
+    let name = payload.name.trim();
+    let file = match project_file(storage.as_ref(), project_id, file_id).await {
+        Ok(file) => file,
+        Err(response) => return response,
+    };
+    let tree = match load_tree(storage.as_ref(), &access.project).await {
+        Ok(tree) => tree,
+        Err(response) => return response,
+    };
+    if let Some(response) = check_name(&tree, file.folder, name, Some(file_id)) {
+        return response;
+    }
 
-async fn get_file_by_id_handler(firebase: Arc<FirebaseService>, id: String) -> impl IntoResponse {
-    match firebase.get_file_by_id(&id).await {
-        Ok(Some(file)) => (StatusCode::OK, Json(file)).into_response(),
-        Ok(None) => (StatusCode::NOT_FOUND, Json(json!({"error": "File not found"}))).into_response(),
+    match storage.rename_file(&file_id.to_string(), name).await {
+        Ok(true) => (StatusCode::OK, Json(json!({"message": "File renamed"}))).into_response(),
+        Ok(false) => name_taken(),
         Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({"error": e.to_string()}))).into_response(),
     }
 }
 
-async fn get_file_by_name_handler(firebase: Arc<FirebaseService>, name: String) -> impl IntoResponse {
-    match firebase.get_file_by_name(&name).await {
-        Ok(Some(file)) => (StatusCode::OK, Json(file)).into_response(),
-        Ok(None) => (StatusCode::NOT_FOUND, Json(json!({"error": "File not found"}))).into_response(),
+async fn move_file_handler(
+    storage: Arc<dyn Storage>,
+    Extension(access): Extension<ProjectAccess>,
+    Path((project_id, file_id)): Path<(Uuid, Uuid)>,
+    Json(payload): Json<MoveFileRequest>,
+) -> impl IntoResponse {
+    let file = match project_file(storage.as_ref(), project_id, file_id).await {
+        Ok(file) => file,
+        Err(response) => return response,
+    };
+    let tree = match load_tree(storage.as_ref(), &access.project).await {
+        Ok(tree) => tree,
+        Err(response) => return response,
+    };
+    if let Some(response) = check_parent(&tree, payload.folder).or_else(|| check_name(&tree, payload.folder, &file.name, Some(file_id))) {
+        return response;
+    }
+
+    match storage.move_file(&file_id.to_string(), payload.folder).await {
+        Ok(true) => (StatusCode::OK, Json(json!({"message": "File moved"}))).into_response(),
+        Ok(false) => name_taken(),
         Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({"error": e.to_string()}))).into_response(),
     }
 }
 
 async fn update_file_content_handler(
-    firebase: Arc<FirebaseService>,
-    id: String,
+    storage: Arc<dyn Storage>,
+    hub: Arc<CollabHub>,
+    documents: Arc<DocumentStore>,
+    user: AuthUser,
+    Path((project_id, file_id)): Path<(Uuid, Uuid)>,
     Json(payload): Json<UpdateContentRequest>,
 ) -> impl IntoResponse {
-    match firebase.update_file_content(&id, &payload.new_content).await {
-        Ok(_) => (StatusCode::OK, Json(json!({"message": "File updated"}))).into_response(),
+    if let Err(response) = project_file(storage.as_ref(), project_id, file_id).await {
+        return response;
+    }
+    let file_id = file_id.to_string();
+    let author = user.user_id.to_string();
+
+    // Go through the live document when the file is open so editors see the change.
+    // `revision` is then the live document's, which is saved a few seconds later.
+    match documents.replace_if_open(&file_id, &payload.new_content, &author).await {
+        Ok(Some(edit)) => {
+            let revision = edit.revision;
+            hub.broadcast_edit(&file_id, Uuid::nil(), edit).await;
+            (StatusCode::OK, Json(json!({"message": "File updated", "revision": revision, "live": true}))).into_response()
+        }
+        Ok(None) => match storage.update_file_content(&file_id, &payload.new_content, &[author]).await {
+            Ok(revision) => (StatusCode::OK, Json(json!({"message": "File updated", "revision": revision, "live": false}))).into_response(),
+            Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({"error": e.to_string()}))).into_response(),
+        },
         Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({"error": e.to_string()}))).into_response(),
     }
 }
 
+/// Drops the file's live document without saving it, then deletes the file
+/// and unlinks it from its project.
+pub async fn remove_file(storage: &dyn Storage, documents: &DocumentStore, file_id: Uuid) -> StorageResult<bool> {
+    documents.discard(&file_id.to_string()).await;
+    storage.delete_file(&file_id.to_string()).await
+}
+
 async fn delete_file_handler(
-    firebase: Arc<FirebaseService>,
-     id: String
+    storage: Arc<dyn Storage>,
+    documents: Arc<DocumentStore>,
+    Path((project_id, file_id)): Path<(Uuid, Uuid)>,
 ) -> impl IntoResponse {
-    match firebase.get_file_by_id(&id).await {
-        Ok(None) => {
-            return (
-                StatusCode::NOT_FOUND,
-                Json(json!({"error": "File not found"})),
-            )
-            .into_response();
-        }
-        Ok(Some(_)) => {
-            // Proceed to delete the file if it exists
-            match firebase.delete_file(&id).await {
-                Ok(true) => {
-                    return (
-                        StatusCode::OK,
-                        Json(json!({"message": "File deleted"})),
-                    )
-                    .into_response();
-                }
-                Ok(false) => {
-                    return (
-                        StatusCode::INTERNAL_SERVER_ERROR,
-                        Json(json!({"error": "Error while deleting file"})),
-                    )
-                    .into_response();
-                }
-                Err(e) => {
-                    return (
-                        StatusCode::INTERNAL_SERVER_ERROR,
-                        Json(json!({"error": e.to_string()})),
-                    )
-                    .into_response();
-                }
-            }
-        }
-        Err(e) => {
-            return (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(json!({"error": e.to_string()})),
-            )
-            .into_response();
+    if let Err(response) = project_file(storage.as_ref(), project_id, file_id).await {
+        return response;
+    }
+
+    match remove_file(storage.as_ref(), &documents, file_id).await {
+        Ok(_) => (StatusCode::OK, Json(json!({"message": "File deleted"}))).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({"error": e.to_string()}))).into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::Method;
+
+    use crate::model::project::Role;
+    use crate::test_support::{app::TestApp, http::send_as};
+
+    #[tokio::test]
+    async fn file_lifecycle() {
+        let app = TestApp::new().await;
+        let files = Router::new().nest(
+            "/project/{project_id}/files",
+            file_routes(Arc::clone(&app.storage), Arc::new(CollabHub::new()), Arc::clone(&app.documents)),
+        );
+        let router = app.serve(files);
+
+        let (owner, viewer) = (Uuid::new_v4(), Uuid::new_v4());
+        let project_id = app.project(owner, &[(viewer, Role::Viewer)]).await;
+        let token = app.token(owner);
+        let token = Some(token.as_str());
+        let base = format!("/project/{}/files", project_id);
+
+        let (status, body) = send_as(&router, token, Method::POST, &base, Some(json!({ "name": "main.py" }))).await;
+        assert_eq!(status, StatusCode::CREATED);
+        let file_id = body["file_id"].as_str().unwrap().to_string();
+        let file_uri = format!("{}/{}", base, file_id);
+
+        let update = json!({ "new_content": "print('hi')\n" });
+        let (status, body) = send_as(&router, token, Method::PUT, &format!("{}/content", file_uri), Some(update)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!((&body["revision"], &body["live"]), (&json!(1), &json!(false)));
+
+        // An open file answers with its live document's revision instead, and is saved on close.
+        app.documents.snapshot(&file_id).await.unwrap();
+        let update = json!({ "new_content": "print('hi')\n" });
+        let (_, body) = send_as(&router, token, Method::PUT, &format!("{}/content", file_uri), Some(update)).await;
+        let (revision, _) = app.documents.peek(&file_id).await.unwrap();
+        assert_eq!((&body["revision"], &body["live"]), (&json!(revision), &json!(true)));
+        app.documents.close(&file_id).await;
+
+        let rename = json!({ "name": "main.rs" });
+        let (status, _) = send_as(&router, token, Method::PUT, &format!("{}/name", file_uri), Some(rename)).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let (_, body) = send_as(&router, token, Method::GET, &file_uri, None).await;
+        assert_eq!(body["name"], "main.rs");
+        assert_eq!(body["file_type"], "Rust");
+        assert_eq!(body["content"], "print('hi')\n");
+
+        let (_, body) = send_as(&router, token, Method::GET, &base, None).await;
+        let summary = json!({ "id": file_id, "name": "main.rs", "path": "main.rs", "folder": null, "file_type": "Rust", "revision": 2 });
+        assert_eq!(body["files"], json!([summary]));
+
+        let viewer_token = app.token(viewer);
+        let (status, _) = send_as(&router, Some(&viewer_token), Method::DELETE, &file_uri, None).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        let (status, _) = send_as(&router, token, Method::DELETE, &file_uri, None).await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, _) = send_as(&router, token, Method::GET, &file_uri, None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let project = app.storage.get_project(&project_id.to_string()).await.unwrap().unwrap();
+        assert!(project.files.is_empty());
+    }
+
+    #[tokio::test]
+    async fn files_are_only_reachable_through_their_own_project() {
+        let app = TestApp::new().await;
+        let files = Router::new().nest(
+            "/project/{project_id}/files",
+            file_routes(Arc::clone(&app.storage), Arc::new(CollabHub::new()), Arc::clone(&app.documents)),
+        );
+        let router = app.serve(files);
+
+        let user = Uuid::new_v4();
+        let mine = app.project(user, &[]).await;
+        let theirs = app.project(Uuid::new_v4(), &[]).await;
+        let secret = app.storage.create_file("secret.py".into(), user, theirs, None).await.unwrap().unwrap();
+
+        let token = app.token(user);
+        let uri = format!("/project/{}/files/{}", mine, secret);
+        let (status, _) = send_as(&router, Some(&token), Method::GET, &uri, None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn names_are_scoped_to_the_project_and_creation_links_atomically() {
+        let app = TestApp::new().await;
+        let files = Router::new().nest(
+            "/project/{project_id}/files",
+            file_routes(Arc::clone(&app.storage), Arc::new(CollabHub::new()), Arc::clone(&app.documents)),
+        );
+        let router = app.serve(files);
+
+        let user = Uuid::new_v4();
+        let token = app.token(user);
+        let first = app.project(user, &[]).await;
+        let second = app.project(user, &[]).await;
+
+        // Another project using the same name does not block this one.
+        for project_id in [first, second] {
+            let uri = format!("/project/{}/files", project_id);
+            let (status, _) = send_as(&router, Some(&token), Method::POST, &uri, Some(json!({ "name": "main.c" }))).await;
+            assert_eq!(status, StatusCode::CREATED);
         }
+        let uri = format!("/project/{}/files", first);
+        let (status, _) = send_as(&router, Some(&token), Method::POST, &uri, Some(json!({ "name": "main.c" }))).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        // Names are stored as they were checked, without surrounding whitespace.
+        let (status, _) = send_as(&router, Some(&token), Method::POST, &uri, Some(json!({ "name": " main.c " }))).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        let (status, body) = send_as(&router, Some(&token), Method::POST, &uri, Some(json!({ "name": " util.c\n" }))).await;
+        assert_eq!(status, StatusCode::CREATED);
+        let file_id = body["file_id"].as_str().unwrap();
+        assert_eq!(app.storage.get_file_by_id(file_id).await.unwrap().unwrap().name, "util.c");
+
+        // Creates racing for one name pass the check together; storage lets only one through.
+        let racing = (0..3).map(|_| send_as(&router, Some(&token), Method::POST, &uri, Some(json!({ "name": "race.c" }))));
+        let mut statuses: Vec<StatusCode> = futures_util::future::join_all(racing).await.into_iter().map(|(status, _)| status).collect();
+        statuses.sort();
+        assert_eq!(statuses, [StatusCode::CREATED, StatusCode::CONFLICT, StatusCode::CONFLICT]);
+
+        let project = app.storage.get_project(&first.to_string()).await.unwrap().unwrap();
+        assert_eq!(project.files.len(), 3);
+
+        // Without a project to link into, no file is written at all.
+        let missing = Uuid::new_v4();
+        assert!(app.storage.create_file("orphan.c".into(), user, missing, None).await.is_err());
+        assert!(app.storage.list_project_files(&missing.to_string()).await.unwrap().is_empty());
     }
 }
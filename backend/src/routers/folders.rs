@@ -0,0 +1,345 @@
+use axum::{
+    extract::{Path, Json},
+    http::StatusCode,
+    middleware,
+    response::{IntoResponse, Response},
+    routing::{delete, get, post, put},
+    Extension, Router,
+};
+use serde::Deserialize;
+use serde_json::json;
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::connection::document::DocumentStore;
+use crate::connection::session::AuthUser;
+use crate::connection::storage::Storage;
+use crate::model::folder::{valid_entry_name, Folder, ProjectTree};
+use crate::model::project::Project;
+use crate::routers::access::{by_method, require_access, ProjectAccess};
+use crate::routers::files::remove_file;
+
+#[derive(Debug, Deserialize)]
+pub struct CreateFolderRequest {
+    pub name: String,
+    #[serde(default)]
+    pub parent: Option<Uuid>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RenameFolderRequest {
+    pub name: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MoveFolderRequest {
+    /// Destination folder, or `null` for the project root.
+    pub parent: Option<Uuid>,
+}
+
+/// Folder operations and the tree listing, nested under `/project/{project_id}`.
+pub fn folder_routes(storage: Arc<dyn Storage>, documents: Arc<DocumentStore>) -> Router {
+    Router::new()
+        .route(
+            "/tree",
+            get({
+                let storage = Arc::clone(&storage);
+                move |access| tree_handler(storage, access)
+            }),
+        )
+        .route(
+            "/folders",
+            post({
+                let storage = Arc::clone(&storage);
+                move |user, access, payload| create_folder_handler(storage, user, access, payload)
+            }),
+        )
+        .route(
+            "/folders/{folder_id}",
+            delete({
+                let storage = Arc::clone(&storage);
+                move |access, path| delete_folder_handler(storage, documents, access, path)
+            }),
+        )
+        .route(
+            "/folders/{folder_id}/name",
+            put({
+                let storage = Arc::clone(&storage);
+                move |access, path, payload| rename_folder_handler(storage, access, path, payload)
+            }),
+        )
+        .route(
+            "/folders/{folder_id}/parent",
+            put({
+                let storage = Arc::clone(&storage);
+                move |access, path, payload| move_folder_handler(storage, access, path, payload)
+            }),
+        )
+        .route_layer(middleware::from_fn(move |user, path, request, next| {
+            require_access(Arc::clone(&storage), by_method, user, path, request, next)
+        }))
+}
+
+fn error(status: StatusCode, message: &str) -> Response {
+    (status, Json(json!({ "error": message }))).into_response()
+}
+
+/// Loads a project's folders and the files linked to it.
+pub(crate) async fn load_tree(storage: &dyn Storage, project: &Project) -> Result<ProjectTree, Response> {
+    let project_id = project.id.to_string();
+    let internal = |e: Box<dyn std::error::Error + Send + Sync>| error(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string());
+
+    let folders = storage.list_folders(&project_id).await.map_err(internal)?;
+    let mut files = storage.list_project_files(&project_id).await.map_err(internal)?;
+    files.retain(|file| project.files.contains(&file.id));
+
+    Ok(ProjectTree { folders, files })
+}
+
+/// Rejects a `parent` that is not a folder of this project (`None` is the root).
+pub(crate) fn check_parent(tree: &ProjectTree, parent: Option<Uuid>) -> Option<Response> {
+    match parent {
+        Some(id) if tree.folder(id).is_none() => Some(error(StatusCode::NOT_FOUND, "Parent folder not found")),
+        _ => None,
+    }
+}
+
+/// Rejects a `name` that is malformed or already used in `parent` by an entry other than `except`.
+pub(crate) fn check_name(tree: &ProjectTree, parent: Option<Uuid>, name: &str, except: Option<Uuid>) -> Option<Response> {
+    if !valid_entry_name(name) {
+        return Some(error(StatusCode::BAD_REQUEST, "Name is required and cannot contain '/'"));
+    }
+    if tree.name_taken(parent, name, except) {
+        return Some(name_taken());
+    }
+    None
+}
+
+/// The answer when storage refuses a name another entry took in the meantime.
+pub(crate) fn name_taken() -> Response {
+    error(StatusCode::CONFLICT, "An entry with this name already exists in that folder")
+}
+
+async fn tree_handler(storage: Arc<dyn Storage>, Extension(access): Extension<ProjectAccess>) -> impl IntoResponse {
+    match load_tree(storage.as_ref(), &access.project).await {
+        Ok(tree) => (StatusCode::OK, Json(json!({ "project_id": access.project.id, "tree": tree.nodes(None) }))).into_response(),
+        Err(response) => response,
+    }
+}
+
+async fn create_folder_handler(
+    storage: Arc<dyn Storage>,
+    user: AuthUser,
+    Extension(access): Extension<ProjectAccess>,
+    Json(payload): Json<CreateFolderRequest>,
+) -> impl IntoResponse {
+    let name = payload.name.trim();
+    let tree = match load_tree(storage.as_ref(), &access.project).await {
+        Ok(tree) => tree,
+        Err(response) => return response,
+    };
+    if let Some(response) = check_parent(&tree, payload.parent).or_else(|| check_name(&tree, payload.parent, name, None)) {
+        return response;
+    }
+
+    let folder = Folder {
+        id: Uuid::new_v4(),
+        project_id: access.project.id,
+        parent: payload.parent,
+        name: name.to_string(),
+        created_by: user.user_id,
+    };
+    match storage.create_folder(&folder).await {
+        Ok(true) => (StatusCode::CREATED, Json(folder)).into_response(),
+        Ok(false) => name_taken(),
+        Err(e) => error(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+    }
+}
+
+async fn rename_folder_handler(
+    storage: Arc<dyn Storage>,
+    Extension(access): Extension<ProjectAccess>,
+    Path((_, folder_id)): Path<(Uuid, Uuid)>,
+    Json(payload): Json<RenameFolderRequest>,
+) -> impl IntoResponse {
+    let name = payload.name.trim();
+    let tree = match load_tree(storage.as_ref(), &access.project).await {
+        Ok(tree) => tree,
+        Err(response) => return response,
+    };
+    let Some(folder) = tree.folder(folder_id) else {
+        return error(StatusCode::NOT_FOUND, "Folder not found");
+    };
+    if let Some(response) = check_name(&tree, folder.parent, name, Some(folder_id)) {
+        return response;
+    }
+
+    match storage.update_folder(&folder_id.to_string(), name, folder.parent).await {
+        Ok(true) => (StatusCode::OK, Json(json!({"message": "Folder renamed"}))).into_response(),
+        Ok(false) => name_taken(),
+        Err(e) => error(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+    }
+}
+
+async fn move_folder_handler(
+    storage: Arc<dyn Storage>,
+    Extension(access): Extension<ProjectAccess>,
+    Path((_, folder_id)): Path<(Uuid, Uuid)>,
+    Json(payload): Json<MoveFolderRequest>,
+) -> impl IntoResponse {
+    let tree = match load_tree(storage.as_ref(), &access.project).await {
+        Ok(tree) => tree,
+        Err(response) => return response,
+    };
+    let Some(folder) = tree.folder(folder_id) else {
+        return error(StatusCode::NOT_FOUND, "Folder not found");
+    };
+    if let Some(response) = check_parent(&tree, payload.parent) {
+        return response;
+    }
+    if payload.parent.is_some_and(|parent| tree.subtree(folder_id).contains(&parent)) {
+        return error(StatusCode::BAD_REQUEST, "A folder cannot be moved into itself");
+    }
+    if let Some(response) = check_name(&tree, payload.parent, &folder.name, Some(folder_id)) {
+        return response;
+    }
+
+    match storage.update_folder(&folder_id.to_string(), &folder.name, payload.parent).await {
+        Ok(true) => (StatusCode::OK, Json(json!({"message": "Folder moved"}))).into_response(),
+        Ok(false) => name_taken(),
+        Err(e) => error(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+    }
+}
+
+/// Deletes a folder and everything below it.
+async fn delete_folder_handler(
+    storage: Arc<dyn Storage>,
+    documents: Arc<DocumentStore>,
+    Extension(access): Extension<ProjectAccess>,
+    Path((_, folder_id)): Path<(Uuid, Uuid)>,
+) -> impl IntoResponse {
+    let tree = match load_tree(storage.as_ref(), &access.project).await {
+        Ok(tree) => tree,
+        Err(response) => return response,
+    };
+    if tree.folder(folder_id).is_none() {
+        return error(StatusCode::NOT_FOUND, "Folder not found");
+    }
+
+    let folders = tree.subtree(folder_id);
+    let files: Vec<Uuid> = tree
+        .files
+        .iter()
+        .filter(|file| file.folder.is_some_and(|folder| folders.contains(&folder)))
+        .map(|file| file.id)
+        .collect();
+
+    for file_id in &files {
+        if let Err(e) = remove_file(storage.as_ref(), &documents, *file_id).await {
+            return error(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string());
+        }
+    }
+    // Children first, so an interruption never leaves a folder whose parent is gone.
+    for id in folders.iter().rev() {
+        if let Err(e) = storage.delete_folder(&id.to_string()).await {
+            return error(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string());
+        }
+    }
+
+    (
+        StatusCode::OK,
+        Json(json!({ "message": "Folder deleted", "deleted_folders": folders.len(), "deleted_files": files.len() })),
+    )
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::Method;
+    use serde_json::Value;
+
+    use crate::connection::real_time::CollabHub;
+    use crate::model::project::Role;
+    use crate::routers::files::file_routes;
+    use crate::test_support::{app::TestApp, http::send_as};
+
+    fn paths(nodes: &Value) -> Vec<String> {
+        let mut found = Vec::new();
+        for node in nodes.as_array().unwrap() {
+            found.push(node["path"].as_str().unwrap().to_string());
+            if node["kind"] == "folder" {
+                found.extend(paths(&node["children"]));
+            }
+        }
+        found
+    }
+
+    #[tokio::test]
+    async fn folders_nest_move_and_delete_recursively() {
+        let app = TestApp::new().await;
+        let routes = Router::new()
+            .nest("/project/{project_id}", folder_routes(Arc::clone(&app.storage), Arc::clone(&app.documents)))
+            .nest("/project/{project_id}/files", file_routes(Arc::clone(&app.storage), Arc::new(CollabHub::new()), Arc::clone(&app.documents)));
+        let router = app.serve(routes);
+
+        let (owner, viewer) = (Uuid::new_v4(), Uuid::new_v4());
+        let project_id = app.project(owner, &[(viewer, Role::Viewer)]).await;
+        let token = app.token(owner);
+        let token = Some(token.as_str());
+        let base = format!("/project/{}", project_id);
+
+        let folder = |name: &str, parent: Option<&Value>| json!({ "name": name, "parent": parent });
+        let (status, src) = send_as(&router, token, Method::POST, &format!("{}/folders", base), Some(folder("src", None))).await;
+        assert_eq!(status, StatusCode::CREATED);
+        let (_, util) = send_as(&router, token, Method::POST, &format!("{}/folders", base), Some(folder("util", Some(&src["id"])))).await;
+        let (_, docs) = send_as(&router, token, Method::POST, &format!("{}/folders", base), Some(folder("docs", None))).await;
+
+        let file = |name: &str, folder: &Value| json!({ "name": name, "folder": folder });
+        let files = format!("{}/files", base);
+        let (status, _) = send_as(&router, token, Method::POST, &files, Some(file("main.rs", &src["id"]))).await;
+        assert_eq!(status, StatusCode::CREATED);
+        let (status, _) = send_as(&router, token, Method::POST, &files, Some(file("mod.rs", &util["id"]))).await;
+        assert_eq!(status, StatusCode::CREATED);
+
+        // The same name is fine in another folder but not twice in one.
+        let (status, _) = send_as(&router, token, Method::POST, &files, Some(file("main.rs", &Value::Null))).await;
+        assert_eq!(status, StatusCode::CREATED);
+        let (status, _) = send_as(&router, token, Method::POST, &files, Some(file("main.rs", &src["id"]))).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        let (status, _) = send_as(&router, token, Method::POST, &format!("{}/folders", base), Some(folder("util", Some(&src["id"])))).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+
+        let (_, body) = send_as(&router, token, Method::GET, &format!("{}/tree", base), None).await;
+        assert_eq!(paths(&body["tree"]), ["docs", "src", "src/util", "src/util/mod.rs", "src/main.rs", "main.rs"]);
+
+        // Moving a folder into its own subtree is refused; moving it elsewhere carries its contents.
+        let util_parent = format!("{}/folders/{}/parent", base, util["id"].as_str().unwrap());
+        let src_parent = format!("{}/folders/{}/parent", base, src["id"].as_str().unwrap());
+        let (status, _) = send_as(&router, token, Method::PUT, &src_parent, Some(json!({ "parent": util["id"] }))).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let (status, _) = send_as(&router, token, Method::PUT, &util_parent, Some(json!({ "parent": docs["id"] }))).await;
+        assert_eq!(status, StatusCode::OK);
+        let rename = format!("{}/folders/{}/name", base, docs["id"].as_str().unwrap());
+        let (status, _) = send_as(&router, token, Method::PUT, &rename, Some(json!({ "name": "lib" }))).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let (_, body) = send_as(&router, token, Method::GET, &format!("{}/tree", base), None).await;
+        assert_eq!(paths(&body["tree"]), ["lib", "lib/util", "lib/util/mod.rs", "src", "src/main.rs", "main.rs"]);
+
+        let viewer_token = app.token(viewer);
+        let lib = format!("{}/folders/{}", base, docs["id"].as_str().unwrap());
+        let (status, _) = send_as(&router, Some(&viewer_token), Method::DELETE, &lib, None).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        let (status, body) = send_as(&router, token, Method::DELETE, &lib, None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["deleted_folders"], 2);
+        assert_eq!(body["deleted_files"], 1);
+
+        let (_, body) = send_as(&router, token, Method::GET, &format!("{}/tree", base), None).await;
+        assert_eq!(paths(&body["tree"]), ["src", "src/main.rs", "main.rs"]);
+        let project = app.storage.get_project(&project_id.to_string()).await.unwrap().unwrap();
+        assert_eq!(project.files.len(), 2);
+    }
+}
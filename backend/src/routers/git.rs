@@ -0,0 +1,664 @@
+use axum::{
+    body::Bytes,
+    extract::{DefaultBodyLimit, Json, Query},
+    http::{header, StatusCode},
+    middleware,
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Extension, Router,
+};
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path as FsPath, PathBuf};
+use std::process::Stdio;
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::process::Command;
+
+use crate::connection::document::DocumentStore;
+use crate::connection::session::AuthUser;
+use crate::connection::storage::Storage;
+use crate::model::project::Permission;
+use crate::model::revision::now_millis;
+use crate::routers::access::{by_method, require_access, ProjectAccess};
+use crate::routers::archive::{import_entries, unpack, MAX_ARCHIVE_BYTES};
+use crate::routers::folders::load_tree;
+
+#[derive(Debug, Deserialize)]
+pub struct RepositoryImportRequest {
+    /// Path of a repository under `GIT_REPOS_ROOT`.
+    pub repository: String,
+    #[serde(rename = "ref", default = "default_ref")]
+    pub reference: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BundleImportQuery {
+    #[serde(rename = "ref", default = "default_ref")]
+    pub reference: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RepositoryExportRequest {
+    /// Path of a repository under `GIT_REPOS_ROOT`.
+    pub repository: String,
+    #[serde(default = "default_branch")]
+    pub branch: String,
+    /// Overwrite the branch even when the export does not fast-forward it.
+    /// Only the project owner may do this, since it discards others' commits.
+    #[serde(default)]
+    pub force: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BundleExportQuery {
+    #[serde(default = "default_branch")]
+    pub branch: String,
+}
+
+fn default_ref() -> String {
+    "HEAD".to_string()
+}
+
+fn default_branch() -> String {
+    "main".to_string()
+}
+
+/// Git import and export, nested under `/project/{project_id}`. Local
+/// repositories are only reachable below `repos_root`; without one, only
+/// bundles are accepted.
+pub fn git_routes(storage: Arc<dyn Storage>, documents: Arc<DocumentStore>, repos_root: Option<PathBuf>) -> Router {
+    let repos_root = Arc::new(repos_root);
+
+    Router::new()
+        .route(
+            "/git/import",
+            post({
+                let storage = Arc::clone(&storage);
+                let repos_root = Arc::clone(&repos_root);
+                move |user, access, payload| import_repository_handler(storage, repos_root, user, access, payload)
+            }),
+        )
+        .route(
+            "/git/import/bundle",
+            post({
+                let storage = Arc::clone(&storage);
+                move |user, access, query, body| import_bundle_handler(storage, user, access, query, body)
+            })
+            .layer(DefaultBodyLimit::max(MAX_ARCHIVE_BYTES)),
+        )
+        .route(
+            "/git/export",
+            post({
+                let storage = Arc::clone(&storage);
+                let documents = Arc::clone(&documents);
+                move |user, access, payload| export_repository_handler(storage, documents, repos_root, user, access, payload)
+            }),
+        )
+        .route(
+            "/git/bundle",
+            get({
+                let storage = Arc::clone(&storage);
+                move |user, access, query| export_bundle_handler(storage, documents, user, access, query)
+            }),
+        )
+        .route_layer(middleware::from_fn(move |user, path, request, next| {
+            require_access(Arc::clone(&storage), by_method, user, path, request, next)
+        }))
+}
+
+fn error(status: StatusCode, message: &str) -> Response {
+    (status, Json(json!({ "error": message }))).into_response()
+}
+
+/// Most of git's stderr kept for error messages; the rest is discarded.
+const MAX_GIT_ERROR_BYTES: u64 = 64 * 1024;
+
+/// Why a `git` invocation produced no output.
+#[derive(Debug)]
+enum GitError {
+    /// Git failed; holds what it printed on stderr.
+    Failed(String),
+    /// Stdout passed `MAX_ARCHIVE_BYTES`, so git was stopped.
+    TooLarge,
+}
+
+impl fmt::Display for GitError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GitError::Failed(message) => f.write_str(message),
+            GitError::TooLarge => write!(f, "Git output is larger than {} bytes", MAX_ARCHIVE_BYTES),
+        }
+    }
+}
+
+/// Runs `git` with the given arguments, feeding `input` on stdin, and returns
+/// stdout. Git is killed as soon as stdout passes `MAX_ARCHIVE_BYTES`.
+async fn git(args: &[&str], input: Option<&[u8]>) -> Result<Vec<u8>, GitError> {
+    let mut child = Command::new("git")
+        .args(args)
+        .env("GIT_TERMINAL_PROMPT", "0")
+        .stdin(if input.is_some() { Stdio::piped() } else { Stdio::null() })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| GitError::Failed(format!("Could not run git: {}", e)))?;
+
+    if let (Some(input), Some(mut stdin)) = (input, child.stdin.take()) {
+        stdin.write_all(input).await.map_err(|e| GitError::Failed(e.to_string()))?;
+    }
+
+    // Stderr is drained alongside stdout so git never blocks writing to it.
+    let (Some(stdout), Some(mut stderr)) = (child.stdout.take(), child.stderr.take()) else {
+        return Err(GitError::Failed("Git output was not captured".into()));
+    };
+    let errors = tokio::spawn(async move {
+        let mut errors = Vec::new();
+        let _ = (&mut stderr).take(MAX_GIT_ERROR_BYTES).read_to_end(&mut errors).await;
+        let _ = tokio::io::copy(&mut stderr, &mut tokio::io::sink()).await;
+        errors
+    });
+
+    let mut output = Vec::new();
+    stdout
+        .take(MAX_ARCHIVE_BYTES as u64 + 1)
+        .read_to_end(&mut output)
+        .await
+        .map_err(|e| GitError::Failed(e.to_string()))?;
+    if output.len() > MAX_ARCHIVE_BYTES {
+        let _ = child.kill().await;
+        return Err(GitError::TooLarge);
+    }
+
+    let status = child.wait().await.map_err(|e| GitError::Failed(e.to_string()))?;
+    if status.success() {
+        Ok(output)
+    } else {
+        let errors = errors.await.unwrap_or_default();
+        Err(GitError::Failed(String::from_utf8_lossy(&errors).trim().to_string()))
+    }
+}
+
+/// Refs and branch names are passed to git as arguments, so anything that
+/// could read as an option or a revision range is refused.
+fn valid_ref(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with('-')
+        && !name.contains("..")
+        && !name.chars().any(|c| c.is_whitespace() || c.is_control() || "~^:?*[\\".contains(c))
+}
+
+/// Resolves `requested` inside `root`, refusing anything that escapes it.
+fn resolve_repository(root: &Option<PathBuf>, requested: &str) -> Result<PathBuf, (StatusCode, String)> {
+    let Some(root) = root else {
+        return Err((StatusCode::BAD_REQUEST, "Local repositories are disabled; set GIT_REPOS_ROOT".into()));
+    };
+    let root = root.canonicalize().map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    let path = root
+        .join(requested.trim_start_matches('/'))
+        .canonicalize()
+        .map_err(|_| (StatusCode::NOT_FOUND, "Repository not found".to_string()))?;
+    if !path.starts_with(&root) {
+        return Err((StatusCode::FORBIDDEN, "Repository is outside GIT_REPOS_ROOT".into()));
+    }
+    Ok(path)
+}
+
+/// Snapshots `reference` of the repository at `repository` as a zip and
+/// imports it like an uploaded archive.
+async fn import_from(
+    storage: &dyn Storage,
+    user: &AuthUser,
+    access: &ProjectAccess,
+    repository: &FsPath,
+    reference: &str,
+) -> Response {
+    if !valid_ref(reference) {
+        return error(StatusCode::BAD_REQUEST, "Invalid ref");
+    }
+    let repository = repository.to_string_lossy();
+    let archive = match git(&["-C", &repository, "archive", "--format=zip", reference], None).await {
+        Ok(bytes) => bytes,
+        Err(GitError::TooLarge) => return error(StatusCode::PAYLOAD_TOO_LARGE, "Repository snapshot is too large to import"),
+        Err(e) => return error(StatusCode::BAD_REQUEST, &e.to_string()),
+    };
+
+    let (entries, skipped) = match tokio::task::spawn_blocking(move || unpack(&archive)).await {
+        Ok(Ok(result)) => result,
+        Ok(Err(message)) => return error(StatusCode::BAD_REQUEST, &message),
+        Err(e) => return error(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+    };
+    match import_entries(storage, user, access, entries, skipped).await {
+        Ok(report) => (StatusCode::CREATED, Json(report)).into_response(),
+        Err(response) => response,
+    }
+}
+
+async fn import_repository_handler(
+    storage: Arc<dyn Storage>,
+    repos_root: Arc<Option<PathBuf>>,
+    user: AuthUser,
+    Extension(access): Extension<ProjectAccess>,
+    Json(payload): Json<RepositoryImportRequest>,
+) -> impl IntoResponse {
+    let repository = match resolve_repository(&repos_root, &payload.repository) {
+        Ok(path) => path,
+        Err((status, message)) => return error(status, &message),
+    };
+    import_from(storage.as_ref(), &user, &access, &repository, &payload.reference).await
+}
+
+async fn import_bundle_handler(
+    storage: Arc<dyn Storage>,
+    user: AuthUser,
+    Extension(access): Extension<ProjectAccess>,
+    Query(query): Query<BundleImportQuery>,
+    body: Bytes,
+) -> impl IntoResponse {
+    let workspace = match tempfile::tempdir() {
+        Ok(dir) => dir,
+        Err(e) => return error(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+    };
+    let bundle = workspace.path().join("upload.bundle");
+    let repository = workspace.path().join("repo.git");
+    if let Err(e) = tokio::fs::write(&bundle, &body).await {
+        return error(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string());
+    }
+
+    let (bundle, repository_arg) = (bundle.to_string_lossy(), repository.to_string_lossy());
+    if let Err(e) = git(&["clone", "--bare", "--quiet", &bundle, &repository_arg], None).await {
+        return error(StatusCode::BAD_REQUEST, &format!("Not a usable git bundle: {}", e));
+    }
+    import_from(storage.as_ref(), &user, &access, &repository, &query.reference).await
+}
+
+/// Who to credit for a commit, as `Name <email>`.
+fn signature(users: &HashMap<String, String>, author: &str) -> String {
+    users
+        .get(author)
+        .cloned()
+        .unwrap_or_else(|| format!("{} <{}@users.codecollab>", author, author))
+}
+
+// Names end up inside `Name <email>`, so strip what would break that line.
+fn clean(text: &str) -> String {
+    text.chars().filter(|c| !matches!(c, '<' | '>' | '\n' | '\r')).collect::<String>().trim().to_string()
+}
+
+/// Quotes a path for fast-import when it contains characters that need it.
+fn quote_path(path: &str) -> String {
+    if !path.contains(['"', '\\', '\n']) && !path.starts_with(' ') {
+        return path.to_string();
+    }
+    let escaped = path.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n");
+    format!("\"{}\"", escaped)
+}
+
+struct HistoryCommit {
+    timestamp: u64,
+    path: String,
+    revision: u64,
+    authors: Vec<String>,
+    content: String,
+}
+
+/// Builds a `git fast-import` stream that replays the project's saved
+/// revisions in time order, one commit per revision credited to the people who
+/// made it. Paths are the files' current paths. A final commit by `user`
+/// covers anything newer than the last save (or never saved at all).
+/// Returns the stream and the number of commits in it.
+async fn history_stream(
+    storage: &dyn Storage,
+    documents: &DocumentStore,
+    user: &AuthUser,
+    access: &ProjectAccess,
+    branch: &str,
+) -> Result<(Vec<u8>, usize), Response> {
+    let internal = |e: Box<dyn std::error::Error + Send + Sync>| error(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string());
+    let tree = load_tree(storage, &access.project).await?;
+
+    let mut history = Vec::new();
+    let mut current = Vec::new();
+    for file in &tree.files {
+        let path = tree.file_path(file);
+        for revision in storage.list_file_revisions(&file.id.to_string()).await.map_err(internal)? {
+            history.push(HistoryCommit {
+                timestamp: revision.timestamp,
+                path: path.clone(),
+                revision: revision.revision,
+                authors: revision.authors,
+                content: revision.content,
+            });
+        }
+        let content = match documents.peek(&file.id.to_string()).await {
+            Some((_, live)) => live,
+            None => file.content.clone(),
+        };
+        current.push((path, content));
+    }
+    history.sort_by(|a, b| (a.timestamp, &a.path, a.revision).cmp(&(b.timestamp, &b.path, b.revision)));
+    current.sort();
+
+    // Resolve every author id once.
+    let mut users = HashMap::new();
+    let exporter = user.user_id.to_string();
+    let mut ids: Vec<&String> = history.iter().flat_map(|c| c.authors.iter()).collect();
+    ids.push(&exporter);
+    for id in ids {
+        if users.contains_key(id) {
+            continue;
+        }
+        if let Some(details) = storage.get_user_by_id(id).await.map_err(internal)? {
+            let name = if details.display_name.trim().is_empty() { &details.username } else { &details.display_name };
+            users.insert(id.clone(), format!("{} <{}>", clean(name), clean(&details.email)));
+        }
+    }
+
+    let reference = format!("refs/heads/{}", branch);
+    let mut stream = Vec::new();
+    let mut commit = |signature: &str, seconds: u64, message: &str, changes: &[(&str, &str)]| {
+        stream.extend_from_slice(format!("commit {}\n", reference).as_bytes());
+        stream.extend_from_slice(format!("author {} {} +0000\n", signature, seconds).as_bytes());
+        stream.extend_from_slice(format!("committer {} {} +0000\n", signature, seconds).as_bytes());
+        stream.extend_from_slice(format!("data {}\n{}\n", message.len(), message).as_bytes());
+        for (path, content) in changes {
+            stream.extend_from_slice(format!("M 100644 inline {}\n", quote_path(path)).as_bytes());
+            stream.extend_from_slice(format!("data {}\n", content.len()).as_bytes());
+            stream.extend_from_slice(content.as_bytes());
+            stream.push(b'\n');
+        }
+    };
+
+    let mut replayed: HashMap<&str, &str> = HashMap::new();
+    for entry in &history {
+        let author = signature(&users, entry.authors.first().unwrap_or(&exporter));
+        let mut message = if entry.revision == 1 {
+            format!("Add {}", entry.path)
+        } else {
+            format!("Update {} (revision {})", entry.path, entry.revision)
+        };
+        let co_authors: Vec<String> = entry.authors.iter().skip(1).map(|a| signature(&users, a)).collect();
+        if !co_authors.is_empty() {
+            message.push('\n');
+            for co_author in co_authors {
+                message.push_str(&format!("\nCo-authored-by: {}", co_author));
+            }
+        }
+        commit(&author, entry.timestamp / 1000, &message, &[(&entry.path, &entry.content)]);
+        replayed.insert(&entry.path, &entry.content);
+    }
+
+    let pending: Vec<(&str, &str)> = current
+        .iter()
+        .filter(|(path, content)| replayed.get(path.as_str()) != Some(&content.as_str()))
+        .map(|(path, content)| (path.as_str(), content.as_str()))
+        .collect();
+    let mut commits = history.len();
+    if !pending.is_empty() || commits == 0 {
+        commits += 1;
+        let message = format!("Export {} from CodeCollab", clean(&access.project.name));
+        commit(&signature(&users, &exporter), now_millis() / 1000, &message, &pending);
+    }
+
+    Ok((stream, commits))
+}
+
+/// Builds the project's history into a fresh bare repository inside `workspace`.
+async fn build_repository(
+    storage: &dyn Storage,
+    documents: &DocumentStore,
+    user: &AuthUser,
+    access: &ProjectAccess,
+    branch: &str,
+    workspace: &FsPath,
+) -> Result<(PathBuf, usize), Response> {
+    if !valid_ref(branch) {
+        return Err(error(StatusCode::BAD_REQUEST, "Invalid branch name"));
+    }
+    let (stream, commits) = history_stream(storage, documents, user, access, branch).await?;
+
+    let repository = workspace.join("export.git");
+    let git_dir = repository.to_string_lossy().into_owned();
+    let head = format!("refs/heads/{}", branch);
+    let steps: [(&[&str], Option<&[u8]>); 3] = [
+        (&["init", "--bare", "--quiet", &git_dir], None),
+        (&["--git-dir", &git_dir, "fast-import", "--quiet"], Some(&stream)),
+        (&["--git-dir", &git_dir, "symbolic-ref", "HEAD", &head], None),
+    ];
+    for (args, input) in steps {
+        git(args, input).await.map_err(|e| error(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()))?;
+    }
+
+    Ok((repository, commits))
+}
+
+async fn export_repository_handler(
+    storage: Arc<dyn Storage>,
+    documents: Arc<DocumentStore>,
+    repos_root: Arc<Option<PathBuf>>,
+    user: AuthUser,
+    Extension(access): Extension<ProjectAccess>,
+    Json(payload): Json<RepositoryExportRequest>,
+) -> impl IntoResponse {
+    if payload.force && !access.role.allows(Permission::Manage) {
+        return error(StatusCode::FORBIDDEN, "Only the project owner can force an export");
+    }
+    let target = match resolve_repository(&repos_root, &payload.repository) {
+        Ok(path) => path,
+        Err((status, message)) => return error(status, &message),
+    };
+    let workspace = match tempfile::tempdir() {
+        Ok(dir) => dir,
+        Err(e) => return error(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+    };
+    let (repository, commits) =
+        match build_repository(storage.as_ref(), &documents, &user, &access, &payload.branch, workspace.path()).await {
+            Ok(built) => built,
+            Err(response) => return response,
+        };
+
+    let git_dir = repository.to_string_lossy();
+    let refspec = format!("{}refs/heads/{}:refs/heads/{}", if payload.force { "+" } else { "" }, payload.branch, payload.branch);
+    if let Err(e) = git(&["--git-dir", &git_dir, "push", "--quiet", &target.to_string_lossy(), &refspec], None).await {
+        let message = e.to_string();
+        // A forced push cannot be out of date, so its rejections are not conflicts.
+        let status = if !payload.force && (message.contains("non-fast-forward") || message.contains("rejected")) {
+            StatusCode::CONFLICT
+        } else {
+            StatusCode::BAD_REQUEST
+        };
+        return error(status, &message);
+    }
+
+    let head = git(&["--git-dir", &git_dir, "rev-parse", &format!("refs/heads/{}", payload.branch)], None)
+        .await
+        .map(|out| String::from_utf8_lossy(&out).trim().to_string())
+        .unwrap_or_default();
+    println!("📤 Exported project {} to {} ({} commits)", access.project.id, target.display(), commits);
+    (StatusCode::OK, Json(json!({ "branch": payload.branch, "commit": head, "commits": commits }))).into_response()
+}
+
+async fn export_bundle_handler(
+    storage: Arc<dyn Storage>,
+    documents: Arc<DocumentStore>,
+    user: AuthUser,
+    Extension(access): Extension<ProjectAccess>,
+    Query(query): Query<BundleExportQuery>,
+) -> impl IntoResponse {
+    let workspace = match tempfile::tempdir() {
+        Ok(dir) => dir,
+        Err(e) => return error(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+    };
+    let (repository, _) =
+        match build_repository(storage.as_ref(), &documents, &user, &access, &query.branch, workspace.path()).await {
+            Ok(built) => built,
+            Err(response) => return response,
+        };
+
+    let bundle = workspace.path().join("export.bundle");
+    let branch = format!("refs/heads/{}", query.branch);
+    let (git_dir, bundle_arg) = (repository.to_string_lossy(), bundle.to_string_lossy());
+    if let Err(e) = git(&["--git-dir", &git_dir, "bundle", "create", "--quiet", &bundle_arg, "HEAD", &branch], None).await {
+        return error(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string());
+    }
+    let bytes = match tokio::fs::read(&bundle).await {
+        Ok(bytes) => bytes,
+        Err(e) => return error(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+    };
+
+    let name: String = access
+        .project
+        .name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c } else { '_' })
+        .collect();
+    let disposition = format!("attachment; filename=\"{}.bundle\"", if name.is_empty() { "project" } else { &name });
+    (
+        StatusCode::OK,
+        [(header::CONTENT_TYPE, "application/x-git-bundle".to_string()), (header::CONTENT_DISPOSITION, disposition)],
+        bytes,
+    )
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::Method;
+    use uuid::Uuid;
+
+    use crate::model::project::Role;
+    use crate::test_support::{app::TestApp, http::{send_as, send_bytes}};
+
+    fn run(dir: &FsPath, args: &[&str]) -> String {
+        let output = std::process::Command::new("git")
+            .args(["-c", "user.name=Test", "-c", "user.email=test@example.com", "-c", "init.defaultBranch=main"])
+            .args(args)
+            .current_dir(dir)
+            .output()
+            .expect("git runs");
+        assert!(output.status.success(), "git {:?}: {}", args, String::from_utf8_lossy(&output.stderr));
+        String::from_utf8_lossy(&output.stdout).trim().to_string()
+    }
+
+    #[tokio::test]
+    async fn oversized_git_output_stops_git() {
+        let dir = tempfile::tempdir().unwrap();
+        run(dir.path(), &["init", "--quiet"]);
+        std::fs::write(dir.path().join("big"), vec![b'x'; MAX_ARCHIVE_BYTES + 1]).unwrap();
+        std::fs::write(dir.path().join("small"), "fits").unwrap();
+        let [big, small] = ["big", "small"].map(|name| run(dir.path(), &["hash-object", "-w", name]));
+
+        let repository = dir.path().to_string_lossy();
+        let output = git(&["-C", &repository, "cat-file", "blob", &small], None).await.unwrap();
+        assert_eq!(output, b"fits");
+        let result = git(&["-C", &repository, "cat-file", "blob", &big], None).await;
+        assert!(matches!(result, Err(GitError::TooLarge)), "{:?}", result.map(|out| out.len()));
+    }
+
+    #[tokio::test]
+    async fn repositories_are_imported_at_a_ref_and_only_from_the_root() {
+        let app = TestApp::new().await;
+        let root = tempfile::tempdir().unwrap();
+        let routes = Router::new().nest(
+            "/project/{project_id}",
+            git_routes(Arc::clone(&app.storage), Arc::clone(&app.documents), Some(root.path().to_path_buf())),
+        );
+        let router = app.serve(routes);
+
+        let work = root.path().join("work");
+        std::fs::create_dir_all(work.join("src")).unwrap();
+        run(root.path(), &["init", "--quiet", "work"]);
+        std::fs::write(work.join("src/main.rs"), "fn main() {}\n").unwrap();
+        run(&work, &["add", "."]);
+        run(&work, &["commit", "--quiet", "-m", "first"]);
+        run(&work, &["tag", "v1"]);
+        std::fs::write(work.join("later.py"), "print(1)\n").unwrap();
+        run(&work, &["add", "."]);
+        run(&work, &["commit", "--quiet", "-m", "second"]);
+
+        let owner = Uuid::new_v4();
+        let project_id = app.project(owner, &[]).await;
+        let token = app.token(owner);
+        let uri = format!("/project/{}/git/import", project_id);
+
+        let outside = json!({ "repository": "../", "ref": "main" });
+        let (status, _) = send_as(&router, Some(&token), Method::POST, &uri, Some(outside)).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let option = json!({ "repository": "work", "ref": "--output=/tmp/x" });
+        let (status, _) = send_as(&router, Some(&token), Method::POST, &uri, Some(option)).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let (status, body) = send_as(&router, Some(&token), Method::POST, &uri, Some(json!({ "repository": "work", "ref": "v1" }))).await;
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(body["created_files"], json!(["src/main.rs"]));
+
+        let files = app.storage.list_project_files(&project_id.to_string()).await.unwrap();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].content, "fn main() {}\n");
+        assert_eq!(files[0].file_type, "Rust");
+    }
+
+    #[tokio::test]
+    async fn exports_replay_history_with_its_authors() {
+        let app = TestApp::new().await;
+        let root = tempfile::tempdir().unwrap();
+        let routes = Router::new().nest(
+            "/project/{project_id}",
+            git_routes(Arc::clone(&app.storage), Arc::clone(&app.documents), Some(root.path().to_path_buf())),
+        );
+        let router = app.serve(routes);
+
+        let ada = app.register("ada").await;
+        let grace = app.register("grace").await;
+        let project_id = app.project(ada, &[]).await;
+        let file_id = app.storage.create_file("main.c".into(), ada, project_id, None).await.unwrap().unwrap().to_string();
+        app.storage.update_file_content(&file_id, "int main;\n", &[ada.to_string()]).await.unwrap();
+        app.storage.update_file_content(&file_id, "int main() {}\n", &[grace.to_string(), ada.to_string()]).await.unwrap();
+
+        let token = app.token(ada);
+        let uri = format!("/project/{}/git/bundle?branch=trunk", project_id);
+        let (status, bundle) = send_bytes(&router, Some(&token), Method::GET, &uri, "application/octet-stream", vec![]).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let checkout = tempfile::tempdir().unwrap();
+        std::fs::write(checkout.path().join("project.bundle"), &bundle).unwrap();
+        run(checkout.path(), &["clone", "--quiet", "project.bundle", "clone"]);
+        let clone = checkout.path().join("clone");
+        assert_eq!(run(&clone, &["rev-parse", "--abbrev-ref", "HEAD"]), "trunk");
+        assert_eq!(run(&clone, &["log", "--format=%an <%ae>"]), "Grace <grace@example.com>\nAda <ada@example.com>");
+        assert!(run(&clone, &["log", "-1", "--format=%b"]).contains("Co-authored-by: Ada <ada@example.com>"));
+        assert_eq!(std::fs::read_to_string(clone.join("main.c")).unwrap(), "int main() {}\n");
+
+        // Pushing into a repository under the root; an unchanged project exports the same commits.
+        run(root.path(), &["init", "--bare", "--quiet", "shared.git"]);
+        let export = json!({ "repository": "shared.git", "branch": "trunk" });
+        let uri = format!("/project/{}/git/export", project_id);
+        let (status, first) = send_as(&router, Some(&token), Method::POST, &uri, Some(export.clone())).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(first["commits"], 2);
+        let (status, second) = send_as(&router, Some(&token), Method::POST, &uri, Some(export)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(first["commit"], second["commit"]);
+        assert_eq!(run(&root.path().join("shared.git"), &["rev-parse", "trunk"]), first["commit"].as_str().unwrap());
+
+        // Another project's history does not fast-forward the branch; only the owner may force it.
+        let other = app.project(ada, &[(grace, Role::Editor)]).await;
+        let file_id = app.storage.create_file("other.c".into(), ada, other, None).await.unwrap().unwrap().to_string();
+        app.storage.update_file_content(&file_id, "int x;\n", &[ada.to_string()]).await.unwrap();
+        let uri = format!("/project/{}/git/export", other);
+        let grace_token = app.token(grace);
+        let (status, _) = send_as(&router, Some(&grace_token), Method::POST, &uri, Some(json!({ "repository": "shared.git", "branch": "trunk" }))).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        let forced = json!({ "repository": "shared.git", "branch": "trunk", "force": true });
+        let (status, _) = send_as(&router, Some(&grace_token), Method::POST, &uri, Some(forced.clone())).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert_eq!(run(&root.path().join("shared.git"), &["rev-parse", "trunk"]), first["commit"].as_str().unwrap());
+        let (status, body) = send_as(&router, Some(&token), Method::POST, &uri, Some(forced)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(run(&root.path().join("shared.git"), &["rev-parse", "trunk"]), body["commit"].as_str().unwrap());
+    }
+}
@@ -0,0 +1,257 @@
+use axum::{
+    extract::{Json, Path, Query},
+    http::StatusCode,
+    middleware,
+    response::IntoResponse,
+    routing::{get, post},
+    Router,
+};
+use serde::Deserialize;
+use serde_json::json;
+use similar::TextDiff;
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::connection::document::DocumentStore;
+use crate::connection::storage::Storage;
+use crate::connection::real_time::CollabHub;
+use crate::connection::session::AuthUser;
+use crate::model::revision::{FileRevision, RevisionSummary};
+use crate::routers::access::{by_method, require_access};
+
+#[derive(Debug, Deserialize)]
+pub struct DiffQuery {
+    pub from: u64,
+    pub to: u64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RestoreRequest {
+    pub revision: u64,
+}
+
+pub fn history_routes(storage: Arc<dyn Storage>, hub: Arc<CollabHub>, documents: Arc<DocumentStore>) -> Router {
+    Router::new()
+        .route(
+            "/{file_id}/revisions",
+            get({
+                let storage = Arc::clone(&storage);
+                move |path| list_revisions_handler(storage, path)
+            }),
+        )
+        .route(
+            "/{file_id}/revisions/{revision}",
+            get({
+                let storage = Arc::clone(&storage);
+                move |path| get_revision_handler(storage, path)
+            }),
+        )
+        .route(
+            "/{file_id}/diff",
+            get({
+                let storage = Arc::clone(&storage);
+                move |path, query| diff_revisions_handler(storage, path, query)
+            }),
+        )
+        .route(
+            "/{file_id}/restore",
+            post({
+                let storage = Arc::clone(&storage);
+                move |user, path, payload| restore_revision_handler(storage, hub, documents, user, path, payload)
+            }),
+        )
+        .route_layer(middleware::from_fn(move |user, path, request, next| {
+            require_access(Arc::clone(&storage), by_method, user, path, request, next)
+        }))
+}
+
+async fn list_revisions_handler(storage: Arc<dyn Storage>, Path(file_id): Path<Uuid>) -> impl IntoResponse {
+    match storage.list_file_revisions(&file_id.to_string()).await {
+        Ok(revisions) => {
+            let summaries: Vec<RevisionSummary> = revisions.iter().map(RevisionSummary::from).collect();
+            (StatusCode::OK, Json(json!({ "file_id": file_id, "revisions": summaries }))).into_response()
+        }
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({"error": e.to_string()}))).into_response(),
+    }
+}
+
+async fn get_revision_handler(storage: Arc<dyn Storage>, Path((file_id, revision)): Path<(Uuid, u64)>) -> impl IntoResponse {
+    match storage.get_file_revision(&file_id.to_string(), revision).await {
+        Ok(Some(revision)) => (StatusCode::OK, Json(revision)).into_response(),
+        Ok(None) => (StatusCode::NOT_FOUND, Json(json!({"error": "Revision not found"}))).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({"error": e.to_string()}))).into_response(),
+    }
+}
+
+async fn diff_revisions_handler(
+    storage: Arc<dyn Storage>,
+    Path(file_id): Path<Uuid>,
+    Query(query): Query<DiffQuery>,
+) -> impl IntoResponse {
+    let file_id = file_id.to_string();
+    let (from, to) = match (
+        storage.get_file_revision(&file_id, query.from).await,
+        storage.get_file_revision(&file_id, query.to).await,
+    ) {
+        (Ok(Some(from)), Ok(Some(to))) => (from, to),
+        (Ok(_), Ok(_)) => {
+            return (StatusCode::NOT_FOUND, Json(json!({"error": "Revision not found"}))).into_response();
+        }
+        (Err(e), _) | (_, Err(e)) => {
+            return (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({"error": e.to_string()}))).into_response();
+        }
+    };
+
+    let diff = revision_diff(&from, &to);
+
+    (StatusCode::OK, Json(json!({ "from": from.revision, "to": to.revision, "diff": diff }))).into_response()
+}
+
+/// A unified diff from `from` to `to`, with the revisions as file names.
+fn revision_diff(from: &FileRevision, to: &FileRevision) -> String {
+    TextDiff::from_lines(&from.content, &to.content)
+        .unified_diff()
+        .header(&format!("revision {}", from.revision), &format!("revision {}", to.revision))
+        .to_string()
+}
+
+async fn restore_revision_handler(
+    storage: Arc<dyn Storage>,
+    hub: Arc<CollabHub>,
+    documents: Arc<DocumentStore>,
+    user: AuthUser,
+    Path(file_id): Path<Uuid>,
+    Json(payload): Json<RestoreRequest>,
+) -> impl IntoResponse {
+    let file_id = file_id.to_string();
+    let revision = match storage.get_file_revision(&file_id, payload.revision).await {
+        Ok(Some(revision)) => revision,
+        Ok(None) => return (StatusCode::NOT_FOUND, Json(json!({"error": "Revision not found"}))).into_response(),
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({"error": e.to_string()}))).into_response(),
+    };
+    let author = user.user_id.to_string();
+
+    // If someone has the file open, restore through the live document so their
+    // editors update and the next save records the restore as a new revision.
+    match documents.replace_if_open(&file_id, &revision.content, &author).await {
+        Ok(Some(edit)) => {
+            hub.broadcast_edit(&file_id, Uuid::nil(), edit).await;
+            (StatusCode::OK, Json(json!({ "message": "File restored", "restored_from": revision.revision }))).into_response()
+        }
+        Ok(None) => match storage.update_file_content(&file_id, &revision.content, &[author]).await {
+            Ok(new_revision) => (
+                StatusCode::OK,
+                Json(json!({ "message": "File restored", "restored_from": revision.revision, "revision": new_revision })),
+            )
+                .into_response(),
+            Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({"error": e.to_string()}))).into_response(),
+        },
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({"error": e.to_string()}))).into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::Method;
+
+    use crate::model::project::Role;
+    use crate::test_support::{app::TestApp, http::send_as};
+
+    #[tokio::test]
+    async fn revisions_can_be_listed_diffed_and_restored() {
+        let app = TestApp::new().await;
+        let router = history_routes(Arc::clone(&app.storage), Arc::new(CollabHub::new()), Arc::clone(&app.documents));
+        let router = app.serve(router);
+        let restorer = Uuid::new_v4();
+        let token = app.token(restorer);
+        let send = |method, uri: String, body| {
+            let router = router.clone();
+            let token = token.clone();
+            async move { send_as(&router, Some(&token), method, &uri, body).await }
+        };
+
+        let project_id = app.project(restorer, &[]).await;
+        let file_id = app.storage.create_file("main.py".into(), restorer, project_id, None).await.unwrap().unwrap();
+        let id = file_id.to_string();
+        app.storage.update_file_content(&id, "print(1)\n", &["ada".into()]).await.unwrap();
+        app.storage.update_file_content(&id, "print(2)\n", &["grace".into()]).await.unwrap();
+
+        let (status, body) = send(Method::GET, format!("/{}/revisions", id), None).await;
+        assert_eq!(status, StatusCode::OK);
+        let revisions = body["revisions"].as_array().unwrap();
+        assert_eq!(revisions.len(), 2);
+        assert_eq!(revisions[0]["revision"], 1);
+        assert_eq!(revisions[1]["authors"], json!(["grace"]));
+
+        let (_, body) = send(Method::GET, format!("/{}/revisions/1", id), None).await;
+        assert_eq!(body["content"], "print(1)\n");
+
+        let (_, body) = send(Method::GET, format!("/{}/diff?from=1&to=2", id), None).await;
+        let diff = body["diff"].as_str().unwrap();
+        assert!(diff.contains("-print(1)") && diff.contains("+print(2)"), "{}", diff);
+
+        let restore = json!({ "revision": 1 });
+        let (status, body) = send(Method::POST, format!("/{}/restore", id), Some(restore)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["revision"], 3);
+        let restored = app.storage.get_file_revision(&id, 3).await.unwrap().unwrap();
+        assert_eq!(restored.authors, vec![restorer.to_string()], "the restore is credited to the caller");
+        let file = app.storage.get_file_by_id(&id).await.unwrap().unwrap();
+        assert_eq!(file.content, "print(1)\n");
+    }
+
+    #[tokio::test]
+    async fn viewers_can_read_history_but_not_restore() {
+        let app = TestApp::new().await;
+        let router = app.serve(history_routes(Arc::clone(&app.storage), Arc::new(CollabHub::new()), Arc::clone(&app.documents)));
+
+        let (owner, viewer) = (Uuid::new_v4(), Uuid::new_v4());
+        let project_id = app.project(owner, &[(viewer, Role::Viewer)]).await;
+        let file_id = app.storage.create_file("main.py".into(), owner, project_id, None).await.unwrap().unwrap();
+        app.storage.update_file_content(&file_id.to_string(), "print(1)\n", &[owner.to_string()]).await.unwrap();
+
+        let viewer_token = app.token(viewer);
+        let (status, _) = send_as(&router, Some(&viewer_token), Method::GET, &format!("/{}/revisions", file_id), None).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let restore = json!({ "revision": 1 });
+        let uri = format!("/{}/restore", file_id);
+        let (status, body) = send_as(&router, Some(&viewer_token), Method::POST, &uri, Some(restore)).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert_eq!(body["error"], "A project viewer cannot do this");
+
+        let outsider = app.token(Uuid::new_v4());
+        let (status, _) = send_as(&router, Some(&outsider), Method::GET, &format!("/{}/revisions", file_id), None).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn missing_revision_is_not_found() {
+        let app = TestApp::new().await;
+        let router = app.serve(history_routes(Arc::clone(&app.storage), Arc::new(CollabHub::new()), Arc::clone(&app.documents)));
+        let owner = Uuid::new_v4();
+        let project_id = app.project(owner, &[]).await;
+        let file_id = app.storage.create_file("main.py".into(), owner, project_id, None).await.unwrap().unwrap();
+        let token = app.token(owner);
+
+        let uri = format!("/{}/revisions/7", file_id);
+        let (status, body) = send_as(&router, Some(&token), Method::GET, &uri, None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body["error"], "Revision not found");
+    }
+
+    #[test]
+    fn diffs_are_labelled_with_revisions() {
+        let revision = |revision, content: &str| FileRevision {
+            file_id: Uuid::nil(),
+            revision,
+            authors: vec![],
+            timestamp: 0,
+            content: content.to_string(),
+        };
+        let diff = revision_diff(&revision(1, "a = 1\nb = 2\n"), &revision(3, "a = 1\nb = 3\n"));
+        assert_eq!(diff, "--- revision 1\n+++ revision 3\n@@ -1,2 +1,2 @@\n a = 1\n-b = 2\n+b = 3\n");
+        assert_eq!(revision_diff(&revision(1, "a\n"), &revision(2, "a\n")), "");
+    }
+}
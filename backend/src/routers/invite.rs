@@ -0,0 +1,298 @@
+use axum::{
+    extract::{Json, Path},
+    http::{Method, StatusCode},
+    middleware,
+    response::IntoResponse,
+    routing::{delete, get, post},
+    Extension, Router,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::connection::session::AuthUser;
+use crate::connection::storage::Storage;
+use crate::model::invite::{Invite, Redemption};
+use crate::model::project::{Permission, Role};
+use crate::model::revision::now_millis;
+use crate::routers::access::{require_access, ProjectAccess};
+
+const DEFAULT_EXPIRY_SECS: u64 = 7 * 24 * 60 * 60;
+const MAX_EXPIRY_SECS: u64 = 30 * 24 * 60 * 60;
+
+#[derive(Debug, Deserialize)]
+pub struct CreateInviteRequest {
+    pub role: Role,
+    /// Defaults to a week; capped at 30 days.
+    pub expires_in_secs: Option<u64>,
+    pub max_uses: Option<u32>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct InviteResponse {
+    #[serde(flatten)]
+    invite: Invite,
+    /// Frontend URL that pre-fills the code on the join page.
+    link: String,
+}
+
+impl From<Invite> for InviteResponse {
+    fn from(invite: Invite) -> Self {
+        let frontend = std::env::var("FRONTEND_URL").unwrap_or_else(|_| "http://localhost:3000".to_string());
+        let link = format!("{}/join?code={}", frontend.trim_end_matches('/'), invite.code);
+        Self { invite, link }
+    }
+}
+
+/// Invite management for a project's owner, merged into the project routes.
+pub fn project_invite_routes(storage: Arc<dyn Storage>) -> Router {
+    Router::new()
+        .route(
+            "/{project_id}/invites",
+            post({
+                let storage = Arc::clone(&storage);
+                move |user, access, payload| create_invite_handler(storage, user, access, payload)
+            })
+            .get({
+                let storage = Arc::clone(&storage);
+                move |access| list_invites_handler(storage, access)
+            }),
+        )
+        .route(
+            "/{project_id}/invites/{code}",
+            delete({
+                let storage = Arc::clone(&storage);
+                move |access, path| revoke_invite_handler(storage, access, path)
+            }),
+        )
+        .route_layer(middleware::from_fn(move |user, path, request, next| {
+            require_access(Arc::clone(&storage), manage_only, user, path, request, next)
+        }))
+}
+
+// Invite codes grant access, so even listing them is for the owner only.
+fn manage_only(_: &Method) -> Permission {
+    Permission::Manage
+}
+
+/// Redemption routes for anyone holding a code, mounted at `/invites`.
+pub fn invite_routes(storage: Arc<dyn Storage>) -> Router {
+    Router::new()
+        .route(
+            "/{code}",
+            get({
+                let storage = Arc::clone(&storage);
+                move |user, path| preview_invite_handler(storage, user, path)
+            }),
+        )
+        .route(
+            "/{code}/redeem",
+            post({
+                let storage = Arc::clone(&storage);
+                move |user, path| redeem_invite_handler(storage, user, path)
+            }),
+        )
+}
+
+async fn create_invite_handler(
+    storage: Arc<dyn Storage>,
+    user: AuthUser,
+    Extension(access): Extension<ProjectAccess>,
+    Json(payload): Json<CreateInviteRequest>,
+) -> impl IntoResponse {
+    if payload.role == Role::Owner {
+        return (StatusCode::BAD_REQUEST, Json(json!({"error": "Invites cannot grant ownership"}))).into_response();
+    }
+    if payload.max_uses == Some(0) {
+        return (StatusCode::BAD_REQUEST, Json(json!({"error": "max_uses must be at least 1"}))).into_response();
+    }
+
+    let now = now_millis();
+    let expires_in = payload.expires_in_secs.unwrap_or(DEFAULT_EXPIRY_SECS).min(MAX_EXPIRY_SECS);
+    let invite = Invite {
+        code: Invite::generate_code(),
+        project_id: access.project.id,
+        role: payload.role,
+        created_by: user.user_id,
+        created_at: now,
+        expires_at: now + expires_in * 1000,
+        max_uses: payload.max_uses,
+        uses: 0,
+    };
+
+    match storage.create_invite(&invite).await {
+        Ok(()) => (StatusCode::CREATED, Json(InviteResponse::from(invite))).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({"error": e.to_string()}))).into_response(),
+    }
+}
+
+async fn list_invites_handler(storage: Arc<dyn Storage>, Extension(access): Extension<ProjectAccess>) -> impl IntoResponse {
+    match storage.list_invites(&access.project.id.to_string()).await {
+        Ok(invites) => {
+            let invites: Vec<InviteResponse> = invites.into_iter().map(InviteResponse::from).collect();
+            (StatusCode::OK, Json(json!({ "invites": invites }))).into_response()
+        }
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({"error": e.to_string()}))).into_response(),
+    }
+}
+
+async fn revoke_invite_handler(
+    storage: Arc<dyn Storage>,
+    Extension(access): Extension<ProjectAccess>,
+    Path((_, code)): Path<(Uuid, String)>,
+) -> impl IntoResponse {
+    // Only codes belonging to this project can be revoked through it.
+    match storage.get_invite(&code).await {
+        Ok(Some(invite)) if invite.project_id == access.project.id => {}
+        Ok(_) => return (StatusCode::NOT_FOUND, Json(json!({"error": "Invite not found"}))).into_response(),
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({"error": e.to_string()}))).into_response(),
+    }
+
+    match storage.delete_invite(&code).await {
+        Ok(_) => (StatusCode::OK, Json(json!({ "message": "Invite revoked" }))).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({"error": e.to_string()}))).into_response(),
+    }
+}
+
+// Loads an invite that can still be redeemed, or the response explaining why not.
+async fn usable_invite(storage: &dyn Storage, code: &str) -> Result<Invite, axum::response::Response> {
+    match storage.get_invite(code).await {
+        Ok(Some(invite)) if invite.is_expired(now_millis()) => {
+            Err((StatusCode::GONE, Json(json!({"error": "Invite has expired"}))).into_response())
+        }
+        Ok(Some(invite)) if invite.is_used_up() => {
+            Err((StatusCode::GONE, Json(json!({"error": "Invite has been used up"}))).into_response())
+        }
+        Ok(Some(invite)) => Ok(invite),
+        Ok(None) => Err((StatusCode::NOT_FOUND, Json(json!({"error": "Invite not found"}))).into_response()),
+        Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, Json(json!({"error": e.to_string()}))).into_response()),
+    }
+}
+
+/// Shows what a code leads to before the caller accepts it.
+async fn preview_invite_handler(storage: Arc<dyn Storage>, _user: AuthUser, Path(code): Path<String>) -> impl IntoResponse {
+    let invite = match usable_invite(storage.as_ref(), &code).await {
+        Ok(invite) => invite,
+        Err(response) => return response,
+    };
+
+    match storage.get_project(&invite.project_id.to_string()).await {
+        Ok(Some(project)) => (
+            StatusCode::OK,
+            Json(json!({
+                "project_id": project.id,
+                "project_name": project.name,
+                "role": invite.role,
+                "expires_at": invite.expires_at,
+            })),
+        )
+            .into_response(),
+        Ok(None) => (StatusCode::NOT_FOUND, Json(json!({"error": "Project not found"}))).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({"error": e.to_string()}))).into_response(),
+    }
+}
+
+async fn redeem_invite_handler(storage: Arc<dyn Storage>, user: AuthUser, Path(code): Path<String>) -> impl IntoResponse {
+    let (project_id, role) = match storage.redeem_invite(&code, user.user_id, now_millis()).await {
+        Ok(Redemption::Joined { project_id, role }) => (project_id, role),
+        Ok(Redemption::AlreadyMember { project_id, role }) => {
+            return (
+                StatusCode::OK,
+                Json(json!({ "message": "Already a member", "project_id": project_id, "role": role })),
+            )
+                .into_response();
+        }
+        Ok(Redemption::Expired) => return (StatusCode::GONE, Json(json!({"error": "Invite has expired"}))).into_response(),
+        Ok(Redemption::UsedUp) => return (StatusCode::GONE, Json(json!({"error": "Invite has been used up"}))).into_response(),
+        Ok(Redemption::InviteNotFound) => return (StatusCode::NOT_FOUND, Json(json!({"error": "Invite not found"}))).into_response(),
+        Ok(Redemption::ProjectNotFound) => return (StatusCode::NOT_FOUND, Json(json!({"error": "Project not found"}))).into_response(),
+        Ok(Redemption::UserNotFound) => return (StatusCode::NOT_FOUND, Json(json!({"error": "User not found"}))).into_response(),
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({"error": e.to_string()}))).into_response(),
+    };
+
+    println!("👋 {} joined project {} as {}", user.username, project_id, role.as_str());
+    (
+        StatusCode::OK,
+        Json(json!({ "message": "Joined project", "project_id": project_id, "role": role })),
+    )
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::routers::project::project_routes;
+    use crate::test_support::{app::TestApp, http::send_as};
+
+    #[tokio::test]
+    async fn invites_can_be_created_redeemed_and_revoked() {
+        let app = TestApp::new().await;
+        let projects = app.serve(project_routes(Arc::clone(&app.storage)));
+        let invites = app.serve(invite_routes(Arc::clone(&app.storage)));
+
+        let owner = app.register("owner").await;
+        let guest = app.register("guest").await;
+        let late = app.register("late").await;
+        let project_id = app.project(owner, &[]).await;
+        let [owner_token, guest_token, late_token] = [owner, guest, late].map(|id| app.token(id));
+
+        let create = json!({ "role": "viewer", "max_uses": 1 });
+        let uri = format!("/{}/invites", project_id);
+        let (status, _) = send_as(&projects, Some(&guest_token), Method::POST, &uri, Some(create.clone())).await;
+        assert_eq!(status, StatusCode::FORBIDDEN, "only the owner manages invites");
+        let (status, body) = send_as(&projects, Some(&owner_token), Method::POST, &uri, Some(create)).await;
+        assert_eq!(status, StatusCode::CREATED);
+        let code = body["code"].as_str().unwrap().to_string();
+        assert!(body["link"].as_str().unwrap().ends_with(&format!("/join?code={}", code)));
+
+        let (_, body) = send_as(&invites, Some(&guest_token), Method::GET, &format!("/{}", code), None).await;
+        assert_eq!(body["project_name"], "shared");
+
+        let redeem = format!("/{}/redeem", code);
+        let (status, body) = send_as(&invites, Some(&guest_token), Method::POST, &redeem, None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["role"], "viewer");
+        let project = app.storage.get_project(&project_id.to_string()).await.unwrap().unwrap();
+        assert_eq!(project.role_of(guest), Some(Role::Viewer));
+        let details = app.storage.get_user_by_id(&guest.to_string()).await.unwrap().unwrap();
+        assert_eq!(details.projects, vec![project_id]);
+
+        let (status, body) = send_as(&invites, Some(&late_token), Method::POST, &redeem, None).await;
+        assert_eq!(status, StatusCode::GONE);
+        assert_eq!(body["error"], "Invite has been used up");
+
+        let (_, body) = send_as(&projects, Some(&owner_token), Method::GET, &uri, None).await;
+        assert_eq!(body["invites"][0]["uses"], 1);
+
+        let (status, _) = send_as(&projects, Some(&owner_token), Method::DELETE, &format!("{}/{}", uri, code), None).await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, _) = send_as(&invites, Some(&late_token), Method::POST, &redeem, None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn expired_invites_are_refused() {
+        let app = TestApp::new().await;
+        let invites = app.serve(invite_routes(Arc::clone(&app.storage)));
+        let owner = Uuid::new_v4();
+        let project_id = app.project(owner, &[]).await;
+
+        let invite = Invite {
+            code: Invite::generate_code(),
+            project_id,
+            role: Role::Editor,
+            created_by: owner,
+            created_at: 0,
+            expires_at: 1,
+            max_uses: None,
+            uses: 0,
+        };
+        app.storage.create_invite(&invite).await.unwrap();
+
+        let token = app.token(Uuid::new_v4());
+        let (status, _) = send_as(&invites, Some(&token), Method::POST, &format!("/{}/redeem", invite.code), None).await;
+        assert_eq!(status, StatusCode::GONE);
+    }
+}
@@ -3,32 +3,54 @@ use axum::{
     Json, Router,
 };
 use axum::http::StatusCode;
-use serde_json::Value;
 use serde::{Deserialize, Serialize};
 use std::sync::Arc;
 use uuid::Uuid;
 
-use crate::connection::firebase::FirebaseService;
-use crate::model::user::{UserCred, UserDetails};
+use crate::connection::password::{PasswordHasher, Verification};
+use crate::connection::session::{TokenKind, TokenPair, TokenService};
+use crate::connection::storage::Storage;
+use crate::model::user::UserDetails;
 
-pub fn auth_routes(firebase: Arc<FirebaseService>) -> Router {
+pub fn auth_routes(storage: Arc<dyn Storage>, passwords: Arc<PasswordHasher>, tokens: Arc<TokenService>) -> Router {
     Router::new()
         .route(
             "/login",
             post({
-                let firebase = Arc::clone(&firebase);
-                move |payload| login_handler(firebase, payload)
+                let storage = Arc::clone(&storage);
+                let passwords = Arc::clone(&passwords);
+                let tokens = Arc::clone(&tokens);
+                move |payload| login_handler(storage, passwords, tokens, payload)
             }),
         )
+        .route(
+            "/refresh",
+            post({
+                let tokens = Arc::clone(&tokens);
+                move |payload| refresh_handler(tokens, payload)
+            }),
+        )
+        .route("/logout", post(move |payload| logout_handler(tokens, payload)))
         .route(
             "/register",
             post({
-                let firebase = Arc::clone(&firebase);
-                move |payload| register_handler(firebase, payload)
+                let storage = Arc::clone(&storage);
+                move |payload| register_handler(storage, passwords, payload)
             }),
         )
 }
 
+// Argon2 is deliberately slow, so keep it off the async worker threads.
+async fn hash_password(passwords: Arc<PasswordHasher>, password: String) -> Result<String, StatusCode> {
+    tokio::task::spawn_blocking(move || passwords.hash(&password))
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .map_err(|e| {
+            eprintln!("❌ {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })
+}
+
 #[derive(Debug, Deserialize)]
 pub struct AuthPayload {
     pub email: String,
@@ -43,146 +65,237 @@ pub struct LoginPayload {
     pub password: String,
 }
 
+#[derive(Debug, Deserialize)]
+pub struct RefreshPayload {
+    pub refresh_token: String,
+}
+
 #[derive(Debug, Serialize)]
 pub struct AuthResponse {
     message: String,
 }
 
-// ✅ Register handler: store user in Firestore
+#[derive(Debug, Serialize)]
+pub struct LoginResponse {
+    message: String,
+    user_id: Uuid,
+    #[serde(flatten)]
+    tokens: TokenPair,
+}
+
+// ✅ Register handler: store user through the configured storage backend
 async fn register_handler(
-    firebase: Arc<FirebaseService>,
+    storage: Arc<dyn Storage>,
+    passwords: Arc<PasswordHasher>,
     Json(payload): Json<AuthPayload>,
-) -> Json<AuthResponse> {
-    println!("🚀 Registering user: {:?}", payload);
+) -> Result<Json<AuthResponse>, StatusCode> {
+    println!("🚀 Registering user: {}", payload.username);
 
-    let user_id = Uuid::new_v4();
+    let password_hash = hash_password(passwords, payload.password).await?;
 
     let user_details = UserDetails {
-        id: user_id,
+        id: Uuid::new_v4(),
         display_name: payload.display_name,
         username: payload.username,
         email: payload.email.clone(),
         projects: vec![],
     };
 
-    let user_cred = UserCred {
-        id: user_id,
-        password: payload.password,
-    };
-
-    // Build Firestore URL
-    let project_id = "dcode-7b1a0";
-    let database_id = "(default)";
-    let collection_name = "users";
-    let url = format!(
-        "https://firestore.googleapis.com/v1/projects/{}/databases/{}/documents/{}?documentId={}",
-        project_id, database_id, collection_name, user_id
-    );
-
-    // Serialize to Firestore format
-    let body = serde_json::json!({
-        "fields": {
-            "id": { "stringValue": user_id.to_string() },
-            "display_name": { "stringValue": user_details.display_name },
-            "username": { "stringValue": user_details.username },
-            "email": { "stringValue": user_details.email },
-            "projects": {
-                "arrayValue": {
-                    "values": []
-                }
-            },
-            "password": { "stringValue": user_cred.password }
-        }
-    });
-
-    let response = firebase
-        .client
-        .post(&url)
-        .bearer_auth(&firebase.access_token)
-        .json(&body)
-        .send()
+    storage
+        .create_user(&user_details, &password_hash)
         .await
-        .expect("Failed to send Firestore request");
-
-    let status = response.status();
-    let response_body = response.text().await.unwrap_or_default();
-
-    println!("✅ Firestore Status: {}", status);
-    println!("✅ Firestore Body: {}", response_body);
+        .map_err(|e| {
+            eprintln!("❌ Failed to register user: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
 
-    Json(AuthResponse {
+    Ok(Json(AuthResponse {
         message: format!("User registered: {}", payload.email),
-    })
+    }))
 }
 
 
 async fn login_handler(
-    firebase: Arc<FirebaseService>,
+    storage: Arc<dyn Storage>,
+    passwords: Arc<PasswordHasher>,
+    tokens: Arc<TokenService>,
     Json(payload): Json<LoginPayload>,
-) -> Result<Json<AuthResponse>, StatusCode> {
-    println!("🚀 Login attempt: {:?}", payload);
-
-    let project_id = "dcode-7b1a0";
-    let database_id = "(default)";
-    let collection_name = "users";
-    let url = format!(
-        "https://firestore.googleapis.com/v1/projects/{}/databases/{}/documents:runQuery",
-        project_id, database_id
-    );
-
-    let body = serde_json::json!({
-        "structuredQuery": {
-            "from": [{ "collectionId": collection_name }],
-            "where": {
-                "fieldFilter": {
-                    "field": { "fieldPath": "username" },
-                    "op": "EQUAL",
-                    "value": { "stringValue": payload.username }
-                }
-            },
-            "limit": 1
-        }
-    });
-
-    // Handle Firestore request errors
-    let response = firebase.client
-        .post(&url)
-        .bearer_auth(&firebase.access_token)
-        .json(&body)
-        .send()
+) -> Result<Json<LoginResponse>, StatusCode> {
+    println!("🚀 Login attempt: {}", payload.username);
+
+    let credentials = storage
+        .get_user_credentials(&payload.username)
         .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .ok_or(StatusCode::NOT_FOUND)?;
 
-    // Check Firestore response status
-    if !response.status().is_success() {
-        return Err(StatusCode::INTERNAL_SERVER_ERROR);
+    let verification = {
+        let passwords = Arc::clone(&passwords);
+        let password = payload.password.clone();
+        tokio::task::spawn_blocking(move || passwords.verify(&password, &credentials.password))
+            .await
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+    };
+
+    match verification {
+        Verification::Invalid => return Err(StatusCode::UNAUTHORIZED),
+        // Upgrade plaintext or outdated hashes now that we know the password.
+        // A failure here should not block the login; we'll retry next time.
+        Verification::Valid { needs_rehash: true } => {
+            let upgraded = match hash_password(passwords, payload.password).await {
+                Ok(hash) => storage.update_user_password(&credentials.id.to_string(), &hash).await,
+                Err(_) => Err("hashing failed".into()),
+            };
+            if let Err(e) = upgraded {
+                eprintln!("❌ Failed to rehash password for {}: {}", payload.username, e);
+            }
+        }
+        Verification::Valid { needs_rehash: false } => {}
     }
 
-    let response_body = response.text()
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let pair = tokens.issue(credentials.id, &payload.username).map_err(|e| {
+        eprintln!("❌ Failed to issue tokens: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
 
-    let json_response: Vec<Value> = serde_json::from_str(&response_body)
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(Json(LoginResponse {
+        message: format!("✅ Login successful: {}", payload.username),
+        user_id: credentials.id,
+        tokens: pair,
+    }))
+}
 
-    // Extract document from response
-    let document = json_response.get(0)
-        .and_then(|doc| doc.get("document"))
-        .ok_or(StatusCode::NOT_FOUND)?;
+async fn refresh_handler(tokens: Arc<TokenService>, Json(payload): Json<RefreshPayload>) -> Result<Json<TokenPair>, StatusCode> {
+    tokens.refresh(&payload.refresh_token).map(Json).ok_or(StatusCode::UNAUTHORIZED)
+}
 
-    let fields = document.get("fields")
-        .ok_or(StatusCode::INTERNAL_SERVER_ERROR)?;
+// Logging out revokes the refresh token; the access token lapses on its own
+// within a few minutes.
+async fn logout_handler(tokens: Arc<TokenService>, Json(payload): Json<RefreshPayload>) -> Json<AuthResponse> {
+    if let Some(claims) = tokens.verify(&payload.refresh_token, TokenKind::Refresh) {
+        tokens.revoke(&claims);
+    }
+    Json(AuthResponse { message: "Logged out".to_string() })
+}
 
-    let stored_password = fields.get("password")
-        .and_then(|val| val.get("stringValue"))
-        .and_then(|val| val.as_str())
-        .unwrap_or("");
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::Method;
+    use serde_json::json;
 
-    if stored_password != payload.password {
-        return Err(StatusCode::UNAUTHORIZED);
+    use crate::test_support::{app::TestApp, http::send};
+    use argon2::Params;
+
+    fn passwords() -> Arc<PasswordHasher> {
+        Arc::new(PasswordHasher::new(Params::new(1024, 1, 1, None).unwrap()))
     }
 
-    Ok(Json(AuthResponse {
-        message: format!("✅ Login successful: {}", payload.username),
-    }))
+    fn routes(app: &TestApp) -> Router {
+        auth_routes(Arc::clone(&app.storage), passwords(), Arc::clone(&app.tokens))
+    }
+
+    async fn register(router: &Router) {
+        let payload = json!({
+            "email": "ada@example.com",
+            "password": "correct horse",
+            "display_name": "Ada",
+            "username": "ada",
+        });
+        let (status, body) = send(router, Method::POST, "/register", Some(payload)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["message"], "User registered: ada@example.com");
+    }
+
+    #[tokio::test]
+    async fn registered_user_can_log_in() {
+        let router = routes(&TestApp::new().await);
+        register(&router).await;
+
+        let login = json!({ "username": "ada", "password": "correct horse" });
+        let (status, body) = send(&router, Method::POST, "/login", Some(login)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["message"], "✅ Login successful: ada");
+        assert_eq!(body["token_type"], "Bearer");
+        assert!(body["access_token"].is_string() && body["refresh_token"].is_string());
+    }
+
+    #[tokio::test]
+    async fn refresh_rotates_tokens_and_logout_revokes_them() {
+        let router = routes(&TestApp::new().await);
+        register(&router).await;
+
+        let login = json!({ "username": "ada", "password": "correct horse" });
+        let (_, body) = send(&router, Method::POST, "/login", Some(login)).await;
+        let first = json!({ "refresh_token": body["refresh_token"] });
+
+        let (status, body) = send(&router, Method::POST, "/refresh", Some(first.clone())).await;
+        assert_eq!(status, StatusCode::OK);
+        let second = json!({ "refresh_token": body["refresh_token"] });
+
+        let (status, _) = send(&router, Method::POST, "/refresh", Some(first)).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED, "refresh tokens are single use");
+
+        let (status, _) = send(&router, Method::POST, "/logout", Some(second.clone())).await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, _) = send(&router, Method::POST, "/refresh", Some(second)).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn wrong_password_is_unauthorized() {
+        let router = routes(&TestApp::new().await);
+        register(&router).await;
+
+        let login = json!({ "username": "ada", "password": "wrong" });
+        let (status, _) = send(&router, Method::POST, "/login", Some(login)).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn unknown_user_is_not_found() {
+        let router = routes(&TestApp::new().await);
+
+        let login = json!({ "username": "nobody", "password": "x" });
+        let (status, _) = send(&router, Method::POST, "/login", Some(login)).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn passwords_are_stored_hashed() {
+        let app = TestApp::new().await;
+        let router = routes(&app);
+        register(&router).await;
+
+        let stored = app.storage.get_user_credentials("ada").await.unwrap().unwrap().password;
+        assert!(stored.starts_with("$argon2id$"), "{}", stored);
+    }
+
+    #[tokio::test]
+    async fn plaintext_password_is_upgraded_on_login() {
+        let app = TestApp::new().await;
+        let router = routes(&app);
+        let details = UserDetails {
+            id: Uuid::new_v4(),
+            display_name: "Grace".into(),
+            username: "grace".into(),
+            email: "grace@example.com".into(),
+            projects: vec![],
+        };
+        app.storage.create_user(&details, "cobol").await.unwrap();
+
+        let wrong = json!({ "username": "grace", "password": "fortran" });
+        let (status, _) = send(&router, Method::POST, "/login", Some(wrong)).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(app.storage.get_user_credentials("grace").await.unwrap().unwrap().password, "cobol");
+
+        let login = json!({ "username": "grace", "password": "cobol" });
+        let (status, _) = send(&router, Method::POST, "/login", Some(login.clone())).await;
+        assert_eq!(status, StatusCode::OK);
+        let stored = app.storage.get_user_credentials("grace").await.unwrap().unwrap().password;
+        assert!(stored.starts_with("$argon2id$"), "{}", stored);
+
+        let (status, _) = send(&router, Method::POST, "/login", Some(login)).await;
+        assert_eq!(status, StatusCode::OK);
+    }
 }
@@ -1,53 +1,114 @@
 // src/routers/project_router.rs
 use axum::{
   extract::Path,
+  http::{Method, StatusCode},
+  middleware,
   routing::{delete, get, post, put},
-  Json, Router,
+  Extension, Json, Router,
 };
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::sync::Arc;
 use uuid::Uuid;
 
-use crate::connection::firebase::FirebaseService;
-use crate::model::project::Project;
+use crate::connection::session::AuthUser;
+use crate::connection::storage::Storage;
+use crate::model::project::{MemberChange, MemberUpdate, Permission, Project, Role};
+use crate::routers::access::{by_method, require_access, ProjectAccess};
+use crate::routers::invite::project_invite_routes;
 
-pub fn project_routes(firebase: Arc<FirebaseService>) -> Router {
-    Router::new()
-        .route(
-            "/",
-            post({
-                let firebase = Arc::clone(&firebase);
-                move |payload| create_project_handler(firebase, payload)
-            }),
-        )
+pub fn project_routes(storage: Arc<dyn Storage>) -> Router {
+    let project = Router::new()
         .route(
             "/{project_id}",  // Changed from :project_id
             get({
-                let firebase = Arc::clone(&firebase);
-                move |path| get_project_handler(firebase, path)
+                let storage = Arc::clone(&storage);
+                move |path| get_project_handler(storage, path)
             }),
         )
         .route(
             "/{project_id}",  // Changed from :project_id
             put({
-                let firebase = Arc::clone(&firebase);
-                move |path, payload| update_project_handler(firebase, path, payload)
+                let storage = Arc::clone(&storage);
+                move |path, payload| update_project_handler(storage, path, payload)
             }),
         )
         .route(
             "/{project_id}",  // Changed from :project_id
             delete({
-                let firebase = Arc::clone(&firebase);
-                move |path| delete_project_handler(firebase, path)
+                let storage = Arc::clone(&storage);
+                move |path| delete_project_handler(storage, path)
             }),
         )
+        .route_layer(middleware::from_fn({
+            let storage = Arc::clone(&storage);
+            move |user, path, request, next| require_access(Arc::clone(&storage), project_permission, user, path, request, next)
+        }));
+
+    let members = Router::new()
+        .route("/{project_id}/members", get(list_members_handler))
+        .route(
+            "/{project_id}/members/{user_id}",
+            put({
+                let storage = Arc::clone(&storage);
+                move |access, path, payload| set_member_role_handler(storage, access, path, payload)
+            })
+            .delete({
+                let storage = Arc::clone(&storage);
+                move |access, path| remove_member_handler(storage, access, path)
+            }),
+        )
+        .route_layer(middleware::from_fn({
+            let storage = Arc::clone(&storage);
+            move |user, path, request, next| require_access(Arc::clone(&storage), members_permission, user, path, request, next)
+        }));
+
+    Router::new()
+        .route(
+            "/",
+            post({
+                let storage = Arc::clone(&storage);
+                move |user, payload| create_project_handler(storage, user, payload)
+            }),
+        )
+        .merge(project)
+        .merge(members)
+        .merge(project_invite_routes(storage))
+}
+
+// Editors may rename a project; only its owner may delete it.
+fn project_permission(method: &Method) -> Permission {
+    if method == Method::DELETE {
+        Permission::Manage
+    } else {
+        by_method(method)
+    }
+}
+
+// Any member may see who else is in the project; only the owner changes it.
+fn members_permission(method: &Method) -> Permission {
+    if method == Method::GET {
+        Permission::Read
+    } else {
+        Permission::Manage
+    }
 }
 
 #[derive(Debug, Deserialize)]
 pub struct CreateProjectRequest {
   pub name: String,
   pub description: String,
-  pub owner: Uuid,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MemberRoleRequest {
+  pub role: Role,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Member {
+  user_id: Uuid,
+  role: Role,
 }
 
 #[derive(Debug, Serialize)]
@@ -60,166 +121,75 @@ pub struct ProjectResponse {
 
 // Create Project Handler
 async fn create_project_handler(
-  firebase: Arc<FirebaseService>,
+  storage: Arc<dyn Storage>,
+  user: AuthUser,
   Json(payload): Json<CreateProjectRequest>,
 ) -> Json<ProjectResponse> {
-  let project_id = Uuid::new_v4();
+  println!("🚀 {} is creating project {}", user.username, payload.name);
+
   let project = Project {
-      id: project_id,
+      id: Uuid::new_v4(),
       name: payload.name,
       description: payload.description,
-      owner: payload.owner,
-      members: vec![payload.owner], // Include owner as initial member
+      owner: user.user_id,
+      members: vec![user.user_id], // Include owner as initial member
+      roles: HashMap::from([(user.user_id, Role::Owner)]),
       files: vec![],
   };
 
-  let project_id_str = project_id.to_string();
-  let url = format!(
-      "https://firestore.googleapis.com/v1/projects/dcode-7b1a0/databases/(default)/documents/projects?documentId={}",
-      project_id_str
-  );
-
-  let body = serde_json::json!({
-      "fields": {
-          "id": { "stringValue": project_id_str },
-          "name": { "stringValue": project.name },
-          "description": { "stringValue": project.description },
-          "owner": { "stringValue": project.owner.to_string() },
-          "members": {
-              "arrayValue": {
-                  "values": project.members.iter().map(|m| {
-                      serde_json::json!({ "stringValue": m.to_string() })
-                  }).collect::<Vec<_>>()
-              }
-          },
-          "files": {
-              "arrayValue": {
-                  "values": project.files.iter().map(|f| {
-                      serde_json::json!({ "stringValue": f.to_string() })
-                  }).collect::<Vec<_>>()
-              }
-          }
-      }
-  });
-
-  let response = firebase
-      .client
-      .post(&url)
-      .bearer_auth(&firebase.access_token)
-      .json(&body)
-      .send()
-      .await
-      .expect("Failed to create project");
-
-  let status = response.status();
-  let response_body = response.text().await.unwrap_or_default();
-
-  Json(ProjectResponse {
-      success: status.is_success(),
-      message: if status.is_success() {
-          "Project created successfully".to_string()
-      } else {
-          format!("Failed to create project: {}", response_body)
-      },
-      project: Some(project),
-  })
+  match storage.create_project(&project).await {
+      Ok(()) => Json(ProjectResponse {
+          success: true,
+          message: "Project created successfully".to_string(),
+          project: Some(project),
+      }),
+      Err(e) => Json(ProjectResponse {
+          success: false,
+          message: format!("Failed to create project: {}", e),
+          project: None,
+      }),
+  }
 }
 
 // Get Project Handler
 async fn get_project_handler(
-  firebase: Arc<FirebaseService>,
+  storage: Arc<dyn Storage>,
   Path(project_id): Path<Uuid>,
 ) -> Json<ProjectResponse> {
-  let url = format!(
-      "https://firestore.googleapis.com/v1/projects/dcode-7b1a0/databases/(default)/documents/projects/{}",
-      project_id
-  );
-
-  let response = firebase
-      .client
-      .get(&url)
-      .bearer_auth(&firebase.access_token)
-      .send()
-      .await
-      .expect("Failed to fetch project");
-
-  let status = response.status();
-  let response_body = response.text().await.unwrap_or_default();
-
-  if status.is_success() {
-      let document: serde_json::Value = serde_json::from_str(&response_body).unwrap();
-      let fields = document["fields"].as_object().unwrap();
-
-      let project = Project {
-          id: project_id,
-          name: fields["name"]["stringValue"].as_str().unwrap().to_string(),
-          description: fields["description"]["stringValue"].as_str().unwrap().to_string(),
-          owner: Uuid::parse_str(fields["owner"]["stringValue"].as_str().unwrap()).unwrap(),
-          members: fields["members"]["arrayValue"]["values"]
-              .as_array()
-              .unwrap()
-              .iter()
-              .filter_map(|v| Uuid::parse_str(v["stringValue"].as_str().unwrap()).ok())
-              .collect(),
-          files: fields["files"]["arrayValue"]["values"]
-              .as_array()
-              .unwrap()
-              .iter()
-              .filter_map(|v| Uuid::parse_str(v["stringValue"].as_str().unwrap()).ok())
-              .collect(),
-      };
-
-      Json(ProjectResponse {
+  match storage.get_project(&project_id.to_string()).await {
+      Ok(Some(project)) => Json(ProjectResponse {
           success: true,
           message: "Project retrieved successfully".to_string(),
           project: Some(project),
-      })
-  } else {
-      Json(ProjectResponse {
+      }),
+      Ok(None) => Json(ProjectResponse {
           success: false,
-          message: format!("Failed to fetch project: {}", response_body),
+          message: "Failed to fetch project: not found".to_string(),
           project: None,
-      })
+      }),
+      Err(e) => Json(ProjectResponse {
+          success: false,
+          message: format!("Failed to fetch project: {}", e),
+          project: None,
+      }),
   }
 }
 
 // Update Project Handler
 async fn update_project_handler(
-  firebase: Arc<FirebaseService>,
+  storage: Arc<dyn Storage>,
   Path(project_id): Path<Uuid>,
   Json(payload): Json<CreateProjectRequest>,
 ) -> Json<ProjectResponse> {
-  let url = format!(
-      "https://firestore.googleapis.com/v1/projects/dcode-7b1a0/databases/(default)/documents/projects/{}",
-      project_id
-  );
-
-  let body = serde_json::json!({
-      "fields": {
-          "name": { "stringValue": payload.name },
-          "description": { "stringValue": payload.description },
-          "owner": { "stringValue": payload.owner.to_string() }
-      }
-  });
-
-  let response = firebase
-      .client
-      .patch(&url)
-      .bearer_auth(&firebase.access_token)
-      .json(&body)
-      .send()
-      .await
-      .expect("Failed to update project");
-
-  let status = response.status();
-  let response_body = response.text().await.unwrap_or_default();
+  let result = storage
+      .update_project_metadata(&project_id.to_string(), &payload.name, &payload.description)
+      .await;
 
   Json(ProjectResponse {
-      success: status.is_success(),
-      message: if status.is_success() {
-          "Project updated successfully".to_string()
-      } else {
-          format!("Failed to update project: {}", response_body)
+      success: result.is_ok(),
+      message: match result {
+          Ok(()) => "Project updated successfully".to_string(),
+          Err(e) => format!("Failed to update project: {}", e),
       },
       project: None,
   })
@@ -227,32 +197,184 @@ async fn update_project_handler(
 
 // Delete Project Handler
 async fn delete_project_handler(
-  firebase: Arc<FirebaseService>,
+  storage: Arc<dyn Storage>,
   Path(project_id): Path<Uuid>,
 ) -> Json<ProjectResponse> {
-  let url = format!(
-      "https://firestore.googleapis.com/v1/projects/dcode-7b1a0/databases/(default)/documents/projects/{}",
-      project_id
-  );
-
-  let response = firebase
-      .client
-      .delete(&url)
-      .bearer_auth(&firebase.access_token)
-      .send()
-      .await
-      .expect("Failed to delete project");
-
-  let status = response.status();
-  let response_body = response.text().await.unwrap_or_default();
+  let result = storage.delete_project(&project_id.to_string()).await;
 
   Json(ProjectResponse {
-      success: status.is_success(),
-      message: if status.is_success() {
-          "Project deleted successfully".to_string()
-      } else {
-          format!("Failed to delete project: {}", response_body)
+      success: matches!(result, Ok(true)),
+      message: match result {
+          Ok(true) => "Project deleted successfully".to_string(),
+          Ok(false) => "Failed to delete project: not found".to_string(),
+          Err(e) => format!("Failed to delete project: {}", e),
       },
       project: None,
   })
-}
\ No newline at end of file
+}
+
+// List Members Handler
+async fn list_members_handler(Extension(access): Extension<ProjectAccess>) -> Json<Vec<Member>> {
+  let project = access.project;
+  let mut members = vec![Member { user_id: project.owner, role: Role::Owner }];
+  members.extend(
+      project
+          .members
+          .iter()
+          .filter(|id| **id != project.owner)
+          .filter_map(|id| project.role_of(*id).map(|role| Member { user_id: *id, role })),
+  );
+  Json(members)
+}
+
+fn member_error(status: StatusCode, message: &str) -> (StatusCode, Json<ProjectResponse>) {
+  (status, Json(ProjectResponse { success: false, message: message.to_string(), project: None }))
+}
+
+async fn change_member(storage: &dyn Storage, project_id: Uuid, user_id: Uuid, change: MemberChange, message: &str) -> (StatusCode, Json<ProjectResponse>) {
+  match storage.update_member(&project_id.to_string(), user_id, change).await {
+      Ok(MemberUpdate::Updated(project)) => (
+          StatusCode::OK,
+          Json(ProjectResponse { success: true, message: message.to_string(), project: Some(project) }),
+      ),
+      Ok(MemberUpdate::NotMember) => member_error(StatusCode::NOT_FOUND, "User is not a member of this project"),
+      Ok(MemberUpdate::ProjectNotFound) => member_error(StatusCode::NOT_FOUND, "Project not found"),
+      Err(e) => member_error(StatusCode::INTERNAL_SERVER_ERROR, &format!("Failed to update members: {}", e)),
+  }
+}
+
+// Set Member Role Handler
+async fn set_member_role_handler(
+  storage: Arc<dyn Storage>,
+  Extension(access): Extension<ProjectAccess>,
+  Path((_, user_id)): Path<(Uuid, Uuid)>,
+  Json(payload): Json<MemberRoleRequest>,
+) -> (StatusCode, Json<ProjectResponse>) {
+  let project = access.project;
+
+  if payload.role == Role::Owner || user_id == project.owner {
+      return member_error(StatusCode::BAD_REQUEST, "Project ownership cannot be changed");
+  }
+
+  change_member(storage.as_ref(), project.id, user_id, MemberChange::SetRole(payload.role), "Member role updated").await
+}
+
+// Remove Member Handler
+async fn remove_member_handler(
+  storage: Arc<dyn Storage>,
+  Extension(access): Extension<ProjectAccess>,
+  Path((_, user_id)): Path<(Uuid, Uuid)>,
+) -> (StatusCode, Json<ProjectResponse>) {
+  let project = access.project;
+
+  if user_id == project.owner {
+      return member_error(StatusCode::BAD_REQUEST, "The owner cannot be removed from a project");
+  }
+
+  change_member(storage.as_ref(), project.id, user_id, MemberChange::Remove, "Member removed").await
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use axum::http::{Method, StatusCode};
+  use serde_json::json;
+
+  use crate::test_support::{app::TestApp, http::{send, send_as}};
+
+  #[tokio::test]
+  async fn project_lifecycle() {
+      let app = TestApp::new().await;
+      let router = app.serve(project_routes(Arc::clone(&app.storage)));
+      let owner = Uuid::new_v4();
+      let token = app.token(owner);
+      let token = Some(token.as_str());
+
+      // The owner comes from the token, not the body.
+      let create = json!({ "name": "demo", "description": "first", "owner": Uuid::new_v4() });
+      let (status, body) = send_as(&router, token, Method::POST, "/", Some(create)).await;
+      assert_eq!(status, StatusCode::OK);
+      assert_eq!(body["success"], true);
+      assert_eq!(body["project"]["owner"], json!(owner));
+      assert_eq!(body["project"]["members"], json!([owner]));
+      let project_id = body["project"]["id"].as_str().unwrap().to_string();
+      let uri = format!("/{}", project_id);
+
+      let update = json!({ "name": "renamed", "description": "second" });
+      let (_, body) = send_as(&router, token, Method::PUT, &uri, Some(update)).await;
+      assert_eq!(body["success"], true);
+
+      let (_, body) = send_as(&router, token, Method::GET, &uri, None).await;
+      assert_eq!(body["success"], true);
+      assert_eq!(body["project"]["name"], "renamed");
+      assert_eq!(body["project"]["description"], "second");
+      assert_eq!(body["project"]["members"], json!([owner]), "metadata updates keep members");
+
+      let (_, body) = send_as(&router, token, Method::DELETE, &uri, None).await;
+      assert_eq!(body["success"], true);
+
+      let (status, body) = send_as(&router, token, Method::GET, &uri, None).await;
+      assert_eq!(status, StatusCode::NOT_FOUND);
+      assert_eq!(body["error"], "Project not found");
+  }
+
+  #[tokio::test]
+  async fn requests_without_a_valid_token_are_rejected() {
+      let app = TestApp::new().await;
+      let router = app.serve(project_routes(Arc::clone(&app.storage)));
+      let create = json!({ "name": "demo", "description": "first" });
+
+      let (status, body) = send(&router, Method::POST, "/", Some(create.clone())).await;
+      assert_eq!(status, StatusCode::UNAUTHORIZED);
+      assert_eq!(body["error"], "Missing access token");
+
+      let (status, _) = send_as(&router, Some("not-a-token"), Method::POST, "/", Some(create)).await;
+      assert_eq!(status, StatusCode::UNAUTHORIZED);
+  }
+
+  #[tokio::test]
+  async fn roles_limit_what_members_can_do() {
+      let app = TestApp::new().await;
+      let router = app.serve(project_routes(Arc::clone(&app.storage)));
+
+      let (owner, editor, viewer) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+      let project_id = app.project(owner, &[(editor, Role::Editor), (viewer, Role::Viewer)]).await;
+      let uri = format!("/{}", project_id);
+      let [owner_token, editor_token, viewer_token, outsider_token] = [owner, editor, viewer, Uuid::new_v4()].map(|id| app.token(id));
+      let update = json!({ "name": "renamed", "description": "" });
+
+      let (status, _) = send_as(&router, Some(&viewer_token), Method::GET, &uri, None).await;
+      assert_eq!(status, StatusCode::OK);
+      let (status, body) = send_as(&router, Some(&outsider_token), Method::GET, &uri, None).await;
+      assert_eq!(status, StatusCode::FORBIDDEN);
+      assert_eq!(body["error"], "You are not a member of this project");
+
+      let (status, _) = send_as(&router, Some(&viewer_token), Method::PUT, &uri, Some(update.clone())).await;
+      assert_eq!(status, StatusCode::FORBIDDEN);
+      let (status, _) = send_as(&router, Some(&editor_token), Method::PUT, &uri, Some(update)).await;
+      assert_eq!(status, StatusCode::OK);
+      let (status, _) = send_as(&router, Some(&editor_token), Method::DELETE, &uri, None).await;
+      assert_eq!(status, StatusCode::FORBIDDEN);
+
+      // Only the owner manages members.
+      let member_uri = format!("/{}/members/{}", project_id, viewer);
+      let promote = json!({ "role": "editor" });
+      let (status, _) = send_as(&router, Some(&editor_token), Method::PUT, &member_uri, Some(promote.clone())).await;
+      assert_eq!(status, StatusCode::FORBIDDEN);
+      let (status, _) = send_as(&router, Some(&owner_token), Method::PUT, &member_uri, Some(promote)).await;
+      assert_eq!(status, StatusCode::OK);
+      let (status, _) = send_as(&router, Some(&viewer_token), Method::PUT, &uri, Some(json!({ "name": "x", "description": "" }))).await;
+      assert_eq!(status, StatusCode::OK, "promoted viewer can now write");
+
+      let owner_uri = format!("/{}/members/{}", project_id, owner);
+      let (status, _) = send_as(&router, Some(&owner_token), Method::DELETE, &owner_uri, None).await;
+      assert_eq!(status, StatusCode::BAD_REQUEST);
+      let (status, _) = send_as(&router, Some(&owner_token), Method::DELETE, &member_uri, None).await;
+      assert_eq!(status, StatusCode::OK);
+      let (status, _) = send_as(&router, Some(&viewer_token), Method::GET, &uri, None).await;
+      assert_eq!(status, StatusCode::FORBIDDEN, "removed members lose access");
+
+      let (_, body) = send_as(&router, Some(&owner_token), Method::GET, &format!("/{}/members", project_id), None).await;
+      assert_eq!(body, json!([{ "user_id": owner, "role": "owner" }, { "user_id": editor, "role": "editor" }]));
+  }
+}
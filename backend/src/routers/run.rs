@@ -0,0 +1,413 @@
+use axum::{
+    extract::ws::{Message, WebSocket, WebSocketUpgrade},
+    response::IntoResponse,
+    routing::get,
+    Router,
+};
+use futures_util::{SinkExt, Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::mpsc;
+
+use crate::connection::session::AuthUser;
+use crate::executor::diagnostics::Format;
+use crate::executor::runner::{Executor, Stage};
+use crate::executor::sandbox::{read_from, Process};
+use crate::executor::toolchain::Toolchain;
+
+/// Input a session accepts before it stops listening, so a client cannot
+/// queue unbounded data for a program that never reads it.
+const MAX_STDIN_BYTES: usize = 1024 * 1024;
+
+/// Messages from the client, framed as `{ "event": ..., "data": ... }` like the
+/// collaboration socket.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "event", content = "data", rename_all = "kebab-case")]
+pub enum RunClientEvent {
+    /// Compiles and starts the program; only the first one counts.
+    Start { language: String, code: String },
+    Stdin(String),
+    /// Closes the program's standard input.
+    Eof,
+    Kill,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", content = "data", rename_all = "kebab-case")]
+pub enum RunServerEvent {
+    Started { stage: Stage },
+    Stdout(String),
+    Stderr(String),
+    /// Always the last frame of a session.
+    Exit(RunExit),
+    Error(String),
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RunExit {
+    /// `compile` when the build failed and the program never ran.
+    pub stage: Stage,
+    pub exit_code: i32,
+    pub signal: Option<i32>,
+    /// The client asked for the program to be stopped.
+    pub killed: bool,
+    pub timed_out: bool,
+    pub output_truncated: bool,
+    pub wall_time_ms: u64,
+    pub cpu_time_ms: u64,
+    pub max_memory_kb: u64,
+}
+
+/// Interactive runs over a WebSocket, nested under `/ws`.
+pub fn run_routes(executor: Arc<Executor>) -> Router {
+    Router::new().route("/run", get(move |user, ws| run_handler(executor, user, ws)))
+}
+
+async fn run_handler(executor: Arc<Executor>, _user: AuthUser, ws: WebSocketUpgrade) -> impl IntoResponse {
+    ws.on_upgrade(move |socket| handle_socket(executor, socket))
+}
+
+async fn handle_socket(executor: Arc<Executor>, socket: WebSocket) {
+    let (mut sender, mut receiver) = socket.split();
+
+    let (language, code) = loop {
+        match next_event(&mut receiver).await {
+            Some(RunClientEvent::Start { language, code }) => break (language, code),
+            Some(_) => {
+                if send_event(&mut sender, &RunServerEvent::Error("Send a start message first".into())).await.is_err() {
+                    return;
+                }
+            }
+            None => return,
+        }
+    };
+    let Some(toolchain) = Toolchain::find(&language) else {
+        let _ = send_event(&mut sender, &RunServerEvent::Error(format!("Unsupported language: {}", language))).await;
+        return;
+    };
+
+    if let Err(e) = run_session(&executor, toolchain, &code, &mut sender, &mut receiver).await {
+        eprintln!("❌ Run session failed: {}", e);
+        let _ = send_event(&mut sender, &RunServerEvent::Error(e.to_string())).await;
+    }
+}
+
+async fn run_session<S, R>(
+    executor: &Executor,
+    toolchain: &Toolchain,
+    code: &str,
+    sender: &mut S,
+    receiver: &mut R,
+) -> Result<(), Box<dyn Error + Send + Sync>>
+where
+    S: SinkExt<Message, Error = axum::Error> + Unpin,
+    R: Stream<Item = Result<Message, axum::Error>> + Unpin,
+{
+    let _permit = executor.permit().await?;
+    let workspace = executor.sandbox().workspace()?;
+    executor.sandbox().materialize(&workspace, &[(toolchain.source.to_string(), code.to_string())]).await?;
+    let plan = toolchain.single_file();
+
+    if let Some(command) = &plan.compile {
+        let limits = executor.compile_limits(toolchain);
+        let process = executor
+            .sandbox()
+            .spawn(command, &workspace, limits)
+            .map_err(|e| format!("Could not start {}: {}", command[0], e))?;
+        let Some(exit) = stream(Stage::Compile, process, limits.output_bytes, toolchain.diagnostics, sender, receiver).await? else {
+            return Ok(());
+        };
+        if exit.exit_code != 0 || exit.killed {
+            send_event(sender, &RunServerEvent::Exit(exit)).await?;
+            return Ok(());
+        }
+    }
+
+    let limits = executor.session_limits(toolchain);
+    let process = executor
+        .sandbox()
+        .spawn(&plan.run, &workspace, limits)
+        .map_err(|e| format!("Could not start {}: {}", plan.run[0], e))?;
+    if let Some(exit) = stream(Stage::Run, process, limits.output_bytes, None, sender, receiver).await? {
+        send_event(sender, &RunServerEvent::Exit(exit)).await?;
+    }
+    Ok(())
+}
+
+/// Relays one process's output to the client as it arrives and the client's
+/// input and kill requests to the process. Input is only passed on while
+/// running the program itself. Output in a compiler `format` is passed on a
+/// line at a time, as a person would read it. Returns `None` when the client
+/// went away.
+async fn stream<S, R>(
+    stage: Stage,
+    mut process: Process,
+    output_limit: usize,
+    format: Option<Format>,
+    sender: &mut S,
+    receiver: &mut R,
+) -> Result<Option<RunExit>, Box<dyn Error + Send + Sync>>
+where
+    S: SinkExt<Message, Error = axum::Error> + Unpin,
+    R: Stream<Item = Result<Message, axum::Error>> + Unpin,
+{
+    send_event(sender, &RunServerEvent::Started { stage }).await?;
+
+    let killer = process.killer();
+    let (mut stdout, mut stderr) = (process.stdout.take(), process.stderr.take());
+    let mut stdin = None;
+    if let (Stage::Run, Some(mut pipe)) = (stage, process.stdin.take()) {
+        let (tx, mut rx) = mpsc::unbounded_channel::<Vec<u8>>();
+        // Writes can block on a full pipe; keep them off the relay loop.
+        tokio::spawn(async move {
+            while let Some(chunk) = rx.recv().await {
+                if pipe.write_all(&chunk).await.is_err() {
+                    break;
+                }
+            }
+        });
+        stdin = Some(tx);
+    }
+
+    let mut waiter = tokio::spawn(process.wait());
+    let mut usage = None;
+    // Once the process is gone, stray descendants get a moment to flush before we stop reading.
+    let grace = tokio::time::sleep(Duration::MAX);
+    tokio::pin!(grace);
+
+    let (mut out_chunk, mut err_chunk) = ([0u8; 8192], [0u8; 8192]);
+    let (mut out_pending, mut err_pending) = (Vec::new(), Vec::new());
+    let (mut forwarded, mut stdin_bytes) = (0, 0);
+    let (mut killed, mut output_truncated) = (false, false);
+
+    while usage.is_none() || stdout.is_some() || stderr.is_some() {
+        let (read, from_stdout) = tokio::select! {
+            result = &mut waiter, if usage.is_none() => {
+                usage = Some(result??);
+                grace.as_mut().reset(tokio::time::Instant::now() + Duration::from_secs(1));
+                continue;
+            }
+            _ = &mut grace => break,
+            read = read_from(&mut stdout, &mut out_chunk) => {
+                let read = read?;
+                if read == 0 { stdout = None; }
+                (read, true)
+            }
+            read = read_from(&mut stderr, &mut err_chunk) => {
+                let read = read?;
+                if read == 0 { stderr = None; }
+                (read, false)
+            }
+            event = next_event(receiver) => {
+                match event {
+                    Some(RunClientEvent::Stdin(text)) => {
+                        stdin_bytes += text.len();
+                        match &stdin {
+                            Some(tx) if stdin_bytes <= MAX_STDIN_BYTES => {
+                                let _ = tx.send(text.into_bytes());
+                            }
+                            Some(_) => {
+                                stdin = None;
+                                send_event(sender, &RunServerEvent::Error("Too much input; standard input was closed".into())).await?;
+                            }
+                            None => {}
+                        }
+                    }
+                    Some(RunClientEvent::Eof) => stdin = None,
+                    Some(RunClientEvent::Kill) => {
+                        killed = true;
+                        killer.kill();
+                    }
+                    Some(RunClientEvent::Start { .. }) => {
+                        send_event(sender, &RunServerEvent::Error("A program is already running".into())).await?;
+                    }
+                    None => {
+                        killer.kill();
+                        let _ = waiter.await;
+                        return Ok(None);
+                    }
+                }
+                continue;
+            }
+        };
+
+        let room = output_limit.saturating_sub(forwarded);
+        if read > room && !output_truncated {
+            output_truncated = true;
+            killer.kill();
+        }
+        let kept = read.min(room);
+        forwarded += kept;
+        let (pending, chunk) = if from_stdout { (&mut out_pending, &out_chunk) } else { (&mut err_pending, &err_chunk) };
+        pending.extend_from_slice(&chunk[..kept]);
+        let mut text = decode(pending, read == 0);
+        if let Some(format) = format {
+            if read != 0 {
+                let partial = text.split_off(text.rfind('\n').map_or(0, |end| end + 1));
+                pending.splice(0..0, partial.into_bytes());
+            }
+            text = format.readable(&text).into_owned();
+        }
+        if !text.is_empty() {
+            let event = if from_stdout { RunServerEvent::Stdout(text) } else { RunServerEvent::Stderr(text) };
+            send_event(sender, &event).await?;
+        }
+    }
+
+    let usage = match usage {
+        Some(usage) => usage,
+        None => waiter.await??,
+    };
+    Ok(Some(RunExit {
+        stage,
+        exit_code: usage.exit_code,
+        signal: usage.signal,
+        killed,
+        timed_out: usage.timed_out,
+        output_truncated,
+        wall_time_ms: usage.wall_time_ms,
+        cpu_time_ms: usage.cpu_time_ms,
+        max_memory_kb: usage.max_memory_kb,
+    }))
+}
+
+/// Takes the decodable prefix of `pending`, leaving a character that was cut
+/// off between reads for the next chunk unless the stream has `ended`.
+fn decode(pending: &mut Vec<u8>, ended: bool) -> String {
+    let complete = match std::str::from_utf8(pending) {
+        Err(e) if e.error_len().is_none() && !ended => e.valid_up_to(),
+        _ => pending.len(),
+    };
+    let rest = pending.split_off(complete);
+    let text = String::from_utf8_lossy(pending).into_owned();
+    *pending = rest;
+    text
+}
+
+/// The next well-formed client event, or `None` once the socket closes.
+async fn next_event<R>(receiver: &mut R) -> Option<RunClientEvent>
+where
+    R: Stream<Item = Result<Message, axum::Error>> + Unpin,
+{
+    loop {
+        match receiver.next().await {
+            Some(Ok(Message::Text(text))) => match serde_json::from_str(&text) {
+                Ok(event) => return Some(event),
+                Err(e) => eprintln!("❌ Ignoring malformed run message: {}", e),
+            },
+            Some(Ok(Message::Close(_))) | Some(Err(_)) | None => return None,
+            Some(Ok(_)) => {}
+        }
+    }
+}
+
+async fn send_event<S>(sender: &mut S, event: &RunServerEvent) -> Result<(), axum::Error>
+where
+    S: SinkExt<Message, Error = axum::Error> + Unpin,
+{
+    let text = serde_json::to_string(event).expect("RunServerEvent is always serializable");
+    sender.send(Message::Text(text.into())).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+    use tokio_tungstenite::tungstenite::Message as WsMessage;
+    use uuid::Uuid;
+
+    use crate::executor::sandbox::{Isolation, Limits, Sandbox};
+    use crate::test_support::sandbox;
+    use crate::test_support::app::TestApp;
+
+    type Client = tokio_tungstenite::WebSocketStream<tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>>;
+
+    async fn connect() -> Client {
+        let limits = Limits {
+            cpu_seconds: 2,
+            memory_bytes: 256 * 1024 * 1024,
+            wall_clock: Duration::from_secs(5),
+            output_bytes: 64 * 1024,
+            processes: 64,
+            file_bytes: 1024 * 1024,
+        };
+        let executor = Executor::new(Sandbox::new(sandbox::helper(), Isolation::Namespaces), limits, 2).with_session_timeout(Duration::from_secs(20));
+        let app = TestApp::new().await;
+        let router = app.serve(Router::new().nest("/ws", run_routes(Arc::new(executor))));
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, router).await.unwrap() });
+
+        let url = format!("ws://{}/ws/run?access_token={}", addr, app.token(Uuid::new_v4()));
+        tokio_tungstenite::connect_async(url).await.expect("connect").0
+    }
+
+    async fn send(client: &mut Client, event: Value) {
+        client.send(WsMessage::Text(event.to_string().into())).await.unwrap();
+    }
+
+    async fn receive(client: &mut Client) -> Value {
+        loop {
+            let frame = tokio::time::timeout(Duration::from_secs(15), client.next()).await.expect("frame in time");
+            if let Some(Ok(WsMessage::Text(text))) = frame {
+                return serde_json::from_str(&text).unwrap();
+            }
+        }
+    }
+
+    /// Collects frames up to and including the exit frame.
+    async fn until_exit(client: &mut Client) -> (String, Value) {
+        let mut stdout = String::new();
+        loop {
+            let frame = receive(client).await;
+            match frame["event"].as_str().unwrap() {
+                "stdout" => stdout.push_str(frame["data"].as_str().unwrap()),
+                "exit" => return (stdout, frame["data"].clone()),
+                _ => {}
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn sessions_stream_output_and_take_input() {
+        let mut client = connect().await;
+        let code = "import sys\nprint('name?', flush=True)\nname = sys.stdin.readline().strip()\nprint('hi', name, file=sys.stderr, flush=True)\nprint(len(sys.stdin.read()))";
+        send(&mut client, json!({ "event": "start", "data": { "language": "python", "code": code } })).await;
+
+        assert_eq!(receive(&mut client).await, json!({ "event": "started", "data": { "stage": "run" } }));
+        assert_eq!(receive(&mut client).await, json!({ "event": "stdout", "data": "name?\n" }));
+        send(&mut client, json!({ "event": "stdin", "data": "ada\n" })).await;
+        assert_eq!(receive(&mut client).await, json!({ "event": "stderr", "data": "hi ada\n" }));
+
+        send(&mut client, json!({ "event": "stdin", "data": "abc" })).await;
+        send(&mut client, json!({ "event": "eof" })).await;
+        let (stdout, exit) = until_exit(&mut client).await;
+        assert_eq!(stdout, "3\n");
+        assert_eq!(exit["exitCode"], 0);
+        assert_eq!(exit["killed"], false);
+    }
+
+    #[tokio::test]
+    async fn kill_stops_the_program_and_compile_errors_end_the_session() {
+        let mut client = connect().await;
+        let code = "import time\nprint('ready', flush=True)\ntime.sleep(60)";
+        send(&mut client, json!({ "event": "start", "data": { "language": "python", "code": code } })).await;
+        let (_, _) = (receive(&mut client).await, receive(&mut client).await);
+        send(&mut client, json!({ "event": "kill" })).await;
+        let (_, exit) = until_exit(&mut client).await;
+        assert_eq!(exit["killed"], true);
+        assert_eq!(exit["signal"], libc::SIGKILL);
+
+        let mut client = connect().await;
+        send(&mut client, json!({ "event": "start", "data": { "language": "c", "code": "int main( {" } })).await;
+        assert_eq!(receive(&mut client).await["data"]["stage"], "compile");
+        let (_, exit) = until_exit(&mut client).await;
+        assert_eq!(exit["stage"], "compile");
+        assert_ne!(exit["exitCode"], 0);
+    }
+}
@@ -2,159 +2,153 @@ use axum::{
     extract::{Path, Json},
     http::StatusCode,
     response::IntoResponse,
-    routing::{get, post},
+    routing::get,
     Router,
 };
-use serde::{Deserialize, Serialize};
+use serde::Deserialize;
+use serde_json::json;
 use std::sync::Arc;
-use uuid::Uuid;
-use crate::Connections::firebase::FirebaseService;
-use crate::model::user::{UserCred, UserDetails};
+
+use crate::connection::session::AuthUser;
+use crate::connection::storage::Storage;
+use crate::model::user::PublicProfile;
 
 #[derive(Debug, Deserialize)]
-pub struct CreateUserRequest {
+pub struct UpdateProfileRequest {
     pub display_name: String,
     pub username: String,
     pub email: String,
-    pub password: String,
-}
-
-#[derive(Debug, Serialize)]
-pub struct UserResponse {
-    pub message: String,
-    pub user_id: Option<Uuid>,
-}
-
-#[derive(Debug, Serialize)]
-pub struct LoginResponse {
-    pub authenticated: bool,
-    pub user_details: Option<UserDetails>,
 }
 
-pub fn user_routes(firebase: Arc<FirebaseService>) -> Router {
+/// User lookups and profile edits, nested under `/users`. Accounts are
+/// created through `/auth/register`. Only `/me` shows the caller's email
+/// and projects; lookups of others return their public profile.
+pub fn user_routes(storage: Arc<dyn Storage>) -> Router {
     Router::new()
-        .route("/users/create", post(move |payload| create_user_handler(firebase.clone(), payload)))
-        .route("/users/id/:id", get(move |Path(id)| get_user_by_id_handler(firebase.clone(), id)))
-        .route("/users/username/:username", get(move |Path(username)| get_user_by_username_handler(firebase.clone(), username)))
-        .route("/users/login", post(move |payload| login_user_handler(firebase.clone(), payload)))
-}
-
-async fn create_user_handler(
-    firebase: Arc<FirebaseService>,
-    Json(payload): Json<CreateUserRequest>,
-) -> impl IntoResponse {
-    // Check if the user already exists by email
-    if let Ok(Some(_)) = firebase.get_user_by_email(&payload.email).await {
-        return (
-            StatusCode::BAD_REQUEST,
-            Json(UserResponse {
-                message: "User with this email already exists".into(),
-                user_id: None,
-            }),
-        ).into_response();
-    }
-
-    // If user does not exist, create the user
-    match firebase.create_user(
-        payload.display_name,
-        payload.username,
-        payload.email,
-        payload.password
-    ).await {
-        Ok(user_id) => (
-            StatusCode::CREATED,
-            Json(UserResponse {
-                message: "User created successfully".into(),
-                user_id: Some(user_id),
+        .route(
+            "/me",
+            get({
+                let storage = Arc::clone(&storage);
+                move |user| me_handler(storage, user)
+            })
+            .put({
+                let storage = Arc::clone(&storage);
+                move |user, payload| update_profile_handler(storage, user, payload)
             }),
-        ),
-        Err(e) => (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(UserResponse {
-                message: e.to_string(),
-                user_id: None,
+        )
+        .route(
+            "/id/{id}",
+            get({
+                let storage = Arc::clone(&storage);
+                move |user, path| get_user_by_id_handler(storage, user, path)
             }),
-        ),
-    }
+        )
+        .route(
+            "/username/{username}",
+            get(move |user, path| get_user_by_username_handler(storage, user, path)),
+        )
 }
 
-async fn get_user_by_id_handler(firebase: Arc<FirebaseService>, id: String) -> impl IntoResponse {
-    // We need to implement a get_user_by_id method in the FirebaseService
-    match firebase.get_user_by_id(&id).await {
-        Ok(Some(user)) => (StatusCode::OK, Json(user)).into_response(),
+async fn me_handler(storage: Arc<dyn Storage>, user: AuthUser) -> impl IntoResponse {
+    match storage.get_user_by_id(&user.user_id.to_string()).await {
+        Ok(Some(details)) => (StatusCode::OK, Json(details)).into_response(),
         Ok(None) => (StatusCode::NOT_FOUND, Json(json!({"error": "User not found"}))).into_response(),
         Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({"error": e.to_string()}))).into_response(),
     }
 }
 
-async fn get_user_by_username_handler(firebase: Arc<FirebaseService>, username: String) -> impl IntoResponse {
-    // We need to implement a get_user_by_username method in the FirebaseService
-    match firebase.get_user_by_username(&username).await {
-        Ok(Some(user)) => (StatusCode::OK, Json(user)).into_response(),
+async fn get_user_by_id_handler(storage: Arc<dyn Storage>, _user: AuthUser, Path(id): Path<String>) -> impl IntoResponse {
+    match storage.get_user_by_id(&id).await {
+        Ok(Some(user)) => (StatusCode::OK, Json(PublicProfile::from(user))).into_response(),
         Ok(None) => (StatusCode::NOT_FOUND, Json(json!({"error": "User not found"}))).into_response(),
         Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({"error": e.to_string()}))).into_response(),
     }
 }
 
-#[derive(Debug, Deserialize)]
-pub struct LoginRequest {
-    pub email: String,
-    pub password: String,
+async fn get_user_by_username_handler(storage: Arc<dyn Storage>, _user: AuthUser, Path(username): Path<String>) -> impl IntoResponse {
+    match storage.get_user_by_username(&username).await {
+        Ok(Some(user)) => (StatusCode::OK, Json(PublicProfile::from(user))).into_response(),
+        Ok(None) => (StatusCode::NOT_FOUND, Json(json!({"error": "User not found"}))).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({"error": e.to_string()}))).into_response(),
+    }
 }
 
-async fn login_user_handler(
-    firebase: Arc<FirebaseService>,
-    Json(payload): Json<LoginRequest>,
+async fn update_profile_handler(
+    storage: Arc<dyn Storage>,
+    user: AuthUser,
+    Json(payload): Json<UpdateProfileRequest>,
 ) -> impl IntoResponse {
-    match firebase.check_user_password(&payload.email, &payload.password).await {
-        Ok(true) => {
-            // If authenticated, get the user details
-            match firebase.get_user_by_username(&payload.email).await {
-                Ok(Some(user_doc)) => {
-                    // Parse the user document into UserDetails
-                    let user_details = parse_user_details(&user_doc);
-                    (StatusCode::OK, Json(LoginResponse {
-                        authenticated: true,
-                        user_details: Some(user_details),
-                    })).into_response()
-                },
-                _ => (StatusCode::OK, Json(LoginResponse {
-                    authenticated: true,
-                    user_details: None,
-                })).into_response(),
-            }
-        },
-        Ok(false) => (StatusCode::UNAUTHORIZED, Json(LoginResponse {
-            authenticated: false,
-            user_details: None,
-        })).into_response(),
+    if payload.username.trim().is_empty() || payload.email.trim().is_empty() {
+        return (StatusCode::BAD_REQUEST, Json(json!({"error": "Username and email are required"}))).into_response();
+    }
+
+    // Usernames and emails identify accounts, so they must stay unique.
+    match storage.get_user_by_username(&payload.username).await {
+        Ok(Some(other)) if other.id != user.user_id => {
+            return (StatusCode::CONFLICT, Json(json!({"error": "Username is already taken"}))).into_response();
+        }
+        Ok(_) => {}
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({"error": e.to_string()}))).into_response(),
+    }
+    match storage.get_user_by_email(&payload.email).await {
+        Ok(Some(other)) if other.id != user.user_id => {
+            return (StatusCode::CONFLICT, Json(json!({"error": "Email is already in use"}))).into_response();
+        }
+        Ok(_) => {}
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({"error": e.to_string()}))).into_response(),
+    }
+
+    let user_id = user.user_id.to_string();
+    if let Err(e) = storage.update_user_profile(&user_id, &payload.display_name, &payload.username, &payload.email).await {
+        return (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({"error": e.to_string()}))).into_response();
+    }
+
+    match storage.get_user_by_id(&user_id).await {
+        Ok(Some(details)) => (StatusCode::OK, Json(details)).into_response(),
+        Ok(None) => (StatusCode::NOT_FOUND, Json(json!({"error": "User not found"}))).into_response(),
         Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({"error": e.to_string()}))).into_response(),
     }
 }
 
-// Helper function to parse user document into UserDetails struct
-fn parse_user_details(user_doc: &serde_json::Value) -> UserDetails {
-    let fields = &user_doc["fields"];
-    
-    let id_str = fields["id"]["stringValue"].as_str().unwrap_or_default();
-    let id = Uuid::parse_str(id_str).unwrap_or_else(|_| Uuid::nil());
-    
-    let projects = fields["projects"]["arrayValue"]["values"]
-        .as_array()
-        .unwrap_or(&Vec::new())
-        .iter()
-        .filter_map(|v| {
-            v["stringValue"].as_str().and_then(|id_str| {
-                Uuid::parse_str(id_str).ok()
-            })
-        })
-        .collect();
-
-    UserDetails {
-        id,
-        display_name: fields["display_name"]["stringValue"].as_str().unwrap_or_default().to_string(),
-        username: fields["username"]["stringValue"].as_str().unwrap_or_default().to_string(),
-        email: fields["email"]["stringValue"].as_str().unwrap_or_default().to_string(),
-        projects,
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::Method;
+    use uuid::Uuid;
+
+    use crate::test_support::{app::TestApp, http::send_as};
+
+    #[tokio::test]
+    async fn profile_lookup_and_update() {
+        let app = TestApp::new().await;
+        let router = app.serve(Router::new().nest("/users", user_routes(Arc::clone(&app.storage))));
+
+        let ada = app.register("ada").await;
+        let grace = app.register("grace").await;
+        let token = app.token(ada);
+        let token = Some(token.as_str());
+
+        let (status, _) = send_as(&router, None, Method::GET, "/users/username/grace", None).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        let (status, body) = send_as(&router, token, Method::GET, "/users/username/grace", None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, json!({ "id": grace, "username": "grace", "display_name": "Grace" }));
+        let (_, by_id) = send_as(&router, token, Method::GET, &format!("/users/id/{}", grace), None).await;
+        assert_eq!(by_id, body);
+
+        let taken = json!({ "display_name": "Ada", "username": "grace", "email": "ada@example.com" });
+        let (status, _) = send_as(&router, token, Method::PUT, "/users/me", Some(taken)).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+
+        let update = json!({ "display_name": "Ada L.", "username": "ada", "email": "ada@lovelace.dev" });
+        let (status, body) = send_as(&router, token, Method::PUT, "/users/me", Some(update)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["display_name"], "Ada L.");
+
+        let (_, body) = send_as(&router, token, Method::GET, "/users/me", None).await;
+        assert_eq!(body["email"], "ada@lovelace.dev");
+        let (status, _) = send_as(&router, token, Method::GET, &format!("/users/id/{}", Uuid::new_v4()), None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,46 @@
+//! The setup most route tests share: storage over a fresh Firestore fake, the
+//! live documents on top of it and a token service for signing callers in.
+
+use axum::Router;
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::connection::document::DocumentStore;
+use crate::connection::session::TokenService;
+use crate::connection::storage::Storage;
+use crate::model::project::Role;
+use crate::test_support::{auth, firestore};
+
+pub struct TestApp {
+    pub storage: Arc<dyn Storage>,
+    pub documents: Arc<DocumentStore>,
+    pub tokens: Arc<TokenService>,
+}
+
+impl TestApp {
+    pub async fn new() -> Self {
+        let storage: Arc<dyn Storage> = firestore::firebase().await;
+        let documents = Arc::new(DocumentStore::new(Arc::clone(&storage)));
+        TestApp { storage, documents, tokens: auth::tokens() }
+    }
+
+    /// `routes` with the sessions the `AuthUser` extractor needs.
+    pub fn serve(&self, routes: Router) -> Router {
+        auth::with_sessions(routes, &self.tokens)
+    }
+
+    /// An access token for `user_id`.
+    pub fn token(&self, user_id: Uuid) -> String {
+        auth::access_token(&self.tokens, user_id)
+    }
+
+    /// Stores a project owned by `owner` with the given extra members and returns its id.
+    pub async fn project(&self, owner: Uuid, members: &[(Uuid, Role)]) -> Uuid {
+        auth::project_with_members(self.storage.as_ref(), owner, members).await
+    }
+
+    /// Stores a user called `username` and returns their id.
+    pub async fn register(&self, username: &str) -> Uuid {
+        auth::register(self.storage.as_ref(), username).await
+    }
+}
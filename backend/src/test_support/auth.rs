@@ -0,0 +1,56 @@
+//! Session helpers for exercising routes that require an `AuthUser`.
+
+use axum::{Extension, Router};
+use std::collections::HashMap;
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::connection::session::TokenService;
+use crate::connection::storage::Storage;
+use crate::model::project::{Project, Role};
+use crate::model::user::UserDetails;
+
+/// A token service with a fixed key, shared by every test.
+pub fn tokens() -> Arc<TokenService> {
+    Arc::new(TokenService::new(b"test-secret"))
+}
+
+/// Adds the token service the `AuthUser` extractor looks for.
+pub fn with_sessions(router: Router, tokens: &Arc<TokenService>) -> Router {
+    router.layer(Extension(Arc::clone(tokens)))
+}
+
+/// An access token for `user_id`.
+pub fn access_token(tokens: &TokenService, user_id: Uuid) -> String {
+    tokens.issue(user_id, "tester").expect("sign token").access_token
+}
+
+/// Stores a project owned by `owner` with the given extra members and returns its id.
+pub async fn project_with_members(storage: &dyn Storage, owner: Uuid, members: &[(Uuid, Role)]) -> Uuid {
+    let mut roles: HashMap<Uuid, Role> = members.iter().copied().collect();
+    roles.insert(owner, Role::Owner);
+    let project = Project {
+        id: Uuid::new_v4(),
+        name: "shared".into(),
+        description: String::new(),
+        owner,
+        members: std::iter::once(owner).chain(members.iter().map(|(id, _)| *id)).collect(),
+        roles,
+        files: vec![],
+    };
+    storage.create_project(&project).await.expect("create project");
+    project.id
+}
+
+/// Stores a user called `username`, shown as the name capitalised, and returns their id.
+pub async fn register(storage: &dyn Storage, username: &str) -> Uuid {
+    let details = UserDetails {
+        id: Uuid::new_v4(),
+        display_name: format!("{}{}", username[..1].to_uppercase(), &username[1..]),
+        username: username.into(),
+        email: format!("{}@example.com", username),
+        projects: vec![],
+    };
+    storage.create_user(&details, "hash").await.expect("create user");
+    details.id
+}
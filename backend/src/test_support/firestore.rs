@@ -0,0 +1,341 @@
+//! An in-memory stand-in for the slice of the Firestore v1 REST API the backend
+//! uses: document create/get/list/patch/delete, `runQuery` with a single
+//! `EQUAL` field filter and `limit`, and `commit` for atomic batches of up to
+//! 500 updates (with or without a mask and `updateTransforms`), deletes and
+//! array transforms with `exists` and `updateTime` preconditions.
+
+use axum::{
+    extract::{Path, Query, RawQuery, State},
+    http::StatusCode,
+    response::IntoResponse,
+    routing::{get, post},
+    Json, Router,
+};
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::net::TcpListener;
+
+use crate::connection::firebase::FirebaseService;
+
+const PROJECT_ID: &str = "test-project";
+
+#[derive(Clone, Default)]
+struct Document {
+    fields: Map<String, Value>,
+    /// Changes on every write, for `updateTime` preconditions.
+    update_time: String,
+}
+
+// collection -> document id -> document
+type Collections = HashMap<String, BTreeMap<String, Document>>;
+
+static CLOCK: AtomicU64 = AtomicU64::new(0);
+
+// A fresh timestamp; only ever compared for equality.
+fn stamp() -> String {
+    format!("1970-01-01T00:00:00.{:09}Z", CLOCK.fetch_add(1, Ordering::Relaxed) + 1)
+}
+
+#[derive(Clone, Default)]
+struct Emulator {
+    collections: Arc<Mutex<Collections>>,
+}
+
+#[derive(Deserialize)]
+struct CreateQuery {
+    #[serde(rename = "documentId")]
+    document_id: Option<String>,
+}
+
+fn document_json(project: &str, collection: &str, id: &str, document: &Document) -> Value {
+    json!({
+        "name": format!("projects/{}/databases/(default)/documents/{}/{}", project, collection, id),
+        "fields": document.fields,
+        "updateTime": document.update_time,
+    })
+}
+
+fn failed_precondition(message: &str) -> axum::response::Response {
+    (StatusCode::BAD_REQUEST, Json(json!({ "error": { "code": 400, "message": message, "status": "FAILED_PRECONDITION" } })))
+        .into_response()
+}
+
+// Without a mask `fields` replaces the document; with one, only the listed
+// fields are written, and removed when absent from `fields`.
+fn write_fields(document: &mut Document, fields: Map<String, Value>, mask: Option<Vec<String>>) {
+    match mask {
+        None => document.fields = fields,
+        Some(mask) => {
+            for path in mask {
+                match fields.get(&path) {
+                    Some(value) => document.fields.insert(path, value.clone()),
+                    None => document.fields.remove(&path),
+                };
+            }
+        }
+    }
+    document.update_time = stamp();
+}
+
+fn not_found(collection: &str, id: &str) -> axum::response::Response {
+    let message = format!("No document to update: {}/{}", collection, id);
+    (StatusCode::NOT_FOUND, Json(json!({ "error": { "code": 404, "message": message, "status": "NOT_FOUND" } })))
+        .into_response()
+}
+
+async fn create_document(
+    State(emulator): State<Emulator>,
+    Path((project, _database, collection)): Path<(String, String, String)>,
+    Query(query): Query<CreateQuery>,
+    Json(body): Json<Value>,
+) -> impl IntoResponse {
+    let id = query.document_id.unwrap_or_else(|| uuid::Uuid::new_v4().simple().to_string());
+    let fields = body["fields"].as_object().cloned().unwrap_or_default();
+
+    let mut collections = emulator.collections.lock().unwrap();
+    let documents = collections.entry(collection.clone()).or_default();
+    if documents.contains_key(&id) {
+        let message = format!("Document already exists: {}/{}", collection, id);
+        return (StatusCode::CONFLICT, Json(json!({ "error": { "code": 409, "message": message, "status": "ALREADY_EXISTS" } })))
+            .into_response();
+    }
+    let document = Document { fields, update_time: stamp() };
+    let body = document_json(&project, &collection, &id, &document);
+    documents.insert(id, document);
+
+    (StatusCode::OK, Json(body)).into_response()
+}
+
+async fn list_documents(
+    State(emulator): State<Emulator>,
+    Path((project, _database, collection)): Path<(String, String, String)>,
+) -> impl IntoResponse {
+    let collections = emulator.collections.lock().unwrap();
+    let documents: Vec<Value> = collections
+        .get(&collection)
+        .map(|docs| docs.iter().map(|(id, document)| document_json(&project, &collection, id, document)).collect())
+        .unwrap_or_default();
+
+    if documents.is_empty() {
+        Json(json!({}))
+    } else {
+        Json(json!({ "documents": documents }))
+    }
+}
+
+async fn get_document(
+    State(emulator): State<Emulator>,
+    Path((project, _database, collection, id)): Path<(String, String, String, String)>,
+) -> impl IntoResponse {
+    let collections = emulator.collections.lock().unwrap();
+    match collections.get(&collection).and_then(|docs| docs.get(&id)) {
+        Some(document) => (StatusCode::OK, Json(document_json(&project, &collection, &id, document))).into_response(),
+        None => not_found(&collection, &id),
+    }
+}
+
+// Missing documents are created, as in Firestore.
+async fn patch_document(
+    State(emulator): State<Emulator>,
+    Path((project, _database, collection, id)): Path<(String, String, String, String)>,
+    RawQuery(raw_query): RawQuery,
+    Json(body): Json<Value>,
+) -> impl IntoResponse {
+    let mask: Vec<String> = raw_query
+        .unwrap_or_default()
+        .split('&')
+        .filter_map(|pair| pair.strip_prefix("updateMask.fieldPaths="))
+        .map(String::from)
+        .collect();
+    let new_fields = body["fields"].as_object().cloned().unwrap_or_default();
+
+    let mut collections = emulator.collections.lock().unwrap();
+    let document = collections.entry(collection.clone()).or_default().entry(id.clone()).or_default();
+    write_fields(document, new_fields, (!mask.is_empty()).then_some(mask));
+
+    Json(document_json(&project, &collection, &id, document))
+}
+
+async fn delete_document(
+    State(emulator): State<Emulator>,
+    Path((_project, _database, collection, id)): Path<(String, String, String, String)>,
+) -> impl IntoResponse {
+    if let Some(docs) = emulator.collections.lock().unwrap().get_mut(&collection) {
+        docs.remove(&id);
+    }
+    Json(json!({}))
+}
+
+async fn run_query(
+    State(emulator): State<Emulator>,
+    Path((project, _database)): Path<(String, String)>,
+    Json(body): Json<Value>,
+) -> impl IntoResponse {
+    let query = &body["structuredQuery"];
+    let Some(collection) = query["from"][0]["collectionId"].as_str() else {
+        return (StatusCode::BAD_REQUEST, Json(json!({ "error": { "message": "missing collectionId" } }))).into_response();
+    };
+
+    let filter = &query["where"]["fieldFilter"];
+    if !filter.is_null() && filter["op"] != "EQUAL" {
+        return (StatusCode::BAD_REQUEST, Json(json!({ "error": { "message": "only EQUAL filters are supported" } }))).into_response();
+    }
+    let limit = query["limit"].as_u64().map(|l| l as usize).unwrap_or(usize::MAX);
+
+    let collections = emulator.collections.lock().unwrap();
+    let results: Vec<Value> = collections
+        .get(collection)
+        .into_iter()
+        .flat_map(|docs| docs.iter())
+        .filter(|(_, document)| {
+            filter.is_null()
+                || filter["field"]["fieldPath"]
+                    .as_str()
+                    .and_then(|path| document.fields.get(path))
+                    .is_some_and(|value| *value == filter["value"])
+        })
+        .take(limit)
+        .map(|(id, document)| json!({ "document": document_json(&project, collection, id, document) }))
+        .collect();
+
+    // Firestore answers an empty query with a single result that has no document.
+    if results.is_empty() {
+        (StatusCode::OK, Json(json!([{ "readTime": "1970-01-01T00:00:00Z" }]))).into_response()
+    } else {
+        (StatusCode::OK, Json(Value::Array(results))).into_response()
+    }
+}
+
+// "projects/p/databases/(default)/documents/files/abc" -> ("files", "abc")
+fn split_document_name(name: &str) -> Option<(String, String)> {
+    let (_, path) = name.split_once("/documents/")?;
+    let (collection, id) = path.split_once('/')?;
+    Some((collection.to_string(), id.to_string()))
+}
+
+fn write_target(write: &Value) -> Option<(String, String)> {
+    let name = write["update"]["name"]
+        .as_str()
+        .or_else(|| write["delete"].as_str())
+        .or_else(|| write["transform"]["document"].as_str())?;
+    split_document_name(name)
+}
+
+fn apply_transform(fields: &mut Map<String, Value>, transform: &Value) {
+    let Some(path) = transform["fieldPath"].as_str() else { return };
+    let mut values = fields
+        .get(path)
+        .and_then(|field| field["arrayValue"]["values"].as_array().cloned())
+        .unwrap_or_default();
+
+    if let Some(added) = transform["appendMissingElements"]["values"].as_array() {
+        for value in added {
+            if !values.contains(value) {
+                values.push(value.clone());
+            }
+        }
+    }
+    if let Some(removed) = transform["removeAllFromArray"]["values"].as_array() {
+        values.retain(|value| !removed.contains(value));
+    }
+
+    fields.insert(path.to_string(), json!({ "arrayValue": { "values": values } }));
+}
+
+// All preconditions are checked before anything is written, and the writes
+// land together, so a failed commit leaves the store untouched.
+async fn commit(
+    State(emulator): State<Emulator>,
+    Json(body): Json<Value>,
+) -> impl IntoResponse {
+    let writes = body["writes"].as_array().cloned().unwrap_or_default();
+    if writes.len() > 500 {
+        let message = "maximum 500 writes allowed per request";
+        return (StatusCode::BAD_REQUEST, Json(json!({ "error": { "code": 400, "message": message, "status": "INVALID_ARGUMENT" } })))
+            .into_response();
+    }
+    let mut collections = emulator.collections.lock().unwrap();
+
+    let mut targets = Vec::with_capacity(writes.len());
+    for write in &writes {
+        let Some((collection, id)) = write_target(write) else {
+            return (StatusCode::BAD_REQUEST, Json(json!({ "error": { "message": "unsupported write" } }))).into_response();
+        };
+        let current = collections.get(&collection).and_then(|docs| docs.get(&id));
+        let exists = current.is_some();
+        if let Some(update_time) = write["currentDocument"]["updateTime"].as_str() {
+            if current.is_none_or(|document| document.update_time != update_time) {
+                return failed_precondition(&format!("The document {}/{} was changed", collection, id));
+            }
+        }
+        match write["currentDocument"]["exists"].as_bool() {
+            Some(true) if !exists => return not_found(&collection, &id),
+            Some(false) if exists => {
+                let message = format!("Document already exists: {}/{}", collection, id);
+                return (StatusCode::CONFLICT, Json(json!({ "error": { "code": 409, "message": message, "status": "ALREADY_EXISTS" } })))
+                    .into_response();
+            }
+            _ => {}
+        }
+        targets.push((collection, id));
+    }
+
+    for (write, (collection, id)) in writes.iter().zip(targets) {
+        let documents = collections.entry(collection).or_default();
+        if write.get("delete").is_some() {
+            documents.remove(&id);
+        } else if let Some(update) = write.get("update") {
+            let fields = update["fields"].as_object().cloned().unwrap_or_default();
+            let mask = write["updateMask"]["fieldPaths"]
+                .as_array()
+                .map(|paths| paths.iter().filter_map(|path| path.as_str().map(String::from)).collect());
+            let document = documents.entry(id).or_default();
+            write_fields(document, fields, mask);
+            for transform in write["updateTransforms"].as_array().into_iter().flatten() {
+                apply_transform(&mut document.fields, transform);
+            }
+        } else {
+            let document = documents.entry(id).or_default();
+            for transform in write["transform"]["fieldTransforms"].as_array().into_iter().flatten() {
+                apply_transform(&mut document.fields, transform);
+            }
+            document.update_time = stamp();
+        }
+    }
+
+    let results: Vec<Value> = writes.iter().map(|_| json!({})).collect();
+    (StatusCode::OK, Json(json!({ "writeResults": results, "commitTime": "1970-01-01T00:00:00Z" }))).into_response()
+}
+
+/// Starts the emulator on an ephemeral port and returns its `/v1` base URL.
+pub async fn spawn_emulator() -> String {
+    let app = Router::new()
+        .route("/v1/projects/{project}/databases/{database}/documents:runQuery", post(run_query))
+        .route("/v1/projects/{project}/databases/{database}/documents:commit", post(commit))
+        .route(
+            "/v1/projects/{project}/databases/{database}/documents/{collection}",
+            post(create_document).get(list_documents),
+        )
+        .route(
+            "/v1/projects/{project}/databases/{database}/documents/{collection}/{id}",
+            get(get_document).patch(patch_document).delete(delete_document),
+        )
+        .with_state(Emulator::default());
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind emulator");
+    let addr = listener.local_addr().expect("emulator address");
+    tokio::spawn(async move {
+        axum::serve(listener, app).await.expect("emulator server");
+    });
+
+    format!("http://{}/v1", addr)
+}
+
+/// A `FirebaseService` talking to a fresh, empty emulator.
+pub async fn firebase() -> Arc<FirebaseService> {
+    let base_url = spawn_emulator().await;
+    Arc::new(FirebaseService::with_base_url(&base_url, PROJECT_ID, "test-token"))
+}
@@ -0,0 +1,71 @@
+//! Helpers for driving a router in-process and reading JSON back.
+
+use axum::{
+    body::{to_bytes, Body},
+    http::{Method, Request, StatusCode},
+    Router,
+};
+use serde_json::Value;
+use tower::ServiceExt;
+
+/// Sends one request to `router` and returns the status with the body parsed as JSON
+/// (`Value::Null` for an empty or non-JSON body).
+pub async fn send(router: &Router, method: Method, uri: &str, body: Option<Value>) -> (StatusCode, Value) {
+    send_as(router, None, method, uri, body).await
+}
+
+/// Like [`send`], with `Authorization: Bearer <token>` when a token is given.
+pub async fn send_as(
+    router: &Router,
+    token: Option<&str>,
+    method: Method,
+    uri: &str,
+    body: Option<Value>,
+) -> (StatusCode, Value) {
+    let mut request = Request::builder().method(method).uri(uri);
+    if let Some(token) = token {
+        request = request.header("authorization", format!("Bearer {}", token));
+    }
+    let body = match body {
+        Some(json) => {
+            request = request.header("content-type", "application/json");
+            Body::from(json.to_string())
+        }
+        None => Body::empty(),
+    };
+
+    let response = router
+        .clone()
+        .oneshot(request.body(body).expect("valid request"))
+        .await
+        .expect("router is infallible");
+    let status = response.status();
+    let bytes = to_bytes(response.into_body(), usize::MAX).await.expect("readable body");
+
+    (status, serde_json::from_slice(&bytes).unwrap_or(Value::Null))
+}
+
+/// Sends raw bytes with the given content type and returns the status with the raw response body.
+pub async fn send_bytes(
+    router: &Router,
+    token: Option<&str>,
+    method: Method,
+    uri: &str,
+    content_type: &str,
+    body: Vec<u8>,
+) -> (StatusCode, Vec<u8>) {
+    let mut request = Request::builder().method(method).uri(uri).header("content-type", content_type);
+    if let Some(token) = token {
+        request = request.header("authorization", format!("Bearer {}", token));
+    }
+
+    let response = router
+        .clone()
+        .oneshot(request.body(Body::from(body)).expect("valid request"))
+        .await
+        .expect("router is infallible");
+    let status = response.status();
+    let bytes = to_bytes(response.into_body(), usize::MAX).await.expect("readable body");
+
+    (status, bytes.to_vec())
+}
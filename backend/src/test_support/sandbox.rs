@@ -0,0 +1,23 @@
+//! The `codecollab-sandbox` helper for tests that run code. Cargo only builds
+//! binaries for integration tests, so the first test to need it builds it.
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::OnceLock;
+
+pub fn helper() -> PathBuf {
+    static HELPER: OnceLock<PathBuf> = OnceLock::new();
+    HELPER
+        .get_or_init(|| {
+            let mut build = Command::new(env!("CARGO"));
+            build.args(["build", "--quiet", "--bin", "codecollab-sandbox", "--manifest-path", concat!(env!("CARGO_MANIFEST_DIR"), "/Cargo.toml")]);
+            if !cfg!(debug_assertions) {
+                build.arg("--release");
+            }
+            assert!(build.status().expect("cargo runs").success(), "the sandbox helper builds");
+            // Test binaries live in `target/<profile>/deps`, binaries one level up.
+            let test_binary = std::env::current_exe().expect("test binary path");
+            test_binary.parent().and_then(Path::parent).expect("target directory").join("codecollab-sandbox")
+        })
+        .clone()
+}
@@ -1,13 +1,17 @@
 import { useEffect, useRef, useState } from "react";
 import { useDispatch } from "react-redux";
 import { updateFileContent } from "../redux/Slices/fileSlice";
-import { io, Socket } from "socket.io-client";
 
 interface FileUpdatePayload {
   fileId: string;
   content: string;
 }
 
+interface SocketMessage {
+  event: string;
+  data: any;
+}
+
 interface ActiveUser {
   userId: string;
   userName: string;
@@ -20,39 +24,57 @@ export const useFileSocket = (
   userName: string
 ) => {
   const dispatch = useDispatch();
-  const socketRef = useRef<Socket | null>(null);
+  const socketRef = useRef<WebSocket | null>(null);
   const [activeUsers, setActiveUsers] = useState<ActiveUser[]>([]);
 
   useEffect(() => {
-    const socket = io("http://localhost:5000");
+    const token = localStorage.getItem("accessToken") ?? "";
+    const socket = new WebSocket(
+      `ws://localhost:5000/ws/files/${fileId}?access_token=${encodeURIComponent(token)}`
+    );
     socketRef.current = socket;
 
-    socket.emit("join-file", { projectId, fileId, userId, userName });
+    const emit = (event: string, data: unknown) => {
+      if (socket.readyState === WebSocket.OPEN) {
+        socket.send(JSON.stringify({ event, data }));
+      }
+    };
 
-    socket.on("file-updated", (payload: FileUpdatePayload) => {
-      if (payload.fileId === fileId) {
-        dispatch(updateFileContent(payload));
+    socket.onopen = () => {
+      emit("join-file", { projectId, fileId, userId, userName });
+    };
+
+    socket.onmessage = (message: MessageEvent<string>) => {
+      const { event, data }: SocketMessage = JSON.parse(message.data);
+      switch (event) {
+        case "file-updated":
+          if ((data as FileUpdatePayload).fileId === fileId) {
+            dispatch(updateFileContent(data as FileUpdatePayload));
+          }
+          break;
+        case "active-users":
+          setActiveUsers(data as ActiveUser[]);
+          break;
+        case "user-joined":
+          setActiveUsers((prev) => [...prev, data as ActiveUser]);
+          break;
+        case "user-left":
+          setActiveUsers((prev) => prev.filter(u => u.userId !== (data as ActiveUser).userId));
+          break;
       }
-    });
-
-    socket.on("active-users", (users: ActiveUser[]) => {
-      setActiveUsers(users);
-    });
-    socket.on("user-joined", (user: ActiveUser) => {
-      setActiveUsers((prev) => [...prev, user]);
-    });
-    socket.on("user-left", (user: ActiveUser) => {
-      setActiveUsers((prev) => prev.filter(u => u.userId !== user.userId));
-    });
+    };
 
     return () => {
-      socket.emit("leave-file", { projectId, fileId, userId });
-      socket.disconnect();
+      emit("leave-file", { projectId, fileId, userId });
+      socket.close();
     };
   }, [projectId, fileId, userId, userName, dispatch]);
 
   const emitFileUpdate = (content: string) => {
-    socketRef.current?.emit("update-file", { fileId, content });
+    const socket = socketRef.current;
+    if (socket?.readyState === WebSocket.OPEN) {
+      socket.send(JSON.stringify({ event: "update-file", data: { fileId, content } }));
+    }
   };
 
   return { emitFileUpdate, activeUsers };
@@ -1,5 +1,6 @@
 import React, { useState } from "react";
-import { useNavigate, Link } from "react-router-dom";
+import { useNavigate, Link, useSearchParams } from "react-router-dom";
+import axios from "axios";
 import { useDispatch } from "react-redux";
 import { User, LogOut } from "lucide-react";
 import { useApplyTheme } from "../hooks/useApplyTheme";
@@ -7,15 +8,27 @@ import { setRoomId } from "../redux/Slices/roomSlice";
 
 const JoinRoom = () => {
   useApplyTheme();
-  const [roomId, setRoomIdState] = useState(""); // Renamed to avoid conflict
+  const [searchParams] = useSearchParams();
+  // Invite links land here as /join?code=XXXX
+  const [roomId, setRoomIdState] = useState(searchParams.get("code") ?? ""); // Renamed to avoid conflict
   const [showDropdown, setShowDropdown] = useState(false);
   const navigate = useNavigate();
   const dispatch = useDispatch();
 
-  const handleJoin = () => {
+  const handleJoin = async () => {
     if (roomId) {
-      dispatch(setRoomId(roomId)); 
-      navigate(`/editor/${roomId}`);
+      try {
+        const token = localStorage.getItem("accessToken");
+        const { data } = await axios.post(
+          `http://localhost:5000/invites/${roomId.trim().toUpperCase()}/redeem`,
+          {},
+          { headers: { Authorization: `Bearer ${token}` } }
+        );
+        dispatch(setRoomId(data.project_id));
+        navigate(`/editor/${data.project_id}`);
+      } catch (err: any) {
+        alert(err.response?.data?.error || "Could not join with this code.");
+      }
     } else {
       alert("Please enter Room ID.");
     }
@@ -30,6 +30,9 @@ const SignIn = () => {
       );
       console.log(response.data);
       if (response.status === 201 || response.status === 200) {
+        localStorage.setItem("accessToken", response.data.access_token);
+        localStorage.setItem("refreshToken", response.data.refresh_token);
+        axios.defaults.headers.common["Authorization"] = `Bearer ${response.data.access_token}`;
         dispatch(setAuthField({ field: "username", value: username }));
         dispatch(setAuthField({ field: "password", value: password }));
         navigate("/home");